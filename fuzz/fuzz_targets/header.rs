@@ -0,0 +1,17 @@
+//! Header parsing of any bytes, as read from the pixels of an image: it may fail, but
+//! must not panic.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use picturer::format::{self, Delta, Info, Volume};
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Volume::parse(data);
+    let _ = Delta::parse(data);
+    let _ = format::is_container(data);
+    let _ = format::container_len(data);
+    let _ = format::legacy_len(data);
+    let _ = format::content_type(data);
+    let _ = format::meta_hidden(data);
+    let _ = Info::read(data, None);
+});
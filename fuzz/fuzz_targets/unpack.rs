@@ -0,0 +1,28 @@
+//! Unpacking of any bytes as a container, whole, as a range and streamed: it may fail,
+//! but must not panic.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use picturer::format::{self, Source};
+
+/// A container wholly in memory, for the functions that read one from a [`Source`].
+struct Bytes<'a>(&'a [u8]);
+
+impl Source for Bytes<'_> {
+    fn prefix(&mut self, len: usize) -> anyhow::Result<&[u8]> {
+        Ok(&self.0[..len.min(self.0.len())])
+    }
+
+    fn total_len(&self) -> Option<usize> {
+        Some(self.0.len())
+    }
+}
+
+fuzz_target!(|data: &[u8]| {
+    // Small enough that containers made to expand fail fast rather than time out.
+    format::set_max_output(1 << 24);
+    let _ = format::unpack(data, None);
+    let _ = format::unpack_range(&mut Bytes(data), 3, Some(1 << 21), None);
+    let _ = format::unpack_to(&mut Bytes(data), None, &mut std::io::sink(), |_, _| {});
+    let _ = format::append(data, b"appended", None);
+});
@@ -0,0 +1,51 @@
+/*
+ * C API of picturer, exported by the library built with the capi feature:
+ *
+ *     cargo build --release --features capi
+ *     cc app.c -Iinclude -Ltarget/release -lpicturer
+ *
+ * Output goes into a buffer the caller owns, of *out_len bytes. When it is too small,
+ * or out is NULL, a call returns PICTURER_ERR_BUFFER_TOO_SMALL and stores the size
+ * needed in *out_len, so the usual pattern is to call once with NULL, allocate, and
+ * call again.
+ */
+#ifndef PICTURER_H
+#define PICTURER_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PICTURER_OK 0
+/* A pointer or the codec name is invalid. */
+#define PICTURER_ERR_ARGUMENT 1
+/* out is smaller than *out_len, which now holds the size needed. */
+#define PICTURER_ERR_BUFFER_TOO_SMALL 2
+/* The data does not fit one image, or the codec is not built in. */
+#define PICTURER_ERR_ENCODE 3
+/* The input is not a self-contained picturer image, or it is corrupt. */
+#define PICTURER_ERR_DECODE 4
+/* Something went wrong inside picturer; this is a bug. */
+#define PICTURER_ERR_INTERNAL 5
+
+/*
+ * Encodes len bytes at data as a PNG file. codec is a name such as "raw", "zlib-6"
+ * or "zstd-19", or NULL for the default.
+ */
+int picturer_encode(const uint8_t *data, size_t len, const char *codec,
+                    uint8_t *out, size_t *out_len);
+
+/* Decodes a PNG file written by picturer back into the bytes it holds. */
+int picturer_decode(const uint8_t *png, size_t len, uint8_t *out, size_t *out_len);
+
+/* A static description of a status code. */
+const char *picturer_strerror(int status);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,234 @@
+//! Encryption to age recipients, so that payloads are encrypted to the X25519 keys age
+//! and rage already made, or to SSH public keys as age does, and decrypted with their
+//! identity files or SSH private keys, without either tool. With the pq feature,
+//! [`crate::pq`] recipients and identities are taken wherever age's are.
+use crate::pq;
+use ::age::secrecy::SecretString;
+use ::age::{ssh, x25519, Decryptor, Encryptor, IdentityFile};
+use anyhow::{anyhow, bail, ensure, Context};
+use std::fs::File;
+use std::io::{BufRead, BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Public keys to encrypt to.
+pub type Recipients = Vec<Box<dyn ::age::Recipient + Send + Sync>>;
+
+/// Secret keys read from identity files.
+pub type Identities = Vec<Box<dyn ::age::Identity + Send + Sync>>;
+
+/// The recipients given with `--age-recipient`: an `age1...` or `picturerpq1...` public
+/// key, or a file of them, one per line as `age -R` takes them, where an
+/// `AGE-SECRET-KEY-1...` line stands for its public key.
+pub fn recipients(given: &str) -> anyhow::Result<Recipients> {
+    if let Ok(recipient) = given.parse::<x25519::Recipient>() {
+        return Ok(vec![Box::new(recipient)]);
+    }
+    if let Some(recipients) = pq::recipient(given) {
+        return recipients;
+    }
+    let text = std::fs::read_to_string(given).with_context(|| {
+        format!("--age-recipient is neither an age1 public key nor a readable file: {given}")
+    })?;
+    let recipients = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.is_empty() && !line.starts_with('#'))
+        .map(|line| {
+            pq::recipient(line).unwrap_or_else(|| {
+                line.parse::<x25519::Recipient>()
+                    .or_else(|_| line.parse::<x25519::Identity>().map(|key| key.to_public()))
+                    .map(|recipient| vec![Box::new(recipient) as _])
+                    .map_err(|_| anyhow!("{given} holds a line that is not an age X25519 key"))
+            })
+        })
+        .collect::<anyhow::Result<Vec<Recipients>>>()?
+        .into_iter()
+        .flatten()
+        .collect::<Recipients>();
+    ensure!(!recipients.is_empty(), "{given} holds no age recipients");
+    Ok(recipients)
+}
+
+/// The recipients given with `--recipient-ssh`: a `.pub` file or `authorized_keys`, of
+/// whose lines the ssh-ed25519 and ssh-rsa keys are taken and the rest skipped.
+pub fn ssh_recipients(path: &Path) -> anyhow::Result<Recipients> {
+    let text = std::fs::read_to_string(path)
+        .with_context(|| format!("cannot read SSH public key {}", path.display()))?;
+    let mut recipients = Recipients::new();
+    for line in text.lines().map(str::trim) {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        match line.parse::<ssh::Recipient>() {
+            Ok(recipient) => recipients.push(Box::new(recipient)),
+            Err(ssh::ParseRecipientKeyError::Ignore) => {}
+            Err(ssh::ParseRecipientKeyError::Unsupported(kind)) => {
+                eprintln!(
+                    "skipping a {kind} key of {}, which age cannot encrypt to",
+                    path.display()
+                );
+            }
+            Err(_) => bail!(
+                "{} holds a line that is not a usable SSH public key",
+                path.display()
+            ),
+        }
+    }
+    ensure!(
+        !recipients.is_empty(),
+        "{} holds no ssh-ed25519 or ssh-rsa keys",
+        path.display()
+    );
+    Ok(recipients)
+}
+
+/// The identities in the files given with `--age-identity`, in the format of
+/// `age-keygen`, or SSH private keys, whose passphrase is asked for on the terminal if
+/// they have one.
+pub fn identities(paths: &[PathBuf]) -> anyhow::Result<Identities> {
+    let mut identities = Identities::new();
+    for path in paths {
+        let mut file = BufReader::new(
+            File::open(path)
+                .with_context(|| format!("cannot read identity file {}", path.display()))?,
+        );
+        if file.fill_buf()?.starts_with(b"-----BEGIN") {
+            let name = path.display().to_string();
+            let identity = match ssh::Identity::from_buffer(file, Some(name))
+                .with_context(|| format!("{} is not an SSH private key", path.display()))?
+            {
+                // Decrypted once here, rather than for every stanza of the age header.
+                ssh::Identity::Encrypted(key) => {
+                    let passphrase =
+                        rpassword::prompt_password(format!("Passphrase of {}: ", path.display()))
+                            .context(
+                            "cannot ask for the passphrase of an SSH key without a terminal",
+                        )?;
+                    key.decrypt(SecretString::from(passphrase))
+                        .with_context(|| format!("cannot unlock {}", path.display()))?
+                        .into()
+                }
+                ssh::Identity::Unsupported(_) => bail!(
+                    "{} is an SSH key of a kind age cannot decrypt with",
+                    path.display()
+                ),
+                identity @ ssh::Identity::Unencrypted(_) => identity,
+            };
+            identities.push(Box::new(identity));
+            continue;
+        }
+        let mut text = Zeroizing::new(String::new());
+        file.read_to_string(&mut text)
+            .with_context(|| format!("cannot read identity file {}", path.display()))?;
+        let mut found = Identities::new();
+        let mut rest = Zeroizing::new(String::new());
+        for line in text.lines() {
+            if let Some(hybrid) = pq::identity(line.trim()) {
+                found.extend(hybrid?);
+            } else {
+                rest.push_str(line);
+                rest.push('\n');
+            }
+        }
+        let keys = |line: &&str| !line.trim().is_empty() && !line.starts_with('#');
+        if found.is_empty() || rest.lines().any(|line| keys(&line)) {
+            found.extend(
+                IdentityFile::from_buffer(rest.as_bytes())
+                    .and_then(|file| file.into_identities().map_err(std::io::Error::other))
+                    .with_context(|| format!("{} is not an age identity file", path.display()))?,
+            );
+        }
+        ensure!(
+            !found.is_empty(),
+            "{} holds no age identities",
+            path.display()
+        );
+        identities.extend(found);
+    }
+    Ok(identities)
+}
+
+/// Encrypts what `input` reads to every one of `recipients`, writing the age file to
+/// `out`.
+pub fn encrypt(mut input: impl Read, recipients: &Recipients, out: &Path) -> anyhow::Result<()> {
+    let encryptor = Encryptor::with_recipients(
+        recipients
+            .iter()
+            .map(|recipient| recipient.as_ref() as &dyn ::age::Recipient),
+    )?;
+    let mut writer = encryptor.wrap_output(BufWriter::new(File::create(out)?))?;
+    std::io::copy(&mut input, &mut writer)?;
+    writer.finish()?.flush()?;
+    Ok(())
+}
+
+/// Decrypts the age file `input` reads with whichever of `identities` it was encrypted
+/// to, as a reader that decrypts only as far as it is read.
+pub fn decrypt_reader<R: Read>(input: R, identities: &Identities) -> anyhow::Result<impl Read> {
+    let decryptor = Decryptor::new(input).context("the payload is not encrypted with age")?;
+    decryptor
+        .decrypt(
+            identities
+                .iter()
+                .map(|identity| identity.as_ref() as &dyn ::age::Identity),
+        )
+        .context("decrypting with age failed")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ::age::secrecy::ExposeSecret;
+
+    /// Writes a new identity to `dir` as `age-keygen` does, and gives its path and
+    /// public key.
+    fn identity(dir: &Path, name: &str) -> (PathBuf, String) {
+        let identity = x25519::Identity::generate();
+        let path = dir.join(name);
+        let public = identity.to_public().to_string();
+        let text = format!(
+            "# public key: {public}\n{}\n",
+            identity.to_string().expose_secret()
+        );
+        std::fs::write(&path, text).unwrap();
+        (path, public)
+    }
+
+    fn decrypt(path: &Path, identities: &Identities) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decrypt_reader(File::open(path)?, identities)?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn a_payload_decrypts_with_any_identity_it_was_encrypted_to() {
+        let dir = tempfile::tempdir().unwrap();
+        let (first, public) = identity(dir.path(), "first.txt");
+        let (second, _) = identity(dir.path(), "second.txt");
+        let mut given = recipients(&public).unwrap();
+        // A file of recipients takes an identity for its public key.
+        given.extend(recipients(second.to_str().unwrap()).unwrap());
+        let payload = vec![7; 100_000];
+        let out = dir.path().join("payload.age");
+        encrypt(&payload[..], &given, &out).unwrap();
+        for path in [first, second] {
+            let identities = identities(&[path]).unwrap();
+            assert_eq!(decrypt(&out, &identities).unwrap(), payload);
+        }
+    }
+
+    #[test]
+    fn another_identity_does_not_decrypt() {
+        let dir = tempfile::tempdir().unwrap();
+        let (_, public) = identity(dir.path(), "mine.txt");
+        let (theirs, _) = identity(dir.path(), "theirs.txt");
+        let out = dir.path().join("payload.age");
+        encrypt(&b"payload"[..], &recipients(&public).unwrap(), &out).unwrap();
+        let err = decrypt(&out, &identities(&[theirs]).unwrap()).unwrap_err();
+        assert_eq!(err.to_string(), "decrypting with age failed");
+
+        assert!(decrypt(&dir.path().join("mine.txt"), &Identities::new()).is_err());
+        assert!(recipients("age1notakey").is_err());
+    }
+}
@@ -0,0 +1,169 @@
+//! `picturer analyze`: a map of an image, drawn over it, of which pixels hold the
+//! header, which hold each block and how random its bytes are, which hold padding and
+//! which blocks no longer decode, for debugging layouts and spotting damage at a glance.
+use crate::cli::AnalyzeArgs;
+use crate::dict::Dictionary;
+use anyhow::Context;
+use image::{Rgb, RgbImage};
+use picturer::format::Layout;
+use std::path::Path;
+
+/// Drawn over the header.
+const HEADER: [u8; 3] = [0, 170, 255];
+/// Drawn over padding after the last block.
+const PADDING: [u8; 3] = [48, 48, 48];
+/// Drawn over blocks that do not decode.
+const DAMAGED: [u8; 3] = [255, 0, 255];
+/// Drawn over blocks from the lowest entropy to the highest, between which it is blended.
+const HEAT: [[u8; 3]; 4] = [[0, 0, 160], [0, 190, 0], [255, 220, 0], [230, 40, 0]];
+
+/// Writes the map of `args.image` to `--output`, by default beside it as
+/// `NAME.analysis.png`, and prints what it shows.
+pub fn run(args: &AnalyzeArgs) -> anyhow::Result<()> {
+    let image = &args.image;
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let (read, _) = crate::read_oriented(image)?;
+    let read = picturer::canonical(read)?;
+    let per_pixel = if picturer::is_opaque(&read) { 3 } else { 4 };
+    let original = read.to_luma8();
+    let bytes = picturer::pixels(read);
+    let layout = Layout::read(&bytes, dict.as_ref()).with_context(|| {
+        format!(
+            "{} holds no container laid out pixel by pixel; robust, tiled and hidden \
+             payloads cannot be mapped",
+            image.display()
+        )
+    })?;
+    let entropy: Vec<f64> = layout
+        .blocks
+        .iter()
+        .map(|block| entropy(bytes.get(block.clone()).unwrap_or_default()))
+        .collect();
+    let end = layout
+        .blocks
+        .last()
+        .map_or(layout.header, |block| block.end);
+    let mut block = 0;
+    let map = RgbImage::from_fn(original.width(), original.height(), |x, y| {
+        let at = (y as usize * original.width() as usize + x as usize) * per_pixel;
+        while layout
+            .blocks
+            .get(block)
+            .is_some_and(|range| range.end <= at)
+        {
+            block += 1;
+        }
+        let color = if at < layout.header {
+            HEADER
+        } else if at >= end {
+            PADDING
+        } else if layout.intact.as_ref().is_some_and(|intact| !intact[block]) {
+            DAMAGED
+        } else {
+            heat(entropy[block] / 8.0)
+        };
+        let gray = original.get_pixel(x, y)[0];
+        Rgb(color.map(|channel| blend(channel, gray)))
+    });
+    let out = args.output.clone().unwrap_or_else(|| {
+        let stem = image.file_stem().unwrap_or_default().to_string_lossy();
+        image.with_file_name(format!("{stem}.analysis.png"))
+    });
+    map.save(&out)
+        .with_context(|| format!("cannot write {}", out.display()))?;
+    print_summary(image, &bytes, &layout, &entropy, end);
+    eprintln!("wrote {}", out.display());
+    Ok(())
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn print_summary(image: &Path, bytes: &[u8], layout: &Layout, entropy: &[f64], end: usize) {
+    let share = |len: usize| 100.0 * len as f64 / bytes.len().max(1) as f64;
+    println!("{}", image.display());
+    println!(
+        "  header:  {} bytes ({:.1}%)",
+        layout.header,
+        share(layout.header)
+    );
+    let stored = end.min(bytes.len()).saturating_sub(layout.header);
+    print!(
+        "  blocks:  {}, {stored} bytes ({:.1}%)",
+        layout.blocks.len(),
+        share(stored)
+    );
+    if let Some((lowest, bits)) = entropy
+        .iter()
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.total_cmp(b))
+    {
+        let mean = entropy.iter().sum::<f64>() / entropy.len() as f64;
+        print!(", {mean:.2} bits per byte on average, {bits:.2} at least, in block {lowest}");
+    }
+    println!();
+    let padding = bytes.len().saturating_sub(end);
+    println!("  padding: {padding} bytes ({:.1}%)", share(padding));
+    if end > bytes.len() {
+        println!(
+            "  missing: {} bytes the blocks take past the end of the image",
+            end - bytes.len()
+        );
+    }
+    match &layout.intact {
+        None => println!("  damaged: not checked, as the blocks need their dictionary"),
+        Some(intact) if intact.iter().all(|&intact| intact) => println!("  damaged: none"),
+        Some(intact) => {
+            let damaged: Vec<String> = intact
+                .iter()
+                .enumerate()
+                .filter(|(_, &intact)| !intact)
+                .map(|(i, _)| i.to_string())
+                .collect();
+            println!("  damaged: blocks {}", damaged.join(", "));
+        }
+    }
+}
+
+/// Shannon entropy of `bytes`, in bits per byte.
+#[allow(clippy::cast_precision_loss)]
+fn entropy(bytes: &[u8]) -> f64 {
+    let mut tally = [0usize; 256];
+    for &byte in bytes {
+        tally[usize::from(byte)] += 1;
+    }
+    let len = bytes.len() as f64;
+    tally
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            p * (1.0 / p).log2()
+        })
+        .sum()
+}
+
+/// The color of [`HEAT`] at `t`, from 0 to 1.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+fn heat(t: f64) -> [u8; 3] {
+    let scaled = t.clamp(0.0, 1.0) * (HEAT.len() - 1) as f64;
+    let low = (scaled as usize).min(HEAT.len() - 2);
+    let frac = scaled - low as f64;
+    let mut color = [0; 3];
+    for (channel, out) in color.iter_mut().enumerate() {
+        let (a, b) = (
+            f64::from(HEAT[low][channel]),
+            f64::from(HEAT[low + 1][channel]),
+        );
+        *out = (a + (b - a) * frac).round() as u8;
+    }
+    color
+}
+
+/// Three parts of the map's color to one of the image's brightness, so the pixels show
+/// through.
+fn blend(color: u8, gray: u8) -> u8 {
+    u8::try_from((u16::from(color) * 3 + u16::from(gray)) / 4).unwrap_or(u8::MAX)
+}
@@ -0,0 +1,52 @@
+//! Animated PNGs and GIFs given to decode, whose frames are read one after another as
+//! the volumes of a set, in the order their headers number them.
+use crate::dict::Dictionary;
+use crate::volume::Frames;
+use image::codecs::gif::GifDecoder;
+use image::codecs::png::PngDecoder;
+use image::{AnimationDecoder, ImageDecoder};
+use std::fs::File;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+const PNG_MAGIC: &[u8; 8] = b"\x89PNG\r\n\x1a\n";
+const GIF_MAGIC: &[u8; 4] = b"GIF8";
+
+/// Whether the file at `path` is a GIF, or a PNG with an animation.
+pub fn is_animated(path: &Path) -> anyhow::Result<bool> {
+    let mut start = [0; 8];
+    let read = File::open(path)?.read(&mut start)?;
+    if start[..read].starts_with(GIF_MAGIC) {
+        return Ok(true);
+    }
+    if start[..read] != *PNG_MAGIC {
+        return Ok(false);
+    }
+    Ok(PngDecoder::new(BufReader::new(File::open(path)?))?.is_apng()?)
+}
+
+/// Decodes every frame of the animation at `path` in order, writing the payload its
+/// volumes hold to `output`.
+pub fn decode(
+    path: &Path,
+    output: &mut impl Write,
+    dict: Option<&Dictionary>,
+) -> anyhow::Result<()> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut frames = Frames::default();
+    let mut each = |frame: image::ImageResult<image::Frame>| -> anyhow::Result<()> {
+        frames.decode(path, frame?.buffer(), output, dict)
+    };
+    let mut start = [0; 4];
+    File::open(path)?.read_exact(&mut start)?;
+    if start == *GIF_MAGIC {
+        let mut decoder = GifDecoder::new(reader)?;
+        decoder.set_limits(picturer::limits())?;
+        decoder.into_frames().try_for_each(&mut each)?;
+    } else {
+        let mut decoder = PngDecoder::new(reader)?;
+        decoder.set_limits(picturer::limits())?;
+        decoder.apng()?.into_frames().try_for_each(&mut each)?;
+    }
+    frames.finish(path)
+}
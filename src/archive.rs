@@ -0,0 +1,1059 @@
+use crate::cli::Tree;
+use crate::format::{self, Codec, Reader};
+use anyhow::{bail, ensure, Context};
+use fastcdc::v2020::FastCDC;
+use flate2::read::{ZlibDecoder, ZlibEncoder};
+use ignore::gitignore::GitignoreBuilder;
+use ignore::WalkBuilder;
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::{HashMap, HashSet};
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Component, Path, PathBuf};
+
+/// Start of a payload holding a directory tree rather than a single file.
+const MAGIC: &[u8; 16] = b"PICTURER-ARCHIVE";
+const VERSION: u8 = 1;
+/// Written instead when a name is not valid Unicode, as each entry then says how its
+/// names are stored.
+const ENCODING_VERSION: u8 = 2;
+/// Written instead when the archive compresses some of its chunks itself: each entry then
+/// says how its names are stored, and each chunk how it is compressed.
+const CODEC_VERSION: u8 = 3;
+
+const MIN_CHUNK: usize = 16 << 10;
+const AVG_CHUNK: usize = 64 << 10;
+const MAX_CHUNK: usize = 256 << 10;
+
+const FILE: u8 = 0;
+const DIR: u8 = 1;
+/// A symbolic link, whose chunks hold its target.
+const LINK: u8 = 2;
+/// A file with holes: its first chunk holds its length and where its data lies, and the
+/// rest hold that data back to back.
+const SPARSE: u8 = 3;
+/// Another name for a file stored earlier, whose path its chunks hold.
+const HARDLINK: u8 = 4;
+
+/// Names readable on any system.
+const UTF8: u8 = 0;
+/// Names as the bytes Unix gave, which are not UTF-8.
+const BYTES: u8 = 1;
+/// Names as the little-endian UTF-16 units Windows gave, which are not valid UTF-16.
+const WIDE: u8 = 2;
+
+/// How a chunk is stored, numbered as the container numbers its codecs.
+const STORED: u8 = 0;
+const ZLIB: u8 = 1;
+const ZSTD: u8 = 2;
+
+/// Extensions of files that are compressed already, whose chunks are stored as they are.
+const COMPRESSED: &[&str] = &[
+    "7z", "apk", "avif", "br", "bz2", "docx", "epub", "flac", "gif", "gz", "heic", "jar", "jpeg",
+    "jpg", "jxl", "lz", "lz4", "lzma", "m4a", "mkv", "mov", "mp3", "mp4", "odp", "ods", "odt",
+    "ogg", "opus", "png", "pptx", "rar", "tgz", "webm", "webp", "whl", "woff", "woff2", "xlsx",
+    "xz", "zip", "zst",
+];
+/// Bytes of a file looked at to tell text, and data too random to compress, apart.
+const SAMPLE: usize = 64 << 10;
+/// Bits of entropy per byte above which a sample is taken not to compress.
+const RANDOM_BITS: f64 = 7.5;
+/// zstd level text is compressed at when the payload's codec is not zstd: tighter than
+/// zlib at its highest, and faster.
+#[cfg(feature = "zstd")]
+const TEXT_LEVEL: i32 = 12;
+
+struct Entry {
+    /// Relative to the archive root, `/`-separated, in `encoding`.
+    path: Vec<u8>,
+    /// How the path, and the target of a link, are stored.
+    encoding: u8,
+    kind: u8,
+    mode: u32,
+    chunks: Vec<u32>,
+}
+
+pub fn is_archive(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Packs the tree under `root` into an archive payload. Files are split into
+/// content-defined chunks and every distinct chunk is stored once, so files that
+/// share most of their bytes cost little more than one of them. Symbolic links are
+/// stored as links, or with `--follow-symlinks` replaced by what they point to.
+/// A `deterministic` archive depends only on the names and contents of the files: modes
+/// keep just whether the owner may execute, and holes are not looked for, as what the
+/// file system reports of either differs between copies of the same tree.
+/// Unless `codec` is raw, the chunks of each file are compressed as suits it: those of
+/// files compressed already, or too random to compress, are stored, those of text are
+/// compressed with zstd, and the rest with `codec`. The payload's own codec then finds
+/// them incompressible and leaves them be.
+pub fn pack(
+    root: &Path,
+    tree: &Tree,
+    deterministic: bool,
+    codec: Codec,
+) -> anyhow::Result<Vec<u8>> {
+    let mut archive = Archive {
+        deterministic,
+        codec,
+        ..Archive::default()
+    };
+    archive.walk(root, tree)?;
+    let chunks = archive.compress()?;
+    let stored: usize = chunks.iter().map(|(_, _, chunk)| chunk.len()).sum();
+    let compressed = chunks.iter().filter(|(how, ..)| *how != STORED).count();
+    eprintln!(
+        "archived {} entries, {} bytes in {} unique chunks ({stored} bytes, {compressed} \
+         compressed)",
+        archive.entries.len(),
+        archive.total,
+        archive.chunks.len(),
+    );
+    archive.serialize(&chunks)
+}
+
+struct Archive {
+    entries: Vec<Entry>,
+    chunks: Vec<Vec<u8>>,
+    /// How each chunk is to be compressed, as the file first holding it suits.
+    codecs: Vec<Codec>,
+    ids: HashMap<[u8; 32], u32>,
+    total: u64,
+    /// Path each file with more than one name was first stored under, by its device and
+    /// inode.
+    names: HashMap<(u64, u64), PathBuf>,
+    deterministic: bool,
+    codec: Codec,
+}
+
+impl Default for Archive {
+    fn default() -> Self {
+        Archive {
+            entries: Vec::new(),
+            chunks: Vec::new(),
+            codecs: Vec::new(),
+            ids: HashMap::new(),
+            total: 0,
+            names: HashMap::new(),
+            deterministic: false,
+            codec: Codec::Raw,
+        }
+    }
+}
+
+impl Archive {
+    /// Adds everything under `root` in name order, each directory before what it holds.
+    fn walk(&mut self, root: &Path, tree: &Tree) -> anyhow::Result<()> {
+        let mut exclude = GitignoreBuilder::new(root);
+        for pattern in &tree.exclude {
+            exclude
+                .add_line(None, pattern)
+                .with_context(|| format!("invalid --exclude pattern {pattern}"))?;
+        }
+        let exclude = exclude.build()?;
+        let gitignore = tree.gitignore;
+        let mut walker = WalkBuilder::new(root);
+        walker
+            .standard_filters(false)
+            .follow_links(tree.follow_symlinks)
+            .sort_by_file_name(Ord::cmp)
+            .filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|kind| kind.is_dir());
+                !(gitignore && is_dir && entry.file_name() == ".git"
+                    || exclude.matched(entry.path(), is_dir).is_ignore())
+            });
+        if gitignore {
+            walker
+                .git_ignore(true)
+                .git_exclude(true)
+                .git_global(true)
+                .ignore(true)
+                .parents(true)
+                .require_git(false);
+        }
+        for child in walker.build() {
+            let child = child?;
+            if child.depth() == 0 {
+                continue;
+            }
+            let relative = child.path().strip_prefix(root)?;
+            let Some(kind) = child.file_type() else {
+                continue;
+            };
+            // The target of a link, or the first name of a file stored before.
+            let (kind, mode, chunks, other) = if kind.is_symlink() {
+                (LINK, 0, Vec::new(), Some(fs::read_link(child.path())?))
+            } else if kind.is_dir() {
+                (DIR, mode(&child.metadata()?), Vec::new(), None)
+            } else if kind.is_file() {
+                let meta = child.metadata()?;
+                let id = file_id(&meta);
+                let first = id.and_then(|id| self.names.get(&id)).cloned();
+                if let Some(id) = id {
+                    self.names
+                        .entry(id)
+                        .or_insert_with(|| relative.to_path_buf());
+                }
+                if let Some(first) = first {
+                    (HARDLINK, mode(&meta), Vec::new(), Some(first))
+                } else {
+                    let (kind, chunks) = self.read(child.path(), &meta)?;
+                    (kind, mode(&meta), chunks, None)
+                }
+            } else {
+                eprintln!(
+                    "skipping {}: not a file, directory or link",
+                    child.path().display()
+                );
+                continue;
+            };
+            let names = [
+                Some(relative.as_os_str()),
+                other.as_deref().map(Path::as_os_str),
+            ];
+            let encoding = encoding(names.into_iter().flatten());
+            let mode = if self.deterministic {
+                normal_mode(mode)
+            } else {
+                mode
+            };
+            let chunks = match other {
+                Some(other) => {
+                    let name = encode(other.as_os_str(), encoding);
+                    vec![self.intern(&name, Codec::Raw)?]
+                }
+                None => chunks,
+            };
+            self.entries.push(Entry {
+                path: encode(relative.as_os_str(), encoding),
+                encoding,
+                kind,
+                mode,
+                chunks,
+            });
+        }
+        Ok(())
+    }
+
+    /// Chunks the file at `path`, reading only the data of one with holes, and tells
+    /// which kind of entry it is stored as.
+    fn read(&mut self, path: &Path, meta: &fs::Metadata) -> anyhow::Result<(u8, Vec<u32>)> {
+        let mut file = File::open(path)?;
+        let mut data = Vec::new();
+        let mut chunks = Vec::new();
+        let extents = if self.deterministic {
+            None
+        } else {
+            data_extents(&file, meta)?
+        };
+        let kind = if let Some(extents) = extents {
+            let mut map = Vec::new();
+            map.extend(meta.len().to_le_bytes());
+            map.extend(u32::try_from(extents.len())?.to_le_bytes());
+            for (offset, len) in extents {
+                map.extend(offset.to_le_bytes());
+                map.extend(len.to_le_bytes());
+                file.seek(SeekFrom::Start(offset))?;
+                (&mut file).take(len).read_to_end(&mut data)?;
+            }
+            chunks.push(self.intern(&map, Codec::Raw)?);
+            SPARSE
+        } else {
+            file.read_to_end(&mut data)?;
+            FILE
+        };
+        self.total += meta.len();
+        let codec = self.codec_for(path, &data);
+        for chunk in FastCDC::new(&data, MIN_CHUNK, AVG_CHUNK, MAX_CHUNK) {
+            let chunk = &data[chunk.offset..chunk.offset + chunk.length];
+            chunks.push(self.intern(chunk, codec)?);
+        }
+        Ok((kind, chunks))
+    }
+
+    /// How the chunks of the file at `path`, holding `data`, are compressed.
+    fn codec_for(&self, path: &Path, data: &[u8]) -> Codec {
+        let extension = path.extension().and_then(OsStr::to_str);
+        let compressed = extension
+            .is_some_and(|extension| COMPRESSED.contains(&extension.to_ascii_lowercase().as_str()));
+        let sample = &data[..data.len().min(SAMPLE)];
+        if self.codec == Codec::Raw || compressed || entropy(sample) > RANDOM_BITS {
+            return Codec::Raw;
+        }
+        #[cfg(feature = "zstd")]
+        if is_text(sample) {
+            return match self.codec {
+                Codec::Zstd(_) => self.codec,
+                _ => Codec::Zstd(TEXT_LEVEL),
+            };
+        }
+        self.codec
+    }
+
+    /// Every chunk as it is stored: how it is compressed, its length before that, and its
+    /// bytes. A chunk compression does not shrink is stored as it is.
+    fn compress(&self) -> anyhow::Result<Vec<(u8, usize, Vec<u8>)>> {
+        self.chunks
+            .par_iter()
+            .zip(&self.codecs)
+            .map(|(chunk, &codec)| {
+                let compressed = match codec {
+                    Codec::Raw => None,
+                    Codec::Zlib(level) => {
+                        let mut out = Vec::new();
+                        let level = flate2::Compression::new(level);
+                        ZlibEncoder::new(&chunk[..], level).read_to_end(&mut out)?;
+                        Some((ZLIB, out))
+                    }
+                    Codec::Zstd(level) => Some((ZSTD, zstd_compress(chunk, level)?)),
+                };
+                Ok(match compressed {
+                    Some((how, out)) if out.len() < chunk.len() => (how, chunk.len(), out),
+                    _ => (STORED, chunk.len(), chunk.clone()),
+                })
+            })
+            .collect()
+    }
+
+    fn intern(&mut self, chunk: &[u8], codec: Codec) -> anyhow::Result<u32> {
+        let hash: [u8; 32] = Sha256::digest(chunk).into();
+        if let Some(&id) = self.ids.get(&hash) {
+            return Ok(id);
+        }
+        let id = u32::try_from(self.chunks.len())?;
+        self.chunks.push(chunk.to_vec());
+        self.codecs.push(codec);
+        self.ids.insert(hash, id);
+        Ok(id)
+    }
+
+    /// The archive, with its chunks stored as [`Archive::compress`] made them. One
+    /// compressing none is written as before chunks could be compressed.
+    fn serialize(&self, chunks: &[(u8, usize, Vec<u8>)]) -> anyhow::Result<Vec<u8>> {
+        let unicode = self.entries.iter().all(|entry| entry.encoding == UTF8);
+        let codecs = chunks.iter().any(|(how, ..)| *how != STORED);
+        let mut buf = Vec::new();
+        buf.extend(MAGIC);
+        buf.push(if codecs {
+            CODEC_VERSION
+        } else if unicode {
+            VERSION
+        } else {
+            ENCODING_VERSION
+        });
+        buf.extend(u32::try_from(self.entries.len())?.to_le_bytes());
+        for entry in &self.entries {
+            buf.extend(u32::try_from(entry.path.len())?.to_le_bytes());
+            buf.extend(&entry.path);
+            if codecs || !unicode {
+                buf.push(entry.encoding);
+            }
+            buf.push(entry.kind);
+            buf.extend(entry.mode.to_le_bytes());
+            buf.extend(u32::try_from(entry.chunks.len())?.to_le_bytes());
+            for id in &entry.chunks {
+                buf.extend(id.to_le_bytes());
+            }
+        }
+        buf.extend(u32::try_from(chunks.len())?.to_le_bytes());
+        for (how, len, chunk) in chunks {
+            if codecs {
+                buf.push(*how);
+                if *how != STORED {
+                    buf.extend(u32::try_from(*len)?.to_le_bytes());
+                }
+            }
+            buf.extend(u32::try_from(chunk.len())?.to_le_bytes());
+            buf.extend(chunk);
+        }
+        Ok(buf)
+    }
+}
+
+/// Recreates the tree packed by [`pack`] under `out`. Symbolic links are recreated only
+/// with `allow_links`, as one could point anywhere; otherwise they are skipped. The files
+/// may hold no more than `--max-output-size` between them.
+pub fn extract(payload: &[u8], out: &Path, allow_links: bool) -> anyhow::Result<()> {
+    let (entries, chunks) = parse(payload)?;
+    // Every path is checked before anything is written, so a hostile archive leaves
+    // nothing behind.
+    let paths = entries
+        .iter()
+        .map(relative)
+        .collect::<anyhow::Result<Vec<PathBuf>>>()?;
+    fs::create_dir_all(out)?;
+    let mut dirs = Vec::new();
+    let mut skipped = 0;
+    let mut files = HashSet::new();
+    // Bytes of every file written so far, holes and all.
+    let mut extracted = 0u64;
+    for (entry, relative) in entries.into_iter().zip(paths) {
+        let path = target(out, &relative)?;
+        let name = relative.display();
+        if entry.kind != DIR {
+            if let Some(parent) = path.parent() {
+                fs::create_dir_all(parent)?;
+            }
+        }
+        let chunk = |id: u32| {
+            let chunk = chunks
+                .get(usize::try_from(id)?)
+                .with_context(|| format!("{name} references a missing chunk"))?;
+            decompress(chunk)
+        };
+        // Chunks may be listed any number of times, so what they add up to is checked
+        // against the limit one at a time rather than once they are all in memory.
+        let join = |ids: &[u32], extracted: u64| {
+            let mut data = Vec::new();
+            for &id in ids {
+                let chunk = chunk(id)?;
+                format::check_output(extracted.saturating_add((data.len() + chunk.len()) as u64))?;
+                data.extend_from_slice(&chunk);
+            }
+            anyhow::Ok(data)
+        };
+        match entry.kind {
+            DIR => {
+                fs::create_dir_all(&path)?;
+                // Applied last, so read-only directories can still be filled.
+                dirs.push((path, entry.mode));
+                continue;
+            }
+            FILE => {
+                let mut file = File::create(&path)?;
+                for &id in &entry.chunks {
+                    let chunk = chunk(id)?;
+                    extracted = extracted.saturating_add(chunk.len() as u64);
+                    format::check_output(extracted)?;
+                    file.write_all(&chunk)?;
+                }
+            }
+            SPARSE => {
+                let Some((map, data)) = entry.chunks.split_first() else {
+                    bail!("{name} has no map of its data")
+                };
+                let map = join(&[*map], extracted)?;
+                write_sparse(&path, &map, &join(data, extracted)?, &mut extracted)
+                    .with_context(|| format!("cannot extract {name}"))?;
+            }
+            HARDLINK => {
+                let first =
+                    PathBuf::from(decode(&join(&entry.chunks, extracted)?, entry.encoding)?);
+                // Only a file this archive wrote may be linked to, never one outside it.
+                ensure!(
+                    files.contains(&first),
+                    "refusing to extract {name}: it names {}, which is not a file extracted before it",
+                    first.display()
+                );
+                if path.is_file() {
+                    fs::remove_file(&path)?;
+                }
+                fs::hard_link(out.join(&first), &path)?;
+                continue;
+            }
+            LINK if allow_links => {
+                let target = decode(&join(&entry.chunks, extracted)?, entry.encoding)?;
+                symlink(&target, &path)?;
+                continue;
+            }
+            LINK => {
+                skipped += 1;
+                continue;
+            }
+            kind => bail!("{name} has unknown entry kind {kind}"),
+        }
+        set_mode(&path, entry.mode)?;
+        files.insert(relative);
+    }
+    for (path, mode) in dirs.into_iter().rev() {
+        set_mode(&path, mode)?;
+    }
+    if skipped > 0 {
+        eprintln!("skipped {skipped} symbolic links; --allow-symlinks recreates them");
+    }
+    Ok(())
+}
+
+/// Reads the entries of an archive and the chunks they are made of.
+fn parse(payload: &[u8]) -> anyhow::Result<(Vec<Entry>, Vec<Chunk<'_>>)> {
+    let codecs = payload.get(MAGIC.len()) == Some(&CODEC_VERSION);
+    let (mut reader, entries) = parse_entries(payload)?;
+    let chunks = (0..reader.u32()?)
+        .map(|_| {
+            let how = if codecs { reader.u8()? } else { STORED };
+            let raw_len = if how == STORED {
+                None
+            } else {
+                Some(usize::try_from(reader.u32()?)?)
+            };
+            let len = usize::try_from(reader.u32()?)?;
+            let bytes = reader.take(len)?;
+            Ok(Chunk {
+                how,
+                raw_len: raw_len.unwrap_or(len),
+                bytes,
+            })
+        })
+        .collect::<anyhow::Result<Vec<Chunk>>>()?;
+    Ok((entries, chunks))
+}
+
+/// A chunk as an archive stores it.
+struct Chunk<'a> {
+    how: u8,
+    /// Its length once decompressed.
+    raw_len: usize,
+    bytes: &'a [u8],
+}
+
+/// The bytes of `chunk`, decompressed if it is, and no more than it says it holds.
+fn decompress<'a>(chunk: &Chunk<'a>) -> anyhow::Result<Cow<'a, [u8]>> {
+    let out = match chunk.how {
+        STORED => return Ok(Cow::Borrowed(chunk.bytes)),
+        ZLIB => {
+            let mut out = Vec::new();
+            ZlibDecoder::new(chunk.bytes)
+                .take(chunk.raw_len as u64)
+                .read_to_end(&mut out)?;
+            out
+        }
+        ZSTD => zstd_decompress(chunk.bytes, chunk.raw_len)?,
+        how => bail!("unknown chunk codec {how}"),
+    };
+    ensure!(out.len() == chunk.raw_len, "a chunk is cut short");
+    Ok(Cow::Owned(out))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_compress(chunk: &[u8], level: i32) -> anyhow::Result<Vec<u8>> {
+    Ok(zstd::bulk::compress(chunk, level)?)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_compress(_chunk: &[u8], _level: i32) -> anyhow::Result<Vec<u8>> {
+    bail!("zstd support is not built in")
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decompress(chunk: &[u8], len: usize) -> anyhow::Result<Vec<u8>> {
+    Ok(zstd::bulk::decompress(chunk, len)?)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decompress(_chunk: &[u8], _len: usize) -> anyhow::Result<Vec<u8>> {
+    bail!("the archive has zstd chunks, and zstd support is not built in")
+}
+
+/// Bits of information per byte of `sample`, from how often each value occurs.
+#[allow(clippy::cast_precision_loss)]
+fn entropy(sample: &[u8]) -> f64 {
+    let mut counts = [0usize; 256];
+    for &byte in sample {
+        counts[usize::from(byte)] += 1;
+    }
+    let len = sample.len() as f64;
+    counts
+        .iter()
+        .filter(|&&count| count > 0)
+        .map(|&count| {
+            let p = count as f64 / len;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Whether `sample` reads as text: UTF-8, but for a character the sample may cut in
+/// two, with no NUL.
+#[cfg(feature = "zstd")]
+fn is_text(sample: &[u8]) -> bool {
+    let utf8 = match std::str::from_utf8(sample) {
+        Ok(_) => true,
+        Err(error) => error.error_len().is_none(),
+    };
+    utf8 && !sample.contains(&0)
+}
+
+/// Paths of the entries of an archive, from a prefix of its payload that holds at least
+/// the table of them, with `/` after directories and `@` after symbolic links.
+pub fn list(prefix: &[u8]) -> anyhow::Result<Vec<String>> {
+    let (_, entries) = parse_entries(prefix)?;
+    entries
+        .iter()
+        .map(|entry| {
+            let path = decode(&entry.path, entry.encoding)?;
+            let mark = match entry.kind {
+                DIR => "/",
+                LINK => "@",
+                _ => "",
+            };
+            Ok(format!("{}{mark}", path.to_string_lossy()))
+        })
+        .collect()
+}
+
+/// The table of entries at the start of an archive, and a reader of what follows it.
+fn parse_entries(payload: &[u8]) -> anyhow::Result<(Reader<'_>, Vec<Entry>)> {
+    let Some(rest) = payload.strip_prefix(MAGIC) else {
+        bail!("payload is not an archive")
+    };
+    let mut reader = Reader(rest);
+    let version = reader.u8()?;
+    ensure!(
+        matches!(version, VERSION | ENCODING_VERSION | CODEC_VERSION),
+        "unsupported archive version {version}"
+    );
+    let entries = (0..reader.u32()?)
+        .map(|_| {
+            let len = usize::try_from(reader.u32()?)?;
+            let path = reader.take(len)?.to_vec();
+            let encoding = if version == VERSION {
+                UTF8
+            } else {
+                reader.u8()?
+            };
+            let kind = reader.u8()?;
+            let mode = reader.u32()?;
+            let chunks = (0..reader.u32()?)
+                .map(|_| reader.u32())
+                .collect::<anyhow::Result<Vec<u32>>>()?;
+            Ok(Entry {
+                path,
+                encoding,
+                kind,
+                mode,
+                chunks,
+            })
+        })
+        .collect::<anyhow::Result<Vec<Entry>>>()?;
+    Ok((reader, entries))
+}
+
+/// Writes a file with holes from its `map` and `data`, as [`Archive::read`] stores them.
+/// Its length counts towards the `extracted` bytes, which may be no more than
+/// `--max-output-size`, as the map is not to be trusted to say a sensible one.
+fn write_sparse(path: &Path, map: &[u8], data: &[u8], extracted: &mut u64) -> anyhow::Result<()> {
+    let mut map = Reader(map);
+    let total = map.u64()?;
+    *extracted = extracted.saturating_add(total);
+    format::check_output(*extracted)?;
+    let file = File::create(path)?;
+    // Setting the length first leaves every range not written a hole.
+    file.set_len(total)?;
+    let mut data = Reader(data);
+    for _ in 0..map.u32()? {
+        let offset = map.u64()?;
+        let len = usize::try_from(map.u64()?)?;
+        ensure!(
+            offset
+                .checked_add(len as u64)
+                .is_some_and(|end| end <= total),
+            "data at {offset} runs past the end of the file"
+        );
+        write_at(&file, data.take(len)?, offset)?;
+    }
+    Ok(())
+}
+
+/// Checks that an archive path stays inside the extraction directory.
+fn relative(entry: &Entry) -> anyhow::Result<PathBuf> {
+    let relative = PathBuf::from(decode(&entry.path, entry.encoding)?);
+    ensure!(
+        !entry.path.is_empty()
+            && relative
+                .components()
+                .all(|component| matches!(component, Component::Normal(_))),
+        "refusing to extract {:?}: not a plain relative path",
+        relative.display()
+    );
+    Ok(relative)
+}
+
+/// How the `names` of an entry are stored: in UTF-8 if they are all valid Unicode, so
+/// that any system can read them, or else as this system spells them.
+fn encoding<'a>(mut names: impl Iterator<Item = &'a OsStr>) -> u8 {
+    if names.all(|name| name.to_str().is_some()) {
+        UTF8
+    } else if cfg!(windows) {
+        WIDE
+    } else {
+        BYTES
+    }
+}
+
+/// `name` in `encoding`, with `/` between its components whatever the system uses.
+fn encode(name: &OsStr, encoding: u8) -> Vec<u8> {
+    match name.to_str() {
+        Some(name) if encoding == UTF8 => name.replace(std::path::MAIN_SEPARATOR, "/").into(),
+        _ => system_bytes(name),
+    }
+}
+
+/// A name stored by [`encode`], as this system spells it.
+fn decode(bytes: &[u8], encoding: u8) -> anyhow::Result<OsString> {
+    match encoding {
+        UTF8 => Ok(std::str::from_utf8(bytes)?.into()),
+        BYTES => Ok(from_bytes(bytes)),
+        WIDE => {
+            ensure!(bytes.len().is_multiple_of(2), "a name is cut short");
+            let units: Vec<u16> = bytes
+                .chunks_exact(2)
+                .map(|pair| u16::from_le_bytes([pair[0], pair[1]]))
+                .collect();
+            Ok(from_wide(&units))
+        }
+        encoding => bail!("unknown name encoding {encoding}"),
+    }
+}
+
+#[cfg(unix)]
+fn system_bytes(name: &OsStr) -> Vec<u8> {
+    use std::os::unix::ffi::OsStrExt;
+    name.as_bytes().to_vec()
+}
+
+#[cfg(windows)]
+fn system_bytes(name: &OsStr) -> Vec<u8> {
+    use std::os::windows::ffi::OsStrExt;
+    name.encode_wide()
+        .map(|unit| {
+            if unit == u16::from(b'\\') {
+                u16::from(b'/')
+            } else {
+                unit
+            }
+        })
+        .flat_map(u16::to_le_bytes)
+        .collect()
+}
+
+#[cfg(not(any(unix, windows)))]
+fn system_bytes(name: &OsStr) -> Vec<u8> {
+    name.to_string_lossy().into_owned().into()
+}
+
+#[cfg(unix)]
+fn from_bytes(bytes: &[u8]) -> OsString {
+    use std::os::unix::ffi::OsStringExt;
+    OsString::from_vec(bytes.to_vec())
+}
+
+#[cfg(not(unix))]
+fn from_bytes(bytes: &[u8]) -> OsString {
+    let name = String::from_utf8_lossy(bytes).into_owned();
+    eprintln!("{name}: the name is in bytes only Unix can spell, so some were replaced");
+    name.into()
+}
+
+#[cfg(windows)]
+fn from_wide(units: &[u16]) -> OsString {
+    use std::os::windows::ffi::OsStringExt;
+    OsString::from_wide(units)
+}
+
+#[cfg(not(windows))]
+fn from_wide(units: &[u16]) -> OsString {
+    let name = String::from_utf16_lossy(units);
+    eprintln!("{name}: the name is in UTF-16 only Windows can spell, so some was replaced");
+    name.into()
+}
+
+/// Joins `relative` onto `out`, refusing if any part of it already exists under `out`
+/// as a symbolic link, which would carry the write outside `out`.
+pub fn target(out: &Path, relative: &Path) -> anyhow::Result<PathBuf> {
+    let mut path = out.to_path_buf();
+    for component in relative.components() {
+        path.push(component);
+        let is_link = fs::symlink_metadata(&path).is_ok_and(|meta| meta.file_type().is_symlink());
+        ensure!(
+            !is_link,
+            "refusing to extract {}: {} is a symbolic link",
+            relative.display(),
+            path.display()
+        );
+    }
+    Ok(path)
+}
+
+/// Offsets and lengths of the data in `file`, or `None` if it has no holes worth
+/// skipping or the system cannot tell where they are.
+#[cfg(any(target_os = "linux", target_os = "android", target_os = "freebsd"))]
+fn data_extents(file: &File, meta: &fs::Metadata) -> anyhow::Result<Option<Vec<(u64, u64)>>> {
+    use std::os::fd::AsRawFd;
+    use std::os::unix::fs::MetadataExt;
+    let len = meta.len();
+    // A file taking as many blocks as its length has no holes, and no lseek is spent on it.
+    if meta.blocks().saturating_mul(512) >= len {
+        return Ok(None);
+    }
+    let seek = |offset: u64, whence| {
+        // SAFETY: lseek only moves the offset of a descriptor `file` keeps open.
+        let at = unsafe { libc::lseek(file.as_raw_fd(), i64::try_from(offset)?, whence) };
+        match u64::try_from(at) {
+            Ok(at) => anyhow::Ok(Some(at)),
+            Err(_) if std::io::Error::last_os_error().raw_os_error() == Some(libc::ENXIO) => {
+                Ok(None)
+            }
+            Err(_) => Err(std::io::Error::last_os_error().into()),
+        }
+    };
+    let mut extents = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        // ENXIO past the last data: the rest of the file is a hole.
+        let Some(start) = seek(offset, libc::SEEK_DATA)? else {
+            break;
+        };
+        let end = seek(start, libc::SEEK_HOLE)?.unwrap_or(len).min(len);
+        extents.push((start, end - start));
+        offset = end;
+    }
+    Ok((extents != [(0, len)]).then_some(extents))
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "android", target_os = "freebsd")))]
+fn data_extents(_file: &File, _meta: &fs::Metadata) -> anyhow::Result<Option<Vec<(u64, u64)>>> {
+    Ok(None)
+}
+
+#[cfg(unix)]
+fn write_at(file: &File, data: &[u8], offset: u64) -> anyhow::Result<()> {
+    use std::os::unix::fs::FileExt;
+    Ok(file.write_all_at(data, offset)?)
+}
+
+#[cfg(not(unix))]
+fn write_at(mut file: &File, data: &[u8], offset: u64) -> anyhow::Result<()> {
+    use std::io::Write;
+    file.seek(SeekFrom::Start(offset))?;
+    Ok(file.write_all(data)?)
+}
+
+/// The device and inode of a file with more than one name, which tell its names apart
+/// from copies.
+#[cfg(unix)]
+fn file_id(meta: &fs::Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    (meta.nlink() > 1).then(|| (meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn file_id(_meta: &fs::Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+#[cfg(unix)]
+pub fn symlink(target: &OsStr, path: &Path) -> anyhow::Result<()> {
+    std::os::unix::fs::symlink(target, path)
+        .with_context(|| format!("cannot create link {}", path.display()))
+}
+
+#[cfg(not(unix))]
+pub fn symlink(_target: &OsStr, path: &Path) -> anyhow::Result<()> {
+    bail!(
+        "cannot create link {}: symbolic links are only recreated on Unix",
+        path.display()
+    )
+}
+
+/// `mode` with its permissions set as git would check the file out: 755 if the owner may
+/// execute it, or else 644.
+fn normal_mode(mode: u32) -> u32 {
+    let permissions = if mode & 0o100 == 0 { 0o644 } else { 0o755 };
+    mode & !0o7777 | permissions
+}
+
+#[cfg(unix)]
+fn mode(meta: &fs::Metadata) -> u32 {
+    use std::os::unix::fs::PermissionsExt;
+    meta.permissions().mode()
+}
+
+#[cfg(not(unix))]
+fn mode(_meta: &fs::Metadata) -> u32 {
+    0
+}
+
+#[cfg(unix)]
+pub fn set_mode(path: &Path, mode: u32) -> anyhow::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    Ok(fs::set_permissions(
+        path,
+        fs::Permissions::from_mode(mode & 0o7777),
+    )?)
+}
+
+#[cfg(not(unix))]
+pub fn set_mode(_path: &Path, _mode: u32) -> anyhow::Result<()> {
+    Ok(())
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    /// An archive of `entries`, each a path, a kind and the one chunk it holds, written
+    /// as [`pack`] would but with no check on what the paths say.
+    fn archive(entries: &[(&str, u8, &[u8])]) -> Vec<u8> {
+        let mut archive = Archive::default();
+        for (index, (path, kind, _)) in entries.iter().enumerate() {
+            archive.entries.push(Entry {
+                path: path.as_bytes().to_vec(),
+                encoding: UTF8,
+                kind: *kind,
+                mode: if *kind == DIR { 0o755 } else { 0o644 },
+                chunks: if *kind == DIR {
+                    Vec::new()
+                } else {
+                    vec![u32::try_from(index).unwrap()]
+                },
+            });
+        }
+        let chunks: Vec<_> = entries
+            .iter()
+            .map(|(_, _, data)| (STORED, data.len(), data.to_vec()))
+            .collect();
+        archive.serialize(&chunks).unwrap()
+    }
+
+    /// An archive of plain files, as [`archive`] writes it.
+    pub(crate) fn files(files: &[(&str, &[u8])]) -> Vec<u8> {
+        let entries: Vec<_> = files
+            .iter()
+            .map(|&(path, data)| (path, FILE, data))
+            .collect();
+        archive(&entries)
+    }
+
+    #[test]
+    fn extracts_a_tree_as_it_is() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let payload = archive(&[("a", DIR, b""), ("a/b.txt", FILE, b"inside")]);
+        extract(&payload, &out, false).unwrap();
+        assert_eq!(fs::read(out.join("a/b.txt")).unwrap(), b"inside");
+    }
+
+    #[test]
+    fn paths_that_leave_the_directory_write_nothing() {
+        for path in ["../evil", "a/../../evil", "/tmp/evil", "./evil", ""] {
+            let dir = tempfile::tempdir().unwrap();
+            let out = dir.path().join("out");
+            let payload = archive(&[("fine", FILE, b"fine"), (path, FILE, b"evil")]);
+            let error = extract(&payload, &out, false).unwrap_err();
+            assert!(
+                error.to_string().contains("not a plain relative path"),
+                "{path:?}: {error}"
+            );
+            assert!(!out.exists(), "{path:?} left {} behind", out.display());
+            assert!(!dir.path().join("evil").exists());
+        }
+    }
+
+    #[test]
+    fn hard_links_only_reach_files_extracted_before_them() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let outside = dir.path().join("secret");
+        fs::write(&outside, b"secret").unwrap();
+        let payload = archive(&[("link", HARDLINK, b"../secret")]);
+        assert!(extract(&payload, &out, false).is_err());
+        assert!(!out.join("link").exists());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn planted_links_are_not_written_through() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let outside = dir.path().join("outside");
+        fs::create_dir_all(&out).unwrap();
+        fs::create_dir_all(&outside).unwrap();
+        std::os::unix::fs::symlink(&outside, out.join("a")).unwrap();
+        let payload = archive(&[("a/evil", FILE, b"evil")]);
+        let error = extract(&payload, &out, false).unwrap_err();
+        assert!(error.to_string().contains("is a symbolic link"), "{error}");
+        assert_eq!(fs::read_dir(&outside).unwrap().count(), 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn links_are_recreated_only_when_allowed() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = archive(&[("link", LINK, b"/etc/passwd")]);
+        let skipped = dir.path().join("skipped");
+        extract(&payload, &skipped, false).unwrap();
+        assert!(fs::symlink_metadata(skipped.join("link")).is_err());
+        let allowed = dir.path().join("allowed");
+        extract(&payload, &allowed, true).unwrap();
+        assert_eq!(
+            fs::read_link(allowed.join("link")).unwrap(),
+            Path::new("/etc/passwd")
+        );
+    }
+
+    #[test]
+    fn sparse_files_may_not_claim_more_than_the_output_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let mut map = u64::MAX.to_le_bytes().to_vec();
+        map.extend(0u32.to_le_bytes());
+        let payload = archive(&[("holes", SPARSE, &map)]);
+        let error = extract(&payload, &out, false).unwrap_err();
+        assert!(
+            format!("{error:#}").contains("decodes to more than"),
+            "{error:#}"
+        );
+        assert!(!out.join("holes").exists());
+    }
+
+    #[test]
+    fn sparse_data_may_not_run_past_the_end() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let mut map = 16u64.to_le_bytes().to_vec();
+        map.extend(1u32.to_le_bytes());
+        map.extend((1u64 << 40).to_le_bytes());
+        map.extend(0u64.to_le_bytes());
+        let payload = archive(&[("holes", SPARSE, &map)]);
+        let error = extract(&payload, &out, false).unwrap_err();
+        assert!(
+            format!("{error:#}").contains("runs past the end"),
+            "{error:#}"
+        );
+        assert_eq!(fs::metadata(out.join("holes")).unwrap().len(), 16);
+    }
+
+    #[test]
+    fn a_chunk_listed_over_and_over_stops_at_the_output_limit() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out");
+        let archive = Archive {
+            entries: vec![Entry {
+                path: b"bomb".to_vec(),
+                encoding: UTF8,
+                kind: FILE,
+                mode: 0o644,
+                chunks: vec![0; 1 << 20],
+            }],
+            ..Archive::default()
+        };
+        let payload = archive
+            .serialize(&[(STORED, 1 << 20, vec![0; 1 << 20])])
+            .unwrap();
+        assert!(payload.len() < 8 << 20);
+        // Other tests decode far less than this at the same time.
+        format::set_max_output(32 << 20);
+        let result = extract(&payload, &out, false);
+        format::set_max_output(format::DEFAULT_MAX_OUTPUT);
+        let error = result.unwrap_err();
+        assert!(
+            format!("{error:#}").contains("decodes to more than"),
+            "{error:#}"
+        );
+        assert!(fs::metadata(out.join("bomb")).unwrap().len() <= 32 << 20);
+    }
+}
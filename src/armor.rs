@@ -0,0 +1,80 @@
+//! Images written as base64 between header and footer lines with `--armor`, as `gpg
+//! --armor` does, for channels that carry only plain text; decoding takes them as it
+//! takes the image they hold.
+use anyhow::{ensure, Context};
+use base64::Engine;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+
+const BEGIN: &str = "-----BEGIN PICTURER IMAGE-----";
+const END: &str = "-----END PICTURER IMAGE-----";
+
+/// Characters of base64 a line holds, as in PEM.
+const WIDTH: usize = 64;
+
+/// Where the armored form of `image` is written: `out.png` has `out.png.asc`.
+pub fn path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".asc");
+    PathBuf::from(path)
+}
+
+/// Replaces `image` with its armored form, returning where that was written.
+pub fn wrap(image: &Path) -> anyhow::Result<PathBuf> {
+    let data = base64::engine::general_purpose::STANDARD.encode(std::fs::read(image)?);
+    let mut text = String::with_capacity(data.len() + data.len() / WIDTH + 64);
+    text.push_str(BEGIN);
+    text.push('\n');
+    for line in data.as_bytes().chunks(WIDTH) {
+        // Base64 is ASCII, so every chunk of it is too.
+        text.push_str(std::str::from_utf8(line)?);
+        text.push('\n');
+    }
+    text.push_str(END);
+    text.push('\n');
+    let path = path(image);
+    std::fs::write(&path, text).with_context(|| format!("cannot write {}", path.display()))?;
+    std::fs::remove_file(image)?;
+    Ok(path)
+}
+
+/// Whether the header line is among the first lines of the file at `path`, after which
+/// a mail or chat message may have put a greeting.
+pub fn is_armored(path: &Path) -> anyhow::Result<bool> {
+    let mut start = Vec::new();
+    File::open(path)?.take(1024).read_to_end(&mut start)?;
+    let text = String::from_utf8_lossy(&start);
+    Ok(text.lines().any(|line| line.trim() == BEGIN))
+}
+
+/// Writes the image the armored file at `path` holds to `out`. Lines before the header
+/// and after the footer are ignored, and so is whitespace around each line, which text
+/// channels are free to add.
+pub fn unwrap(path: &Path, out: &Path) -> anyhow::Result<()> {
+    let mut data = String::new();
+    let mut inside = false;
+    let mut ended = false;
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        let line = line.trim();
+        if !inside {
+            inside = line == BEGIN;
+        } else if line == END {
+            ended = true;
+            break;
+        } else {
+            data.push_str(line);
+        }
+    }
+    ensure!(
+        ended,
+        "{} is cut short: it has no {END} line",
+        path.display()
+    );
+    let bytes = base64::engine::general_purpose::STANDARD
+        .decode(data)
+        .with_context(|| format!("{} holds something other than base64", path.display()))?;
+    File::create(out)?.write_all(&bytes)?;
+    Ok(())
+}
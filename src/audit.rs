@@ -0,0 +1,227 @@
+//! `picturer audit`: checks every image picturer wrote under a directory, as kept in an
+//! archive, and reports in JSON which are healthy, which a robust image's error
+//! correction is wearing through, and which no longer decode, so that copies can be
+//! renewed before they are lost.
+use crate::cli::AuditArgs;
+use crate::dict::Dictionary;
+use crate::stream::PixelStream;
+use crate::{checksum, member, robust, volume};
+use anyhow::{bail, ensure, Context};
+use ignore::WalkBuilder;
+use picturer::diagnostic::{self, Code};
+use picturer::format::{self, Delta, Source, Volume};
+use picturer::pipeline::Checksum;
+use rayon::prelude::*;
+use serde::Serialize;
+use std::fs::File;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+#[derive(Serialize)]
+struct Report {
+    dir: String,
+    images: usize,
+    healthy: usize,
+    degraded: usize,
+    corrupt: usize,
+    files: Vec<Entry>,
+}
+
+#[derive(Serialize)]
+struct Entry {
+    path: String,
+    status: Status,
+    /// Why the image is degraded or corrupt.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    problem: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    robust: Option<Correction>,
+    /// Blocks stored uncompressed with no checksum over them, in which damage goes
+    /// unnoticed until the payload is used.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    unchecked_blocks: Option<usize>,
+}
+
+#[derive(Serialize, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+enum Status {
+    Healthy,
+    /// Still decodes, but only as error correction repaired it.
+    Degraded,
+    Corrupt,
+}
+
+/// What a robust image needed corrected, as [`robust::Report`] gives it.
+#[derive(Serialize)]
+struct Correction {
+    codewords: usize,
+    damaged: usize,
+    corrected: usize,
+    margin_used: f64,
+}
+
+/// Checks the images under `args.dir` `--jobs` at a time, writes the report to
+/// `--output` or standard output, and fails if any image is corrupt. Files that are not
+/// images picturer wrote, and images in the layout from before the block container,
+/// which cannot be told from other images, are left out.
+pub fn run(args: &AuditArgs) -> anyhow::Result<()> {
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let mut paths = Vec::new();
+    for entry in WalkBuilder::new(&args.dir)
+        .standard_filters(false)
+        .sort_by_file_name(Ord::cmp)
+        .build()
+    {
+        let entry = entry?;
+        if entry.file_type().is_some_and(|kind| kind.is_file()) {
+            paths.push(entry.into_path());
+        }
+    }
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(args.jobs.map_or(0, usize::from))
+        .build()?;
+    let files: Vec<Entry> = pool.install(|| {
+        paths
+            .par_iter()
+            .filter_map(|path| audit(path, dict.as_ref()))
+            .collect()
+    });
+    let count = |status| files.iter().filter(|file| file.status == status).count();
+    let report = Report {
+        dir: args.dir.display().to_string(),
+        images: files.len(),
+        healthy: count(Status::Healthy),
+        degraded: count(Status::Degraded),
+        corrupt: count(Status::Corrupt),
+        files,
+    };
+    let json = serde_json::to_string_pretty(&report)? + "\n";
+    match &args.output {
+        Some(output) => std::fs::write(output, json)
+            .with_context(|| format!("cannot write {}", output.display()))?,
+        None => print!("{json}"),
+    }
+    if report.corrupt > 0 {
+        bail!(
+            "{} of {} images under {} are corrupt",
+            report.corrupt,
+            report.images,
+            args.dir.display()
+        );
+    }
+    eprintln!(
+        "checked {} images under {}: {} healthy, {} degraded",
+        report.images,
+        args.dir.display(),
+        report.healthy,
+        report.degraded
+    );
+    Ok(())
+}
+
+/// The entry for the file at `path`, or `None` if it is not an image picturer wrote.
+fn audit(path: &Path, dict: Option<&Dictionary>) -> Option<Entry> {
+    let mut head = Vec::new();
+    File::open(path).ok()?.take(8).read_to_end(&mut head).ok()?;
+    if !member::IMAGE_MAGIC
+        .iter()
+        .any(|magic| head.starts_with(magic))
+    {
+        return None;
+    }
+    let entry = |status, problem: Option<String>, report: Option<&robust::Report>| Entry {
+        path: path.display().to_string(),
+        status,
+        problem,
+        robust: report.map(|report| Correction {
+            codewords: report.codewords,
+            damaged: report.damaged,
+            corrected: report.corrected,
+            margin_used: report.margin_used(),
+        }),
+        unchecked_blocks: None,
+    };
+    let (bytes, robust) = match crate::read_reporting(path, robust::TOLERANCE, &[]) {
+        Ok(read) => read,
+        Err(error) if !written(path, &error) => return None,
+        Err(error) => return Some(entry(Status::Corrupt, Some(format!("{error:#}")), None)),
+    };
+    if !format::is_container(&bytes) {
+        return None;
+    }
+    let report = robust.map(|(_, report)| report);
+    let unchecked = match check(path, &bytes, dict) {
+        Ok(unchecked) => unchecked,
+        Err(error) => {
+            return Some(entry(
+                Status::Corrupt,
+                Some(format!("{error:#}")),
+                report.as_ref(),
+            ))
+        }
+    };
+    let mut entry = match &report {
+        Some(found) if found.corrected > 0 => {
+            let mut problem = found.to_string();
+            if found.margin_used() >= robust::RENEW_AT {
+                problem += "; it is close to unrecoverable, so encode a fresh copy";
+            }
+            entry(Status::Degraded, Some(problem), Some(found))
+        }
+        _ => entry(Status::Healthy, None, report.as_ref()),
+    };
+    // The error correction of a robust image finds damage wherever it is.
+    if report.is_none() && unchecked > 0 {
+        entry.unchecked_blocks = Some(unchecked);
+    }
+    Some(entry)
+}
+
+/// Whether the image at `path`, which failed to read with `error`, is one picturer wrote.
+/// One with no header is not, and nor is one that only looks resampled, as any photo
+/// does, unless its pixels still start with a header.
+fn written(path: &Path, error: &anyhow::Error) -> bool {
+    match diagnostic::find(error).map(|found| found.code) {
+        Some(Code::NoHeader) => false,
+        Some(Code::Resampled) => PixelStream::open(path)
+            .and_then(|mut pixels| Ok(format::is_container(pixels.prefix(Volume::HEADER_LEN)?)))
+            .unwrap_or(false),
+        _ => true,
+    }
+}
+
+/// Decodes the container `bytes` read from `path` without keeping the payload, and
+/// checks that the other volumes of its set are there and that the image matches its
+/// checksum file, if it has one. The base of a delta is checked as an image of its own.
+/// Returns how many blocks nothing checked, as they are not compressed and neither a
+/// checksum stage nor the checksum file covers them.
+fn check(path: &Path, bytes: &[u8], dict: Option<&Dictionary>) -> anyhow::Result<usize> {
+    let container = match Volume::parse(bytes)? {
+        Some((found, container)) => {
+            let missing: Vec<PathBuf> = volume::siblings(path, found.index, found.count)
+                .into_iter()
+                .filter(|other| !other.is_file())
+                .collect();
+            ensure!(
+                missing.is_empty(),
+                "{} of the other {} volumes of its set are missing, such as {}",
+                missing.len(),
+                found.count - 1,
+                missing[0].display()
+            );
+            container
+        }
+        None => bytes,
+    };
+    let container = Delta::parse(container)?.map_or(container, |(_, container)| container);
+    format::unpack(container, dict)?;
+    if checksum::path(path).is_file() {
+        checksum::matches(path)?;
+        return Ok(0);
+    }
+    let info = format::Info::read(container, dict)?;
+    if info.stages.iter().any(|stage| stage == Checksum::NAME) {
+        return Ok(0);
+    }
+    Ok(info.raw_blocks)
+}
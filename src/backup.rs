@@ -0,0 +1,129 @@
+//! `picturer backup` and `picturer restore`: snapshots of a directory kept as images in
+//! a store. Each chain starts with a full image of the directory's archive, and every
+//! later snapshot is a delta against the image before it, so unchanged files cost
+//! almost nothing. Images are named for the second they were taken, as
+//! `1791993600-full.png` and `1791997200-delta.png`, which sort in the order taken.
+use crate::cli::{BackupArgs, PngLevel};
+use crate::{archive, delta};
+use anyhow::{bail, ensure, Context};
+use picturer::format::{self, Codec, Delta, Packing};
+use sha2::{Digest, Sha256};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// One image in a store.
+struct Snapshot {
+    /// Seconds since the Unix epoch when it was taken.
+    taken: u64,
+    full: bool,
+    path: PathBuf,
+}
+
+/// The snapshots in `store`, oldest first. Files not named as [`backup`] names them
+/// are left alone.
+fn snapshots(store: &Path) -> anyhow::Result<Vec<Snapshot>> {
+    let mut snapshots = Vec::new();
+    for entry in
+        std::fs::read_dir(store).with_context(|| format!("cannot read {}", store.display()))?
+    {
+        let path = entry?.path();
+        let Some(name) = path.file_name().and_then(|name| name.to_str()) else {
+            continue;
+        };
+        let Some((taken, kind)) = name
+            .strip_suffix(".png")
+            .and_then(|stem| stem.split_once('-'))
+        else {
+            continue;
+        };
+        let (Ok(taken), "full" | "delta") = (taken.parse(), kind) else {
+            continue;
+        };
+        snapshots.push(Snapshot {
+            taken,
+            full: kind == "full",
+            path,
+        });
+    }
+    snapshots.sort_by_key(|snapshot| snapshot.taken);
+    Ok(snapshots)
+}
+
+/// Adds a snapshot of `args.dir` to the store, as a delta against the last image
+/// unless none is there or its chain already holds `--full-every` deltas.
+pub fn backup(args: &BackupArgs) -> anyhow::Result<()> {
+    std::fs::create_dir_all(&args.store)?;
+    let snapshots = snapshots(&args.store)?;
+    let taken = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+    if snapshots.last().is_some_and(|last| last.taken >= taken) {
+        bail!("the store holds a snapshot taken this second or later; try again in a second");
+    }
+    let deltas = snapshots
+        .iter()
+        .rev()
+        .take_while(|snapshot| !snapshot.full)
+        .count();
+    let base = snapshots
+        .last()
+        .filter(|_| deltas < args.full_every && snapshots.iter().any(|snapshot| snapshot.full));
+    let codec = args.codec.unwrap_or(Codec::DEFAULT);
+    let payload = archive::pack(&args.dir, &args.tree, false, codec)?;
+    let packing = Packing {
+        created: Some(taken),
+        ..Packing::new(codec)
+    };
+    let kind = if base.is_some() { "delta" } else { "full" };
+    let path = args.store.join(format!("{taken}-{kind}.png"));
+    let container = if let Some(base) = base {
+        let base_payload = decode(&base.path)?;
+        let mut buf = Delta {
+            base: crate::base_reference(&base.path, &path)?,
+            base_hash: Sha256::digest(&base_payload).into(),
+        }
+        .header()?;
+        format::pack_into(&mut buf, &delta::diff(&base_payload, &payload), packing)?;
+        buf
+    } else {
+        format::pack(&payload, packing)?
+    };
+    crate::write_container(&container, &path, false, PngLevel::for_codec(codec))?;
+    eprintln!(
+        "wrote {} ({} bytes of {} payload bytes)",
+        path.display(),
+        container.len(),
+        payload.len()
+    );
+    Ok(())
+}
+
+/// Extracts into `out` the last snapshot in `store` taken at or before `at`, or the last
+/// of all, decoding the chain of deltas it ends.
+pub fn restore(
+    store: &Path,
+    at: Option<SystemTime>,
+    out: &Path,
+    allow_links: bool,
+) -> anyhow::Result<()> {
+    let at = match at {
+        Some(at) => at.duration_since(UNIX_EPOCH)?.as_secs(),
+        None => u64::MAX,
+    };
+    let snapshots = snapshots(store)?;
+    let Some(snapshot) = snapshots.iter().rev().find(|snapshot| snapshot.taken <= at) else {
+        bail!("{} holds no snapshot taken by then", store.display())
+    };
+    let payload = decode(&snapshot.path)?;
+    ensure!(
+        archive::is_archive(&payload),
+        "{} does not hold a directory",
+        snapshot.path.display()
+    );
+    archive::extract(&payload, out, allow_links)?;
+    eprintln!("restored {} to {}", snapshot.path.display(), out.display());
+    Ok(())
+}
+
+/// The payload of the image at `path`, applying it to the images before it if it is a delta.
+fn decode(path: &Path) -> anyhow::Result<Vec<u8>> {
+    crate::decode_image(path, &crate::read_single(path)?, None, None, 0)
+}
@@ -0,0 +1,111 @@
+//! Containers stored as Data Matrix or Aztec symbols with `--format data-matrix` or
+//! `--format aztec`, for small payloads read by scanners that know nothing else, and
+//! read back from images of those symbols. Built with the barcodes feature.
+use crate::cli::Format;
+#[cfg(feature = "barcodes")]
+use anyhow::anyhow;
+#[cfg(not(feature = "barcodes"))]
+use anyhow::bail;
+use image::{DynamicImage, GrayImage};
+
+/// Pixels to a module of a written symbol.
+#[cfg(feature = "barcodes")]
+const MODULE: u32 = 8;
+/// Light modules around a written symbol, the quiet zone scanners need to find it.
+#[cfg(feature = "barcodes")]
+const QUIET: u32 = 4;
+/// Most bytes the largest Data Matrix symbol holds as binary data.
+#[cfg(feature = "barcodes")]
+const DATA_MATRIX_CAPACITY: usize = 1556;
+
+#[cfg(feature = "barcodes")]
+fn symbology(format: Format) -> rxing::BarcodeFormat {
+    if format == Format::Aztec {
+        rxing::BarcodeFormat::AZTEC
+    } else {
+        rxing::BarcodeFormat::DATA_MATRIX
+    }
+}
+
+/// `container` as a symbol of `format`, black on white. Its bytes are given to the
+/// encoder as ISO 8859-1 characters, one to each byte, which both symbologies store as
+/// binary data.
+#[cfg(feature = "barcodes")]
+pub fn encode(container: &[u8], format: Format) -> anyhow::Result<GrayImage> {
+    use rxing::Writer;
+    let contents = container
+        .iter()
+        .map(|&byte| char::from(byte))
+        .collect::<String>();
+    let hints = rxing::EncodeHints {
+        CharacterSet: Some("ISO-8859-1".to_owned()),
+        Margin: Some("0".to_owned()),
+        ..rxing::EncodeHints::default()
+    };
+    let name = if format == Format::Aztec {
+        "an Aztec"
+    } else {
+        "a Data Matrix"
+    };
+    let too_large = || {
+        anyhow!(
+            "a packed payload of {} bytes does not fit {name} symbol",
+            container.len()
+        )
+    };
+    // Checked first, as the Data Matrix encoder panics on more than its largest holds.
+    if format == Format::DataMatrix && container.len() > DATA_MATRIX_CAPACITY {
+        return Err(too_large());
+    }
+    let matrix = rxing::MultiFormatWriter
+        .encode_with_hints(&contents, &symbology(format), 0, 0, &hints)
+        .map_err(|_| too_large())?;
+    let side = |modules: u32| (modules + 2 * QUIET) * MODULE;
+    let (width, height) = (matrix.getWidth(), matrix.getHeight());
+    Ok(GrayImage::from_fn(side(width), side(height), |x, y| {
+        let (x, y) = (
+            (x / MODULE).checked_sub(QUIET),
+            (y / MODULE).checked_sub(QUIET),
+        );
+        let dark = x
+            .zip(y)
+            .is_some_and(|(x, y)| x < width && y < height && matrix.get(x, y));
+        image::Luma([if dark { 0 } else { u8::MAX }])
+    }))
+}
+
+#[cfg(not(feature = "barcodes"))]
+pub fn encode(_container: &[u8], _format: Format) -> anyhow::Result<GrayImage> {
+    bail!("--format data-matrix and aztec need picturer built with the barcodes feature")
+}
+
+/// The container a Data Matrix or Aztec symbol in `image` holds, or `None` if there is
+/// no symbol found.
+#[cfg(feature = "barcodes")]
+pub fn decode(image: &DynamicImage) -> Option<Vec<u8>> {
+    let luma = image.to_luma8();
+    let (width, height) = luma.dimensions();
+    let mut hints = rxing::DecodeHints {
+        PossibleFormats: Some(
+            [Format::DataMatrix, Format::Aztec]
+                .into_iter()
+                .map(symbology)
+                .collect(),
+        ),
+        CharacterSet: Some("ISO-8859-1".to_owned()),
+        ..rxing::DecodeHints::default()
+    };
+    let result =
+        rxing::helpers::detect_in_luma_with_hints(luma.into_raw(), width, height, None, &mut hints)
+            .ok()?;
+    result
+        .getText()
+        .chars()
+        .map(|char| u8::try_from(char).ok())
+        .collect()
+}
+
+#[cfg(not(feature = "barcodes"))]
+pub fn decode(_image: &DynamicImage) -> Option<Vec<u8>> {
+    None
+}
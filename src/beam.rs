@@ -0,0 +1,512 @@
+//! `picturer beam` and `picturer receive`: a file carried from one machine to another
+//! with no network or cable between them, as Aztec symbols shown full screen one after
+//! another and read back by a camera pointed at the screen. The symbols carry a
+//! fountain code of the container: the first show each block once, and every one after
+//! that the XOR of a random few, so any set of symbols a little larger than the file
+//! rebuilds it, whichever the camera missed. Showing needs the gui and barcodes
+//! features; receiving needs barcodes, and the ffmpeg command to read a camera.
+use crate::cli::{BeamArgs, Format, ReceiveArgs};
+use crate::{barcode, interrupt};
+use anyhow::{bail, ensure, Context};
+use image::{DynamicImage, GrayImage, ImageFormat};
+use picturer::format::{self, Codec, Packing, Reader};
+use std::collections::HashSet;
+use std::io::{BufRead, BufReader, IsTerminal};
+use std::process::{Command, Stdio};
+use std::sync::atomic::Ordering;
+
+/// Starts every symbol.
+const MAGIC: &[u8; 2] = b"PB";
+/// Magic, id of the container, its length, and the seed that picks the blocks in it.
+const HEADER_LEN: usize = 2 + 4 + 4 + 4;
+/// Largest container beamed, as a camera reads a few kilobytes a second at best.
+const MAX_LEN: usize = 64 << 20;
+
+/// One symbol: the XOR of the blocks [`sources`] picks for `seed`.
+struct Frame<'a> {
+    id: [u8; 4],
+    len: u32,
+    seed: u32,
+    data: &'a [u8],
+}
+
+impl Frame<'_> {
+    fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN + self.data.len());
+        bytes.extend(MAGIC);
+        bytes.extend(self.id);
+        bytes.extend(self.len.to_le_bytes());
+        bytes.extend(self.seed.to_le_bytes());
+        bytes.extend(self.data);
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> Option<Frame<'_>> {
+        let mut reader = Reader(bytes.strip_prefix(MAGIC)?);
+        let id = reader.take(4).ok()?.try_into().ok()?;
+        let len = reader.u32().ok()?;
+        let seed = reader.u32().ok()?;
+        let data = reader.0;
+        (!data.is_empty()).then_some(Frame {
+            id,
+            len,
+            seed,
+            data,
+        })
+    }
+}
+
+/// The container beamed, cut into blocks of `block` bytes, the last padded with zeros.
+struct Source {
+    id: [u8; 4],
+    len: u32,
+    blocks: Vec<Vec<u8>>,
+}
+
+impl Source {
+    fn new(container: &[u8], block: usize) -> anyhow::Result<Source> {
+        ensure!(
+            container.len() <= MAX_LEN,
+            "a packed payload of {} bytes is too large to beam",
+            container.len()
+        );
+        let blocks = container
+            .chunks(block)
+            .map(|chunk| {
+                let mut chunk = chunk.to_vec();
+                chunk.resize(block, 0);
+                chunk
+            })
+            .collect();
+        Ok(Source {
+            id: id(container),
+            len: u32::try_from(container.len())?,
+            blocks,
+        })
+    }
+
+    /// The symbol for `seed`, as the bytes an Aztec symbol holds.
+    fn frame(&self, seed: u32) -> Vec<u8> {
+        let mut data = vec![0; self.blocks[0].len()];
+        for index in sources(seed, self.blocks.len()) {
+            xor(&mut data, &self.blocks[index]);
+        }
+        Frame {
+            id: self.id,
+            len: self.len,
+            seed,
+            data: &data,
+        }
+        .bytes()
+    }
+}
+
+fn id(container: &[u8]) -> [u8; 4] {
+    let mut id = [0; 4];
+    id.copy_from_slice(&blake3::hash(container).as_bytes()[..4]);
+    id
+}
+
+/// The blocks, of `count`, that the symbol for `seed` XORs: block `seed` alone for the
+/// first `count` seeds, and after those a random set of them, as many as [`degree`]
+/// draws, from a generator every build seeds alike.
+fn sources(seed: u32, count: usize) -> Vec<usize> {
+    let seed = seed as usize;
+    if seed < count {
+        return vec![seed];
+    }
+    let mut state = seed as u64 ^ ((count as u64) << 32);
+    let mut next = move || {
+        // splitmix64
+        state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    };
+    let degree = degree(next(), count);
+    let mut picked = Vec::with_capacity(degree);
+    while picked.len() < degree {
+        let index = usize::try_from(next() % count as u64).unwrap_or(0);
+        if !picked.contains(&index) {
+            picked.push(index);
+        }
+    }
+    picked
+}
+
+/// How many of `count` blocks a symbol XORs, for the random `draw`: the robust soliton
+/// distribution, which peeling needs few symbols more than blocks to finish with. It is
+/// worked out in integers, so that sender and receiver always agree on it.
+fn degree(draw: u64, count: usize) -> usize {
+    const SCALE: u64 = 1 << 32;
+    let k = count as u64;
+    // The spike, at k/R for R = √k, holding ln(R/δ) of the mass for δ = 1/20.
+    let spike = k.isqrt().max(1);
+    let ln = u64::from((20 * (k / spike)).max(1).ilog2() + 1) * 693 / 1000;
+    let weight = |d: u64| {
+        let ideal = if d == 1 {
+            SCALE / k
+        } else {
+            SCALE / (d * (d - 1))
+        };
+        let robust = match d.cmp(&spike) {
+            std::cmp::Ordering::Less => SCALE / (d * spike),
+            std::cmp::Ordering::Equal => SCALE * ln / spike,
+            std::cmp::Ordering::Greater => 0,
+        };
+        ideal + robust
+    };
+    let total: u64 = (1..=k).map(weight).sum();
+    let mut left = draw % total;
+    for d in 1..=k {
+        match left.checked_sub(weight(d)) {
+            Some(rest) => left = rest,
+            None => return usize::try_from(d).unwrap_or(count),
+        }
+    }
+    count
+}
+
+fn xor(into: &mut [u8], from: &[u8]) {
+    for (byte, other) in into.iter_mut().zip(from) {
+        *byte ^= other;
+    }
+}
+
+/// `beam`: packs the file and shows its symbols full screen until Escape is pressed, or
+/// with `--save`, writes them as PNG files instead.
+pub fn send(args: &BeamArgs) -> anyhow::Result<()> {
+    ensure!(
+        cfg!(feature = "barcodes"),
+        "beam needs picturer built with the barcodes feature"
+    );
+    let bytes = std::fs::read(&args.file)?;
+    let container = format::pack(&bytes, Packing::new(Codec::DEFAULT))?;
+    let source = Source::new(&container, usize::from(args.block))?;
+    let count = source.blocks.len();
+    eprintln!(
+        "beaming {} bytes in {count} blocks; a camera reading every symbol needs about {}s",
+        container.len(),
+        count.div_ceil(usize::from(args.fps))
+    );
+    let symbol = move |seed: u32| barcode::encode(&source.frame(seed), Format::Aztec);
+    if let Some(dir) = &args.save {
+        std::fs::create_dir_all(dir)?;
+        let frames = args.count.unwrap_or(u32::try_from(count + count / 2)?);
+        for seed in 0..frames {
+            let path = dir.join(format!("frame-{seed:06}.png"));
+            symbol(seed)?.save_with_format(&path, ImageFormat::Png)?;
+        }
+        eprintln!("wrote {frames} frames to {}", dir.display());
+        return Ok(());
+    }
+    window::show(symbol, args.fps)
+}
+
+/// `receive`: reads symbols from the camera, or from `--from` images, until they
+/// rebuild the container, and writes its payload to the output.
+pub fn receive(args: &ReceiveArgs) -> anyhow::Result<()> {
+    ensure!(
+        cfg!(feature = "barcodes"),
+        "receive needs picturer built with the barcodes feature"
+    );
+    let mut sink = Sink::default();
+    let done = if args.from.is_empty() {
+        from_camera(args.camera.as_deref(), &mut sink)?
+    } else {
+        let mut done = false;
+        for path in &args.from {
+            let image =
+                image::open(path).with_context(|| format!("cannot read {}", path.display()))?;
+            if sink.add(&image) {
+                done = true;
+                break;
+            }
+        }
+        done
+    };
+    if interrupt::PROGRESS_LINE.swap(false, Ordering::Relaxed) {
+        eprintln!();
+    }
+    let Some(peeler) = sink.peeler.take() else {
+        bail!("no symbol of a beam was found")
+    };
+    ensure!(
+        done,
+        "the symbols read rebuild {} of {} blocks; show more of them",
+        peeler.solved,
+        peeler.blocks.len()
+    );
+    let mut container: Vec<u8> = peeler.blocks.into_iter().flatten().flatten().collect();
+    container.truncate(sink.len as usize);
+    ensure!(
+        id(&container) == sink.id,
+        "the rebuilt payload does not match the beam it came from"
+    );
+    let payload = format::unpack(&container, None)?;
+    let _partial = interrupt::Partial::new(&args.out);
+    std::fs::write(&args.out, &payload)?;
+    eprintln!(
+        "received {} bytes from {} symbols into {}",
+        payload.len(),
+        sink.frames,
+        args.out.display()
+    );
+    Ok(())
+}
+
+/// Reads the camera `device`, or the first one, through ffmpeg, a frame at a time as
+/// grey PGM images, until `sink` has all it needs.
+fn from_camera(device: Option<&str>, sink: &mut Sink) -> anyhow::Result<bool> {
+    let (input, default) = if cfg!(target_os = "linux") {
+        ("v4l2", Some("/dev/video0"))
+    } else if cfg!(target_os = "macos") {
+        ("avfoundation", Some("0"))
+    } else {
+        ("dshow", None)
+    };
+    let device = device
+        .or(default)
+        .context("give the camera with --camera, such as \"video=Integrated Camera\"")?;
+    let mut child = Command::new("ffmpeg")
+        .args(["-loglevel", "error", "-f", input, "-i", device])
+        .args(["-f", "image2pipe", "-c:v", "pgm", "-"])
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .context("cannot run ffmpeg, which reads the camera")?;
+    let mut frames = BufReader::new(child.stdout.take().context("ffmpeg has no output")?);
+    let mut done = false;
+    while let Some(frame) = read_pgm(&mut frames)? {
+        if sink.add(&DynamicImage::ImageLuma8(frame)) {
+            done = true;
+            break;
+        }
+    }
+    let _ = child.kill();
+    let status = child.wait()?;
+    ensure!(
+        done || status.success(),
+        "ffmpeg failed reading {device}: {status}"
+    );
+    Ok(done)
+}
+
+/// The next binary PGM image of `reader`, or `None` at its end.
+fn read_pgm(reader: &mut impl BufRead) -> anyhow::Result<Option<GrayImage>> {
+    if reader.fill_buf()?.is_empty() {
+        return Ok(None);
+    }
+    let mut fields = Vec::new();
+    while fields.len() < 4 {
+        let mut field = Vec::new();
+        loop {
+            let mut byte = [0];
+            reader.read_exact(&mut byte)?;
+            if byte[0].is_ascii_whitespace() {
+                break;
+            }
+            field.push(byte[0]);
+        }
+        if !field.is_empty() {
+            fields.push(String::from_utf8(field)?);
+        }
+    }
+    ensure!(
+        fields[0] == "P5",
+        "ffmpeg wrote something other than PGM images"
+    );
+    let width: u32 = fields[1].parse()?;
+    let height: u32 = fields[2].parse()?;
+    ensure!(
+        fields[3] == "255",
+        "ffmpeg wrote PGM images of more than 8 bits"
+    );
+    let mut pixels = vec![0; width as usize * height as usize];
+    reader.read_exact(&mut pixels)?;
+    Ok(GrayImage::from_raw(width, height, pixels))
+}
+
+/// The symbols read so far, of the first beam one was read from.
+#[derive(Default)]
+struct Sink {
+    id: [u8; 4],
+    len: u32,
+    seeds: HashSet<u32>,
+    frames: usize,
+    peeler: Option<Peeler>,
+}
+
+impl Sink {
+    /// Reads the symbol in `image`, if any, and tells whether the container is whole.
+    fn add(&mut self, image: &DynamicImage) -> bool {
+        let Some(bytes) = barcode::decode(image) else {
+            return false;
+        };
+        let Some(frame) = Frame::parse(&bytes) else {
+            return false;
+        };
+        let peeler = self.peeler.get_or_insert_with(|| {
+            self.id = frame.id;
+            self.len = frame.len;
+            Peeler::new((frame.len as usize).div_ceil(frame.data.len()))
+        });
+        if frame.id != self.id || !self.seeds.insert(frame.seed) {
+            return false;
+        }
+        self.frames += 1;
+        peeler.add(
+            sources(frame.seed, peeler.blocks.len()),
+            frame.data.to_vec(),
+        );
+        let done = peeler.solved == peeler.blocks.len();
+        if std::io::stderr().is_terminal() {
+            let (solved, count) = (peeler.solved, peeler.blocks.len());
+            eprint!(
+                "\rrebuilt {solved} of {count} blocks from {} symbols",
+                self.frames
+            );
+            interrupt::PROGRESS_LINE.store(true, Ordering::Relaxed);
+        }
+        done
+    }
+}
+
+/// Solves for the blocks by peeling: a symbol of a single block not known yet gives that
+/// block, which is then taken out of every symbol waiting on it.
+struct Peeler {
+    blocks: Vec<Option<Vec<u8>>>,
+    solved: usize,
+    /// Symbols of more than one unknown block, with those blocks.
+    waiting: Vec<(Vec<usize>, Vec<u8>)>,
+}
+
+impl Peeler {
+    fn new(count: usize) -> Peeler {
+        Peeler {
+            blocks: vec![None; count],
+            solved: 0,
+            waiting: Vec::new(),
+        }
+    }
+
+    fn add(&mut self, sources: Vec<usize>, data: Vec<u8>) {
+        let mut pending = vec![(sources, data)];
+        while let Some((mut sources, mut data)) = pending.pop() {
+            sources.retain(|&index| match &self.blocks[index] {
+                Some(block) => {
+                    xor(&mut data, block);
+                    false
+                }
+                None => true,
+            });
+            match sources[..] {
+                [] => {}
+                [index] => {
+                    self.blocks[index] = Some(data);
+                    self.solved += 1;
+                    // Symbols waiting on the block may now be down to one unknown.
+                    let (ready, waiting): (Vec<_>, Vec<_>) = std::mem::take(&mut self.waiting)
+                        .into_iter()
+                        .partition(|(sources, _)| sources.contains(&index));
+                    self.waiting = waiting;
+                    pending.extend(ready);
+                }
+                _ => self.waiting.push((sources, data)),
+            }
+        }
+    }
+}
+
+#[cfg(all(feature = "gui", feature = "barcodes"))]
+mod window {
+    use eframe::egui;
+    use image::GrayImage;
+    use std::time::{Duration, Instant};
+
+    struct App<F> {
+        symbol: F,
+        interval: Duration,
+        seed: u32,
+        shown: Option<(Instant, egui::TextureHandle)>,
+        error: Option<String>,
+    }
+
+    /// Shows the symbol of each seed in turn, `fps` a second, full screen on white.
+    pub fn show(
+        symbol: impl Fn(u32) -> anyhow::Result<GrayImage> + 'static,
+        fps: u8,
+    ) -> anyhow::Result<()> {
+        let app = App {
+            symbol,
+            interval: Duration::from_secs(1) / u32::from(fps),
+            seed: 0,
+            shown: None,
+            error: None,
+        };
+        let options = eframe::NativeOptions {
+            viewport: egui::ViewportBuilder::default().with_fullscreen(true),
+            ..Default::default()
+        };
+        eframe::run_native("picturer beam", options, Box::new(|_| Ok(Box::new(app))))
+            .map_err(|error| anyhow::anyhow!("cannot open a window: {error}"))
+    }
+
+    impl<F: Fn(u32) -> anyhow::Result<GrayImage>> eframe::App for App<F> {
+        fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+            let ctx = ui.ctx().clone();
+            if ctx.input(|input| input.key_pressed(egui::Key::Escape)) {
+                ctx.send_viewport_cmd(egui::ViewportCommand::Close);
+            }
+            let due = self
+                .shown
+                .as_ref()
+                .is_none_or(|(at, _)| at.elapsed() >= self.interval);
+            if due && self.error.is_none() {
+                match (self.symbol)(self.seed) {
+                    Ok(symbol) => {
+                        let size = [symbol.width() as usize, symbol.height() as usize];
+                        let image = egui::ColorImage::from_gray(size, symbol.as_raw());
+                        let texture =
+                            ctx.load_texture("symbol", image, egui::TextureOptions::NEAREST);
+                        self.shown = Some((Instant::now(), texture));
+                        self.seed = self.seed.wrapping_add(1);
+                    }
+                    Err(error) => self.error = Some(format!("{error:#}")),
+                }
+            }
+            let frame = egui::Frame::NONE.fill(egui::Color32::WHITE);
+            egui::CentralPanel::default().frame(frame).show(ui, |ui| {
+                if let Some(error) = &self.error {
+                    ui.colored_label(egui::Color32::RED, error);
+                    return;
+                }
+                ui.centered_and_justified(|ui| {
+                    if let Some((_, texture)) = &self.shown {
+                        let side = ui.available_size().min_elem();
+                        let image =
+                            egui::Image::new(texture).fit_to_exact_size(egui::vec2(side, side));
+                        ui.add(image);
+                    }
+                });
+            });
+            ctx.request_repaint_after(self.interval);
+        }
+    }
+}
+
+#[cfg(not(all(feature = "gui", feature = "barcodes")))]
+mod window {
+    use image::GrayImage;
+
+    pub fn show(
+        _symbol: impl Fn(u32) -> anyhow::Result<GrayImage>,
+        _fps: u8,
+    ) -> anyhow::Result<()> {
+        anyhow::bail!(
+            "beam shows symbols with picturer built with the gui feature; --save writes \
+             them as files without it"
+        )
+    }
+}
@@ -0,0 +1,107 @@
+use crate::format::Codec;
+use image::ImageFormat;
+use picturer::{Cipher, Decoder, Encoder};
+use std::io::Cursor;
+use std::time::{Duration, Instant};
+
+const CODECS: [Codec; 6] = [
+    Codec::Raw,
+    Codec::Zlib(1),
+    Codec::Zlib(6),
+    Codec::Zlib(9),
+    Codec::Zstd(3),
+    Codec::Zstd(19),
+];
+const SYNTHETIC_LEN: usize = 16 << 20;
+
+/// Runs `data` (or a synthetic mix of text and noise) through every codec,
+/// printing throughput and output size for each.
+pub fn run(data: Option<Vec<u8>>) -> anyhow::Result<()> {
+    let data = data.unwrap_or_else(synthetic);
+    println!("input: {} bytes", data.len());
+    println!(
+        "{:<8} {:>12} {:>7} {:>12} {:>12}",
+        "codec", "png bytes", "ratio", "encode", "decode"
+    );
+    for codec in CODECS {
+        let start = Instant::now();
+        let mut png = Vec::new();
+        crate::encode(&data, codec)?.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        let encode_time = start.elapsed();
+
+        let start = Instant::now();
+        let pixels = image::load_from_memory_with_format(&png, ImageFormat::Png)?
+            .into_rgba8()
+            .into_raw();
+        let decoded = crate::decode(&pixels)?;
+        let decode_time = start.elapsed();
+        anyhow::ensure!(decoded == data, "{codec} did not round-trip");
+
+        #[allow(clippy::cast_precision_loss)]
+        let ratio = png.len() as f64 / data.len().max(1) as f64;
+        println!(
+            "{:<8} {:>12} {:>7.3} {:>12} {:>12}",
+            codec.to_string(),
+            png.len(),
+            ratio,
+            throughput(data.len(), encode_time),
+            throughput(data.len(), decode_time),
+        );
+    }
+    ciphers(&data)
+}
+
+/// Seals and opens `data` with each cipher, uncompressed so that only the cipher is
+/// timed, and tells which one `auto` picks here.
+fn ciphers(data: &[u8]) -> anyhow::Result<()> {
+    println!();
+    println!("{:<18} {:>12} {:>12}", "cipher", "seal", "open");
+    let key = [7; 32];
+    for cipher in [Cipher::AesGcm, Cipher::ChaCha20Poly1305] {
+        let encoder = Encoder::new()
+            .compression(Codec::Raw)
+            .encrypt(key)
+            .cipher(cipher);
+        let start = Instant::now();
+        let container = encoder.pack(data)?;
+        let seal_time = start.elapsed();
+
+        let start = Instant::now();
+        let opened = Decoder::new().key(key).unpack(&container)?;
+        let open_time = start.elapsed();
+        anyhow::ensure!(opened == data, "{cipher} did not round-trip");
+        println!(
+            "{:<18} {:>12} {:>12}",
+            cipher.to_string(),
+            throughput(data.len(), seal_time),
+            throughput(data.len(), open_time),
+        );
+    }
+    println!("auto picks {} here", Cipher::Auto.resolve());
+    Ok(())
+}
+
+fn throughput(len: usize, time: Duration) -> String {
+    #[allow(clippy::cast_precision_loss)]
+    let mib = len as f64 / f64::from(1 << 20);
+    format!("{:.1} MiB/s", mib / time.as_secs_f64())
+}
+
+/// Half repetitive text, half xorshift noise, so both ends of the codec range show up.
+fn synthetic() -> Vec<u8> {
+    let mut data = Vec::with_capacity(SYNTHETIC_LEN);
+    let mut line = 0u64;
+    while data.len() < SYNTHETIC_LEN / 2 {
+        data.extend(format!("{line:08} INFO request handled in {}ms\n", line % 97).bytes());
+        line += 1;
+    }
+    let mut state = 0x2545_f491_4f6c_dd1d_u64;
+    while data.len() < SYNTHETIC_LEN {
+        state ^= state << 13;
+        state ^= state >> 7;
+        state ^= state << 17;
+        data.extend(state.to_le_bytes());
+    }
+    data.truncate(SYNTHETIC_LEN);
+    data
+}
@@ -0,0 +1,673 @@
+//! [`Encoder`] and [`Decoder`]: the settings of [`crate::encode_png`] and
+//! [`crate::decode_png`] as builders, each setting a method, with the ways they fail
+//! told apart by [`Error`].
+use crate::cancel::{Cancel, Cancelled};
+use crate::context::Context;
+use crate::diagnostic::{self, Code};
+use crate::dict::Dictionary;
+use crate::format::{self, Codec, Delta, Packing, Volume};
+use crate::pipeline::{ErrorCorrection, Pipeline};
+use crate::{MAX_BYTES, MAX_OPAQUE_BYTES};
+use aes_gcm::aead::{Aead, KeyInit, Payload};
+use aes_gcm::Aes256Gcm;
+use chacha20poly1305::ChaCha20Poly1305;
+use image::{ImageFormat, ImageReader, RgbaImage};
+use std::fmt;
+use std::io::Cursor;
+use std::ops::ControlFlow;
+
+/// Start of a payload sealed with [`Encoder::encrypt`].
+const SEALED: &[u8; 15] = b"PICTURER-SEALED";
+/// Sealed with a BLAKE3 keystream, as before the cipher could be chosen; only opened.
+const STREAM_VERSION: u8 = 1;
+/// Sealed with the [`Cipher`] the byte after the version names; only opened.
+const CIPHER_VERSION: u8 = 2;
+/// Sealed as with [`CIPHER_VERSION`], with the metadata of the container around the
+/// sealed bytes authenticated along with them.
+const BOUND_VERSION: u8 = 3;
+const NONCE_LEN: usize = 12;
+const TAG_LEN: usize = 16;
+
+/// The cipher [`Encoder::encrypt`] seals with. Either one authenticates what it seals.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Cipher {
+    /// AES-256-GCM where the processor has AES instructions, or else ChaCha20-Poly1305,
+    /// which is several times faster than AES done without them.
+    #[default]
+    Auto,
+    AesGcm,
+    ChaCha20Poly1305,
+}
+
+impl Cipher {
+    /// The cipher this one stands for on this machine.
+    #[must_use]
+    pub fn resolve(self) -> Cipher {
+        match self {
+            Cipher::Auto if aes_hardware() => Cipher::AesGcm,
+            Cipher::Auto => Cipher::ChaCha20Poly1305,
+            cipher => cipher,
+        }
+    }
+
+    fn id(self) -> u8 {
+        match self.resolve() {
+            Cipher::AesGcm => 1,
+            _ => 2,
+        }
+    }
+}
+
+impl fmt::Display for Cipher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Cipher::Auto => "auto",
+            Cipher::AesGcm => "aes-gcm",
+            Cipher::ChaCha20Poly1305 => "chacha20-poly1305",
+        })
+    }
+}
+
+impl std::str::FromStr for Cipher {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        match s {
+            "auto" => Ok(Cipher::Auto),
+            "aes-gcm" => Ok(Cipher::AesGcm),
+            "chacha20-poly1305" => Ok(Cipher::ChaCha20Poly1305),
+            _ => anyhow::bail!("unknown cipher {s}, expected auto, aes-gcm or chacha20-poly1305"),
+        }
+    }
+}
+
+#[cfg(any(target_arch = "x86", target_arch = "x86_64"))]
+fn aes_hardware() -> bool {
+    std::arch::is_x86_feature_detected!("aes") && std::arch::is_x86_feature_detected!("pclmulqdq")
+}
+
+#[cfg(target_arch = "aarch64")]
+fn aes_hardware() -> bool {
+    std::arch::is_aarch64_feature_detected!("aes")
+}
+
+#[cfg(not(any(target_arch = "x86", target_arch = "x86_64", target_arch = "aarch64")))]
+fn aes_hardware() -> bool {
+    false
+}
+
+/// Which channels of each pixel hold bytes.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Channels {
+    /// Red, green, blue and alpha: the most each image holds.
+    #[default]
+    Rgba,
+    /// Red, green and blue, with every pixel opaque, for tools that drop or
+    /// premultiply alpha.
+    Rgb,
+}
+
+/// Why an [`Encoder`] or [`Decoder`] failed.
+#[derive(Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// [`Encoder::ecc`] was given a share of check bytes outside 0 to 0.5.
+    Ecc(f64),
+    /// A dictionary was given with a codec other than zstd.
+    DictionaryCodec,
+    /// The payload packs into this many bytes, more than one image holds.
+    TooLarge(usize),
+    /// The payload is sealed with a key that was not given, or with another one.
+    Key,
+    /// The image is a volume of a set or a delta, which need the other images.
+    NotSelfContained,
+    /// [`Encoder::cancel`] or [`Decoder::cancel`] was cancelled before all was done.
+    Cancelled(Cancelled),
+    /// Anything else, such as a file that is no image picturer wrote; [`Error::code`]
+    /// tells the common causes apart.
+    Other(anyhow::Error),
+}
+
+impl Error {
+    /// The code of the error, for those common enough to have one.
+    #[must_use]
+    pub fn code(&self) -> Option<Code> {
+        match self {
+            Error::Other(error) => diagnostic::find(error).map(|diagnostic| diagnostic.code),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Ecc(share) => write!(
+                f,
+                "error correction takes a share of check bytes above 0 and up to 0.5, not {share}"
+            ),
+            Error::DictionaryCodec => f.write_str("dictionaries need the zstd codec"),
+            Error::TooLarge(len) => {
+                write!(f, "packed payload of {len} bytes does not fit an image")
+            }
+            Error::Key => f.write_str("the payload is sealed with a key that was not given"),
+            Error::NotSelfContained => f.write_str(
+                "image is a volume of a set or a delta, which need other images to decode",
+            ),
+            Error::Cancelled(cancelled) => write!(f, "{cancelled}"),
+            Error::Other(error) => write!(f, "{error:#}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::Other(error) => Some(error.as_ref()),
+            _ => None,
+        }
+    }
+}
+
+impl From<anyhow::Error> for Error {
+    fn from(error: anyhow::Error) -> Self {
+        match error.downcast_ref::<Cancelled>() {
+            Some(&cancelled) => Error::Cancelled(cancelled),
+            None => Error::Other(error),
+        }
+    }
+}
+
+impl From<std::io::Error> for Error {
+    fn from(error: std::io::Error) -> Self {
+        Error::Other(error.into())
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(error: image::ImageError) -> Self {
+        Error::Other(error.into())
+    }
+}
+
+/// Settings for writing a payload as a single image. Those not set are as
+/// [`Packing::new`] of [`Codec::DEFAULT`] leaves them.
+#[derive(Clone, Copy, Default)]
+#[must_use]
+pub struct Encoder<'a> {
+    codec: Option<Codec>,
+    channels: Channels,
+    ecc: Option<f64>,
+    key: Option<[u8; 32]>,
+    cipher: Cipher,
+    dict: Option<&'a Dictionary>,
+    content_type: Option<&'a str>,
+    comment: Option<&'a str>,
+    block_size: Option<usize>,
+    context: Option<&'a Context>,
+    cancel: Option<&'a Cancel>,
+}
+
+impl<'a> Encoder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Compresses blocks with `codec`.
+    pub fn compression(mut self, codec: Codec) -> Self {
+        self.codec = Some(codec);
+        self
+    }
+
+    /// Stores bytes in `channels` of each pixel.
+    pub fn channels(mut self, channels: Channels) -> Self {
+        self.channels = channels;
+        self
+    }
+
+    /// Adds Reed-Solomon check bytes to each block, `share` of every codeword, which
+    /// corrects changed bytes up to half as many.
+    pub fn ecc(mut self, share: f64) -> Self {
+        self.ecc = Some(share);
+        self
+    }
+
+    /// Seals the payload, once compressed, with `key`, which [`Decoder::key`] must give.
+    /// Sealing is deterministic: the same payload sealed with the same key and cipher
+    /// gives the same bytes, which tells that two images hold the same payload but
+    /// nothing more. The content type and comment are sealed along with the payload.
+    pub fn encrypt(mut self, key: [u8; 32]) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Seals with `cipher`, which is stored with the payload for [`Decoder`] to open it
+    /// with. Which cipher it was is readable without the key, and authenticated with
+    /// the payload, so that it cannot be changed unnoticed.
+    pub fn cipher(mut self, cipher: Cipher) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Compresses with the zstd dictionary `dict`, which needs [`Codec::Zstd`].
+    pub fn dictionary(mut self, dict: &'a Dictionary) -> Self {
+        self.dict = Some(dict);
+        self
+    }
+
+    /// Compresses with the contexts of `context`, kept between payloads, and its
+    /// dictionary, which takes the place of any [`Encoder::dictionary`].
+    pub fn context(mut self, context: &'a Context) -> Self {
+        self.dict = context.dictionary();
+        self.context = Some(context);
+        self
+    }
+
+    /// Stops before the next block once `cancel` is cancelled, failing with
+    /// [`Error::Cancelled`].
+    pub fn cancel(mut self, cancel: &'a Cancel) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// Stores the MIME type of the payload in the header, or once encrypted, in the
+    /// sealed container, where only the key reads it.
+    pub fn content_type(mut self, content_type: &'a str) -> Self {
+        self.content_type = Some(content_type);
+        self
+    }
+
+    /// Stores a comment in the header, or once encrypted, in the sealed container, where
+    /// only the key reads it.
+    pub fn comment(mut self, comment: &'a str) -> Self {
+        self.comment = Some(comment);
+        self
+    }
+
+    /// Compresses `block_size` bytes of the payload at a time.
+    pub fn block_size(mut self, block_size: usize) -> Self {
+        self.block_size = Some(block_size);
+        self
+    }
+
+    /// The codec and the pipeline of the blocks, once the settings are checked.
+    fn blocks(&self) -> Result<(Codec, Pipeline), Error> {
+        let codec = self.codec.unwrap_or(Codec::DEFAULT);
+        if self.dict.is_some() && !matches!(codec, Codec::Zstd(_)) {
+            return Err(Error::DictionaryCodec);
+        }
+        let mut pipeline = Pipeline::new();
+        if let Some(share) = self.ecc {
+            if !(share > 0.0 && share <= 0.5) {
+                return Err(Error::Ecc(share));
+            }
+            #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+            let parity = (share * 255.0).round().max(1.0) as u8;
+            pipeline = pipeline.then(ErrorCorrection::new(parity)?);
+        }
+        Ok((codec, pipeline))
+    }
+
+    /// The packing of the container written, given the pipeline from [`Encoder::blocks`].
+    fn packing<'p>(&'p self, codec: Codec, pipeline: &'p Pipeline) -> Packing<'p> {
+        Packing {
+            pipeline: Some(pipeline),
+            content_type: self.content_type,
+            comment: self.comment,
+            block_size: self.block_size,
+            context: self.context,
+            cancel: self.cancel,
+            ..Packing::new(codec)
+        }
+    }
+
+    /// The container the settings pack `bytes` into.
+    pub fn pack(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let (codec, pipeline) = self.blocks()?;
+        let packing = self.packing(codec, &pipeline);
+        let Some(key) = &self.key else {
+            return Ok(format::pack(
+                bytes,
+                Packing {
+                    dict: self.dict,
+                    ..packing
+                },
+            )?);
+        };
+        let inner = format::pack(
+            bytes,
+            Packing {
+                dict: self.dict,
+                content_type: self.content_type,
+                comment: self.comment,
+                context: self.context,
+                cancel: self.cancel,
+                ..Packing::new(codec)
+            },
+        )?;
+        let outer = Packing {
+            codec: Codec::Raw,
+            content_type: None,
+            comment: None,
+            meta_hidden: true,
+            ..packing
+        };
+        let sealed = seal(key, self.cipher, &inner, &outer.metadata())?;
+        Ok(format::pack(&sealed, outer)?)
+    }
+
+    /// Most pixels the image of a payload of `len` bytes takes with these settings,
+    /// whatever the payload holds, as when none of it compresses.
+    pub fn required_pixels(&self, len: usize) -> Result<usize, Error> {
+        let (width, height) = match self.channels {
+            Channels::Rgba => crate::layout_size(self.max_container_len(len)?),
+            Channels::Rgb => crate::opaque_layout_size(self.max_container_len(len)?),
+        };
+        Ok(width * height)
+    }
+
+    /// Most payload bytes that, whatever they hold, fit the pixels of a `width` by
+    /// `height` image with these settings, and no more than the largest image picturer
+    /// writes holds.
+    pub fn max_payload(&self, width: u32, height: u32) -> Result<usize, Error> {
+        let (per_pixel, most) = match self.channels {
+            Channels::Rgba => (4, MAX_BYTES),
+            Channels::Rgb => (3, MAX_OPAQUE_BYTES),
+        };
+        let room = (width as usize)
+            .saturating_mul(height as usize)
+            .saturating_mul(per_pixel)
+            .min(most);
+        // Containers grow with their payload, so the most that fits is found by halving.
+        let (mut low, mut high) = (0, room);
+        if self.max_container_len(0)? > room {
+            return Ok(0);
+        }
+        while low < high {
+            let mid = low + (high - low).div_ceil(2);
+            if self.max_container_len(mid)? <= room {
+                low = mid;
+            } else {
+                high = mid - 1;
+            }
+        }
+        Ok(low)
+    }
+
+    /// Most bytes the container of a payload of `len` bytes takes, sealed or not.
+    fn max_container_len(&self, len: usize) -> Result<usize, Error> {
+        let (codec, pipeline) = self.blocks()?;
+        let packing = self.packing(codec, &pipeline);
+        if self.key.is_none() {
+            return Ok(format::max_container_len(
+                len,
+                &Packing {
+                    dict: self.dict,
+                    ..packing
+                },
+            )?);
+        }
+        let inner = format::max_container_len(
+            len,
+            &Packing {
+                dict: self.dict,
+                content_type: self.content_type,
+                comment: self.comment,
+                ..Packing::new(codec)
+            },
+        )?;
+        Ok(format::max_container_len(
+            SEALED.len() + 2 + NONCE_LEN + inner + TAG_LEN,
+            &Packing {
+                codec: Codec::Raw,
+                content_type: None,
+                comment: None,
+                meta_hidden: true,
+                ..packing
+            },
+        )?)
+    }
+
+    /// `bytes` laid out as the pixels of an image.
+    pub fn encode(&self, bytes: &[u8]) -> Result<RgbaImage, Error> {
+        let container = self.pack(bytes)?;
+        match self.channels {
+            Channels::Rgba if container.len() > MAX_BYTES => Err(Error::TooLarge(container.len())),
+            Channels::Rgb if container.len() > MAX_OPAQUE_BYTES => {
+                Err(Error::TooLarge(container.len()))
+            }
+            Channels::Rgba => Ok(crate::layout(container)?),
+            Channels::Rgb => Ok(crate::layout_opaque(&container)?),
+        }
+    }
+
+    /// `bytes` as a PNG file in memory.
+    pub fn encode_png(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        let mut png = Vec::new();
+        self.encode(bytes)?
+            .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+        Ok(png)
+    }
+}
+
+/// Settings for reading a payload back from a single image.
+#[derive(Clone, Copy, Default)]
+#[must_use]
+pub struct Decoder<'a> {
+    key: Option<[u8; 32]>,
+    dict: Option<&'a Dictionary>,
+    context: Option<&'a Context>,
+    cancel: Option<&'a Cancel>,
+}
+
+impl<'a> Decoder<'a> {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Opens a payload sealed with [`Encoder::encrypt`] with `key`.
+    pub fn key(mut self, key: [u8; 32]) -> Self {
+        self.key = Some(key);
+        self
+    }
+
+    /// Decompresses with the zstd dictionary `dict`.
+    pub fn dictionary(mut self, dict: &'a Dictionary) -> Self {
+        self.dict = Some(dict);
+        self
+    }
+
+    /// Decompresses with the contexts of `context`, kept between payloads, and its
+    /// dictionary, which takes the place of any [`Decoder::dictionary`].
+    pub fn context(mut self, context: &'a Context) -> Self {
+        self.context = Some(context);
+        self
+    }
+
+    /// Stops once `cancel` is cancelled, at the latest when the blocks being decoded are
+    /// done, failing with [`Error::Cancelled`].
+    pub fn cancel(mut self, cancel: &'a Cancel) -> Self {
+        self.cancel = Some(cancel);
+        self
+    }
+
+    /// The container `bytes` unpacked, with the context if one was given.
+    fn unpack_container(&self, bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+        format::unpack_in(bytes, self.dict, self.context, |_, _| {
+            if self.cancel.is_some_and(Cancel::is_cancelled) {
+                ControlFlow::Break(())
+            } else {
+                ControlFlow::Continue(())
+            }
+        })
+    }
+
+    /// The payload of the container `bytes`, as [`crate::pixels`] reads them out of an
+    /// image.
+    pub fn unpack(&self, bytes: &[u8]) -> Result<Vec<u8>, Error> {
+        if Volume::parse(bytes)?.is_some() || Delta::parse(bytes)?.is_some() {
+            return Err(Error::NotSelfContained);
+        }
+        let payload = self.unpack_container(bytes)?;
+        if !payload.starts_with(SEALED) {
+            return Ok(payload);
+        }
+        let Some(key) = &self.key else {
+            return Err(Error::Key);
+        };
+        let inner = open(key, &payload, &format::metadata(bytes)?).ok_or(Error::Key)?;
+        Ok(self.unpack_container(&inner)?)
+    }
+
+    /// The payload of a PNG file in memory.
+    pub fn decode_png(&self, png: &[u8]) -> Result<Vec<u8>, Error> {
+        let reader = ImageReader::new(Cursor::new(png)).with_guessed_format()?;
+        let image = crate::canonical(crate::read_image(reader)?.0)?;
+        self.unpack(&crate::pixels(image))
+    }
+}
+
+/// The keys the stream of [`open_stream`] derives from `key`: one to encrypt with and
+/// one to authenticate.
+fn keys(key: &[u8; 32]) -> ([u8; 32], [u8; 32]) {
+    (
+        blake3::derive_key("picturer seal stream", key),
+        blake3::derive_key("picturer seal tag", key),
+    )
+}
+
+/// `bytes` encrypted with `key` by `cipher`, under a nonce that is the keyed hash of
+/// the plaintext, as a synthetic IV is, so that none has to be kept track of. The
+/// header up to the nonce is authenticated along with the bytes, and so is `metadata`,
+/// that of the container the sealed bytes are packed in, which is left readable.
+fn seal(key: &[u8; 32], cipher: Cipher, bytes: &[u8], metadata: &[u8]) -> Result<Vec<u8>, Error> {
+    let cipher = cipher.resolve();
+    let nonce = blake3::keyed_hash(&blake3::derive_key("picturer seal nonce", key), bytes);
+    let nonce = &nonce.as_bytes()[..NONCE_LEN];
+    let mut sealed = Vec::with_capacity(SEALED.len() + 2 + NONCE_LEN + bytes.len() + TAG_LEN);
+    sealed.extend(SEALED);
+    sealed.push(BOUND_VERSION);
+    sealed.push(cipher.id());
+    let aad = [&sealed[..], metadata].concat();
+    let payload = Payload {
+        msg: bytes,
+        aad: &aad,
+    };
+    let ciphertext = match cipher {
+        Cipher::AesGcm => aes_gcm(key).encrypt(nonce.into(), payload),
+        _ => chacha(key).encrypt(nonce.into(), payload),
+    };
+    let ciphertext = ciphertext.map_err(|_| anyhow::anyhow!("payload too long to seal"))?;
+    sealed.extend(nonce);
+    sealed.extend(ciphertext);
+    Ok(sealed)
+}
+
+fn aes_gcm(key: &[u8; 32]) -> Aes256Gcm {
+    Aes256Gcm::new(&blake3::derive_key("picturer seal aes-256-gcm", key).into())
+}
+
+fn chacha(key: &[u8; 32]) -> ChaCha20Poly1305 {
+    ChaCha20Poly1305::new(&blake3::derive_key("picturer seal chacha20-poly1305", key).into())
+}
+
+/// What [`seal`] sealed, or `None` if `key` is not the one it was sealed with, or the
+/// bytes or the `metadata` of the container they were found in were changed.
+fn open(key: &[u8; 32], sealed: &[u8], metadata: &[u8]) -> Option<Vec<u8>> {
+    let rest = sealed.strip_prefix(SEALED)?;
+    let (&version, rest) = rest.split_first()?;
+    let metadata = match version {
+        STREAM_VERSION => return open_stream(key, rest),
+        CIPHER_VERSION => &[][..],
+        BOUND_VERSION => metadata,
+        _ => return None,
+    };
+    let (&id, rest) = rest.split_first()?;
+    let (nonce, ciphertext) = rest.split_first_chunk::<NONCE_LEN>()?;
+    let aad = [&sealed[..SEALED.len() + 2], metadata].concat();
+    let payload = Payload {
+        msg: ciphertext,
+        aad: &aad,
+    };
+    match id {
+        1 => aes_gcm(key).decrypt(nonce.into(), payload).ok(),
+        2 => chacha(key).decrypt(nonce.into(), payload).ok(),
+        _ => None,
+    }
+}
+
+/// What was sealed, before ciphers, by encrypting as a synthetic IV does it: the keyed
+/// hash of the plaintext is the tag and picks the keystream.
+fn open_stream(key: &[u8; 32], rest: &[u8]) -> Option<Vec<u8>> {
+    let (tag, ciphertext) = rest.split_first_chunk::<32>()?;
+    let (stream, tag_key) = keys(key);
+    let mut bytes = ciphertext.to_vec();
+    xor_stream(&stream, tag, &mut bytes);
+    (blake3::keyed_hash(&tag_key, &bytes) == blake3::Hash::from_bytes(*tag)).then_some(bytes)
+}
+
+fn xor_stream(key: &[u8; 32], tag: &[u8; 32], bytes: &mut [u8]) {
+    let mut stream = blake3::Hasher::new_keyed(key);
+    stream.update(tag);
+    let mut stream = stream.finalize_xof();
+    let mut pad = [0; 64];
+    for chunk in bytes.chunks_mut(pad.len()) {
+        stream.fill(&mut pad);
+        for (byte, pad) in chunk.iter_mut().zip(pad) {
+            *byte ^= pad;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KEY: [u8; 32] = [7; 32];
+
+    #[test]
+    fn sealed_metadata_is_not_in_the_header() {
+        let encoder = Encoder::new()
+            .content_type("text/plain")
+            .comment("quarterly figures")
+            .encrypt(KEY);
+        let container = encoder.pack(b"payload").unwrap();
+        assert_eq!(format::content_type(&container), None);
+        assert!(format::meta_hidden(&container));
+        let needle = b"quarterly figures";
+        assert!(!container
+            .windows(needle.len())
+            .any(|window| window == needle));
+        let decoder = Decoder::new().key(KEY);
+        assert_eq!(decoder.unpack(&container).unwrap(), b"payload");
+    }
+
+    #[test]
+    fn either_cipher_opens_what_it_sealed() {
+        for cipher in [Cipher::AesGcm, Cipher::ChaCha20Poly1305] {
+            let container = Encoder::new()
+                .cipher(cipher)
+                .encrypt(KEY)
+                .pack(b"payload")
+                .unwrap();
+            assert_eq!(
+                Decoder::new().key(KEY).unpack(&container).unwrap(),
+                b"payload"
+            );
+            assert!(matches!(
+                Decoder::new().key([8; 32]).unpack(&container),
+                Err(Error::Key)
+            ));
+        }
+    }
+
+    #[test]
+    fn changed_cipher_or_metadata_does_not_open() {
+        let sealed = seal(&KEY, Cipher::AesGcm, b"inner", b"metadata").unwrap();
+        assert_eq!(open(&KEY, &sealed, b"metadata").unwrap(), b"inner");
+        assert_eq!(open(&KEY, &sealed, b"changed!"), None);
+        let mut swapped = sealed.clone();
+        swapped[SEALED.len() + 1] = Cipher::ChaCha20Poly1305.id();
+        assert_eq!(open(&KEY, &swapped, b"metadata"), None);
+    }
+}
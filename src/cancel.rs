@@ -0,0 +1,65 @@
+//! [`Cancel`]: stopping an encode or decode from another thread, such as that of a GUI
+//! or a service whose client went away, between one block and the next.
+use std::fmt;
+use std::io;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+/// A token the thread encoding or decoding checks before each block, and any clone of
+/// it may set.
+#[derive(Clone, Default)]
+pub struct Cancel(Arc<AtomicBool>);
+
+impl Cancel {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Stops whatever runs with the token, or any clone of it, at its next block.
+    pub fn cancel(&self) {
+        self.0.store(true, Ordering::Relaxed);
+    }
+
+    #[must_use]
+    pub fn is_cancelled(&self) -> bool {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+/// The error of an encode or decode that was cancelled, with how far it got.
+#[derive(Clone, Copy, Debug)]
+pub struct Cancelled {
+    /// Payload bytes compressed or decoded by then.
+    pub done: u64,
+    /// Bytes of the whole payload, if known.
+    pub total: Option<u64>,
+}
+
+impl fmt::Display for Cancelled {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "cancelled after {} ", self.done)?;
+        match self.total {
+            Some(total) => write!(f, "of {total} bytes"),
+            None => f.write_str("bytes"),
+        }
+    }
+}
+
+impl std::error::Error for Cancelled {}
+
+impl Cancelled {
+    /// The cancellation as an I/O error, for code that fails with those.
+    pub(crate) fn into_io(self) -> io::Error {
+        io::Error::new(io::ErrorKind::Interrupted, self)
+    }
+
+    /// The cancellation [`Cancelled::into_io`] made `error` of, or `error` if it was none.
+    pub(crate) fn from_io(error: io::Error) -> Result<Self, io::Error> {
+        let cancelled = error
+            .get_ref()
+            .and_then(|inner| inner.downcast_ref::<Cancelled>())
+            .copied();
+        cancelled.ok_or(error)
+    }
+}
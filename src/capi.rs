@@ -0,0 +1,122 @@
+//! C API, declared in `include/picturer.h`. Every function returns one of the status
+//! codes below and writes into a buffer the caller owns, of `*out_len` bytes. When it
+//! is too small, or `out` is null, the call fails with `PICTURER_ERR_BUFFER_TOO_SMALL`
+//! and stores the size needed in `*out_len`.
+use crate::format::{Codec, Packing};
+use std::ffi::{c_char, c_int, CStr};
+use std::panic::{catch_unwind, AssertUnwindSafe};
+use std::{ptr, slice};
+
+pub const PICTURER_OK: c_int = 0;
+/// A pointer or the codec name is invalid.
+pub const PICTURER_ERR_ARGUMENT: c_int = 1;
+/// `out` is smaller than `*out_len`, which now holds the size needed.
+pub const PICTURER_ERR_BUFFER_TOO_SMALL: c_int = 2;
+/// The data does not fit one image, or the codec is not built in.
+pub const PICTURER_ERR_ENCODE: c_int = 3;
+/// The input is not a self-contained picturer image, or it is corrupt.
+pub const PICTURER_ERR_DECODE: c_int = 4;
+/// Something went wrong inside picturer; this is a bug.
+pub const PICTURER_ERR_INTERNAL: c_int = 5;
+
+/// Encodes `len` bytes at `data` as a PNG file with `codec` (such as `"zlib-9"` or
+/// `"zstd"`, or null for the default).
+///
+/// # Safety
+///
+/// `data` must point to `len` readable bytes, `codec` must be null or a NUL-terminated
+/// string, `out_len` must be valid for reads and writes, and `out` must be null or
+/// point to `*out_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn picturer_encode(
+    data: *const u8,
+    len: usize,
+    codec: *const c_char,
+    out: *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    guard(|| {
+        let Some(data) = input(data, len) else {
+            return PICTURER_ERR_ARGUMENT;
+        };
+        let codec = if codec.is_null() {
+            Codec::DEFAULT
+        } else {
+            match CStr::from_ptr(codec).to_str().map(str::parse) {
+                Ok(Ok(codec)) => codec,
+                _ => return PICTURER_ERR_ARGUMENT,
+            }
+        };
+        match crate::encode_png(data, Packing::new(codec)) {
+            Ok(png) => output(&png, out, out_len),
+            Err(_) => PICTURER_ERR_ENCODE,
+        }
+    })
+}
+
+/// Decodes the PNG file of `len` bytes at `png` back into the bytes it holds.
+///
+/// # Safety
+///
+/// `png` must point to `len` readable bytes, `out_len` must be valid for reads and
+/// writes, and `out` must be null or point to `*out_len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn picturer_decode(
+    png: *const u8,
+    len: usize,
+    out: *mut u8,
+    out_len: *mut usize,
+) -> c_int {
+    guard(|| {
+        let Some(png) = input(png, len) else {
+            return PICTURER_ERR_ARGUMENT;
+        };
+        match crate::decode_png(png, None) {
+            Ok(data) => output(&data, out, out_len),
+            Err(_) => PICTURER_ERR_DECODE,
+        }
+    })
+}
+
+/// A static, NUL-terminated description of a status code.
+#[no_mangle]
+pub extern "C" fn picturer_strerror(status: c_int) -> *const c_char {
+    let text: &CStr = match status {
+        PICTURER_OK => c"success",
+        PICTURER_ERR_ARGUMENT => c"invalid argument",
+        PICTURER_ERR_BUFFER_TOO_SMALL => c"output buffer too small",
+        PICTURER_ERR_ENCODE => c"cannot encode the data",
+        PICTURER_ERR_DECODE => c"not a picturer image, or a corrupt one",
+        PICTURER_ERR_INTERNAL => c"internal error",
+        _ => c"unknown status",
+    };
+    text.as_ptr()
+}
+
+/// Keeps a panic from unwinding into C.
+fn guard(body: impl FnOnce() -> c_int) -> c_int {
+    catch_unwind(AssertUnwindSafe(body)).unwrap_or(PICTURER_ERR_INTERNAL)
+}
+
+/// Borrows `len` bytes at `data`; a null `data` is only accepted when empty.
+unsafe fn input<'a>(data: *const u8, len: usize) -> Option<&'a [u8]> {
+    match (data.is_null(), len) {
+        (true, 0) => Some(&[]),
+        (true, _) => None,
+        (false, _) => Some(slice::from_raw_parts(data, len)),
+    }
+}
+
+/// Copies `bytes` into the caller's buffer, or reports the size it needs to be.
+unsafe fn output(bytes: &[u8], out: *mut u8, out_len: *mut usize) -> c_int {
+    if out_len.is_null() {
+        return PICTURER_ERR_ARGUMENT;
+    }
+    let capacity = *out_len;
+    *out_len = bytes.len();
+    if out.is_null() || capacity < bytes.len() {
+        return PICTURER_ERR_BUFFER_TOO_SMALL;
+    }
+    ptr::copy_nonoverlapping(bytes.as_ptr(), out, bytes.len());
+    PICTURER_OK
+}
@@ -0,0 +1,65 @@
+//! `IMAGE.sha256` files written next to images with `--write-checksum`, in the format
+//! of `sha256sum`, so that an archive of images can be checked for bit rot with
+//! `sha256sum -c` or `picturer verify --check`.
+use crate::hash::hex;
+use crate::manifest::hash_file;
+use crate::volume;
+use anyhow::{bail, Context};
+use std::path::{Path, PathBuf};
+
+/// Where the checksum of `image` is kept: `out.png` has `out.png.sha256`.
+pub fn path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".sha256");
+    PathBuf::from(path)
+}
+
+/// Writes the checksum file of `image`, naming it by file name so that the line holds
+/// wherever the pair is moved together.
+pub fn write(image: &Path) -> anyhow::Result<()> {
+    let name = image.file_name().unwrap_or_default().to_string_lossy();
+    let line = format!("{}  {name}\n", hex(&hash_file(image)?));
+    let path = path(image);
+    std::fs::write(&path, line).with_context(|| format!("cannot write {}", path.display()))
+}
+
+/// Checks `image` against its checksum file, and if it is a volume, every volume of its
+/// set against theirs.
+pub fn check(image: &Path) -> anyhow::Result<()> {
+    // Checked first, since a damaged image may not even be read far enough to tell.
+    check_file(image)?;
+    volume::others(image)?
+        .iter()
+        .try_for_each(|other| check_file(other))
+}
+
+fn check_file(image: &Path) -> anyhow::Result<()> {
+    matches(image)?;
+    eprintln!("{} matches {}", image.display(), path(image).display());
+    Ok(())
+}
+
+/// Checks `image` against its own checksum file alone, printing nothing.
+pub fn matches(image: &Path) -> anyhow::Result<()> {
+    let path = path(image);
+    let text = std::fs::read_to_string(&path)
+        .with_context(|| format!("cannot read checksum {}", path.display()))?;
+    // The name after the digest is not needed: the file checked is the one the
+    // checksum sits next to.
+    let Some((expected, _)) = text
+        .lines()
+        .next()
+        .and_then(|line| line.split_once(' '))
+        .filter(|(digest, _)| digest.len() == 64)
+    else {
+        bail!("{} does not hold a SHA-256 checksum", path.display())
+    };
+    if hex(&hash_file(image)?) != expected.to_ascii_lowercase() {
+        bail!(
+            "{} does not match {}; it changed since it was written",
+            image.display(),
+            path.display()
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,1121 @@
+//! The command line, as clap parses it. Shell completions and the man page are
+//! generated from the same definitions.
+use crate::format::{self, Codec};
+use crate::{dict, fetch, hash, robust, serve, stego, tile};
+use clap::builder::BoolishValueParser;
+use clap::{ArgGroup, Args, Parser, Subcommand, ValueEnum};
+use std::path::PathBuf;
+use std::time::SystemTime;
+
+#[derive(Parser)]
+#[command(
+    name = "picturer",
+    version,
+    about = "Store bytes as the pixels of a PNG image, and read them back",
+    after_help = "Defaults are read from ~/.config/picturer/config.toml, which may set codec, \
+        level, dict, out-dir, max-download, max-output-size, limit-rate, manifest and trusted-keys. Settings also have a PICTURER_* \
+        environment variable, shown with each flag. Flags win over the environment, and \
+        both over the file."
+)]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Encode bytes as color to png
+    #[command(
+        short_flag = 'e',
+        long_about = "Encode bytes as color to png.\n\n\
+            A directory is packed as an archive, storing repeated content once. Files over \
+            1 GiB are split into out.001.png, out.002.png, ... The input may be a pipe, a \
+            FIFO or a process substitution, such as /dev/stdin or <(tar c dir), of any \
+            length: what outgrows one image is spooled to a temporary file to size the \
+            volumes. Layouts that hold one image read a pipe no further than that."
+    )]
+    Encode(Box<EncodeArgs>),
+    /// Decode png data back to bytes
+    #[command(
+        short_flag = 'd',
+        long_about = "Decode png data back to bytes.\n\n\
+            Any volume of a split set decodes the whole set. Archives are extracted into a \
+            directory, or written as they are to a pipe or FIFO. Built with the http \
+            feature, the input may be an http(s) URL."
+    )]
+    Decode(DecodeArgs),
+    /// Decode in memory and report the first byte that differs from the original
+    Verify(VerifyArgs),
+    /// Print a digest of the payload without writing it out
+    Hash(HashArgs),
+    /// Print what the header of an image says about its payload
+    Info(InfoArgs),
+    /// Print the files of an image made from a directory or with --add, decoding only
+    /// the table of them
+    List(ListArgs),
+    /// Find the images under a directory whose payload has an ID, as info prints it
+    Scan {
+        /// The ID, or enough of its leading hex digits to tell it apart
+        #[arg(long)]
+        id: String,
+        dir: PathBuf,
+    },
+    /// Check every image picturer wrote under a directory, its header, blocks, checksum
+    /// file and the error correction a robust image needed, and report each as healthy,
+    /// degraded or corrupt in JSON; fails if any is corrupt
+    Audit(AuditArgs),
+    /// Draw a map over an image of the pixels holding its header, each block, colored
+    /// by how random its bytes are, and padding, with blocks that no longer decode in
+    /// magenta
+    Analyze(AnalyzeArgs),
+    /// Re-encode an image in the current container format, such as one written before
+    /// it had a header, optionally with another codec or as a robust image
+    Migrate(MigrateArgs),
+    /// Add bytes to the end of an image's payload in place
+    Append {
+        image: PathBuf,
+        more: PathBuf,
+        /// The zstd dictionary the image was compressed with
+        #[arg(long, env = "PICTURER_DICT", value_name = "F")]
+        dict: Option<PathBuf>,
+    },
+    /// Check a volume set against its manifest, then decode it; given the URL of a
+    /// manifest, downloads the volumes it lists first
+    Join(JoinArgs),
+    /// Add a snapshot of a directory to a store of images: a full image, then deltas
+    /// each against the image before, restored with picturer restore
+    Backup(BackupArgs),
+    /// Extract the snapshot a backup store held at a point in time
+    Restore {
+        /// Where to extract the snapshot
+        out: PathBuf,
+        /// The directory picturer backup keeps its images in
+        #[arg(long, value_name = "D")]
+        store: PathBuf,
+        /// The last snapshot taken at or before this time, such as 2026-10-14T16:00:00Z
+        /// (default: the last of all)
+        #[arg(long, value_name = "TIME", value_parser = humantime::parse_rfc3339_weak)]
+        at: Option<SystemTime>,
+        /// Recreate the symbolic links of the snapshot
+        #[arg(long)]
+        allow_symlinks: bool,
+    },
+    /// Report size and throughput of every codec on a file or synthetic data
+    Bench { input: Option<PathBuf> },
+    /// Round-trip random payloads through every codec and layout in memory, to check
+    /// this build works here
+    Selftest {
+        /// Repeat the payloads of an earlier run, which prints its seed
+        #[arg(long)]
+        seed: Option<u64>,
+    },
+    /// Write or check the payloads and images, fixed in picturer, that other
+    /// implementations prove they read and write images as it does against
+    #[command(subcommand)]
+    Vectors(VectorsCommand),
+    /// Answer POST /encode[?codec=C] and POST /decode over HTTP (needs the serve feature)
+    Serve(ServeArgs),
+    /// Work with zstd dictionaries
+    #[command(subcommand)]
+    Dict(DictCommand),
+    /// Make, export and list named keys for --sign-key, --verify-key, --keyfile and
+    /// --recipient
+    #[command(subcommand)]
+    Key(KeyCommand),
+    /// Print a completion script for a shell to stdout
+    Completions { shell: clap_complete::Shell },
+    /// Pick a file, mode and codec interactively (needs the tui feature)
+    Tui,
+    /// Open a window that converts the files dropped on it (needs the gui feature)
+    Gui,
+    /// List what each --preset of encode expands to
+    Presets,
+    /// Report how many payload bytes --carrier images can hide at a --density
+    Capacity(CapacityArgs),
+    /// Show a file full screen as a stream of symbols for receive to read with a camera,
+    /// for machines with no network or cable between them (needs the gui and barcodes
+    /// features)
+    Beam(BeamArgs),
+    /// Read a file beamed from another screen with the camera, through ffmpeg, or from
+    /// photos of it (needs the barcodes feature)
+    Receive(ReceiveArgs),
+    /// Print the man page to stdout
+    Man {
+        /// Write picturer.1 and a page per subcommand into this directory instead
+        #[arg(long, value_name = "D")]
+        dir: Option<PathBuf>,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum DictCommand {
+    /// Build a zstd dictionary from sample files
+    Train {
+        #[arg(required = true)]
+        samples: Vec<PathBuf>,
+        #[arg(long, value_name = "F", default_value = "picturer.dict")]
+        out: PathBuf,
+        /// Largest dictionary to write, in bytes
+        #[arg(long, value_name = "N", default_value_t = dict::DEFAULT_SIZE)]
+        size: usize,
+    },
+}
+
+#[derive(Subcommand)]
+pub enum VectorsCommand {
+    /// Write NAME.bin and NAME.png of every vector to a directory, with vectors.json
+    /// giving the settings and hashes of each
+    Write { dir: PathBuf },
+    /// Check this build against every vector or, given a directory, that the NAME.png
+    /// another implementation wrote there for each decodes to its payload
+    Verify { dir: Option<PathBuf> },
+}
+
+#[derive(Subcommand)]
+pub enum KeyCommand {
+    /// Make a random key under ~/.config/picturer/keys and print its public key
+    Generate { name: String },
+    /// Print the public key of a key, for --verify-key or trusted-keys
+    Export { name: String },
+    /// Print the name and public key of every key
+    List,
+    /// Make a post-quantum hybrid identity for --age-identity, of X25519 and ML-KEM-768,
+    /// and print it or write it to a file; its public key, in a comment, is what
+    /// --age-recipient takes (needs the pq feature)
+    GeneratePq {
+        #[arg(short, long)]
+        output: Option<PathBuf>,
+    },
+}
+
+/// What `-e` writes.
+#[derive(Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Format {
+    #[default]
+    Png,
+    /// A page that decodes itself in the browser.
+    Html,
+    /// A robust image saved as a JPEG, for channels that accept nothing else.
+    Jpeg,
+    /// Colored half blocks for a terminal, for small payloads such as keys.
+    AnsiArt,
+    /// A rect for each run of pixels, for documents; decode reads it and rasterized
+    /// copies at any whole scale.
+    Svg,
+    /// A page with the image and how to decode it, for archiving.
+    Pdf,
+    /// A Data Matrix symbol, for small payloads and scanners that read only those.
+    DataMatrix,
+    /// An Aztec symbol, for small payloads and scanners that read only those.
+    Aztec,
+    /// A lossless FFV1 video in Matroska, a volume to each frame, for payloads too
+    /// large for images.
+    Mkv,
+}
+
+/// How hard the PNG an image is saved as is deflated, apart from the payload's codec.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum PngLevel {
+    /// zlib level 1.
+    Fast,
+    /// zlib level 6.
+    Default,
+    /// zlib level 9.
+    Best,
+}
+
+impl PngLevel {
+    /// The level used without `--png-level`: deflating a compressed payload again gains
+    /// next to nothing, so only one stored as it is gets more than the fastest.
+    pub fn for_codec(codec: Codec) -> Self {
+        match codec {
+            Codec::Raw | Codec::Zlib(0) => PngLevel::Default,
+            Codec::Zlib(_) | Codec::Zstd(_) => PngLevel::Fast,
+        }
+    }
+
+    pub fn compression(self) -> mtpng::CompressionLevel {
+        match self {
+            PngLevel::Fast => mtpng::CompressionLevel::Fast,
+            PngLevel::Default => mtpng::CompressionLevel::Default,
+            PngLevel::Best => mtpng::CompressionLevel::High,
+        }
+    }
+}
+
+/// Bundles of `-e` settings chosen with `--preset`.
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Preset {
+    /// Smallest images, with a manifest to check volume sets against and a note in each
+    /// on how to decode it.
+    Archival,
+    /// Quickest encoding.
+    Fast,
+    /// Images any picturer build, and the HTML and wasm decoders, can read.
+    Share,
+    /// Images that can still be decoded after a social network re-compresses them.
+    Social,
+}
+
+impl Preset {
+    pub fn codec(self) -> Codec {
+        match self {
+            Preset::Archival => Codec::ZSTD,
+            Preset::Fast => Codec::Zlib(1),
+            Preset::Share | Preset::Social => Codec::DEFAULT,
+        }
+    }
+
+    pub fn manifest(self) -> bool {
+        matches!(self, Preset::Archival)
+    }
+
+    pub fn recovery_note(self) -> bool {
+        matches!(self, Preset::Archival)
+    }
+
+    pub fn robust(self) -> bool {
+        matches!(self, Preset::Social)
+    }
+}
+
+/// Flags of every mode that reads an input to encode or decode.
+#[derive(Args, Default)]
+pub struct Common {
+    /// Compress with, or decode using, a zstd dictionary (implies zstd)
+    #[arg(long, env = "PICTURER_DICT", value_name = "F")]
+    pub dict: Option<PathBuf>,
+    /// Encode as a delta against, or decode with, this base image
+    #[arg(long, value_name = "B")]
+    pub base: Option<PathBuf>,
+    /// Read the input from the clipboard (needs the clipboard feature)
+    #[arg(long)]
+    pub clipboard: bool,
+    /// Print the time each stage took and how fast it went, once done
+    #[arg(long)]
+    pub timings: bool,
+    /// Keep temporary files in D, and spill what is read and packed for each image there
+    /// rather than holding it in memory
+    #[arg(long, env = "PICTURER_TEMP_DIR", value_name = "D")]
+    pub temp_dir: Option<PathBuf>,
+}
+
+/// Flags of the modes that write an image or a payload.
+#[derive(Args, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct Output {
+    #[arg(
+        long,
+        help = "Draw the image in the terminal (kitty, iTerm2, sixel or ANSI blocks; set \
+            PICTURER_PREVIEW to one of kitty, iterm, sixel, ansi to choose)"
+    )]
+    pub preview: bool,
+    /// Put the output on the clipboard (needs the clipboard feature)
+    #[arg(long)]
+    pub to_clipboard: bool,
+    /// Write the output to standard output, byte for byte, which must be a file or a pipe
+    #[arg(long, conflicts_with = "to_clipboard")]
+    pub stdout: bool,
+    /// Write outputs into D when no output path is given
+    #[arg(long, env = "PICTURER_OUT_DIR", value_name = "D")]
+    pub out_dir: Option<PathBuf>,
+    /// When no output path is given and the one picturer picks exists, or a volume or
+    /// manifest beside it does, number it (out-1.png, out-2.png, ...) instead of
+    /// overwriting them
+    #[arg(long, env = "PICTURER_AUTO_RENAME", value_parser = BoolishValueParser::new())]
+    pub auto_rename: bool,
+}
+
+/// Flags of the modes that read a payload back from a --robust image, a carrier or a zip
+/// or tar file holding the image.
+#[derive(Args)]
+pub struct Recovery {
+    /// Read the image out of the zip or tar file given as the input, finding the one
+    /// picturer wrote, or the volumes of one set; an input of the archive, then ::
+    /// and the path of a member in it, reads that member instead
+    #[arg(long)]
+    pub from_archive: bool,
+    /// Channel values of a robust image further than N from every level are read as
+    /// unknown, which error correction repairs at half the cost of a wrong value; 43
+    /// reads every value as its nearest level
+    #[arg(
+        long,
+        env = "PICTURER_TOLERANCE",
+        value_name = "N",
+        default_value_t = robust::TOLERANCE,
+        value_parser = clap::value_parser!(u8).range(0..=robust::MAX_TOLERANCE.into())
+    )]
+    pub tolerance: u8,
+    /// Passphrase of a carrier written with --passphrase; asked for on the terminal,
+    /// unechoed, if P is left out
+    #[arg(
+        long,
+        env = "PICTURER_PASSPHRASE",
+        value_name = "P",
+        hide_env_values = true,
+        num_args = 0..=1,
+        default_missing_value = ""
+    )]
+    pub passphrase: Option<String>,
+    /// Keyfile of a carrier written with --keyfile
+    #[arg(
+        long,
+        env = "PICTURER_KEYFILE",
+        value_name = "F",
+        conflicts_with = "passphrase"
+    )]
+    pub keyfile: Option<PathBuf>,
+}
+
+impl Default for Recovery {
+    fn default() -> Self {
+        Recovery {
+            from_archive: false,
+            tolerance: robust::TOLERANCE,
+            passphrase: None,
+            keyfile: None,
+        }
+    }
+}
+
+/// Flags of the modes that pack a directory into an archive.
+#[derive(Args, Default)]
+pub struct Tree {
+    /// Store what symbolic links in a directory point to, rather than the links
+    #[arg(long)]
+    pub follow_symlinks: bool,
+    /// Leave out paths of a directory matching this gitignore-style pattern, such as
+    /// '*.o' or target/; may be given more than once
+    #[arg(long, value_name = "PATTERN")]
+    pub exclude: Vec<String>,
+    /// Leave out what .gitignore, .ignore and git's own exclude files of a directory
+    /// ignore, and its .git, as rg and fd do
+    #[arg(long)]
+    pub gitignore: bool,
+}
+
+/// Flags of the modes that hide a payload in other images.
+#[derive(Args)]
+pub struct Hiding {
+    /// Hide the payload in the low bits of a copy of this image instead; given more than
+    /// once, the payload is spread across them, written as out.001.png, out.002.png, ...
+    #[arg(long = "carrier", value_name = "IMG")]
+    pub carriers: Vec<PathBuf>,
+    /// Low bits of each carrier channel the payload takes; more hold more, and change
+    /// the carrier more
+    #[arg(
+        long,
+        env = "PICTURER_DENSITY",
+        value_name = "N",
+        default_value_t = stego::DENSITY,
+        value_parser = clap::value_parser!(u8).range(1..=stego::MAX_DENSITY.into())
+    )]
+    pub density: u8,
+    /// Encrypt the payload and its headers with a key stretched from P and scatter them
+    /// over the carrier, so that without P it cannot be told from the photo it was
+    /// made from (needs --density 1); asked for twice on the terminal, unechoed, if P
+    /// is left out
+    #[arg(
+        long,
+        env = "PICTURER_PASSPHRASE",
+        value_name = "P",
+        hide_env_values = true,
+        num_args = 0..=1,
+        default_missing_value = "",
+        requires = "carriers"
+    )]
+    pub passphrase: Option<String>,
+    /// Like --passphrase, with the key read from F instead: the file itself if it is 32
+    /// bytes long, or else its hash, for scripts that cannot give a passphrase
+    #[arg(
+        long,
+        env = "PICTURER_KEYFILE",
+        value_name = "F",
+        conflicts_with = "passphrase",
+        requires = "carriers"
+    )]
+    pub keyfile: Option<PathBuf>,
+    /// Also let the holder of keyfile F open the carriers; may be given more than once,
+    /// for up to 16 passphrases and keyfiles in all
+    #[arg(long = "recipient", value_name = "F", requires = "carriers")]
+    pub recipients: Vec<PathBuf>,
+}
+
+impl Default for Hiding {
+    fn default() -> Self {
+        Hiding {
+            carriers: Vec::new(),
+            density: stego::DENSITY,
+            passphrase: None,
+            keyfile: None,
+            recipients: Vec::new(),
+        }
+    }
+}
+
+/// Flags of the modes that decode an image, which may be downloaded from a URL.
+#[derive(Args, Default)]
+pub struct Remote {
+    /// Largest download accepted, in bytes (default 2 GiB)
+    #[arg(long, env = "PICTURER_MAX_DOWNLOAD", value_name = "N")]
+    pub max_download: Option<u64>,
+    /// Largest payload decoded, in bytes (default 16 GiB), so that an image made to
+    /// expand without end fails early
+    #[arg(long, env = "PICTURER_MAX_OUTPUT_SIZE", value_name = "N")]
+    pub max_output_size: Option<u64>,
+    /// Download at no more than R bytes a second, or R with K, M or G for KiB, MiB or GiB
+    #[arg(long, env = "PICTURER_LIMIT_RATE", value_name = "R", value_parser = fetch::parse_rate)]
+    pub limit_rate: Option<u64>,
+}
+
+#[derive(Args)]
+#[command(group(
+    ArgGroup::new("encryption")
+        .args(["gpg_recipients", "age_recipients", "ssh_recipients"])
+        .multiple(true)
+))]
+#[allow(clippy::struct_excessive_bools)]
+pub struct EncodeArgs {
+    /// The input, then the image to write (default: the input with .png).
+    /// With --clipboard or --add, only the image
+    #[arg(value_name = "PATH", num_args = 0..=2)]
+    pub paths: Vec<PathBuf>,
+    /// A bundle of settings; flags given alongside it win (see picturer presets)
+    #[arg(long, env = "PICTURER_PRESET", value_enum, value_name = "P")]
+    pub preset: Option<Preset>,
+    /// raw, zlib[-0..9] or zstd[-1..22] (default zlib-9)
+    #[arg(long, env = "PICTURER_CODEC", value_name = "C")]
+    pub codec: Option<Codec>,
+    /// Store a file as a named entry; -d --name E decodes just that one
+    #[arg(long, value_name = "NAME=PATH", value_parser = entry, conflicts_with = "clipboard")]
+    pub add: Vec<(String, PathBuf)>,
+    /// Also write out.manifest.json listing each volume's SHA-256; rerunning keeps
+    /// volumes an interrupted encode finished
+    #[arg(long, env = "PICTURER_MANIFEST", value_parser = BoolishValueParser::new())]
+    pub manifest: bool,
+    /// Store a note in each PNG, as a text chunk image viewers show, saying what the image
+    /// holds and how to decode it with or without picturer
+    #[arg(long)]
+    pub recovery_note: bool,
+    /// Run CMD with {} replaced by each image, printing its output; presets: 0x0, transfer
+    #[arg(long, env = "PICTURER_UPLOAD", value_name = "CMD")]
+    pub upload: Option<String>,
+    /// png, html for a page that downloads the file in any browser, jpeg for a --robust
+    /// image saved as a JPEG, ansi-art for colored text a terminal shows and decode
+    /// reads back, svg for documents, pdf for a page to archive, or data-matrix or aztec
+    /// for a symbol industrial scanners read
+    #[arg(long, env = "PICTURER_FORMAT", value_enum, default_value_t = Format::Png)]
+    pub format: Format,
+    /// Write large flat blocks with heavy error correction, which can be decoded even
+    /// after moderate JPEG re-compression or scaling, holding at most about 1.5 MiB
+    #[arg(long, env = "PICTURER_ROBUST", value_parser = BoolishValueParser::new())]
+    pub robust: bool,
+    /// Store 3 bytes in each pixel and leave every pixel opaque, so that PNG optimizers
+    /// that drop the alpha channel keep the payload intact
+    #[arg(long, conflicts_with_all = ["robust", "carriers"])]
+    pub opaque: bool,
+    /// Keep every image under N bytes, such as 8MB or 25MiB, picking the codec and level
+    /// and splitting into volumes only if no single image is small enough
+    #[arg(
+        long,
+        env = "PICTURER_TARGET_SIZE",
+        value_name = "N",
+        value_parser = parse_size,
+        conflicts_with_all = ["codec", "dict", "base", "robust", "carriers", "tile"]
+    )]
+    pub target_size: Option<u64>,
+    /// Pad each image with random bytes up to a multiple of N bytes, such as 64KiB, or
+    /// with pow2 up to a power of two, so that its size gives only a rough idea of the
+    /// payload's
+    #[arg(
+        long,
+        env = "PICTURER_PAD_TO",
+        value_name = "N|pow2",
+        value_parser = parse_pad,
+        conflicts_with_all = ["robust", "carriers", "target_size"]
+    )]
+    pub pad_to: Option<PadTo>,
+    /// Store an input image as its decoded pixels and size rather than its file, so that
+    /// re-muxing or re-compressing the file changes nothing stored; decode writes them
+    /// in the format the output is named for (default: the input's)
+    #[arg(long, conflicts_with = "add")]
+    pub wrap: bool,
+    /// Cut the image into tiles of W by H pixels, each with a header and a hash, so that
+    /// damage is confined to the tiles it touches and tools can work tile by tile
+    #[arg(
+        long,
+        value_name = "WxH",
+        value_parser = tile::parse_size,
+        conflicts_with_all = ["opaque", "robust", "carriers"]
+    )]
+    pub tile: Option<(u32, u32)>,
+    /// Also write IMAGE.sha256 next to each image written, which sha256sum -c and
+    /// picturer verify --check read
+    #[arg(
+        long,
+        env = "PICTURER_WRITE_CHECKSUM",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with_all = ["data_uri", "to_clipboard", "stdout"]
+    )]
+    pub write_checksum: bool,
+    /// Sign each image written with the Ed25519 key in F, the file itself if it is 32
+    /// bytes long or else its hash, writing the signature to IMAGE.sig; F may also name
+    /// a key of picturer key
+    #[arg(
+        long,
+        env = "PICTURER_SIGN_KEY",
+        value_name = "F",
+        conflicts_with_all = ["data_uri", "to_clipboard", "stdout"]
+    )]
+    pub sign_key: Option<PathBuf>,
+    /// Deflate effort of the PNG itself, apart from --codec (default: fast, or default
+    /// for a raw or zlib-0 payload)
+    #[arg(long, env = "PICTURER_PNG_LEVEL", value_enum, value_name = "L")]
+    pub png_level: Option<PngLevel>,
+    /// Compress the payload in blocks of N bytes, 65536 to 1 GiB, rather than a size
+    /// picked for it and the cores compressing it (1 MiB with --deterministic)
+    #[arg(
+        long,
+        env = "PICTURER_CHUNK_SIZE",
+        value_name = "N",
+        value_parser = clap::value_parser!(u64)
+            .range(format::MIN_BLOCK_SIZE as u64..=crate::MAX_BYTES as u64)
+    )]
+    pub chunk_size: Option<u64>,
+    /// Encrypt the payload with gpg to this key or user ID before encoding it; may be
+    /// given more than once
+    #[arg(
+        long = "gpg-recipient",
+        value_name = "ID",
+        conflicts_with_all = ["age_recipients", "ssh_recipients"]
+    )]
+    pub gpg_recipients: Vec<String>,
+    /// Encrypt the payload with age to this age1... public key, or post-quantum
+    /// picturerpq1... one (needs the pq feature), or to those in a file of them, before
+    /// encoding it; may be given more than once
+    #[arg(long = "age-recipient", value_name = "R")]
+    pub age_recipients: Vec<String>,
+    /// Encrypt the payload with age to the SSH public key in this .pub file, or to each
+    /// of a file of them, as age -R does; may be given more than once
+    #[arg(long = "recipient-ssh", value_name = "F")]
+    pub ssh_recipients: Vec<PathBuf>,
+    /// Leave the comment, --meta pairs, content type and time of encoding out of the
+    /// header, so `info` shows them only once decoded; without it a readable copy stays
+    /// there, which decoding checks against the one encrypted with the payload
+    #[arg(long, requires = "encryption")]
+    pub hide_metadata: bool,
+    /// Print the image as a data: URI with base64 contents instead of writing it
+    #[arg(long, conflicts_with_all = ["to_clipboard", "stdout"])]
+    pub data_uri: bool,
+    /// Write the image as base64 between BEGIN and END lines, to OUT.asc, for channels
+    /// that carry only plain text; decode takes such files as it takes images
+    #[arg(long, conflicts_with_all = ["data_uri", "to_clipboard", "stdout"])]
+    pub armor: bool,
+    /// Also write N-1 identical copies, OUT.copy2.png and so on; decoding any of them
+    /// whole reads every copy beside it and takes each byte most of them agree on
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u8).range(1..),
+        conflicts_with_all = ["armor", "data_uri", "to_clipboard", "stdout"]
+    )]
+    pub copies: u8,
+    /// Cut blocks to end on records ending with D, such as \n, so that decoding with
+    /// --whole-records returns whole records; takes \n, \r, \t, \0, \\ and \xHH escapes
+    #[arg(long, value_name = "D", value_parser = parse_delimiter)]
+    pub split_on: Option<Delimiter>,
+    /// Make the same input always give a bit-identical image: directories are stored
+    /// with normalised modes and no holes looked for, and carriers hidden with a secret
+    /// take salts derived from it rather than random ones
+    #[arg(
+        long,
+        env = "PICTURER_DETERMINISTIC",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with = "encryption"
+    )]
+    pub deterministic: bool,
+    #[arg(
+        long,
+        help = "Leave out when the image was encoded, which --deterministic also does unless \
+            SOURCE_DATE_EPOCH gives the time to record"
+    )]
+    pub no_timestamp: bool,
+    /// Text to store with the payload, shown by `info`
+    #[arg(long, value_name = "TEXT")]
+    pub comment: Option<String>,
+    /// Store a key/value pair with the payload, shown by `info`; repeat for more
+    #[arg(long, value_name = "KEY=VALUE", value_parser = meta)]
+    pub meta: Vec<(String, String)>,
+    #[command(flatten)]
+    pub tree: Tree,
+    #[command(flatten)]
+    pub hiding: Hiding,
+    #[command(flatten)]
+    pub common: Common,
+    #[command(flatten)]
+    pub output: Output,
+}
+
+/// As parsed from no flags at all, for the callers that build the arguments themselves.
+impl Default for EncodeArgs {
+    fn default() -> Self {
+        EncodeArgs {
+            paths: Vec::new(),
+            preset: None,
+            codec: None,
+            add: Vec::new(),
+            manifest: false,
+            recovery_note: false,
+            upload: None,
+            format: Format::Png,
+            robust: false,
+            opaque: false,
+            target_size: None,
+            pad_to: None,
+            wrap: false,
+            tile: None,
+            write_checksum: false,
+            sign_key: None,
+            png_level: None,
+            chunk_size: None,
+            gpg_recipients: Vec::new(),
+            age_recipients: Vec::new(),
+            ssh_recipients: Vec::new(),
+            hide_metadata: false,
+            data_uri: false,
+            armor: false,
+            copies: 1,
+            split_on: None,
+            deterministic: false,
+            no_timestamp: false,
+            comment: None,
+            meta: Vec::new(),
+            tree: Tree::default(),
+            hiding: Hiding::default(),
+            common: Common::default(),
+            output: Output::default(),
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct BeamArgs {
+    pub file: PathBuf,
+    /// Symbols shown a second; fewer suit a slow camera or a dim room
+    #[arg(long, value_name = "N", default_value_t = 8, value_parser = clap::value_parser!(u8).range(1..=60))]
+    pub fps: u8,
+    /// Bytes of the file in each symbol; fewer make smaller symbols a camera reads from
+    /// further away
+    #[arg(long, value_name = "N", default_value_t = 400, value_parser = clap::value_parser!(u16).range(16..=1500))]
+    pub block: u16,
+    /// Write the symbols into directory D as PNG files instead of showing them, to show
+    /// or print some other way
+    #[arg(long, value_name = "D")]
+    pub save: Option<PathBuf>,
+    /// How many symbols --save writes (default: as many as blocks, and half again)
+    #[arg(long, value_name = "N", requires = "save")]
+    pub count: Option<u32>,
+}
+
+#[derive(Args)]
+pub struct ReceiveArgs {
+    /// Where to write the file
+    pub out: PathBuf,
+    /// The camera, as ffmpeg names it (default: /dev/video0 on Linux, 0 on macOS)
+    #[arg(long, value_name = "DEV", conflicts_with = "from")]
+    pub camera: Option<String>,
+    /// Read the symbols from these images, such as photos of the screen, instead
+    #[arg(long, value_name = "IMAGE", num_args = 1..)]
+    pub from: Vec<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct JoinArgs {
+    /// The manifest, or its http(s) URL
+    pub manifest: PathBuf,
+    /// Where to write the payload (default: the manifest's name with .bin)
+    pub out: Option<PathBuf>,
+    /// Recreate the symbolic links of an extracted directory
+    #[arg(long)]
+    pub allow_symlinks: bool,
+    /// Largest volume downloaded, in bytes (default 2 GiB)
+    #[arg(long, env = "PICTURER_MAX_DOWNLOAD", value_name = "N")]
+    pub max_download: Option<u64>,
+    /// Download at no more than R bytes a second in all, or R with K, M or G for KiB,
+    /// MiB or GiB
+    #[arg(long, env = "PICTURER_LIMIT_RATE", value_name = "R", value_parser = fetch::parse_rate)]
+    pub limit_rate: Option<u64>,
+    /// Decode up to N volumes of a set at once (default: one per core); each holds its
+    /// pixels and payload in memory until written
+    #[arg(long, short = 'j', value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: Option<u16>,
+}
+
+#[derive(Args)]
+pub struct BackupArgs {
+    /// The directory to take a snapshot of
+    pub dir: PathBuf,
+    /// The directory to keep the images in, created if missing
+    #[arg(long, value_name = "D")]
+    pub store: PathBuf,
+    /// Start a new chain with a full image after this many deltas, so that restoring
+    /// decodes no more than that and a damaged image breaks only its own chain
+    #[arg(long, value_name = "N", default_value_t = 7)]
+    pub full_every: usize,
+    /// raw, zlib[-0..9] or zstd[-1..22] (default zlib-9)
+    #[arg(long, env = "PICTURER_CODEC", value_name = "C")]
+    pub codec: Option<Codec>,
+    #[command(flatten)]
+    pub tree: Tree,
+}
+
+#[derive(Args)]
+pub struct AuditArgs {
+    pub dir: PathBuf,
+    /// Where to write the report (default: standard output)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// Check up to N images at once (default: one per core)
+    #[arg(long, short = 'j', value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: Option<u16>,
+    /// The zstd dictionary the images were compressed with
+    #[arg(long, env = "PICTURER_DICT", value_name = "F")]
+    pub dict: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct AnalyzeArgs {
+    pub image: PathBuf,
+    /// Where to write the map (default: the image's name with .analysis.png)
+    #[arg(short, long)]
+    pub output: Option<PathBuf>,
+    /// The zstd dictionary the image was compressed with
+    #[arg(long, env = "PICTURER_DICT", value_name = "F")]
+    pub dict: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct MigrateArgs {
+    pub old: PathBuf,
+    pub new: PathBuf,
+    /// raw, zlib[-0..9] or zstd[-1..22] (default zlib-9)
+    #[arg(long, env = "PICTURER_CODEC", value_name = "C")]
+    pub codec: Option<Codec>,
+    /// Write large flat blocks with heavy error correction, as encode --robust does
+    #[arg(long)]
+    pub robust: bool,
+    /// The zstd dictionary the old image was compressed with
+    #[arg(long, env = "PICTURER_DICT", value_name = "F")]
+    pub dict: Option<PathBuf>,
+}
+
+#[derive(Args)]
+pub struct CapacityArgs {
+    #[command(flatten)]
+    pub hiding: Hiding,
+    /// Count the header a zstd dictionary adds
+    #[arg(long, env = "PICTURER_DICT", value_name = "F")]
+    pub dict: Option<PathBuf>,
+}
+
+#[derive(Args, Default)]
+#[allow(clippy::struct_excessive_bools)]
+pub struct DecodeArgs {
+    /// The image, then where to write the payload (default: the image with the
+    /// extension of the payload's type, or .bin). With --clipboard, only the output
+    #[arg(value_name = "PATH", num_args = 0..=2)]
+    pub paths: Vec<PathBuf>,
+    /// Decode up to N volumes of a set at once (default: one per core); each holds its
+    /// pixels and payload in memory until written
+    #[arg(long, short = 'j', value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: Option<u16>,
+    /// Decode only the payload from this byte on
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    pub offset: u64,
+    /// Decode only this many bytes of the payload
+    #[arg(long, value_name = "N")]
+    pub length: Option<u64>,
+    /// Widen --offset and --length to the whole records they touch, in an image encoded
+    /// with --split-on
+    #[arg(long)]
+    pub whole_records: bool,
+    /// Decode just the entry E of an image made with --add
+    #[arg(long, value_name = "E")]
+    pub name: Option<String>,
+    /// Decrypt the payload with gpg after decoding it, for images made with
+    /// --gpg-recipient
+    #[arg(long, conflicts_with_all = ["name", "offset", "length"])]
+    pub gpg_decrypt: bool,
+    /// Decrypt the payload with age after decoding it, with the identities of this
+    /// identity file or this SSH private key, for images made with --age-recipient or
+    /// --recipient-ssh; may be given more than once
+    #[arg(
+        long = "age-identity",
+        value_name = "F",
+        conflicts_with_all = ["name", "offset", "length", "gpg_decrypt"]
+    )]
+    pub age_identities: Vec<PathBuf>,
+    /// Name the output .bin when the image stores no content type, rather than after
+    /// what its bytes look like
+    #[arg(long)]
+    pub no_sniff: bool,
+    /// Extract a payload holding a directory or named entries into D itself rather
+    /// than a directory named after the image, and write a single file into D; it is
+    /// created if needed
+    #[arg(long, value_name = "D", conflicts_with_all = ["name", "offset", "length"])]
+    pub output_dir: Option<PathBuf>,
+    /// Recreate the symbolic links of an extracted directory, which are otherwise
+    /// skipped as they may point anywhere
+    #[arg(long)]
+    pub allow_symlinks: bool,
+    /// Decode the payload again for as long as it is itself a picturer image, keeping
+    /// only the innermost payload, and report how deeply it was nested
+    #[arg(long, conflicts_with_all = ["name", "offset", "length"])]
+    pub recursive: bool,
+    /// Unpack a decoded tar, .tar.gz or zip file into a directory named after it, or
+    /// into --output-dir, and remove it
+    #[arg(
+        long,
+        env = "PICTURER_AUTO_EXTRACT",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with_all = ["name", "offset", "length", "clipboard"]
+    )]
+    pub auto_extract: bool,
+    /// Refuse to decode if IMAGE.sig, or that of any volume of its set, is not a
+    /// signature by this Ed25519 public key: 64 hex digits, a file holding them, or the
+    /// name of a key of picturer key; may be given more than once, for any of several
+    /// keys
+    #[arg(long, value_name = "K", conflicts_with = "clipboard")]
+    pub verify_key: Vec<String>,
+    /// Also refuse images that are not signed at all, trusting the --verify-key keys and
+    /// the trusted-keys of the config file
+    #[arg(
+        long,
+        env = "PICTURER_REQUIRE_SIGNATURE",
+        value_parser = BoolishValueParser::new(),
+        conflicts_with = "clipboard"
+    )]
+    pub require_signature: bool,
+    #[command(flatten)]
+    pub common: Common,
+    #[command(flatten)]
+    pub remote: Remote,
+    #[command(flatten)]
+    pub recovery: Recovery,
+    #[command(flatten)]
+    pub output: Output,
+}
+
+#[derive(Args)]
+pub struct VerifyArgs {
+    #[arg(value_name = "IMAGE", num_args = 0..=1)]
+    pub paths: Vec<PathBuf>,
+    /// The file or directory the payload should match
+    #[arg(long, value_name = "ORIGINAL", required_unless_present = "check")]
+    pub against: Option<PathBuf>,
+    /// Check the image, and every volume of its set, against the IMAGE.sha256 file
+    /// --write-checksum wrote next to it
+    #[arg(long)]
+    pub check: bool,
+    /// How a directory given as ORIGINAL was encoded
+    #[command(flatten)]
+    pub tree: Tree,
+    /// Compare a directory as encoded with --deterministic
+    #[arg(long)]
+    pub deterministic: bool,
+    /// Compare a directory as encoded with this --codec, which picks how its files are
+    /// compressed (default zlib-9)
+    #[arg(long, env = "PICTURER_CODEC", value_name = "C")]
+    pub codec: Option<Codec>,
+    #[command(flatten)]
+    pub common: Common,
+    #[command(flatten)]
+    pub remote: Remote,
+    #[command(flatten)]
+    pub recovery: Recovery,
+}
+
+#[derive(Args)]
+pub struct InfoArgs {
+    #[arg(value_name = "IMAGE", num_args = 0..=1)]
+    pub paths: Vec<PathBuf>,
+    /// Print the header as a JSON object
+    #[arg(long)]
+    pub json: bool,
+    #[command(flatten)]
+    pub common: Common,
+    #[command(flatten)]
+    pub remote: Remote,
+    #[command(flatten)]
+    pub recovery: Recovery,
+}
+
+#[derive(Args)]
+pub struct ListArgs {
+    #[arg(value_name = "IMAGE", num_args = 0..=1)]
+    pub paths: Vec<PathBuf>,
+    /// Decrypt the payload with gpg as far as the table, for images made with
+    /// --gpg-recipient; those made with --hide-metadata as well are decrypted anyway
+    #[arg(long)]
+    pub gpg_decrypt: bool,
+    /// Decrypt the payload with age as far as the table, with the identities of this
+    /// identity file or SSH private key; may be given more than once
+    #[arg(
+        long = "age-identity",
+        value_name = "F",
+        conflicts_with = "gpg_decrypt"
+    )]
+    pub age_identities: Vec<PathBuf>,
+    #[command(flatten)]
+    pub common: Common,
+    #[command(flatten)]
+    pub remote: Remote,
+    #[command(flatten)]
+    pub recovery: Recovery,
+}
+
+#[derive(Args)]
+pub struct HashArgs {
+    #[arg(value_name = "IMAGE", num_args = 0..=1)]
+    pub paths: Vec<PathBuf>,
+    #[arg(long, env = "PICTURER_ALGO", value_enum, default_value_t = hash::Algorithm::Sha256)]
+    pub algo: hash::Algorithm,
+    #[command(flatten)]
+    pub common: Common,
+    #[command(flatten)]
+    pub remote: Remote,
+    #[command(flatten)]
+    pub recovery: Recovery,
+}
+
+#[derive(Args)]
+pub struct ServeArgs {
+    #[arg(
+        long,
+        env = "PICTURER_LISTEN",
+        value_name = "ADDR",
+        default_value = "127.0.0.1:8080"
+    )]
+    pub listen: String,
+    /// Largest request body accepted, in bytes
+    #[arg(long, env = "PICTURER_MAX_SIZE", value_name = "N", default_value_t = serve::DEFAULT_LIMIT)]
+    pub max_size: u64,
+    /// Largest payload /decode answers with, in bytes (default 256 MiB), so that a small
+    /// body made to expand without end fails early
+    #[arg(long, env = "PICTURER_MAX_OUTPUT_SIZE", value_name = "N")]
+    pub max_output_size: Option<u64>,
+    /// Handle up to N requests at once (default: one per core); each holds its body and
+    /// what it decodes to in memory, and the rest wait their turn
+    #[arg(long, short = 'j', value_name = "N", value_parser = clap::value_parser!(u16).range(1..))]
+    pub jobs: Option<u16>,
+    /// Require requests to send Authorization: Bearer T; may be given more than once
+    #[arg(long = "token", env = "PICTURER_TOKEN", value_name = "T")]
+    pub tokens: Vec<String>,
+}
+
+fn entry(value: &str) -> Result<(String, PathBuf), String> {
+    match value.split_once('=') {
+        Some((name, path)) => Ok((name.to_owned(), PathBuf::from(path))),
+        None => Err("expected name=path".to_owned()),
+    }
+}
+
+fn meta(value: &str) -> Result<(String, String), String> {
+    match value.split_once('=') {
+        Some((key, value)) if !key.is_empty() => Ok((key.to_owned(), value.to_owned())),
+        _ => Err("expected key=value".to_owned()),
+    }
+}
+
+/// Bytes that end a record, named so that clap takes them as one value rather than
+/// a list of numbers.
+pub type Delimiter = Vec<u8>;
+
+/// Bytes given as `N`, or with a suffix: `K`, `M` and `G` or `KiB`, `MiB` and `GiB` for
+/// powers of 1024, `KB`, `MB` and `GB` for powers of 1000.
+fn parse_size(value: &str) -> Result<u64, String> {
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (digits, unit) = value.split_at(split);
+    let unit: u64 = match unit.trim().to_ascii_lowercase().as_str() {
+        "" | "b" => 1,
+        "k" | "kib" => 1 << 10,
+        "m" | "mib" => 1 << 20,
+        "g" | "gib" => 1 << 30,
+        "kb" => 1_000,
+        "mb" => 1_000_000,
+        "gb" => 1_000_000_000,
+        _ => return Err(format!("{value} is not a size such as 8MB or 25MiB")),
+    };
+    digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|size| size.checked_mul(unit))
+        .filter(|&size| size > 0)
+        .ok_or_else(|| format!("{value} is not a size such as 8MB or 25MiB"))
+}
+
+/// What `--pad-to` rounds the bytes of each image up to.
+#[derive(Clone, Copy, Debug)]
+pub enum PadTo {
+    Multiple(u64),
+    PowerOfTwo,
+}
+
+impl PadTo {
+    /// Bytes an image of `len` bytes is padded to, at most `max`.
+    pub fn bucket(self, len: u64, max: u64) -> u64 {
+        let padded = match self {
+            PadTo::Multiple(size) => len.div_ceil(size).saturating_mul(size),
+            PadTo::PowerOfTwo => len.checked_next_power_of_two().unwrap_or(u64::MAX),
+        };
+        padded.min(max).max(len)
+    }
+}
+
+/// `pow2` or `power-of-two`, or a size as [`parse_size`] reads it.
+fn parse_pad(value: &str) -> Result<PadTo, String> {
+    match value.to_ascii_lowercase().as_str() {
+        "pow2" | "power-of-two" => Ok(PadTo::PowerOfTwo),
+        _ => parse_size(value).map(PadTo::Multiple),
+    }
+}
+
+/// The bytes given with `--split-on`, with their escapes replaced.
+fn parse_delimiter(value: &str) -> Result<Delimiter, String> {
+    let mut bytes = Vec::new();
+    let mut rest = value.as_bytes();
+    while let Some((&byte, more)) = rest.split_first() {
+        rest = more;
+        if byte != b'\\' {
+            bytes.push(byte);
+            continue;
+        }
+        let Some((&escape, more)) = rest.split_first() else {
+            return Err("ends with a lone \\".to_owned());
+        };
+        rest = more;
+        bytes.push(match escape {
+            b'n' => b'\n',
+            b'r' => b'\r',
+            b't' => b'\t',
+            b'0' => 0,
+            b'\\' => b'\\',
+            b'x' => {
+                let hex = rest
+                    .get(..2)
+                    .and_then(|hex| u8::from_str_radix(std::str::from_utf8(hex).ok()?, 16).ok())
+                    .ok_or("\\x takes two hex digits")?;
+                rest = &rest[2..];
+                hex
+            }
+            _ => return Err(format!("unknown escape \\{}", char::from(escape))),
+        });
+    }
+    if bytes.is_empty() {
+        return Err("the delimiter is empty".to_owned());
+    }
+    Ok(bytes)
+}
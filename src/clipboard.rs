@@ -0,0 +1,109 @@
+//! The system clipboard, standing in for an input or output file.
+//! Encoding reads text and writes an image; decoding reads an image and writes text.
+
+#[cfg(feature = "clipboard")]
+mod system {
+    use anyhow::Context;
+    use std::borrow::Cow;
+
+    pub fn read_text() -> anyhow::Result<String> {
+        arboard::Clipboard::new()?
+            .get_text()
+            .context("the clipboard holds no text")
+    }
+
+    /// RGBA pixels of the image on the clipboard.
+    pub fn read_image() -> anyhow::Result<image::RgbaImage> {
+        let image = arboard::Clipboard::new()?
+            .get_image()
+            .context("the clipboard holds no image")?;
+        image::RgbaImage::from_raw(
+            u32::try_from(image.width)?,
+            u32::try_from(image.height)?,
+            image.bytes.into_owned(),
+        )
+        .context("clipboard image has an unexpected size")
+    }
+
+    pub fn write_text(text: String) -> anyhow::Result<()> {
+        let mut clipboard = arboard::Clipboard::new()?;
+        Ok(wait(clipboard.set()).text(text)?)
+    }
+
+    pub fn write_image(image: &image::RgbaImage) -> anyhow::Result<()> {
+        let mut clipboard = arboard::Clipboard::new()?;
+        Ok(wait(clipboard.set()).image(arboard::ImageData {
+            width: image.width() as usize,
+            height: image.height() as usize,
+            bytes: Cow::Borrowed(image.as_raw()),
+        })?)
+    }
+
+    /// X11 and Wayland clipboards are served by the program that set them, so on Linux
+    /// picturer stays running until something else takes the clipboard over.
+    #[cfg(target_os = "linux")]
+    fn wait(set: arboard::Set<'_>) -> arboard::Set<'_> {
+        use arboard::SetExtLinux;
+        eprintln!("serving the clipboard until it is replaced");
+        set.wait()
+    }
+
+    #[cfg(not(target_os = "linux"))]
+    fn wait(set: arboard::Set<'_>) -> arboard::Set<'_> {
+        set
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+mod system {
+    const MISSING: &str = "picturer was built without the clipboard feature";
+
+    pub fn read_text() -> anyhow::Result<String> {
+        anyhow::bail!(MISSING)
+    }
+
+    pub fn read_image() -> anyhow::Result<image::RgbaImage> {
+        anyhow::bail!(MISSING)
+    }
+
+    pub fn write_text(_text: String) -> anyhow::Result<()> {
+        anyhow::bail!(MISSING)
+    }
+
+    pub fn write_image(_image: &image::RgbaImage) -> anyhow::Result<()> {
+        anyhow::bail!(MISSING)
+    }
+}
+
+use crate::cli::PngLevel;
+use crate::fetch::TempFile;
+use anyhow::{ensure, Context};
+use std::path::Path;
+
+/// Saves the clipboard to a temporary file that can be encoded (`text`) or decoded.
+pub fn read(text: bool) -> anyhow::Result<TempFile> {
+    if text {
+        let file = TempFile::new("clipboard.txt")?;
+        std::fs::write(&file.path, system::read_text()?)?;
+        Ok(file)
+    } else {
+        let file = TempFile::new("clipboard.png")?;
+        crate::write_png(&system::read_image()?, &file.path, PngLevel::Default)?;
+        Ok(file)
+    }
+}
+
+/// Puts a written image (`image`) or decoded payload on the clipboard.
+pub fn write(path: &Path, image: bool) -> anyhow::Result<()> {
+    ensure!(
+        path.is_file(),
+        "only a single image or file can go on the clipboard"
+    );
+    if image {
+        system::write_image(&image::open(path)?.into_rgba8())
+    } else {
+        let text = String::from_utf8(std::fs::read(path)?)
+            .context("decoded payload is not text; write it to a file instead")?;
+        system::write_text(text)
+    }
+}
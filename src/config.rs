@@ -0,0 +1,101 @@
+//! Defaults read from `~/.config/picturer/config.toml`. Flags on the command line win
+//! over anything set here.
+use crate::format::Codec;
+use anyhow::Context;
+use serde::Deserialize;
+use std::path::PathBuf;
+
+/// The file as written, before the codec and level are combined.
+#[derive(Default, Deserialize)]
+#[serde(deny_unknown_fields, rename_all = "kebab-case")]
+struct File {
+    codec: Option<String>,
+    level: Option<i32>,
+    dict: Option<PathBuf>,
+    out_dir: Option<PathBuf>,
+    max_download: Option<u64>,
+    max_output_size: Option<u64>,
+    limit_rate: Option<String>,
+    manifest: Option<bool>,
+    trusted_keys: Option<Vec<String>>,
+}
+
+#[derive(Default)]
+pub struct Config {
+    /// Used, together with `dict`, only when neither `--codec` nor `--dict` is given.
+    pub codec: Option<Codec>,
+    pub dict: Option<PathBuf>,
+    pub out_dir: Option<PathBuf>,
+    pub max_download: Option<u64>,
+    pub max_output_size: Option<u64>,
+    /// Bytes a second downloads are held to.
+    pub limit_rate: Option<u64>,
+    pub manifest: bool,
+    /// Ed25519 public keys, as hex or files, whose signatures `-d` accepts alongside any
+    /// `--verify-key`.
+    pub trusted_keys: Vec<String>,
+}
+
+impl Config {
+    /// Reads the config file, or returns the built-in defaults if there is none.
+    pub fn load() -> anyhow::Result<Self> {
+        let Some(path) = path() else {
+            return Ok(Config::default());
+        };
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(Config::default())
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("cannot read {}", path.display()))
+            }
+        };
+        let file: File =
+            toml::from_str(&text).with_context(|| format!("invalid {}", path.display()))?;
+        let codec = match (file.codec, file.level) {
+            (Some(codec), Some(level)) => Some(format!("{codec}-{level}")),
+            (Some(codec), None) => Some(codec),
+            (None, Some(level)) => Some(format!("zlib-{level}")),
+            (None, None) => None,
+        };
+        let codec = codec
+            .map(|codec| codec.parse())
+            .transpose()
+            .with_context(|| format!("invalid codec in {}", path.display()))?;
+        let limit_rate = file
+            .limit_rate
+            .map(|rate| crate::fetch::parse_rate(&rate))
+            .transpose()
+            .map_err(|error| {
+                anyhow::anyhow!("invalid limit-rate in {}: {error}", path.display())
+            })?;
+        Ok(Config {
+            codec,
+            dict: file.dict,
+            out_dir: file.out_dir,
+            max_download: file.max_download,
+            max_output_size: file.max_output_size,
+            limit_rate,
+            manifest: file.manifest.unwrap_or(false),
+            trusted_keys: file.trusted_keys.unwrap_or_default(),
+        })
+    }
+}
+
+fn path() -> Option<PathBuf> {
+    Some(dir()?.join("config.toml"))
+}
+
+/// `$XDG_CONFIG_HOME/picturer`, falling back to `~/.config`, where the config file and
+/// keys are kept.
+pub fn dir() -> Option<PathBuf> {
+    let dir = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| {
+            let home = std::env::var_os("HOME").or_else(|| std::env::var_os("USERPROFILE"))?;
+            Some(PathBuf::from(home).join(".config"))
+        })?;
+    Some(dir.join("picturer"))
+}
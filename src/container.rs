@@ -0,0 +1,911 @@
+//! The container format itself: its headers, read and written, and a decoder for
+//! containers of raw and zlib blocks. It needs only `core` and `alloc`, so that with
+//! the std feature off the library builds for targets without an operating system and
+//! decodes images whose pixels the caller reads out itself. Compressing, decoding in
+//! parallel, zstd, dictionaries and pipelines are in `format`, with std.
+use crate::diagnostic::{self, Code};
+use alloc::borrow::ToOwned;
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use anyhow::{bail, ensure};
+use core::fmt::{self, Write as _};
+use miniz_oxide::inflate::{self, TINFLStatus};
+
+pub(crate) const MAGIC: &[u8; 4] = b"PICT";
+/// Version 2 added tagged header fields between the block size and the block count.
+const VERSION: u8 = 2;
+/// Version 3 marks containers whose blocks pass through [`Pipeline`] stages, which
+/// older readers would hand back untransformed.
+const PIPELINE_VERSION: u8 = 3;
+/// Version 4 marks containers with blocks left uncompressed, which older readers would
+/// try to decompress.
+const RAW_BLOCKS_VERSION: u8 = 4;
+/// Largest block size a container may give: no image holds more, so none is written
+/// larger, and a header that says otherwise would only let a block inflate without end.
+pub(crate) const MAX_BLOCK_SIZE: u64 = crate::MAX_BYTES as u64;
+
+/// How blocks are compressed. Only the codec is stored in the header, not the level.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Codec {
+    Raw,
+    Zlib(u32),
+    Zstd(i32),
+}
+
+impl Codec {
+    pub const DEFAULT: Codec = Codec::Zlib(9);
+    pub const ZSTD: Codec = Codec::Zstd(19);
+
+    fn id(self) -> u8 {
+        match self {
+            Codec::Raw => 0,
+            Codec::Zlib(_) => 1,
+            Codec::Zstd(_) => 2,
+        }
+    }
+
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) fn name(self) -> &'static str {
+        match self {
+            Codec::Raw => "raw",
+            Codec::Zlib(_) => "zlib",
+            Codec::Zstd(_) => "zstd",
+        }
+    }
+
+    fn from_id(id: u8) -> anyhow::Result<Self> {
+        match id {
+            0 => Ok(Codec::Raw),
+            1 => Ok(Codec::DEFAULT),
+            2 => Ok(Codec::ZSTD),
+            _ => bail!("unknown codec {id}"),
+        }
+    }
+}
+
+impl fmt::Display for Codec {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Codec::Raw => write!(f, "raw"),
+            Codec::Zlib(level) => write!(f, "zlib-{level}"),
+            Codec::Zstd(level) => write!(f, "zstd-{level}"),
+        }
+    }
+}
+
+/// Parses `raw`, `zlib`, `zstd` or a codec with a level such as `zlib-6` or `zstd-3`.
+impl core::str::FromStr for Codec {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Self> {
+        let (name, level) = match s.split_once('-') {
+            Some((name, level)) => (name, Some(level)),
+            None => (s, None),
+        };
+        Ok(match (name, level) {
+            ("raw", None) => Codec::Raw,
+            ("zlib", None) => Codec::DEFAULT,
+            ("zlib", Some(level)) => match level.parse()? {
+                level @ 0..=9 => Codec::Zlib(level),
+                level => bail!("zlib level {level} is not in 0..=9"),
+            },
+            ("zstd", None) => Codec::ZSTD,
+            ("zstd", Some(level)) => match level.parse()? {
+                level @ 1..=22 => Codec::Zstd(level),
+                level => bail!("zstd level {level} is not in 1..=22"),
+            },
+            _ => bail!("unknown codec {s}"),
+        })
+    }
+}
+
+/// The error for a header or block that ends before its length says it should.
+pub(crate) fn truncated() -> anyhow::Error {
+    diagnostic::error(Code::Truncated, "input file is truncated")
+}
+
+/// The error for a block that does not decompress, as `error` says.
+pub(crate) fn damaged(error: &impl fmt::Display) -> anyhow::Error {
+    diagnostic::error(
+        Code::Damaged,
+        format!("a block does not decompress: {error}"),
+    )
+}
+
+/// Fails with an error naming `limit` if `len` payload bytes are more than it.
+pub(crate) fn check_limit(len: u64, limit: u64) -> anyhow::Result<()> {
+    if len > limit {
+        return Err(over_limit(limit));
+    }
+    Ok(())
+}
+
+fn over_limit(limit: u64) -> anyhow::Error {
+    diagnostic::error(
+        Code::OutputLimit,
+        format!("image decodes to more than {limit} bytes"),
+    )
+}
+
+/// Packs `bytes` into a container of uncompressed blocks of `block_size` bytes, as
+/// `format::pack` does with [`Codec::Raw`], for encoders without std.
+pub fn pack_raw(bytes: &[u8], block_size: usize) -> anyhow::Result<Vec<u8>> {
+    ensure!(block_size > 0, "block size is zero");
+    let fields = Fields {
+        len: Some(bytes.len() as u64),
+        ..Fields::default()
+    };
+    let blocks = bytes
+        .chunks(block_size)
+        .map(<[u8]>::to_vec)
+        .collect::<Vec<_>>();
+    let mut buf = Vec::new();
+    write_container(&mut buf, Codec::Raw, block_size as u64, &fields, &blocks)?;
+    Ok(buf)
+}
+
+/// Decodes the payload of a container, one block after another, or of an image from
+/// before the block container. Payloads of more than `limit` bytes are refused, as are
+/// blocks compressed with zstd and containers whose blocks passed through a pipeline,
+/// which only `format::unpack` decodes.
+pub fn unpack(bytes: &[u8], limit: u64) -> anyhow::Result<Vec<u8>> {
+    if !bytes.starts_with(MAGIC) {
+        return unpack_legacy(bytes, limit);
+    }
+    let index = Index::parse(bytes)?;
+    index.check_stored(bytes.len())?;
+    ensure!(
+        index.fields.pipeline.is_none(),
+        "image has pipeline stages, which need the std feature to undo"
+    );
+    let total = index.fields.len;
+    check_limit(total.unwrap_or(0), limit)?;
+    let block_size = usize::try_from(index.block_size).unwrap_or(usize::MAX);
+    let mut payload = Vec::new();
+    for (i, (&offset, &len)) in index.offsets.iter().zip(&index.lengths).enumerate() {
+        let block = bytes.get(offset..offset + len).ok_or_else(truncated)?;
+        // No block inflates past what is left of the limit either, so the limit holds
+        // even without a stored length.
+        let room =
+            usize::try_from(limit.saturating_sub(payload.len() as u64)).unwrap_or(usize::MAX);
+        let block = match index.codec {
+            _ if index.is_raw(i) => Some(block.to_vec()),
+            Codec::Raw => Some(block.to_vec()),
+            Codec::Zlib(_) => inflate(block, block_size.min(room))?,
+            Codec::Zstd(_) => {
+                bail!("image is compressed with zstd, which needs the std and zstd features")
+            }
+        };
+        if block.is_none() && room < block_size {
+            return Err(over_limit(limit));
+        }
+        let Some(block) = block.filter(|block| block.len() <= block_size) else {
+            bail!(
+                "a block decodes to more than the block size of {} bytes",
+                index.block_size
+            )
+        };
+        payload.extend(block);
+        check_limit(payload.len() as u64, limit)?;
+    }
+    if let Some(total) = total {
+        ensure!(
+            payload.len() as u64 == total,
+            "image decodes to {} bytes, but its header says {total}",
+            payload.len()
+        );
+    }
+    Ok(payload)
+}
+
+/// Decodes an image from before the block container, a compression flag byte followed
+/// by a `u64` length.
+pub(crate) fn unpack_legacy(bytes: &[u8], limit: u64) -> anyhow::Result<Vec<u8>> {
+    let Some((&flag, rest)) = bytes.split_first() else {
+        bail!("input file is empty")
+    };
+    let Some((length, data)) = rest.split_first_chunk() else {
+        bail!("input file is invalid")
+    };
+    let length = usize::try_from(u64::from_le_bytes(*length))?;
+    let Some(buf) = data.get(..length) else {
+        return Err(truncated());
+    };
+    if flag == 0 {
+        return Ok(buf.to_owned());
+    }
+    let max = usize::try_from(limit).unwrap_or(usize::MAX);
+    inflate(buf, max)?.ok_or_else(|| over_limit(limit))
+}
+
+/// Decompresses the zlib stream `bytes`, or gives `None` if it holds more than `limit`
+/// bytes.
+fn inflate(bytes: &[u8], limit: usize) -> anyhow::Result<Option<Vec<u8>>> {
+    match inflate::decompress_to_vec_zlib_with_limit(bytes, limit) {
+        Ok(out) => Ok(Some(out)),
+        Err(error) if error.status == TINFLStatus::HasMoreOutput => Ok(None),
+        Err(error) => Err(damaged(&error)),
+    }
+}
+
+pub(crate) fn write_container(
+    buf: &mut Vec<u8>,
+    codec: Codec,
+    block_size: u64,
+    fields: &Fields,
+    blocks: &[Vec<u8>],
+) -> anyhow::Result<()> {
+    ensure!(
+        block_size <= MAX_BLOCK_SIZE,
+        "blocks of {block_size} bytes are more than the largest, {MAX_BLOCK_SIZE}; a record \
+         may be no longer than that"
+    );
+    buf.extend(MAGIC);
+    buf.push(if fields.raw_blocks.contains(&true) {
+        RAW_BLOCKS_VERSION
+    } else if fields.pipeline.is_some() {
+        PIPELINE_VERSION
+    } else {
+        VERSION
+    });
+    buf.push(codec.id());
+    buf.extend(block_size.to_le_bytes());
+    let fields = fields.serialize();
+    buf.extend(u32::try_from(fields.len())?.to_le_bytes());
+    buf.extend(fields);
+    buf.extend(u32::try_from(blocks.len())?.to_le_bytes());
+    for block in blocks {
+        buf.extend((block.len() as u64).to_le_bytes());
+    }
+    for block in blocks {
+        buf.extend(block);
+    }
+    Ok(())
+}
+
+/// Prefix of one image in a multi-volume set, followed by a regular container
+/// holding `total` bytes of the payload starting at `offset`.
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub struct Volume {
+    /// Zero-based position in the set.
+    pub index: u32,
+    pub count: u32,
+    pub offset: u64,
+    /// Length of the whole payload, not of this volume.
+    pub total: u64,
+}
+
+impl Volume {
+    const MAGIC: &[u8; 4] = b"PICV";
+    pub const HEADER_LEN: usize = 4 + 4 + 4 + 8 + 8;
+
+    #[must_use]
+    pub fn header(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        buf.extend(Self::MAGIC);
+        buf.extend(self.index.to_le_bytes());
+        buf.extend(self.count.to_le_bytes());
+        buf.extend(self.offset.to_le_bytes());
+        buf.extend(self.total.to_le_bytes());
+        buf
+    }
+
+    /// Splits a volume header off `bytes`, returning `None` for single images.
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Option<(Volume, &[u8])>> {
+        let Some(rest) = bytes.strip_prefix(Self::MAGIC) else {
+            return Ok(None);
+        };
+        let mut reader = Reader(rest);
+        let volume = Volume {
+            index: reader.u32()?,
+            count: reader.u32()?,
+            offset: reader.u64()?,
+            total: reader.u64()?,
+        };
+        ensure!(
+            volume.index < volume.count,
+            "volume index {} out of range for a set of {}",
+            volume.index,
+            volume.count
+        );
+        Ok(Some((volume, reader.0)))
+    }
+}
+
+/// Prefix of an image whose payload is a patch against the payload of another image,
+/// followed by a regular container holding the patch.
+pub struct Delta {
+    /// Where the base image is, relative to this image's directory unless absolute.
+    pub base: String,
+    /// SHA-256 of the base payload the patch applies to.
+    pub base_hash: [u8; 32],
+}
+
+impl Delta {
+    pub const MAGIC: &[u8; 4] = b"PICD";
+
+    #[must_use]
+    pub fn is_delta(bytes: &[u8]) -> bool {
+        bytes.starts_with(Self::MAGIC)
+    }
+
+    pub fn header(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        buf.extend(Self::MAGIC);
+        buf.extend(self.base_hash);
+        buf.extend(u32::try_from(self.base.len())?.to_le_bytes());
+        buf.extend(self.base.as_bytes());
+        Ok(buf)
+    }
+
+    /// Splits a delta header off `bytes`, returning `None` for images holding a full payload.
+    pub fn parse(bytes: &[u8]) -> anyhow::Result<Option<(Delta, &[u8])>> {
+        let Some(rest) = bytes.strip_prefix(Self::MAGIC) else {
+            return Ok(None);
+        };
+        let mut reader = Reader(rest);
+        let base_hash = reader.array()?;
+        let len = usize::try_from(reader.u32()?)?;
+        let base = String::from_utf8(reader.take(len)?.to_vec())?;
+        Ok(Some((Delta { base, base_hash }, reader.0)))
+    }
+}
+
+/// Whether `bytes` start with the header of a container, a volume or a delta.
+#[must_use]
+pub fn is_container(bytes: &[u8]) -> bool {
+    bytes.starts_with(MAGIC) || bytes.starts_with(Volume::MAGIC) || Delta::is_delta(bytes)
+}
+
+/// Most payload bytes that pack into a container of at most `len` bytes when stored raw
+/// in blocks of `block_size`, with its length and the time it was created in its
+/// header, and a `dict` field if one is set.
+#[must_use]
+pub fn raw_capacity(len: usize, dict: bool, block_size: usize) -> usize {
+    let fields = Fields {
+        dict_id: dict.then_some(0),
+        created: Some(0),
+        len: Some(0),
+        ..Fields::default()
+    };
+    let header = MAGIC.len() + 1 + 1 + 8 + 4 + fields.serialize().len() + 4;
+    let room = len.saturating_sub(header);
+    // Every block adds its stored length, a u64, to the index.
+    let full = room / (block_size + 8);
+    let rest = room % (block_size + 8);
+    full * block_size + rest.saturating_sub(8)
+}
+
+/// Length of the container at the start of `bytes`, with any volume or delta header
+/// before it, as its headers give it; `None` if they cannot be read.
+#[must_use]
+pub fn container_len(bytes: &[u8]) -> Option<usize> {
+    let container = strip_prefix(bytes);
+    let len = Index::parse(container).ok()?.len;
+    (bytes.len() - container.len()).checked_add(len)
+}
+
+/// MIME type of the payload of the container at the start of `bytes`, with any volume
+/// or delta header before it, if the encoder stored one.
+#[must_use]
+pub fn content_type(bytes: &[u8]) -> Option<String> {
+    Index::parse(strip_prefix(bytes)).ok()?.fields.content_type
+}
+
+/// [`Fields::metadata`] of the container at the start of `bytes`, with any volume or
+/// delta header before it.
+pub fn metadata(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    Ok(Index::parse(strip_prefix(bytes))?.fields.metadata())
+}
+
+/// ID of the payload of the container at the start of `bytes`, with any volume or delta
+/// header before it, if the encoder stored one.
+#[must_use]
+pub fn payload_id(bytes: &[u8]) -> Option<[u8; 16]> {
+    Index::parse(strip_prefix(bytes)).ok()?.fields.id
+}
+
+/// `id` as printed, in the groups of a UUID.
+#[must_use]
+pub fn format_id(id: &[u8; 16]) -> String {
+    id.iter()
+        .enumerate()
+        .fold(String::new(), |mut s, (i, byte)| {
+            if matches!(i, 4 | 6 | 8 | 10) {
+                s.push('-');
+            }
+            let _ = write!(s, "{byte:02x}");
+            s
+        })
+}
+
+/// Whether the container at the start of `bytes`, with any volume or delta header
+/// before it, left its metadata to a container inside its encrypted payload.
+#[must_use]
+pub fn meta_hidden(bytes: &[u8]) -> bool {
+    Index::parse(strip_prefix(bytes)).is_ok_and(|index| index.fields.meta_hidden)
+}
+
+/// `bytes` without the volume or delta header they may start with.
+pub(crate) fn strip_prefix(bytes: &[u8]) -> &[u8] {
+    match (Volume::parse(bytes), Delta::parse(bytes)) {
+        (Ok(Some((_, rest))), _) | (_, Ok(Some((_, rest)))) => rest,
+        _ => bytes,
+    }
+}
+
+/// Length of the image data at the start of `bytes` in the format before the block
+/// container, a compression flag and a `u64` length, if they read as one.
+#[must_use]
+pub fn legacy_len(bytes: &[u8]) -> Option<usize> {
+    let Some((&[0 | 1], rest)) = bytes.split_first_chunk::<1>() else {
+        return None;
+    };
+    let (length, data) = rest.split_first_chunk::<8>()?;
+    let length = usize::try_from(u64::from_le_bytes(*length)).ok()?;
+    (length <= data.len()).then(|| 1 + 8 + length)
+}
+
+/// Optional header values, each stored as a tag byte, a `u32` length and the value.
+/// Unknown tags are skipped so older readers can still decode newer images.
+#[derive(Default)]
+pub(crate) struct Fields {
+    /// ID of the zstd dictionary every block was compressed with.
+    pub(crate) dict_id: Option<u32>,
+    /// Descriptor of the stages every block passed through.
+    pub(crate) pipeline: Option<Vec<u8>>,
+    /// MIME type of the payload, as the encoder detected it.
+    pub(crate) content_type: Option<String>,
+    /// Seconds since the Unix epoch when the payload was encoded.
+    pub(crate) created: Option<u64>,
+    /// Free text the encoder was given to describe the payload.
+    pub(crate) comment: Option<String>,
+    /// Key/value pairs the encoder was given, each stored as a `u32` length and the
+    /// key, then a `u32` length and the value.
+    pub(crate) meta: BTreeMap<String, String>,
+    /// Set, with no value, when the metadata is encrypted with the payload in a
+    /// container of its own. Any metadata here as well is a public copy, which decoders
+    /// check against the encrypted one.
+    pub(crate) meta_hidden: bool,
+    /// Which blocks are stored uncompressed, as a bitmap from the lowest bit of the first
+    /// byte; stored only if any are.
+    pub(crate) raw_blocks: Vec<bool>,
+    /// Bytes of the payload once decoded.
+    pub(crate) len: Option<u64>,
+    /// Identifies the payload, so that the image holding it can be found again.
+    pub(crate) id: Option<[u8; 16]>,
+    /// Bytes each record of the payload ends with, when its blocks were cut to end on
+    /// records rather than every block size.
+    pub(crate) records: Option<Vec<u8>>,
+    /// Where each block starts in the payload, as `u64`s; stored only when the blocks
+    /// are not all the block size.
+    pub(crate) starts: Vec<u64>,
+}
+
+impl Fields {
+    const DICT_ID: u8 = 1;
+    const PIPELINE: u8 = 2;
+    const CONTENT_TYPE: u8 = 3;
+    const CREATED: u8 = 4;
+    const COMMENT: u8 = 5;
+    const META: u8 = 6;
+    const META_HIDDEN: u8 = 7;
+    const RAW_BLOCKS: u8 = 8;
+    const LEN: u8 = 9;
+    const ID: u8 = 10;
+    const RECORDS: u8 = 11;
+    const STARTS: u8 = 12;
+
+    pub(crate) fn serialize(&self) -> Vec<u8> {
+        let mut buf = Vec::new();
+        let mut field = |tag: u8, value: &[u8]| {
+            buf.push(tag);
+            buf.extend(u32::try_from(value.len()).unwrap_or(u32::MAX).to_le_bytes());
+            buf.extend(value);
+        };
+        if let Some(id) = self.dict_id {
+            field(Self::DICT_ID, &id.to_le_bytes());
+        }
+        if let Some(pipeline) = &self.pipeline {
+            field(Self::PIPELINE, pipeline);
+        }
+        if let Some(content_type) = &self.content_type {
+            field(Self::CONTENT_TYPE, content_type.as_bytes());
+        }
+        if let Some(created) = self.created {
+            field(Self::CREATED, &created.to_le_bytes());
+        }
+        if let Some(comment) = &self.comment {
+            field(Self::COMMENT, comment.as_bytes());
+        }
+        if !self.meta.is_empty() {
+            let mut meta = Vec::new();
+            for text in self.meta.iter().flat_map(|(key, value)| [key, value]) {
+                meta.extend(u32::try_from(text.len()).unwrap_or(u32::MAX).to_le_bytes());
+                meta.extend(text.as_bytes());
+            }
+            field(Self::META, &meta);
+        }
+        if self.meta_hidden {
+            field(Self::META_HIDDEN, &[]);
+        }
+        if self.raw_blocks.contains(&true) {
+            let mut bitmap = vec![0; self.raw_blocks.len().div_ceil(8)];
+            for (i, _) in self.raw_blocks.iter().enumerate().filter(|(_, raw)| **raw) {
+                bitmap[i / 8] |= 1 << (i % 8);
+            }
+            field(Self::RAW_BLOCKS, &bitmap);
+        }
+        if let Some(len) = self.len {
+            field(Self::LEN, &len.to_le_bytes());
+        }
+        if let Some(id) = &self.id {
+            field(Self::ID, id);
+        }
+        if let Some(records) = &self.records {
+            field(Self::RECORDS, records);
+        }
+        if !self.starts.is_empty() {
+            let starts = self
+                .starts
+                .iter()
+                .flat_map(|start| start.to_le_bytes())
+                .collect::<Vec<u8>>();
+            field(Self::STARTS, &starts);
+        }
+        buf
+    }
+
+    /// The fields that describe the payload rather than how it is stored, its content
+    /// type, time of encoding, comment and meta pairs, serialized on their own: what a
+    /// seal authenticates along with the payload, and a copy of them is compared by.
+    pub(crate) fn metadata(&self) -> Vec<u8> {
+        Fields {
+            content_type: self.content_type.clone(),
+            created: self.created,
+            comment: self.comment.clone(),
+            meta: self.meta.clone(),
+            ..Fields::default()
+        }
+        .serialize()
+    }
+
+    fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut fields = Fields::default();
+        let mut reader = Reader(bytes);
+        while !reader.0.is_empty() {
+            let tag = reader.u8()?;
+            let len = usize::try_from(reader.u32()?)?;
+            let mut value = Reader(reader.take(len)?);
+            match tag {
+                Self::DICT_ID => fields.dict_id = Some(value.u32()?),
+                Self::PIPELINE => fields.pipeline = Some(value.0.to_vec()),
+                Self::CONTENT_TYPE => {
+                    fields.content_type = Some(String::from_utf8_lossy(value.0).into_owned());
+                }
+                Self::CREATED => fields.created = Some(value.u64()?),
+                Self::COMMENT => {
+                    fields.comment = Some(String::from_utf8_lossy(value.0).into_owned());
+                }
+                Self::META => {
+                    while !value.0.is_empty() {
+                        let mut text = || -> anyhow::Result<String> {
+                            let len = usize::try_from(value.u32()?)?;
+                            Ok(String::from_utf8_lossy(value.take(len)?).into_owned())
+                        };
+                        let key = text()?;
+                        fields.meta.insert(key, text()?);
+                    }
+                }
+                Self::META_HIDDEN => fields.meta_hidden = true,
+                Self::RAW_BLOCKS => {
+                    fields.raw_blocks = value
+                        .0
+                        .iter()
+                        .flat_map(|byte| (0..8).map(move |bit| byte & (1 << bit) != 0))
+                        .collect();
+                }
+                Self::LEN => fields.len = Some(value.u64()?),
+                Self::ID => fields.id = Some(value.array()?),
+                Self::RECORDS => {
+                    ensure!(!value.0.is_empty(), "record delimiter is empty");
+                    fields.records = Some(value.0.to_vec());
+                }
+                Self::STARTS => {
+                    while !value.0.is_empty() {
+                        fields.starts.push(value.u64()?);
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(fields)
+    }
+}
+
+/// Block table at the start of a container.
+pub(crate) struct Index {
+    #[cfg_attr(not(feature = "std"), allow(dead_code))]
+    pub(crate) version: u8,
+    pub(crate) codec: Codec,
+    pub(crate) block_size: u64,
+    pub(crate) fields: Fields,
+    /// Position of each block from the start of the container.
+    pub(crate) offsets: Vec<usize>,
+    pub(crate) lengths: Vec<usize>,
+    /// Length of the whole container, up to the end of its last block.
+    pub(crate) len: usize,
+}
+
+impl Index {
+    pub(crate) fn parse(bytes: &[u8]) -> anyhow::Result<Self> {
+        let Some(rest) = bytes.strip_prefix(MAGIC) else {
+            bail!("input file is invalid")
+        };
+        let mut reader = Reader(rest);
+        let version = reader.u8()?;
+        if !(1..=RAW_BLOCKS_VERSION).contains(&version) {
+            return Err(diagnostic::error(
+                Code::Version,
+                format!("unsupported format version {version}"),
+            ));
+        }
+        let codec = Codec::from_id(reader.u8()?)?;
+        let block_size = reader.u64()?;
+        ensure!(block_size > 0, "block size is zero");
+        ensure!(
+            block_size <= MAX_BLOCK_SIZE,
+            "block size of {block_size} bytes is more than the largest, {MAX_BLOCK_SIZE}"
+        );
+        let fields = if version >= 2 {
+            let len = usize::try_from(reader.u32()?)?;
+            Fields::parse(reader.take(len)?)?
+        } else {
+            Fields::default()
+        };
+        let count = reader.u32()?;
+        let lengths = (0..count)
+            .map(|_| Ok(usize::try_from(reader.u64()?)?))
+            .collect::<anyhow::Result<Vec<usize>>>()?;
+        let mut position = bytes.len() - reader.0.len();
+        let offsets = lengths
+            .iter()
+            .map(|len| {
+                let offset = position;
+                position = position.checked_add(*len)?;
+                Some(offset)
+            })
+            .collect::<Option<Vec<usize>>>()
+            .ok_or(anyhow::Error::msg("block lengths overflow"))?;
+        ensure!(
+            fields.starts.is_empty()
+                || fields.starts.len() == lengths.len()
+                    && fields.starts.is_sorted()
+                    && fields.starts[0] == 0,
+            "the header gives {} block starts for {} blocks",
+            fields.starts.len(),
+            lengths.len()
+        );
+        Ok(Index {
+            version,
+            codec,
+            block_size,
+            fields,
+            offsets,
+            lengths,
+            len: position,
+        })
+    }
+
+    /// Fails if the blocks reach past the `available` bytes the image holds, before
+    /// any is decoded.
+    pub(crate) fn check_stored(&self, available: usize) -> anyhow::Result<()> {
+        if self.len > available {
+            return Err(diagnostic::error(
+                Code::Cropped,
+                format!(
+                    "the header says the blocks take {} bytes, but the image holds only \
+                     {available}; it was likely cropped or cut short",
+                    self.len
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Whether block `i` is stored uncompressed, whatever the codec.
+    pub(crate) fn is_raw(&self, i: usize) -> bool {
+        self.fields.raw_blocks.get(i).copied().unwrap_or(false)
+    }
+}
+
+/// Cursor over little-endian header fields.
+pub struct Reader<'a>(pub &'a [u8]);
+
+impl<'a> Reader<'a> {
+    pub fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        let Some((head, tail)) = self.0.split_at_checked(len) else {
+            return Err(truncated());
+        };
+        self.0 = tail;
+        Ok(head)
+    }
+
+    pub fn array<const N: usize>(&mut self) -> anyhow::Result<[u8; N]> {
+        Ok(self.take(N)?.try_into()?)
+    }
+
+    pub fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.array::<1>()?[0])
+    }
+
+    pub fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_le_bytes(self.array()?))
+    }
+
+    pub fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_le_bytes(self.array()?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A container of `blocks` compressed with zlib, with no stored length.
+    fn zlib(block_size: u64, blocks: &[&[u8]]) -> Vec<u8> {
+        let blocks: Vec<Vec<u8>> = blocks
+            .iter()
+            .map(|block| miniz_oxide::deflate::compress_to_vec_zlib(block, 9))
+            .collect();
+        let mut buf = Vec::new();
+        write_container(
+            &mut buf,
+            Codec::DEFAULT,
+            block_size,
+            &Fields::default(),
+            &blocks,
+        )
+        .unwrap();
+        buf
+    }
+
+    #[test]
+    fn a_block_size_past_the_largest_is_refused() {
+        let mut bytes = zlib(MAX_BLOCK_SIZE, &[b"payload"]);
+        assert_eq!(unpack(&bytes, u64::MAX).unwrap(), b"payload");
+        bytes[6..14].copy_from_slice(&(MAX_BLOCK_SIZE + 1).to_le_bytes());
+        assert!(Index::parse(&bytes).is_err());
+        assert!(write_container(
+            &mut Vec::new(),
+            Codec::Raw,
+            MAX_BLOCK_SIZE + 1,
+            &Fields::default(),
+            &[]
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn a_bomb_stops_at_the_limit_without_a_stored_length() {
+        let zeros = vec![0; 8 << 20];
+        let bytes = zlib(MAX_BLOCK_SIZE, &[&zeros]);
+        assert!(bytes.len() < 64 << 10);
+        let error = unpack(&bytes, 1 << 20).unwrap_err();
+        assert!(
+            error.to_string().contains("more than 1048576 bytes"),
+            "{error}"
+        );
+        // Across blocks too, each within the limit on its own.
+        let bytes = zlib(MAX_BLOCK_SIZE, &[&zeros[..600 << 10], &zeros[..600 << 10]]);
+        assert!(unpack(&bytes, 1 << 20).is_err());
+        assert_eq!(unpack(&bytes, 2 << 20).unwrap().len(), 1200 << 10);
+    }
+
+    #[test]
+    fn containers_unpack_with_their_header_fields() {
+        let payload: Vec<u8> = (0..10_000u32).map(|i| (i * 7).to_le_bytes()[0]).collect();
+        let raw = pack_raw(&payload, 4_096).unwrap();
+        assert!(is_container(&raw));
+        assert_eq!(container_len(&raw), Some(raw.len()));
+        assert_eq!(unpack(&raw, u64::MAX).unwrap(), payload);
+        let mut padded = raw.clone();
+        padded.extend([0; 100]);
+        assert_eq!(unpack(&padded, u64::MAX).unwrap(), payload);
+
+        let fields = Fields {
+            content_type: Some("text/plain".into()),
+            comment: Some("a comment".into()),
+            meta: BTreeMap::from([("key".into(), "value".into())]),
+            len: Some(payload.len() as u64),
+            id: Some([7; 16]),
+            raw_blocks: vec![false, true],
+            ..Fields::default()
+        };
+        let blocks = [
+            miniz_oxide::deflate::compress_to_vec_zlib(&payload[..4_096], 9),
+            payload[4_096..].to_vec(),
+        ];
+        let mut zlib = Vec::new();
+        write_container(&mut zlib, Codec::DEFAULT, 8_192, &fields, &blocks).unwrap();
+        let index = Index::parse(&zlib).unwrap();
+        assert_eq!(index.version, RAW_BLOCKS_VERSION);
+        assert_eq!((index.codec, index.block_size), (Codec::DEFAULT, 8_192));
+        assert_eq!(index.fields.serialize(), fields.serialize());
+        assert_eq!(index.len, zlib.len());
+        assert_eq!(content_type(&zlib).as_deref(), Some("text/plain"));
+        assert_eq!(payload_id(&zlib), Some([7; 16]));
+        assert_eq!(unpack(&zlib, u64::MAX).unwrap(), payload);
+        assert!(unpack(&zlib, 9_999).is_err());
+    }
+
+    #[test]
+    fn a_container_cut_short_anywhere_is_refused() {
+        let bytes = zlib(1_024, &[&[1; 1_024], &[2; 100]]);
+        for len in 0..bytes.len() {
+            assert!(unpack(&bytes[..len], u64::MAX).is_err(), "{len} bytes");
+        }
+        let volume = Volume {
+            index: 0,
+            count: 2,
+            offset: 0,
+            total: 10,
+        }
+        .header();
+        for len in Volume::MAGIC.len()..volume.len() {
+            assert!(Volume::parse(&volume[..len]).is_err(), "{len} bytes");
+        }
+        assert!(unpack_legacy(&[0, 10, 0, 0, 0, 0, 0, 0, 0, 1, 2], u64::MAX).is_err());
+    }
+
+    #[test]
+    fn oversized_header_fields_are_refused() {
+        let bytes = zlib(1_024, &[b"payload"]);
+        let edited = |at: usize, value: &[u8]| {
+            let mut bytes = bytes.clone();
+            bytes[at..at + value.len()].copy_from_slice(value);
+            bytes
+        };
+        // The fields, empty here, said to run past the end of the header.
+        assert!(unpack(&edited(14, &u32::MAX.to_le_bytes()), u64::MAX).is_err());
+        // More blocks than there are lengths for.
+        assert!(unpack(&edited(18, &u32::MAX.to_le_bytes()), u64::MAX).is_err());
+        // A block longer than the image, and one that overflows the offsets.
+        let error = unpack(&edited(22, &1_000_000u64.to_le_bytes()), u64::MAX).unwrap_err();
+        assert!(error.to_string().contains("cropped"), "{error}");
+        assert!(unpack(&edited(22, &u64::MAX.to_le_bytes()), u64::MAX).is_err());
+        // A zero block size, and a version from the future.
+        assert!(unpack(&edited(6, &0u64.to_le_bytes()), u64::MAX).is_err());
+        assert!(unpack(&edited(4, &[RAW_BLOCKS_VERSION + 1]), u64::MAX).is_err());
+
+        // A field whose value runs past the fields.
+        let mut fields = vec![Fields::LEN];
+        fields.extend(100u32.to_le_bytes());
+        fields.extend(7u64.to_le_bytes());
+        assert!(Fields::parse(&fields).is_err());
+        // A stored length too short to read.
+        let mut fields = vec![Fields::LEN];
+        fields.extend(4u32.to_le_bytes());
+        fields.extend(7u32.to_le_bytes());
+        assert!(Fields::parse(&fields).is_err());
+
+        let volume = Volume {
+            index: 2,
+            count: 2,
+            offset: 0,
+            total: 10,
+        };
+        assert!(Volume::parse(&volume.header()).is_err());
+        let mut delta = Delta {
+            base: "base.png".into(),
+            base_hash: [0; 32],
+        }
+        .header()
+        .unwrap();
+        delta[36..40].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert!(Delta::parse(&delta).is_err());
+    }
+}
@@ -0,0 +1,134 @@
+//! [`Context`]: zstd contexts, with their dictionary loaded, kept from one payload to the
+//! next, so a service encoding and decoding many pays for setting them up once instead of
+//! once for every block.
+use crate::dict::Dictionary;
+#[cfg(feature = "zstd")]
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+#[cfg(feature = "zstd")]
+type Compressor = zstd::bulk::Compressor<'static>;
+#[cfg(feature = "zstd")]
+type Decompressor = zstd::zstd_safe::DCtx<'static>;
+
+/// Compression and decompression contexts, and the working memory each holds, shared
+/// between threads: a block takes one that is idle, or sets up another if there is none,
+/// and gives it back once done. Given to [`crate::Encoder::context`] and
+/// [`crate::Decoder::context`], or as [`crate::format::Packing::context`].
+pub struct Context {
+    dict: Option<Dictionary>,
+    /// Idle compressors, with the level each compresses at.
+    #[cfg(feature = "zstd")]
+    compressors: Mutex<Vec<(i32, Compressor)>>,
+    #[cfg(feature = "zstd")]
+    decompressors: Mutex<Vec<Decompressor>>,
+}
+
+/// Fails to build if a context can no longer be shared between worker threads.
+const _: fn() = || {
+    fn shared<T: Send + Sync>() {}
+    shared::<Context>();
+};
+
+impl Context {
+    /// Contexts compressing at `level` and decompressing with `dict`, if any, one of each
+    /// for every thread that compresses blocks set up already.
+    pub fn new(level: i32, dict: Option<Dictionary>) -> anyhow::Result<Self> {
+        let context = Context {
+            dict,
+            #[cfg(feature = "zstd")]
+            compressors: Mutex::new(Vec::new()),
+            #[cfg(feature = "zstd")]
+            decompressors: Mutex::new(Vec::new()),
+        };
+        #[cfg(feature = "zstd")]
+        for _ in 0..rayon::current_num_threads() {
+            let compressor = context.compressor(level)?;
+            let decompressor = context.decompressor()?;
+            lock(&context.compressors).push((level, compressor));
+            lock(&context.decompressors).push(decompressor);
+        }
+        #[cfg(not(feature = "zstd"))]
+        let _ = level;
+        Ok(context)
+    }
+
+    /// The dictionary the contexts compress and decompress with.
+    #[must_use]
+    pub fn dictionary(&self) -> Option<&Dictionary> {
+        self.dict.as_ref()
+    }
+}
+
+#[cfg(feature = "zstd")]
+impl Context {
+    fn dict_bytes(&self) -> &[u8] {
+        self.dict.as_ref().map_or(&[], |dict| &dict.bytes)
+    }
+
+    fn compressor(&self, level: i32) -> std::io::Result<Compressor> {
+        Compressor::with_dictionary(level, self.dict_bytes())
+    }
+
+    fn decompressor(&self) -> anyhow::Result<Decompressor> {
+        let mut decompressor = Decompressor::try_create().ok_or(anyhow::Error::msg(
+            "zstd cannot allocate a decompression context",
+        ))?;
+        decompressor
+            .load_dictionary(self.dict_bytes())
+            .map_err(|code| anyhow::anyhow!(zstd::zstd_safe::get_error_name(code)))?;
+        Ok(decompressor)
+    }
+
+    /// `block` compressed at `level` with an idle compressor.
+    pub(crate) fn compress(&self, block: &[u8], level: i32) -> std::io::Result<Vec<u8>> {
+        let idle = {
+            let mut compressors = lock(&self.compressors);
+            let found = compressors.iter().position(|&(at, _)| at == level);
+            found.map(|i| compressors.swap_remove(i).1)
+        };
+        let mut compressor = match idle {
+            Some(compressor) => compressor,
+            None => self.compressor(level)?,
+        };
+        let compressed = compressor.compress(block);
+        lock(&self.compressors).push((level, compressor));
+        compressed
+    }
+
+    /// `block` decompressed with an idle decompressor, reading no more than `limit` bytes
+    /// out of it.
+    pub(crate) fn decompress(&self, block: &[u8], limit: u64) -> anyhow::Result<Vec<u8>> {
+        use std::io::Read;
+        let idle = lock(&self.decompressors).pop();
+        let mut decompressor = match idle {
+            Some(decompressor) => decompressor,
+            None => self.decompressor()?,
+        };
+        // A block that failed to decode leaves its frame half read; the dictionary stays.
+        let _ = decompressor.reset(zstd::zstd_safe::ResetDirective::SessionOnly);
+        let mut out = Vec::new();
+        let read = zstd::stream::read::Decoder::with_context(block, &mut decompressor)
+            .take(limit)
+            .read_to_end(&mut out);
+        lock(&self.decompressors).push(decompressor);
+        read.map_err(|error| crate::container::damaged(&error))?;
+        Ok(out)
+    }
+}
+
+#[cfg(not(feature = "zstd"))]
+#[allow(clippy::unused_self)]
+impl Context {
+    pub(crate) fn compress(&self, _block: &[u8], _level: i32) -> std::io::Result<Vec<u8>> {
+        Err(std::io::Error::other(crate::format::NO_ZSTD))
+    }
+
+    pub(crate) fn decompress(&self, _block: &[u8], _limit: u64) -> anyhow::Result<Vec<u8>> {
+        anyhow::bail!(crate::format::NO_ZSTD)
+    }
+}
+
+#[cfg(feature = "zstd")]
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(PoisonError::into_inner)
+}
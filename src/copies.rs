@@ -0,0 +1,89 @@
+//! Images written with `--copies N`: the image and N-1 identical copies beside it,
+//! `out.png`, `out.copy2.png`, `out.copy3.png`, ... Decoding any of them reads every
+//! copy it finds and takes, for each byte, the value most copies agree on, so damage
+//! that differs between copies is voted out without error correction.
+use anyhow::Context;
+use std::path::{Path, PathBuf};
+
+/// Path of copy `copy` of `image`, counting `image` itself as the first.
+pub fn path(image: &Path, copy: u8) -> PathBuf {
+    if copy <= 1 {
+        return image.to_owned();
+    }
+    let stem = image.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = image.extension().unwrap_or_default().to_string_lossy();
+    image.with_file_name(format!("{stem}.copy{copy}.{ext}"))
+}
+
+/// Recovers the path of the first copy from the path of any.
+fn first(copy: &Path) -> PathBuf {
+    let stem = copy.file_stem().unwrap_or_default().to_string_lossy();
+    let stem = match stem.rsplit_once(".copy") {
+        Some((stem, index)) if index.parse::<u8>().is_ok_and(|index| index > 1) => stem,
+        _ => &stem,
+    };
+    let ext = copy.extension().unwrap_or_default().to_string_lossy();
+    copy.with_file_name(format!("{stem}.{ext}"))
+}
+
+/// Writes copies 2 to `copies` of `image`, returning their paths.
+pub fn write(image: &Path, copies: u8) -> anyhow::Result<Vec<PathBuf>> {
+    (2..=copies)
+        .map(|copy| {
+            let path = path(image, copy);
+            std::fs::copy(image, &path)
+                .with_context(|| format!("cannot write {}", path.display()))?;
+            Ok(path)
+        })
+        .collect()
+}
+
+/// Paths of the other copies of `image` that exist, which are none unless it was written
+/// with `--copies`.
+pub fn others(image: &Path) -> Vec<PathBuf> {
+    let first = first(image);
+    (1..=u8::MAX)
+        .map(|copy| path(&first, copy))
+        .filter(|path| path != image && path.is_file())
+        .collect()
+}
+
+/// What voting between copies found.
+#[derive(Default)]
+pub struct Vote {
+    /// Bytes on which not every copy agreed.
+    pub differing: usize,
+    /// Bytes on which no value had more copies than every other, taken from the first
+    /// copy that holds one of the values tied.
+    pub tied: usize,
+}
+
+/// The value most of `copies` hold at each byte, which must all be as long as the
+/// first; ties go to the value of the first copy that holds one of those tied.
+pub fn vote(copies: &[Vec<u8>]) -> (Vec<u8>, Vote) {
+    let Some((first, rest)) = copies.split_first() else {
+        return (Vec::new(), Vote::default());
+    };
+    let mut voted = first.clone();
+    let mut vote = Vote::default();
+    for (i, byte) in voted.iter_mut().enumerate() {
+        if rest.iter().all(|copy| copy[i] == *byte) {
+            continue;
+        }
+        vote.differing += 1;
+        let mut tally = [0usize; 256];
+        for copy in copies {
+            tally[usize::from(copy[i])] += 1;
+        }
+        let most = tally.iter().copied().max().unwrap_or(0);
+        if tally.iter().filter(|&&count| count == most).count() > 1 {
+            vote.tied += 1;
+        }
+        *byte = copies
+            .iter()
+            .map(|copy| copy[i])
+            .find(|&value| tally[usize::from(value)] == most)
+            .unwrap_or(*byte);
+    }
+    (voted, vote)
+}
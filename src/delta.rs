@@ -0,0 +1,214 @@
+use anyhow::bail;
+use std::collections::HashMap;
+
+/// Shortest run of base bytes worth referencing instead of storing inline.
+const WINDOW: usize = 32;
+const MULTIPLIER: u64 = 0x100_0000_01b3;
+
+const LITERAL: u8 = 0;
+const COPY: u8 = 1;
+
+/// Describes `target` as a sequence of literal runs and copies out of `base`.
+/// Base windows are indexed every [`WINDOW`] bytes and matched anywhere in
+/// `target` with a rolling hash, then extended in both directions.
+pub fn diff(base: &[u8], target: &[u8]) -> Vec<u8> {
+    let mut patch = Vec::new();
+    if base.len() < WINDOW || target.len() < WINDOW {
+        literal(&mut patch, target);
+        return patch;
+    }
+    let mut windows = HashMap::new();
+    for start in (0..=base.len() - WINDOW).step_by(WINDOW) {
+        windows
+            .entry(hash(&base[start..start + WINDOW]))
+            .or_insert(start);
+    }
+    let top = (1..WINDOW).fold(1u64, |top, _| top.wrapping_mul(MULTIPLIER));
+    let mut pending = 0;
+    let mut pos = 0;
+    let mut h = hash(&target[..WINDOW]);
+    while pos + WINDOW <= target.len() {
+        let found = windows
+            .get(&h)
+            .copied()
+            .filter(|&at| base[at..at + WINDOW] == target[pos..pos + WINDOW]);
+        if let Some(mut at) = found {
+            let mut start = pos;
+            while start > pending && at > 0 && base[at - 1] == target[start - 1] {
+                start -= 1;
+                at -= 1;
+            }
+            let len = base[at..]
+                .iter()
+                .zip(&target[start..])
+                .take_while(|(a, b)| a == b)
+                .count();
+            literal(&mut patch, &target[pending..start]);
+            copy(&mut patch, at, len);
+            pos = start + len;
+            pending = pos;
+            if pos + WINDOW <= target.len() {
+                h = hash(&target[pos..pos + WINDOW]);
+            }
+            continue;
+        }
+        if pos + WINDOW < target.len() {
+            h = h
+                .wrapping_sub(u64::from(target[pos]).wrapping_mul(top))
+                .wrapping_mul(MULTIPLIER)
+                .wrapping_add(u64::from(target[pos + WINDOW]));
+        }
+        pos += 1;
+    }
+    literal(&mut patch, &target[pending..]);
+    patch
+}
+
+/// Rebuilds the target that [`diff`] described from `base` and `patch`.
+pub fn apply(base: &[u8], patch: &[u8]) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    let mut rest = patch;
+    while let Some((&op, tail)) = rest.split_first() {
+        rest = tail;
+        match op {
+            LITERAL => {
+                let len = usize::try_from(varint(&mut rest)?)?;
+                let Some((bytes, tail)) = rest.split_at_checked(len) else {
+                    bail!("delta is truncated")
+                };
+                out.extend_from_slice(bytes);
+                rest = tail;
+            }
+            COPY => {
+                let at = usize::try_from(varint(&mut rest)?)?;
+                let len = usize::try_from(varint(&mut rest)?)?;
+                let bytes = at
+                    .checked_add(len)
+                    .and_then(|end| base.get(at..end))
+                    .ok_or(anyhow::Error::msg("delta copies past the end of its base"))?;
+                // Copies of the same base bytes could otherwise grow the output without end.
+                picturer::format::check_output((out.len() + len) as u64)?;
+                out.extend_from_slice(bytes);
+            }
+            _ => bail!("unknown delta operation {op}"),
+        }
+    }
+    Ok(out)
+}
+
+fn hash(window: &[u8]) -> u64 {
+    window.iter().fold(0, |h, &b| {
+        h.wrapping_mul(MULTIPLIER).wrapping_add(u64::from(b))
+    })
+}
+
+fn literal(patch: &mut Vec<u8>, bytes: &[u8]) {
+    if !bytes.is_empty() {
+        patch.push(LITERAL);
+        put_varint(patch, bytes.len() as u64);
+        patch.extend_from_slice(bytes);
+    }
+}
+
+fn copy(patch: &mut Vec<u8>, at: usize, len: usize) {
+    patch.push(COPY);
+    put_varint(patch, at as u64);
+    put_varint(patch, len as u64);
+}
+
+fn put_varint(buf: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        #[allow(clippy::cast_possible_truncation)]
+        buf.push(value as u8 | 0x80);
+        value >>= 7;
+    }
+    #[allow(clippy::cast_possible_truncation)]
+    buf.push(value as u8);
+}
+
+fn varint(bytes: &mut &[u8]) -> anyhow::Result<u64> {
+    let mut value = 0u64;
+    for shift in (0..64).step_by(7) {
+        let Some((&byte, tail)) = bytes.split_first() else {
+            bail!("delta is truncated")
+        };
+        *bytes = tail;
+        value |= u64::from(byte & 0x7f) << shift;
+        if byte < 0x80 {
+            return Ok(value);
+        }
+    }
+    bail!("delta has an oversized length")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Bytes that repeat nowhere within a few kilobytes, so that every match is one
+    /// made on purpose.
+    fn noise(len: usize, seed: u64) -> Vec<u8> {
+        let mut state = seed | 1;
+        (0..len)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                state.to_le_bytes()[0]
+            })
+            .collect()
+    }
+
+    #[test]
+    fn an_edited_copy_round_trips_through_a_small_patch() {
+        let base = noise(20_000, 1);
+        let mut target = base.clone();
+        target[5_000..5_010].copy_from_slice(b"edited....");
+        target.splice(12_000..12_000, noise(300, 2));
+        target.drain(..1_000);
+        let patch = diff(&base, &target);
+        assert!(patch.len() < 1_000, "{} byte patch", patch.len());
+        assert_eq!(apply(&base, &patch).unwrap(), target);
+    }
+
+    #[test]
+    fn short_and_unrelated_targets_round_trip() {
+        let base = noise(4_000, 3);
+        for target in [Vec::new(), b"short".to_vec(), noise(4_000, 4)] {
+            assert_eq!(apply(&base, &diff(&base, &target)).unwrap(), target);
+        }
+        assert_eq!(apply(&[], &diff(&[], &base)).unwrap(), base);
+    }
+
+    #[test]
+    fn a_truncated_patch_is_refused() {
+        let base = noise(4_000, 5);
+        let mut target = base.clone();
+        target.extend_from_slice(b"a literal run at the end");
+        let patch = diff(&base, &target);
+        for len in [patch.len() - 1, 1, 2] {
+            let err = apply(&base, &patch[..len]).unwrap_err();
+            assert!(err.to_string().contains("truncated"), "{err}");
+        }
+    }
+
+    #[test]
+    fn a_copy_past_the_base_is_refused() {
+        let base = noise(100, 6);
+        for (at, len) in [(90, 11), (101, 0), (usize::MAX, 2)] {
+            let mut patch = Vec::new();
+            copy(&mut patch, at, len);
+            let err = apply(&base, &patch).unwrap_err();
+            assert!(err.to_string().contains("past the end"), "{err}");
+        }
+    }
+
+    #[test]
+    fn an_unknown_operation_is_refused() {
+        let err = apply(b"base", &[COPY + 1, 0]).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("unknown delta operation {}", COPY + 1)
+        );
+    }
+}
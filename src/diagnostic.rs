@@ -0,0 +1,99 @@
+//! Errors that carry a code and a hint at what to do about them, which the command line
+//! prints under the message. They travel inside [`anyhow::Error`] like any other, so
+//! callers that only show the message see the same text as before.
+use alloc::string::String;
+use core::fmt;
+
+/// What went wrong, for errors common enough to be worth a code and a hint.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum Code {
+    /// The image holds no header picturer writes.
+    NoHeader,
+    /// The image was resized or re-compressed since it was written.
+    Resampled,
+    /// The header says the blocks take more bytes than the image holds.
+    Cropped,
+    /// A header or block ends before its length says it should.
+    Truncated,
+    /// The container was written by a newer picturer.
+    Version,
+    /// The blocks need a zstd dictionary that was not given, or another one was.
+    Dictionary,
+    /// The payload is larger than `--max-output-size` allows.
+    OutputLimit,
+    /// A block does not decompress.
+    Damaged,
+}
+
+impl Code {
+    /// The code as printed, such as `picturer::cropped`.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Code::NoHeader => "picturer::no_header",
+            Code::Resampled => "picturer::resampled",
+            Code::Cropped => "picturer::cropped",
+            Code::Truncated => "picturer::truncated",
+            Code::Version => "picturer::version",
+            Code::Dictionary => "picturer::dictionary",
+            Code::OutputLimit => "picturer::output_limit",
+            Code::Damaged => "picturer::damaged",
+        }
+    }
+
+    /// What to try instead.
+    #[must_use]
+    pub fn help(self) -> &'static str {
+        match self {
+            Code::NoHeader | Code::Resampled | Code::Cropped => {
+                "download the original file rather than a preview or a screenshot, send it \
+                 as a file or document instead of a photo, or encode it again with --robust \
+                 or --format jpeg for channels that always re-encode images"
+            }
+            Code::Truncated => {
+                "the file was cut short, as an interrupted download or copy leaves it; fetch \
+                 or copy it again"
+            }
+            Code::Version => "it was written by a newer picturer; upgrade to decode it",
+            Code::Dictionary => {
+                "pass the dictionary the image was encoded with using --dict; picturer info \
+                 shows the id it needs"
+            }
+            Code::OutputLimit => "raise --max-output-size to allow more",
+            Code::Damaged => {
+                "the image was changed since it was written; if a copy of it exists \
+                 elsewhere, decode that"
+            }
+        }
+    }
+}
+
+/// An error with a [`Code`].
+#[derive(Debug)]
+pub struct Diagnostic {
+    pub code: Code,
+    message: String,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.message)
+    }
+}
+
+impl core::error::Error for Diagnostic {}
+
+/// An error saying `message`, with `code` for the command line to print under it.
+pub fn error(code: Code, message: impl Into<String>) -> anyhow::Error {
+    anyhow::Error::new(Diagnostic {
+        code,
+        message: message.into(),
+    })
+}
+
+/// The first error in the chain of `error` that has a code.
+#[must_use]
+pub fn find(error: &anyhow::Error) -> Option<&Diagnostic> {
+    error.chain().find_map(|cause| cause.downcast_ref())
+}
@@ -0,0 +1,40 @@
+use anyhow::{ensure, Context};
+use std::path::{Path, PathBuf};
+
+/// Largest dictionary `dict train` writes unless told otherwise; the zstd CLI default.
+pub const DEFAULT_SIZE: usize = 110 << 10;
+
+/// Starts every zstd dictionary, ahead of its ID.
+const MAGIC: [u8; 4] = 0xEC30_A437_u32.to_le_bytes();
+
+/// A zstd dictionary, shared between the encoder and decoder of an image.
+/// Its ID is stored in the image header so decoding can name the one it needs.
+pub struct Dictionary {
+    pub id: u32,
+    pub bytes: Vec<u8>,
+}
+
+impl Dictionary {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let bytes = std::fs::read(path)
+            .with_context(|| format!("cannot read dictionary {}", path.display()))?;
+        let id = match bytes.get(..8) {
+            Some(header) if header[..4] == MAGIC => u32::from_le_bytes(header[4..].try_into()?),
+            _ => 0,
+        };
+        ensure!(id != 0, "{} is not a zstd dictionary", path.display());
+        Ok(Dictionary { id, bytes })
+    }
+}
+
+/// Trains a dictionary of at most `max_size` bytes on the contents of `samples`.
+#[cfg(feature = "zstd")]
+pub fn train(samples: &[PathBuf], max_size: usize) -> anyhow::Result<Vec<u8>> {
+    ensure!(!samples.is_empty(), "no samples to train on");
+    zstd::dict::from_files(samples, max_size).context("dictionary training failed")
+}
+
+#[cfg(not(feature = "zstd"))]
+pub fn train(_samples: &[PathBuf], _max_size: usize) -> anyhow::Result<Vec<u8>> {
+    anyhow::bail!(crate::format::NO_ZSTD)
+}
@@ -0,0 +1,158 @@
+use crate::archive;
+use crate::format::Reader;
+use anyhow::{bail, ensure, Context};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Start of a payload holding several named files stored back to back.
+const MAGIC: &[u8; 16] = b"PICTURER-ENTRIES";
+const VERSION: u8 = 1;
+
+/// Magic, version and table length: enough to learn how much more to read.
+pub const PREFIX_LEN: u64 = 16 + 1 + 4;
+
+/// Where one named file sits in the payload.
+pub struct Entry {
+    pub name: String,
+    pub offset: u64,
+    pub len: u64,
+}
+
+pub fn is_entries(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Packs each `(name, path)` pair into one payload: a table of names and lengths
+/// followed by the files' bytes in order. Unlike an archive, the table alone tells
+/// where every file starts, so a single one can be read back with a byte range.
+pub fn pack(files: &[(String, PathBuf)]) -> anyhow::Result<Vec<u8>> {
+    let mut table = Vec::new();
+    table.extend(u32::try_from(files.len())?.to_le_bytes());
+    let mut data = Vec::new();
+    for (index, (name, path)) in files.iter().enumerate() {
+        check_name(name)?;
+        ensure!(
+            files[..index].iter().all(|(other, _)| other != name),
+            "entry name {name} is used twice"
+        );
+        let bytes = fs::read(path).with_context(|| format!("cannot read {}", path.display()))?;
+        table.extend(u32::try_from(name.len())?.to_le_bytes());
+        table.extend(name.as_bytes());
+        table.extend((bytes.len() as u64).to_le_bytes());
+        data.extend(bytes);
+    }
+    let mut buf = Vec::new();
+    buf.extend(MAGIC);
+    buf.push(VERSION);
+    buf.extend(u32::try_from(table.len())?.to_le_bytes());
+    buf.extend(table);
+    buf.extend(data);
+    Ok(buf)
+}
+
+/// Length of the whole header, given its first [`PREFIX_LEN`] bytes.
+pub fn header_len(prefix: &[u8]) -> anyhow::Result<u64> {
+    let mut reader = header(prefix)?;
+    Ok(PREFIX_LEN + u64::from(reader.u32()?))
+}
+
+/// Reads the entry table from the start of a payload.
+pub fn parse(payload: &[u8]) -> anyhow::Result<Vec<Entry>> {
+    let mut reader = header(payload)?;
+    let table_len = reader.u32()?;
+    let mut reader = Reader(reader.take(usize::try_from(table_len)?)?);
+    let mut offset = PREFIX_LEN + u64::from(table_len);
+    (0..reader.u32()?)
+        .map(|_| {
+            let len = usize::try_from(reader.u32()?)?;
+            let name = String::from_utf8(reader.take(len)?.to_vec())?;
+            let len = reader.u64()?;
+            let entry = Entry { name, offset, len };
+            offset = offset.checked_add(len).context("entry table is corrupt")?;
+            Ok(entry)
+        })
+        .collect()
+}
+
+/// Writes every entry of `payload` to a file of the same name under `out`.
+pub fn extract(payload: &[u8], out: &Path) -> anyhow::Result<()> {
+    let table = parse(payload)?;
+    for entry in &table {
+        check_name(&entry.name)?;
+    }
+    fs::create_dir_all(out)?;
+    for entry in table {
+        let bytes = usize::try_from(entry.offset)
+            .ok()
+            .zip(usize::try_from(entry.len).ok())
+            .and_then(|(start, len)| payload.get(start..start.checked_add(len)?))
+            .with_context(|| format!("entry {} is truncated", entry.name))?;
+        fs::write(archive::target(out, Path::new(&entry.name))?, bytes)?;
+    }
+    Ok(())
+}
+
+fn header(payload: &[u8]) -> anyhow::Result<Reader<'_>> {
+    let Some(rest) = payload.strip_prefix(MAGIC) else {
+        bail!("payload does not hold named entries")
+    };
+    let mut reader = Reader(rest);
+    let version = reader.u8()?;
+    ensure!(
+        version == VERSION,
+        "unsupported entry table version {version}"
+    );
+    Ok(reader)
+}
+
+/// Entry names become file names on extraction, so they must be plain ones.
+fn check_name(name: &str) -> anyhow::Result<()> {
+    ensure!(
+        !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\']),
+        "entry name {name:?} is not a plain file name"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A set of entries named `names`, each holding its own name, with no check on them.
+    fn entries(names: &[&str]) -> Vec<u8> {
+        let mut table = u32::try_from(names.len()).unwrap().to_le_bytes().to_vec();
+        for name in names {
+            table.extend(u32::try_from(name.len()).unwrap().to_le_bytes());
+            table.extend(name.as_bytes());
+            table.extend((name.len() as u64).to_le_bytes());
+        }
+        let mut buf = MAGIC.to_vec();
+        buf.push(VERSION);
+        buf.extend(u32::try_from(table.len()).unwrap().to_le_bytes());
+        buf.extend(table);
+        buf.extend(names.concat().as_bytes());
+        buf
+    }
+
+    #[test]
+    fn names_that_are_not_plain_write_nothing() {
+        for name in ["../evil", "a/evil", "a\\evil", "/evil", "..", "."] {
+            let dir = tempfile::tempdir().unwrap();
+            let out = dir.path().join("out");
+            let error = extract(&entries(&["fine", name]), &out).unwrap_err();
+            assert!(
+                error.to_string().contains("not a plain file name"),
+                "{error}"
+            );
+            assert!(!out.exists(), "{name:?} left {} behind", out.display());
+        }
+    }
+
+    #[test]
+    fn plain_names_are_written_under_the_directory() {
+        let dir = tempfile::tempdir().unwrap();
+        extract(&entries(&["one", "two"]), dir.path()).unwrap();
+        assert_eq!(fs::read(dir.path().join("one")).unwrap(), b"one");
+        assert_eq!(fs::read(dir.path().join("two")).unwrap(), b"two");
+    }
+}
@@ -0,0 +1,142 @@
+//! Unpacking a decoded tar, gzipped tar or zip file with `--auto-extract`, so that an
+//! image of a backup decodes straight to the files in it.
+use crate::{archive, format, mime};
+use anyhow::{ensure, Context};
+use std::fs::{self, File};
+use std::io::{BufReader, Read};
+use std::path::{Component, Path, PathBuf};
+
+/// If the file at `path` is a tar, gzipped tar or zip file, unpacks it into `dir`, or a
+/// directory named after it without its extension, and removes it. Symbolic links are
+/// recreated only with `allow_links`, as in a picturer archive. The files may hold no
+/// more than `--max-output-size` between them, as a small compressed file can expand
+/// without end.
+pub fn run(path: &Path, dir: Option<&Path>, allow_links: bool) -> anyhow::Result<()> {
+    let mut head = Vec::new();
+    File::open(path)?.take(8192).read_to_end(&mut head)?;
+    let Some(kind @ ("tar" | "tar.gz" | "zip")) = mime::sniff(&head) else {
+        return Ok(());
+    };
+    let out = dir.map_or_else(|| unsuffixed(path, kind), Path::to_path_buf);
+    fs::create_dir_all(&out)?;
+    let file = BufReader::new(File::open(path)?);
+    let (files, skipped) = match kind {
+        "zip" => unzip(file, &out, allow_links),
+        "tar.gz" => untar(flate2::read::GzDecoder::new(file), &out, allow_links),
+        _ => untar(file, &out, allow_links),
+    }
+    .with_context(|| format!("cannot extract {}", path.display()))?;
+    fs::remove_file(path)?;
+    eprintln!(
+        "extracted {files} files of {} into {}",
+        path.display(),
+        out.display()
+    );
+    if skipped > 0 {
+        eprintln!("skipped {skipped} symbolic links; --allow-symlinks recreates them");
+    }
+    Ok(())
+}
+
+/// `path` without the extension `kind`, or without its last one if it has another, so
+/// that `backup.tar.gz` unpacks into `backup`.
+fn unsuffixed(path: &Path, kind: &str) -> PathBuf {
+    let name = path.file_name().unwrap_or_default().to_string_lossy();
+    match name.strip_suffix(&format!(".{kind}")) {
+        Some(stem) if !stem.is_empty() => path.with_file_name(stem),
+        _ => path.with_extension(""),
+    }
+}
+
+/// Unpacks a tar stream, returning how many entries were written and how many
+/// symbolic links skipped.
+fn untar(reader: impl Read, out: &Path, allow_links: bool) -> anyhow::Result<(usize, usize)> {
+    let mut tar = tar::Archive::new(reader);
+    let (mut files, mut skipped) = (0, 0);
+    let mut extracted = 0u64;
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        let relative = entry.path()?.into_owned();
+        let kind = entry.header().entry_type();
+        if kind.is_symlink() && !allow_links {
+            skipped += 1;
+            continue;
+        }
+        // What an entry writes is what the stream holds of it, so its size is checked
+        // before any of it is read.
+        extracted = extracted.saturating_add(entry.size());
+        format::check_output(extracted)?;
+        // Checks that the path, and what a hard link names, stays inside `out`.
+        ensure!(
+            entry.unpack_in(out)?,
+            "refusing to extract {}: not a plain relative path",
+            relative.display()
+        );
+        if kind.is_file() {
+            files += 1;
+        }
+    }
+    Ok((files, skipped))
+}
+
+/// Unpacks a zip file, returning how many files were written and how many symbolic links
+/// skipped.
+fn unzip(
+    reader: impl Read + std::io::Seek,
+    out: &Path,
+    allow_links: bool,
+) -> anyhow::Result<(usize, usize)> {
+    let mut zip = zip::ZipArchive::new(reader)?;
+    let (mut files, mut skipped) = (0, 0);
+    let mut extracted = 0u64;
+    for index in 0..zip.len() {
+        let mut file = zip.by_index(index)?;
+        let relative = file
+            .enclosed_name()
+            .filter(|name| {
+                name.components()
+                    .all(|part| matches!(part, Component::Normal(_)))
+            })
+            .with_context(|| {
+                format!(
+                    "refusing to extract {}: not a plain relative path",
+                    String::from_utf8_lossy(file.name_raw())
+                )
+            })?;
+        let path = archive::target(out, &relative)?;
+        if file.is_dir() {
+            fs::create_dir_all(&path)?;
+            continue;
+        }
+        if let Some(parent) = path.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if file.is_symlink() {
+            if allow_links {
+                let mut target = String::new();
+                file.read_to_string(&mut target)?;
+                archive::symlink(target.as_ref(), &path)?;
+            } else {
+                skipped += 1;
+            }
+            continue;
+        }
+        // The size a zip file gives is not to be trusted, so what is read is cut off
+        // just past the limit whatever it says.
+        let remaining = format::max_output().saturating_sub(extracted);
+        let written = std::io::copy(
+            &mut (&mut file).take(remaining.saturating_add(1)),
+            &mut File::create(&path)?,
+        )?;
+        extracted = extracted.saturating_add(written);
+        if let Err(err) = format::check_output(extracted) {
+            fs::remove_file(&path)?;
+            return Err(err);
+        }
+        if let Some(mode) = file.unix_mode() {
+            archive::set_mode(&path, mode & 0o777)?;
+        }
+        files += 1;
+    }
+    Ok((files, skipped))
+}
@@ -0,0 +1,308 @@
+use crate::interrupt::Partial;
+use anyhow::ensure;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::OnceLock;
+#[cfg(feature = "http")]
+use std::sync::{Mutex, PoisonError};
+#[cfg(feature = "http")]
+use std::time::{Duration, Instant};
+
+static TEMP_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+/// Largest download accepted unless `--max-download` says otherwise: the biggest
+/// image picturer writes, with room for PNG overhead on incompressible data.
+pub const DEFAULT_LIMIT: u64 = 2 * crate::MAX_BYTES as u64;
+
+pub fn is_url(path: &Path) -> bool {
+    path.to_str()
+        .is_some_and(|path| path.starts_with("http://") || path.starts_with("https://"))
+}
+
+/// Last path segment of `url`, used to name the download and what is decoded from it.
+pub fn file_name(url: &str) -> &str {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let path = rest.split(['?', '#']).next().unwrap_or_default();
+    match path.split_once('/') {
+        Some((_, path)) => path.rsplit('/').next().filter(|name| !name.is_empty()),
+        None => None,
+    }
+    .unwrap_or("download.png")
+}
+
+/// `file`, as listed by the manifest at the URL `base`, as a URL of its own.
+pub fn resolve(base: &str, file: &str) -> String {
+    if is_url(Path::new(file)) {
+        return file.to_owned();
+    }
+    let base = base.split(['?', '#']).next().unwrap_or_default();
+    let host = base.find("://").map_or(0, |scheme| scheme + 3);
+    let end = if file.starts_with('/') {
+        base[host..].find('/')
+    } else {
+        base[host..].rfind('/')
+    };
+    let dir = &base[..end.map_or(base.len(), |end| host + end)];
+    format!("{dir}/{}", file.trim_start_matches('/'))
+}
+
+/// Puts temporary files in `dir` from now on, and spills payloads there, rather than
+/// in the system's temporary directory.
+pub fn set_temp_dir(dir: &Path) -> anyhow::Result<()> {
+    ensure!(
+        dir.is_dir(),
+        "--temp-dir {} is not a directory",
+        dir.display()
+    );
+    let _ = TEMP_DIR.set(dir.to_owned());
+    Ok(())
+}
+
+/// The directory given with `--temp-dir`, if one was.
+pub fn temp_dir() -> Option<&'static Path> {
+    TEMP_DIR.get().map(PathBuf::as_path)
+}
+
+/// A file in the temporary directory, removed again when dropped, which it is on exit
+/// and when a panic unwinds past it.
+pub struct TempFile {
+    pub path: PathBuf,
+    _partial: Partial,
+}
+
+impl TempFile {
+    /// Creates an empty file in the temporary directory whose name ends in `name`, the
+    /// rest of it random. Only this user may read or write it, and it is made where
+    /// nothing was, so that a link planted there first cannot redirect it.
+    pub fn new(name: &str) -> std::io::Result<Self> {
+        let dir = temp_dir().map_or_else(std::env::temp_dir, Path::to_owned);
+        let path = tempfile::Builder::new()
+            .prefix("picturer-")
+            .suffix(&format!("-{name}"))
+            .tempfile_in(dir)?
+            .into_temp_path()
+            .keep()?;
+        Ok(Self::at(path))
+    }
+
+    /// A temporary file at `path`, outside the temporary directory.
+    pub fn at(path: PathBuf) -> Self {
+        TempFile {
+            _partial: Partial::new(&path),
+            path,
+        }
+    }
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+/// Downloads `url`, following redirects, failing if it is longer than `limit` bytes.
+/// A download whose connection drops, or whose server fails for a while, is tried again,
+/// resuming where it stopped if the server takes range requests.
+#[cfg(feature = "http")]
+pub fn download(url: &str, limit: u64) -> anyhow::Result<TempFile> {
+    let download = TempFile::new(file_name(url))?;
+    let mut offset = 0;
+    let mut attempt = 1;
+    loop {
+        match fetch(url, limit, &download.path, offset) {
+            Ok(()) => return Ok(download),
+            Err(error) if attempt < ATTEMPTS && may_pass(&error) => {
+                std::thread::sleep(Duration::from_millis(500 << attempt));
+                offset = fs::metadata(&download.path).map_or(0, |meta| meta.len());
+                attempt += 1;
+            }
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+#[cfg(not(feature = "http"))]
+pub fn download(url: &str, _limit: u64) -> anyhow::Result<TempFile> {
+    anyhow::bail!("cannot decode {url}: picturer was built without the http feature")
+}
+
+/// Writes `url` to `path`, asking for what follows its first `offset` bytes if there are
+/// any, which are kept if the server sends just that.
+#[cfg(feature = "http")]
+fn fetch(url: &str, limit: u64, path: &Path, offset: u64) -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    let mut request = ureq::get(url);
+    if offset > 0 {
+        request = request.header("Range", format!("bytes={offset}-"));
+    }
+    let mut response = request
+        .call()
+        .with_context(|| format!("cannot download {url}"))?;
+    let resumed = offset > 0 && response.status() == ureq::http::StatusCode::PARTIAL_CONTENT;
+    if resumed {
+        let range = response.headers().get("Content-Range");
+        let start =
+            range.and_then(|range| range.to_str().ok()?.strip_prefix("bytes ")?.split_once('-'));
+        ensure!(
+            start.is_some_and(|(start, _)| start == offset.to_string()),
+            "{url} resumed somewhere other than where its download stopped"
+        );
+    }
+    let mut file = if resumed {
+        fs::OpenOptions::new().append(true).open(path)?
+    } else {
+        fs::File::create(path)?
+    };
+    let left = if resumed {
+        limit.saturating_sub(offset)
+    } else {
+        limit
+    };
+    let body = response.body_mut().with_config().limit(left).reader();
+    std::io::copy(&mut Throttled(body), &mut file)
+        .with_context(|| format!("cannot download {url} (limit is {limit} bytes)"))?;
+    Ok(())
+}
+
+/// Bytes a second given as `N`, or with a suffix of `K`, `M` or `G` for KiB, MiB or GiB.
+pub fn parse_rate(value: &str) -> Result<u64, String> {
+    let (digits, unit) = match value.char_indices().last() {
+        Some((at, 'k' | 'K')) => (&value[..at], 1 << 10),
+        Some((at, 'm' | 'M')) => (&value[..at], 1 << 20),
+        Some((at, 'g' | 'G')) => (&value[..at], 1 << 30),
+        _ => (value, 1),
+    };
+    let rate = digits
+        .parse::<u64>()
+        .ok()
+        .and_then(|rate| rate.checked_mul(unit))
+        .ok_or_else(|| format!("{value} is not a rate such as 500K or 2M"))?;
+    if rate == 0 {
+        return Err("the rate must be more than 0".into());
+    }
+    Ok(rate)
+}
+
+/// Makes every download from now on, together, read no more than `rate` bytes a second.
+pub fn set_rate_limit(rate: u64) {
+    let _ = RATE.set(rate.max(1));
+}
+
+/// Bytes a second all downloads together may read, given with `--limit-rate`.
+static RATE: OnceLock<u64> = OnceLock::new();
+/// When the bytes downloaded so far are due at that pace.
+#[cfg(feature = "http")]
+static DUE: Mutex<Option<Instant>> = Mutex::new(None);
+
+/// A reader held to the pace [`set_rate_limit`] set, if any.
+#[cfg(feature = "http")]
+struct Throttled<R>(R);
+
+#[cfg(feature = "http")]
+impl<R: std::io::Read> std::io::Read for Throttled<R> {
+    #[allow(clippy::cast_precision_loss)]
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let Some(&rate) = RATE.get() else {
+            return self.0.read(buf);
+        };
+        // A tenth of a second's worth at a time, so that the pace stays even.
+        let most = buf
+            .len()
+            .min(usize::try_from(rate / 10).unwrap_or(usize::MAX).max(1));
+        let len = self.0.read(&mut buf[..most])?;
+        let wait = {
+            let mut due = DUE.lock().unwrap_or_else(PoisonError::into_inner);
+            let now = Instant::now();
+            let took = Duration::from_secs_f64(len as f64 / rate as f64);
+            let at = due.map_or(now, |due| due.max(now)) + took;
+            *due = Some(at);
+            at - now
+        };
+        std::thread::sleep(wait);
+        Ok(len)
+    }
+}
+
+/// Attempts at every download before [`download`] gives up on it.
+#[cfg(feature = "http")]
+const ATTEMPTS: u32 = 3;
+/// Downloads [`download_all`] makes at once.
+#[cfg(feature = "http")]
+const CONCURRENT: usize = 4;
+
+/// Downloads all of `urls`, a few at a time, as [`download`] does each, into files of
+/// their own even where URLs end in the same name. Stops at the first that fails;
+/// otherwise returns the files in the order of `urls`.
+#[cfg(feature = "http")]
+pub fn download_all(urls: &[String], limit: u64) -> anyhow::Result<Vec<TempFile>> {
+    use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+    let next = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let worker = || {
+        let mut done = Vec::new();
+        while !failed.load(Ordering::Relaxed) {
+            let index = next.fetch_add(1, Ordering::Relaxed);
+            let Some(url) = urls.get(index) else { break };
+            let download = download(url, limit);
+            failed.fetch_or(download.is_err(), Ordering::Relaxed);
+            done.push((index, download));
+        }
+        done
+    };
+    let mut done: Vec<_> = std::thread::scope(|scope| {
+        let workers: Vec<_> = (0..CONCURRENT.min(urls.len()))
+            .map(|_| scope.spawn(worker))
+            .collect();
+        workers
+            .into_iter()
+            .flat_map(|worker| {
+                worker
+                    .join()
+                    .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+            })
+            .collect()
+    });
+    done.sort_by_key(|&(index, _)| index);
+    done.into_iter().map(|(_, download)| download).collect()
+}
+
+#[cfg(not(feature = "http"))]
+pub fn download_all(urls: &[String], _limit: u64) -> anyhow::Result<Vec<TempFile>> {
+    download(urls.first().map_or("", String::as_str), 0).map(|download| vec![download])
+}
+
+/// Whether a failed download may succeed when tried again: not if the server said no,
+/// or the file is over the limit.
+#[cfg(feature = "http")]
+fn may_pass(error: &anyhow::Error) -> bool {
+    let cause = error.chain().find_map(|cause| {
+        cause.downcast_ref::<ureq::Error>().or_else(|| {
+            let io = cause.downcast_ref::<std::io::Error>()?;
+            io.get_ref()?.downcast_ref()
+        })
+    });
+    !matches!(
+        cause,
+        Some(ureq::Error::StatusCode(400..=499) | ureq::Error::BodyExceedsLimit(_))
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn same_name_different_files() {
+        let name = file_name("https://example.com/a/img.png");
+        assert_eq!(name, file_name("https://example.com/b/img.png"));
+        let first = TempFile::new(name).unwrap();
+        let second = TempFile::new(name).unwrap();
+        assert_ne!(first.path, second.path);
+        fs::write(&first.path, b"first").unwrap();
+        fs::write(&second.path, b"second").unwrap();
+        drop(second);
+        assert_eq!(fs::read(&first.path).unwrap(), b"first");
+    }
+}
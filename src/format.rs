@@ -0,0 +1,1138 @@
+//! Packing payloads into containers and decoding them again, compressing and decoding
+//! blocks in parallel. The headers themselves are read and written by [`container`],
+//! whose public items are exported here too.
+use crate::cancel::{Cancel, Cancelled};
+pub(crate) use crate::container::truncated;
+use crate::container::{self, damaged, strip_prefix, write_container, Fields, Index, MAGIC};
+pub use crate::container::{
+    container_len, content_type, format_id, is_container, legacy_len, meta_hidden, metadata,
+    payload_id, raw_capacity, Codec, Delta, Reader, Volume,
+};
+use crate::context::Context;
+use crate::diagnostic::{self, Code};
+use crate::dict::Dictionary;
+use crate::pipeline::Pipeline;
+use crate::progress;
+use crate::timings::{self, Stage};
+use anyhow::{bail, ensure};
+use flate2::bufread::{ZlibDecoder, ZlibEncoder};
+use rayon::prelude::*;
+use std::collections::BTreeMap;
+use std::io::{Read, Write};
+use std::ops::ControlFlow;
+use std::ops::Range;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+/// Raw bytes per independently compressed block when the size is not picked for the
+/// input, as for output that must not depend on the machine writing it.
+pub const BLOCK_SIZE: usize = 1 << 20;
+/// Smallest block size that may be asked for, at which the index still takes only an
+/// eight-thousandth of the container.
+pub const MIN_BLOCK_SIZE: usize = 1 << 16;
+/// Smallest block size picked for an input, below which blocks compress noticeably
+/// worse.
+const MIN_AUTO_BLOCK_SIZE: usize = 1 << 18;
+/// Largest block size picked for an input, above which the blocks compressed at once
+/// hold too much memory.
+const MAX_AUTO_BLOCK_SIZE: usize = 1 << 24;
+/// Largest payload decoded unless [`set_max_output`] allows more, so that an image made
+/// to expand without end fails before it fills memory or disk.
+pub const DEFAULT_MAX_OUTPUT: u64 = 16 << 30;
+static MAX_OUTPUT: AtomicU64 = AtomicU64::new(DEFAULT_MAX_OUTPUT);
+/// Bytes of each block compressed at the fastest level to tell whether it is worth
+/// compressing at all.
+const PROBE_LEN: usize = 1 << 16;
+/// Why zstd images cannot be written or read when the feature is off.
+#[cfg(not(feature = "zstd"))]
+pub(crate) const NO_ZSTD: &str = "picturer was built without the zstd feature";
+
+/// Sets the most payload bytes any image may decode to, for the rest of the process.
+pub fn set_max_output(bytes: u64) {
+    MAX_OUTPUT.store(bytes, Ordering::Relaxed);
+}
+
+/// The most payload bytes any image may decode to.
+#[must_use]
+pub fn max_output() -> u64 {
+    MAX_OUTPUT.load(Ordering::Relaxed)
+}
+
+/// Fails with an error naming the limit if `len` payload bytes are more than it.
+pub fn check_output(len: u64) -> anyhow::Result<()> {
+    container::check_limit(len, max_output())
+}
+
+/// How [`pack`] compresses a payload.
+#[derive(Clone, Copy)]
+pub struct Packing<'a> {
+    pub codec: Codec,
+    /// Shared by every block; only used with zstd.
+    pub dict: Option<&'a Dictionary>,
+    /// Stages every block passes through once compressed.
+    pub pipeline: Option<&'a Pipeline>,
+    /// MIME type of the payload, stored for decoders to name their output by.
+    pub content_type: Option<&'a str>,
+    /// When the payload was encoded, in seconds since the Unix epoch.
+    pub created: Option<u64>,
+    /// Free text describing the payload, stored for `info` to show.
+    pub comment: Option<&'a str>,
+    /// Key/value pairs describing the payload, stored for `info` to show.
+    pub meta: Option<&'a BTreeMap<String, String>>,
+    /// Whether the metadata was left out here because the encrypted payload holds it.
+    pub meta_hidden: bool,
+    /// Raw bytes per block, or `None` to pick them with [`auto_block_size`].
+    pub block_size: Option<usize>,
+    /// Identifies the payload, or `None` to use the first 16 bytes of its BLAKE3 hash.
+    pub id: Option<[u8; 16]>,
+    /// Bytes that end each record of the payload, such as a newline, to cut blocks after
+    /// the last record that fits rather than at the block size, for [`unpack_records`].
+    /// A record longer than the block size takes a block of its own.
+    pub split_on: Option<&'a [u8]>,
+    /// zstd contexts to compress with instead of setting up new ones for every block,
+    /// along with their dictionary, which stands in for [`Packing::dict`]; only used
+    /// with zstd.
+    pub context: Option<&'a Context>,
+    /// Stops packing before the next block once cancelled, failing with [`Cancelled`].
+    /// Blocks already started are finished first.
+    pub cancel: Option<&'a Cancel>,
+}
+
+impl Packing<'_> {
+    #[must_use]
+    pub fn new(codec: Codec) -> Self {
+        Packing {
+            codec,
+            dict: None,
+            pipeline: None,
+            content_type: None,
+            created: None,
+            comment: None,
+            meta: None,
+            meta_hidden: false,
+            block_size: None,
+            id: None,
+            split_on: None,
+            context: None,
+            cancel: None,
+        }
+    }
+
+    /// The metadata the container packed with these settings holds, as [`metadata`]
+    /// reads it back.
+    pub(crate) fn metadata(&self) -> Vec<u8> {
+        Fields {
+            content_type: self.content_type.map(str::to_owned),
+            created: self.created,
+            comment: self.comment.map(str::to_owned),
+            meta: self.meta.cloned().unwrap_or_default(),
+            ..Fields::default()
+        }
+        .serialize()
+    }
+}
+
+/// The block size for a payload of `len` bytes: a power of two from 256 KiB to 16 MiB
+/// that gives each thread compressing them four blocks, so all are kept busy to the
+/// end. With a single thread, splitting gains nothing, so blocks are as large as allowed.
+#[must_use]
+pub fn auto_block_size(len: usize) -> usize {
+    let blocks = match rayon::current_num_threads() {
+        1 => 1,
+        threads => threads * 4,
+    };
+    (len / blocks)
+        .clamp(MIN_AUTO_BLOCK_SIZE, MAX_AUTO_BLOCK_SIZE)
+        .next_power_of_two()
+}
+
+/// Packs `bytes` into a container: magic, version, codec, block size, tagged fields,
+/// block count, the stored length of every block, then the blocks themselves.
+/// Blocks are compressed, and passed through the pipeline, independently and in
+/// parallel. An empty payload has no blocks, so its container is just the header, which
+/// unpacks to nothing.
+pub fn pack(bytes: &[u8], packing: Packing) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    pack_into(&mut buf, bytes, packing)?;
+    Ok(buf)
+}
+
+/// Like [`pack`], but appends the container to `buf`.
+pub fn pack_into(buf: &mut Vec<u8>, bytes: &[u8], mut packing: Packing) -> anyhow::Result<()> {
+    if let Some(context) = packing
+        .context
+        .filter(|_| matches!(packing.codec, Codec::Zstd(_)))
+    {
+        let id = |dict: Option<&Dictionary>| dict.map(|dict| dict.id);
+        ensure!(
+            packing.dict.is_none() || id(packing.dict) == id(context.dictionary()),
+            "the dictionary is not the one of the context"
+        );
+        packing.dict = context.dictionary();
+    }
+    ensure!(
+        packing.dict.is_none() || matches!(packing.codec, Codec::Zstd(_)),
+        "dictionaries need the zstd codec"
+    );
+    ensure!(
+        packing
+            .split_on
+            .is_none_or(|delimiter| !delimiter.is_empty()),
+        "the record delimiter is empty"
+    );
+    let pipeline = packing.pipeline.filter(|pipeline| !pipeline.is_empty());
+    let mut fields = Fields {
+        dict_id: packing.dict.map(|dict| dict.id),
+        pipeline: pipeline.map(Pipeline::descriptor).transpose()?,
+        content_type: packing.content_type.map(str::to_owned),
+        created: packing.created,
+        comment: packing.comment.map(str::to_owned),
+        meta: packing.meta.cloned().unwrap_or_default(),
+        meta_hidden: packing.meta_hidden,
+        raw_blocks: Vec::new(),
+        len: Some(bytes.len() as u64),
+        id: Some(packing.id.unwrap_or_else(|| {
+            let mut id = [0; 16];
+            id.copy_from_slice(&blake3::hash(bytes).as_bytes()[..16]);
+            id
+        })),
+        records: packing.split_on.map(<[u8]>::to_vec),
+        starts: Vec::new(),
+    };
+    let mut block_size = packing
+        .block_size
+        .unwrap_or_else(|| auto_block_size(bytes.len()));
+    let chunks = split(bytes, block_size, packing.split_on);
+    if packing.split_on.is_some() {
+        fields.starts = starts(0, &chunks);
+        block_size = block_size.max(chunks.iter().map(|chunk| chunk.len()).max().unwrap_or(0));
+    }
+    let started = timings::start();
+    let compressed = compress(
+        &chunks,
+        packing.codec,
+        packing.dict,
+        packing.context,
+        packing.cancel,
+    );
+    timings::record(Stage::Compress, started, bytes.len() as u64);
+    let (codec, blocks) = match compressed.map_err(Cancelled::from_io) {
+        Err(Ok(cancelled)) => return Err(cancelled.into()),
+        Ok((blocks, raw)) => {
+            fields.raw_blocks = raw;
+            (packing.codec, blocks)
+        }
+        Err(Err(err)) => {
+            eprintln!("Compression failed: {err:?}. Encoding raw bytes...");
+            fields.dict_id = None;
+            (
+                Codec::Raw,
+                chunks.iter().map(|chunk| chunk.to_vec()).collect(),
+            )
+        }
+    };
+    let started = timings::start();
+    let blocks = match pipeline {
+        Some(pipeline) => apply(pipeline, blocks)?,
+        None => blocks,
+    };
+    let start = buf.len();
+    write_container(buf, codec, block_size as u64, &fields, &blocks)?;
+    let packed = (buf.len() - start) as u64;
+    timings::record(Stage::Pack, started, packed);
+    progress::report(Stage::Pack, packed, Some(packed));
+    Ok(())
+}
+
+/// Most bytes the container of a payload of `len` bytes packed with `packing` takes,
+/// whatever the payload holds: every block stored raw, as one that does not compress
+/// is, and passed through the pipeline. Records, split with `split_on`, are not counted.
+pub fn max_container_len(len: usize, packing: &Packing) -> anyhow::Result<usize> {
+    let block_size = packing.block_size.unwrap_or_else(|| auto_block_size(len));
+    ensure!(block_size > 0, "block size is zero");
+    let count = len.div_ceil(block_size);
+    let pipeline = packing.pipeline.filter(|pipeline| !pipeline.is_empty());
+    let fields = Fields {
+        dict_id: packing.dict.map(|dict| dict.id),
+        pipeline: pipeline.map(Pipeline::descriptor).transpose()?,
+        content_type: packing.content_type.map(str::to_owned),
+        created: packing.created,
+        comment: packing.comment.map(str::to_owned),
+        meta: packing.meta.cloned().unwrap_or_default(),
+        meta_hidden: packing.meta_hidden,
+        raw_blocks: vec![true; count],
+        len: Some(len as u64),
+        id: Some([0; 16]),
+        records: None,
+        starts: Vec::new(),
+    };
+    let header = MAGIC.len() + 1 + 1 + 8 + 4 + fields.serialize().len() + 4 + 8 * count;
+    let stored = |len: usize| match pipeline {
+        Some(pipeline) => Ok(pipeline.apply(vec![0; len])?.len()),
+        None => anyhow::Ok(len),
+    };
+    let mut total = header + len / block_size * stored(block_size)?;
+    if !len.is_multiple_of(block_size) {
+        total += stored(len % block_size)?;
+    }
+    Ok(total)
+}
+
+/// Extends the payload of `container` with `bytes`, keeping every full block as is.
+/// Only a partial last block is decompressed and compressed again, or with records,
+/// the last block, which may end in the middle of one.
+pub fn append(
+    container: &[u8],
+    bytes: &[u8],
+    dict: Option<&Dictionary>,
+) -> anyhow::Result<Vec<u8>> {
+    ensure!(
+        container.starts_with(MAGIC),
+        "image has no block index, decode and encode it again to append"
+    );
+    let index = Index::parse(container)?;
+    let block_size = usize::try_from(index.block_size)?;
+    let records = index.fields.records.clone();
+    let mut kept = index.lengths.len();
+    let mut tail = Vec::new();
+    if let Some(last) = kept.checked_sub(1) {
+        let last_block = index.decode(container, last..=last, dict)?.concat();
+        if last_block.len() < block_size || records.is_some() {
+            kept = last;
+            tail = last_block;
+        }
+    }
+    tail.extend(bytes);
+    let mut blocks = (0..kept)
+        .map(|i| {
+            container
+                .get(index.offsets[i]..index.offsets[i] + index.lengths[i])
+                .map(<[u8]>::to_vec)
+                .ok_or_else(truncated)
+        })
+        .collect::<anyhow::Result<Vec<Vec<u8>>>>()?;
+    let dict = index.dictionary(dict)?;
+    let pipeline = index.pipeline()?;
+    let tail_len = tail.len();
+    let kept_len = index.block_start(kept);
+    let chunks = split(&tail, block_size, records.as_deref());
+    let (tail, raw) = compress(&chunks, index.codec, dict, None, None)?;
+    blocks.extend(apply(&pipeline, tail)?);
+    let mut block_size = index.block_size;
+    let mut fields = index.fields;
+    fields.len = Some(
+        kept_len
+            .checked_add(tail_len as u64)
+            .ok_or(anyhow::Error::msg(
+                "block size overflows the payload length",
+            ))?,
+    );
+    if records.is_some() {
+        fields.starts.truncate(kept);
+        fields.starts.extend(starts(kept_len, &chunks));
+        let longest = chunks.iter().map(|chunk| chunk.len() as u64).max();
+        block_size = block_size.max(longest.unwrap_or(0));
+    }
+    fields.raw_blocks.resize(kept, false);
+    fields.raw_blocks.extend(raw);
+    let mut buf = Vec::new();
+    write_container(&mut buf, index.codec, block_size, &fields, &blocks)?;
+    Ok(buf)
+}
+
+/// `bytes` cut into blocks of `block_size`, or with `delimiter`, into blocks that each
+/// end on the last record that fits, or hold a single record longer than that.
+fn split<'a>(bytes: &'a [u8], block_size: usize, delimiter: Option<&[u8]>) -> Vec<&'a [u8]> {
+    let Some(delimiter) = delimiter else {
+        return bytes.chunks(block_size).collect();
+    };
+    let find = |haystack: &[u8]| {
+        haystack
+            .windows(delimiter.len())
+            .position(|w| w == delimiter)
+    };
+    let mut blocks = Vec::new();
+    let mut rest = bytes;
+    while !rest.is_empty() {
+        let len = if rest.len() <= block_size {
+            rest.len()
+        } else {
+            let fits = rest[..block_size]
+                .windows(delimiter.len())
+                .rposition(|w| w == delimiter);
+            fits.or_else(|| find(rest))
+                .map_or(rest.len(), |at| at + delimiter.len())
+        };
+        let (block, more) = rest.split_at(len);
+        blocks.push(block);
+        rest = more;
+    }
+    blocks
+}
+
+/// Where each of `blocks` starts in the payload, the first at `first`.
+fn starts(first: u64, blocks: &[&[u8]]) -> Vec<u64> {
+    blocks
+        .iter()
+        .scan(first, |start, block| {
+            let this = *start;
+            *start += block.len() as u64;
+            Some(this)
+        })
+        .collect()
+}
+
+/// Compresses each of `blocks`, returning them with whether each was left raw: blocks a
+/// fast probe finds incompressible, such as media or encrypted data, are not compressed
+/// at all, nor kept compressed if that did not make them smaller. Once `cancel` is
+/// cancelled, no block is started and the error is [`Cancelled::into_io`].
+fn compress(
+    blocks: &[&[u8]],
+    codec: Codec,
+    dict: Option<&Dictionary>,
+    context: Option<&Context>,
+    cancel: Option<&Cancel>,
+) -> std::io::Result<(Vec<Vec<u8>>, Vec<bool>)> {
+    let total = blocks.iter().map(|block| block.len() as u64).sum();
+    let done = AtomicU64::new(0);
+    let blocks = blocks
+        .par_iter()
+        .map(|&block| {
+            if cancel.is_some_and(Cancel::is_cancelled) {
+                let done = done.load(Ordering::Relaxed);
+                return Err(Cancelled {
+                    done,
+                    total: Some(total),
+                }
+                .into_io());
+            }
+            let compressed = compress_block(block, codec, dict, context)?;
+            let len = block.len() as u64;
+            let done = done.fetch_add(len, Ordering::Relaxed) + len;
+            progress::report(Stage::Compress, done, Some(total));
+            Ok(compressed)
+        })
+        .collect::<std::io::Result<Vec<_>>>()?;
+    Ok(blocks.into_iter().unzip())
+}
+
+fn compress_block(
+    block: &[u8],
+    codec: Codec,
+    dict: Option<&Dictionary>,
+    context: Option<&Context>,
+) -> std::io::Result<(Vec<u8>, bool)> {
+    // A block too small to compress alone may still shrink with a dictionary.
+    let probe = dict.is_none();
+    let dict = dict.map_or(&[][..], |dict| &dict.bytes);
+    let compressed = match codec {
+        Codec::Raw => return Ok((block.to_vec(), false)),
+        _ if probe && !compressible(block) => return Ok((block.to_vec(), true)),
+        Codec::Zlib(level) => zlib_compress(block, level)?,
+        Codec::Zstd(level) => match context {
+            Some(context) => context.compress(block, level)?,
+            None => zstd_compress(block, level, dict)?,
+        },
+    };
+    Ok(if compressed.len() < block.len() {
+        (compressed, false)
+    } else {
+        (block.to_vec(), true)
+    })
+}
+
+/// Whether zlib at its fastest level saves at least a 32nd of a sample of `block`,
+/// taken from four places in it.
+fn compressible(block: &[u8]) -> bool {
+    let strip = PROBE_LEN / 4;
+    let sample = if block.len() <= PROBE_LEN {
+        block.to_vec()
+    } else {
+        let step = (block.len() - strip) / 3;
+        (0..4)
+            .flat_map(|i| &block[i * step..i * step + strip])
+            .copied()
+            .collect()
+    };
+    zlib_compress(&sample, 1).is_ok_and(|out| out.len() + sample.len() / 32 < sample.len())
+}
+
+fn zlib_compress(block: &[u8], level: u32) -> std::io::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    ZlibEncoder::new(block, flate2::Compression::new(level)).read_to_end(&mut out)?;
+    Ok(out)
+}
+
+fn apply(pipeline: &Pipeline, blocks: Vec<Vec<u8>>) -> anyhow::Result<Vec<Vec<u8>>> {
+    blocks
+        .into_par_iter()
+        .map(|block| pipeline.apply(block))
+        .collect()
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_compress(block: &[u8], level: i32, dict: &[u8]) -> std::io::Result<Vec<u8>> {
+    zstd::bulk::Compressor::with_dictionary(level, dict)?.compress(block)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_compress(_block: &[u8], _level: i32, _dict: &[u8]) -> std::io::Result<Vec<u8>> {
+    Err(std::io::Error::other(NO_ZSTD))
+}
+
+#[cfg(feature = "zstd")]
+fn zstd_decompress(block: &[u8], dict: &[u8], limit: u64) -> anyhow::Result<Vec<u8>> {
+    let mut out = Vec::new();
+    zstd::stream::read::Decoder::with_dictionary(block, dict)?
+        .take(limit)
+        .read_to_end(&mut out)
+        .map_err(|error| damaged(&error))?;
+    Ok(out)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn zstd_decompress(_block: &[u8], _dict: &[u8], _limit: u64) -> anyhow::Result<Vec<u8>> {
+    anyhow::bail!(NO_ZSTD)
+}
+
+/// Reverses [`pack`]. Images written before the block container existed
+/// (a compression flag byte followed by a `u64` length) are still accepted.
+pub fn unpack(bytes: &[u8], dict: Option<&Dictionary>) -> anyhow::Result<Vec<u8>> {
+    unpack_with_progress(bytes, dict, |_, _| {})
+}
+
+/// Like [`unpack`], but calls `progress` with the payload bytes decoded so far and,
+/// if the container stores it, the length of the whole payload as each block is done.
+/// A payload that turns out longer or shorter than the stored length is an error.
+pub fn unpack_with_progress(
+    bytes: &[u8],
+    dict: Option<&Dictionary>,
+    progress: impl Fn(u64, Option<u64>) + Sync,
+) -> anyhow::Result<Vec<u8>> {
+    unpack_in(bytes, dict, None, |done, total| {
+        progress(done, total);
+        ControlFlow::Continue(())
+    })
+}
+
+/// Like [`unpack_with_progress`], but stops once `progress` breaks, before decoding any
+/// more blocks, failing with [`Cancelled`]. It is called with nothing done before the
+/// first block is, to stop even that.
+pub fn unpack_cancellable(
+    bytes: &[u8],
+    dict: Option<&Dictionary>,
+    progress: impl Fn(u64, Option<u64>) -> ControlFlow<()> + Sync,
+) -> anyhow::Result<Vec<u8>> {
+    unpack_in(bytes, dict, None, progress)
+}
+
+/// Like [`unpack`], decompressing with the contexts of `context` and its dictionary.
+pub fn unpack_with_context(bytes: &[u8], context: &Context) -> anyhow::Result<Vec<u8>> {
+    unpack_in(bytes, None, Some(context), |_, _| ControlFlow::Continue(()))
+}
+
+/// [`unpack_cancellable`], with the contexts of `context`, if given, and its dictionary
+/// in place of `dict`.
+pub(crate) fn unpack_in(
+    bytes: &[u8],
+    dict: Option<&Dictionary>,
+    context: Option<&Context>,
+    progress: impl Fn(u64, Option<u64>) -> ControlFlow<()> + Sync,
+) -> anyhow::Result<Vec<u8>> {
+    if !bytes.starts_with(MAGIC) {
+        return container::unpack_legacy(bytes, max_output());
+    }
+    let started = timings::start();
+    let index = Index::parse(bytes)?;
+    index.check_stored(bytes.len())?;
+    let total = index.fields.len;
+    check_output(total.unwrap_or(0))?;
+    timings::record(Stage::Unpack, started, 0);
+    if progress(0, total).is_break() {
+        bail!(Cancelled { done: 0, total });
+    }
+    let done = AtomicU64::new(0);
+    let blocks = index.decode_with(bytes, 0, 0..index.lengths.len(), dict, context, &|len| {
+        let done = done.fetch_add(len, Ordering::Relaxed) + len;
+        progress::report(Stage::Decompress, done, total);
+        progress(done, total)
+    })?;
+    let started = timings::start();
+    let decoded = blocks.iter().map(Vec::len).sum::<usize>();
+    let mut payload = Vec::with_capacity(total.map_or(decoded, |total| {
+        usize::try_from(total).map_or(decoded, |total| total.min(decoded))
+    }));
+    for block in blocks {
+        payload.extend(block);
+    }
+    if let Some(total) = total {
+        ensure!(
+            payload.len() as u64 == total,
+            "image decodes to {} bytes, but its header says {total}",
+            payload.len()
+        );
+    }
+    timings::record(Stage::Unpack, started, payload.len() as u64);
+    let len = payload.len() as u64;
+    progress::report(Stage::Unpack, len, Some(len));
+    Ok(payload)
+}
+
+/// What the header of a container says about its payload.
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct Info {
+    pub version: u8,
+    /// Name of the codec; the level it was compressed at is not stored.
+    pub codec: &'static str,
+    pub block_size: u64,
+    pub blocks: usize,
+    /// How many of the blocks are stored uncompressed, as they did not compress.
+    pub raw_blocks: usize,
+    /// Bytes of the whole container.
+    pub stored: usize,
+    /// Bytes of the payload, or `None` if its last block cannot be decoded, for want of
+    /// its dictionary or a pipeline stage.
+    pub len: Option<u64>,
+    pub dict_id: Option<u32>,
+    /// Names of the pipeline stages, in the order they were applied.
+    pub stages: Vec<String>,
+    pub content_type: Option<String>,
+    /// Seconds since the Unix epoch when the payload was encoded.
+    pub created: Option<u64>,
+    pub comment: Option<String>,
+    pub meta: BTreeMap<String, String>,
+    /// Whether the metadata is encrypted with the payload instead.
+    pub meta_hidden: bool,
+    /// ID of the payload, as [`format_id`] prints it.
+    pub id: Option<String>,
+}
+
+impl Info {
+    /// Reads the header of the container at the start of `bytes`, after any volume or
+    /// delta header. Containers that do not store the payload's length have only their
+    /// last block decoded to learn it.
+    pub fn read(bytes: &[u8], dict: Option<&Dictionary>) -> anyhow::Result<Self> {
+        let container = strip_prefix(bytes);
+        let index = Index::parse(container)?;
+        let len = match index.lengths.len().checked_sub(1) {
+            _ if index.fields.len.is_some() => index.fields.len,
+            None => Some(0),
+            Some(last) => index
+                .decode(container, last..=last, dict)
+                .ok()
+                .and_then(|block| {
+                    index
+                        .block_start(last)
+                        .checked_add(block.concat().len() as u64)
+                }),
+        };
+        let stages = match &index.fields.pipeline {
+            Some(descriptor) => Pipeline::stage_names(descriptor)?,
+            None => Vec::new(),
+        };
+        Ok(Info {
+            version: index.version,
+            codec: index.codec.name(),
+            block_size: index.block_size,
+            blocks: index.lengths.len(),
+            raw_blocks: (0..index.lengths.len())
+                .filter(|&i| index.is_raw(i))
+                .count(),
+            stored: index.len,
+            len,
+            dict_id: index.fields.dict_id,
+            stages,
+            content_type: index.fields.content_type,
+            created: index.fields.created,
+            comment: index.fields.comment,
+            meta: index.fields.meta,
+            meta_hidden: index.fields.meta_hidden,
+            id: index.fields.id.as_ref().map(format_id),
+        })
+    }
+}
+
+/// Where the parts of a container lie in the bytes an image holds, and whether each block
+/// still decodes, for drawing a map of the image.
+pub struct Layout {
+    /// Bytes before the first block: any volume or delta header and the index.
+    pub header: usize,
+    /// Where each block lies, as its header says, which may run past the bytes given.
+    pub blocks: Vec<Range<usize>>,
+    /// Whether each block decodes, or `None` without the dictionary that tells.
+    pub intact: Option<Vec<bool>>,
+}
+
+impl Layout {
+    /// Reads the layout of the container at the start of `bytes`, after any volume or
+    /// delta header, decoding each block on its own to learn which are damaged.
+    pub fn read(bytes: &[u8], dict: Option<&Dictionary>) -> anyhow::Result<Self> {
+        let container = strip_prefix(bytes);
+        let base = bytes.len() - container.len();
+        let index = Index::parse(container)?;
+        let blocks: Vec<Range<usize>> = index
+            .offsets
+            .iter()
+            .zip(&index.lengths)
+            .map(|(&offset, &len)| base + offset..base + offset + len)
+            .collect();
+        let intact = index.dictionary(dict).is_ok().then(|| {
+            (0..blocks.len())
+                .map(|i| index.decode(container, i..=i, dict).is_ok())
+                .collect()
+        });
+        Ok(Layout {
+            header: blocks.first().map_or(base + index.len, |block| block.start),
+            blocks,
+            intact,
+        })
+    }
+}
+
+/// Something that yields a growing prefix of a container, such as an image decoded row by row.
+pub trait Source {
+    /// Returns at least `len` leading bytes, or everything available if there are fewer.
+    fn prefix(&mut self, len: usize) -> anyhow::Result<&[u8]>;
+
+    /// How many bytes there are in all, if that is known before they are read.
+    fn total_len(&self) -> Option<usize> {
+        None
+    }
+
+    /// Returns the bytes from `start` on, at least up to `end` if there are that many.
+    /// Nothing before `start` is asked for again afterwards, so a source may let go of it.
+    fn window(&mut self, start: usize, end: usize) -> anyhow::Result<&[u8]> {
+        Ok(self.prefix(end)?.get(start..).unwrap_or_default())
+    }
+}
+
+/// Extracts `length` payload bytes starting at `offset`, decoding only the blocks
+/// that overlap the range and reading no further into `source` than their end.
+/// Images without a block index are decoded whole and then sliced.
+pub fn unpack_range(
+    source: &mut impl Source,
+    offset: u64,
+    length: Option<u64>,
+    dict: Option<&Dictionary>,
+) -> anyhow::Result<Vec<u8>> {
+    if !source.prefix(MAGIC.len())?.starts_with(MAGIC) {
+        let bytes = container::unpack_legacy(source.prefix(usize::MAX)?, max_output())?;
+        return slice(bytes, offset, length);
+    }
+    let header_len = Index::header_len(source)?;
+    let index = Index::parse(source.prefix(header_len)?)?;
+    let count = index.lengths.len();
+    let end = length.map_or(u64::MAX, |length| offset.saturating_add(length));
+    if count == 0 || end <= offset {
+        return Ok(Vec::new());
+    }
+    let first = index.block_at(offset);
+    let last = index.block_at(end - 1).min(count - 1);
+    if first > last {
+        return Ok(Vec::new());
+    }
+    let span = index.offsets[last] + index.lengths[last];
+    let bytes = source.prefix(span)?;
+    let blocks = index.decode(bytes, first..=last, dict)?.concat();
+    slice(blocks, offset - index.block_start(first), length)
+}
+
+/// Like [`unpack_range`], widened to the whole records the range touches, for payloads
+/// packed with [`Packing::split_on`]. Each of their blocks starts a record, so no block
+/// past those the range overlaps is decoded.
+pub fn unpack_records(
+    source: &mut impl Source,
+    offset: u64,
+    length: Option<u64>,
+    dict: Option<&Dictionary>,
+) -> anyhow::Result<Vec<u8>> {
+    ensure!(
+        source.prefix(MAGIC.len())?.starts_with(MAGIC),
+        "image has no block index, so it holds no records"
+    );
+    let header_len = Index::header_len(source)?;
+    let index = Index::parse(source.prefix(header_len)?)?;
+    let Some(delimiter) = index.fields.records.as_deref() else {
+        bail!("the payload was not split into records; encode it again with --split-on")
+    };
+    let count = index.lengths.len();
+    let end = length.map_or(u64::MAX, |length| offset.saturating_add(length));
+    if count == 0 || end <= offset {
+        return Ok(Vec::new());
+    }
+    let first = index.block_at(offset);
+    let last = index.block_at(end - 1).min(count - 1);
+    let span = index.offsets[last] + index.lengths[last];
+    let bytes = source.prefix(span)?;
+    let blocks = index.decode(bytes, first..=last, dict)?.concat();
+    let base = index.block_start(first);
+    let from = usize::try_from((offset - base).min(blocks.len() as u64))?;
+    let until = usize::try_from((end - base).min(blocks.len() as u64))?;
+    if from == blocks.len() {
+        return Ok(Vec::new());
+    }
+    let matches = |w: &[u8]| w == delimiter;
+    // The record holding the first byte starts after the last delimiter before it, and
+    // the one holding the last byte ends with the first delimiter that ends at or after.
+    let start = blocks[..from]
+        .windows(delimiter.len())
+        .rposition(matches)
+        .map_or(0, |at| at + delimiter.len());
+    let search = until.saturating_sub(delimiter.len()).max(start);
+    let stop = blocks[search..]
+        .windows(delimiter.len())
+        .position(matches)
+        .map_or(blocks.len(), |at| search + at + delimiter.len());
+    Ok(blocks[start..stop].to_vec())
+}
+
+/// Writes the payload of the container in `source` to `out` as it is read, decoding
+/// blocks in parallel a batch at a time and writing each batch once the bytes up to its
+/// end are available, so that a reader of `out` can start before the whole image is.
+/// Only the batch being decoded is held, and it is read no sooner than `out` takes the
+/// one before, so a slow reader holds decoding back rather than letting it pile up.
+/// `progress` is called as in [`unpack_with_progress`]. Images without a block index
+/// are decoded whole.
+pub fn unpack_to(
+    source: &mut impl Source,
+    dict: Option<&Dictionary>,
+    out: &mut impl Write,
+    progress: impl Fn(u64, Option<u64>),
+) -> anyhow::Result<()> {
+    if !source.prefix(MAGIC.len())?.starts_with(MAGIC) {
+        return Ok(out.write_all(&container::unpack_legacy(
+            source.prefix(usize::MAX)?,
+            max_output(),
+        )?)?);
+    }
+    let header_len = Index::header_len(source)?;
+    let index = Index::parse(source.prefix(header_len)?)?;
+    if let Some(len) = source.total_len() {
+        index.check_stored(len)?;
+    }
+    let total = index.fields.len;
+    check_output(total.unwrap_or(0))?;
+    let count = index.lengths.len();
+    let batch = rayon::current_num_threads().max(1);
+    let mut written = 0;
+    for first in (0..count).step_by(batch) {
+        let last = (first + batch).min(count) - 1;
+        let start = index.offsets[first];
+        let bytes = source.window(start, index.offsets[last] + index.lengths[last])?;
+        let blocks = index.decode_with(bytes, start, first..=last, dict, None, &|_| {
+            ControlFlow::Continue(())
+        })?;
+        for block in blocks {
+            written += block.len() as u64;
+            check_output(written)?;
+            out.write_all(&block)?;
+            progress::report(Stage::Decompress, written, total);
+            progress(written, total);
+        }
+        out.flush()?;
+    }
+    if let Some(total) = total {
+        ensure!(
+            written == total,
+            "image decodes to {written} bytes, but its header says {total}"
+        );
+    }
+    Ok(())
+}
+
+pub fn slice(mut bytes: Vec<u8>, offset: u64, length: Option<u64>) -> anyhow::Result<Vec<u8>> {
+    let skip = usize::try_from(offset.min(bytes.len() as u64))?;
+    bytes.drain(..skip);
+    if let Some(length) = length {
+        bytes.truncate(usize::try_from(length.min(bytes.len() as u64))?);
+    }
+    Ok(bytes)
+}
+
+/// A container already in memory.
+impl Source for &[u8] {
+    fn prefix(&mut self, _len: usize) -> anyhow::Result<&[u8]> {
+        Ok(self)
+    }
+
+    fn total_len(&self) -> Option<usize> {
+        Some(self.len())
+    }
+}
+
+/// A [`Source`] with its first `skip` bytes hidden, such as the container after a volume header.
+pub struct Skip<S>(pub S, pub usize);
+
+impl<S: Source> Source for Skip<S> {
+    fn prefix(&mut self, len: usize) -> anyhow::Result<&[u8]> {
+        let bytes = self.0.prefix(len.saturating_add(self.1))?;
+        Ok(bytes.get(self.1..).unwrap_or_default())
+    }
+
+    fn total_len(&self) -> Option<usize> {
+        Some(self.0.total_len()?.saturating_sub(self.1))
+    }
+
+    fn window(&mut self, start: usize, end: usize) -> anyhow::Result<&[u8]> {
+        self.0
+            .window(start.saturating_add(self.1), end.saturating_add(self.1))
+    }
+}
+
+impl Index {
+    /// Magic, version, codec and block size.
+    const FIXED_LEN: usize = 4 + 1 + 1 + 8;
+
+    /// Where block `i` starts in the payload.
+    fn block_start(&self, i: usize) -> u64 {
+        match self.fields.starts.get(i) {
+            Some(&start) => start,
+            None => (i as u64).saturating_mul(self.block_size),
+        }
+    }
+
+    /// The block that holds byte `offset` of the payload: the last if it is past the end
+    /// of blocks of uneven length, and one past the last, or more, if it is past the end
+    /// of blocks of the block size.
+    fn block_at(&self, offset: u64) -> usize {
+        if self.fields.starts.is_empty() {
+            usize::try_from(offset / self.block_size).unwrap_or(usize::MAX)
+        } else {
+            self.fields
+                .starts
+                .partition_point(|&start| start <= offset)
+                .saturating_sub(1)
+        }
+    }
+
+    /// Reads just enough of `source` to learn how long the whole index is.
+    fn header_len(source: &mut impl Source) -> anyhow::Result<usize> {
+        let prefix = source.prefix(Self::FIXED_LEN + 4)?;
+        let mut reader = Reader(prefix.get(4..).unwrap_or_default());
+        let version = reader.u8()?;
+        reader.take(1 + 8)?;
+        let mut len = Self::FIXED_LEN;
+        if version >= 2 {
+            len += 4 + usize::try_from(reader.u32()?)?;
+        }
+        let prefix = source.prefix(len.saturating_add(4))?;
+        let mut reader = Reader(prefix.get(len..).unwrap_or_default());
+        let count = usize::try_from(reader.u32()?)?;
+        count
+            .checked_mul(8)
+            .and_then(|lengths| lengths.checked_add(len + 4))
+            .ok_or(anyhow::Error::msg(
+                "block count overflows the header length",
+            ))
+    }
+
+    /// Checks that `dict` is the one the blocks were compressed with.
+    fn dictionary<'a>(
+        &self,
+        dict: Option<&'a Dictionary>,
+    ) -> anyhow::Result<Option<&'a Dictionary>> {
+        match (self.fields.dict_id, dict) {
+            (None, _) => Ok(None),
+            (Some(id), None) => Err(diagnostic::error(
+                Code::Dictionary,
+                format!("image was compressed with zstd dictionary {id:08x}, which was not given"),
+            )),
+            (Some(id), Some(dict)) if dict.id != id => Err(diagnostic::error(
+                Code::Dictionary,
+                format!(
+                    "image needs zstd dictionary {id:08x}, but the given one is {:08x}",
+                    dict.id
+                ),
+            )),
+            (Some(_), Some(dict)) => Ok(Some(dict)),
+        }
+    }
+
+    /// The stages the blocks passed through, rebuilt from the registry.
+    fn pipeline(&self) -> anyhow::Result<Pipeline> {
+        self.fields
+            .pipeline
+            .as_deref()
+            .map_or_else(|| Ok(Pipeline::new()), Pipeline::from_descriptor)
+    }
+
+    /// Undoes the pipeline on the given blocks and decompresses them, in parallel.
+    fn decode(
+        &self,
+        bytes: &[u8],
+        blocks: impl Iterator<Item = usize>,
+        dict: Option<&Dictionary>,
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        self.decode_with(bytes, 0, blocks, dict, None, &|_| ControlFlow::Continue(()))
+    }
+
+    /// Like [`Index::decode`], with `bytes` starting at offset `base` of the container,
+    /// decompressing with the contexts of `context`, if given, and its dictionary, and
+    /// calling `done` with the length of each block decoded. Once `done` breaks, no block
+    /// is started and the error is [`Cancelled`].
+    fn decode_with(
+        &self,
+        bytes: &[u8],
+        base: usize,
+        blocks: impl Iterator<Item = usize>,
+        dict: Option<&Dictionary>,
+        context: Option<&Context>,
+        done: &(dyn Fn(u64) -> ControlFlow<()> + Sync),
+    ) -> anyhow::Result<Vec<Vec<u8>>> {
+        let dict = match context {
+            Some(context) => context.dictionary(),
+            None => dict,
+        };
+        let dict = self.dictionary(dict)?.map_or(&[][..], |dict| &dict.bytes);
+        let pipeline = self.pipeline()?;
+        // A block never holds more than the block size, so reading one past it tells a
+        // block made to expand without end. Nor may it hold more than is left of the
+        // output limit, which is checked as blocks are done.
+        let limit = |decoded: u64| {
+            self.block_size
+                .min(max_output().saturating_sub(decoded))
+                .saturating_add(1)
+        };
+        let total = AtomicU64::new(0);
+        let cancelled = AtomicBool::new(false);
+        let blocks = blocks
+            .map(|i| {
+                let start = self.offsets[i] - base;
+                let block = bytes
+                    .get(start..start + self.lengths[i])
+                    .ok_or_else(truncated)?;
+                Ok((block, self.is_raw(i)))
+            })
+            .collect::<anyhow::Result<Vec<(&[u8], bool)>>>()?;
+        let started = timings::start();
+        let blocks = blocks
+            .into_par_iter()
+            .map(|(block, raw)| {
+                if cancelled.load(Ordering::Relaxed) {
+                    bail!(Cancelled {
+                        done: total.load(Ordering::Relaxed),
+                        total: self.fields.len,
+                    });
+                }
+                let block = pipeline.reverse(block)?;
+                let limit = limit(total.load(Ordering::Relaxed));
+                let block = match self.codec {
+                    _ if raw => block.into_owned(),
+                    Codec::Raw => block.into_owned(),
+                    Codec::Zlib(_) => {
+                        let mut out = Vec::new();
+                        ZlibDecoder::new(&block[..])
+                            .take(limit)
+                            .read_to_end(&mut out)
+                            .map_err(|error| damaged(&error))?;
+                        out
+                    }
+                    Codec::Zstd(_) => match context {
+                        Some(context) => context.decompress(&block, limit)?,
+                        None => zstd_decompress(&block, dict, limit)?,
+                    },
+                };
+                let len = block.len() as u64;
+                ensure!(
+                    len <= self.block_size,
+                    "a block decodes to more than the block size of {} bytes",
+                    self.block_size
+                );
+                check_output(total.fetch_add(len, Ordering::Relaxed) + len)?;
+                if done(len).is_break() {
+                    cancelled.store(true, Ordering::Relaxed);
+                }
+                Ok(block)
+            })
+            .collect::<anyhow::Result<Vec<_>>>()?;
+        timings::record(Stage::Decompress, started, total.into_inner());
+        Ok(blocks)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::Cell;
+    use std::rc::Rc;
+
+    const BLOCK: usize = 4096;
+
+    /// A container in memory that records each window asked of it, with how many payload
+    /// bytes had been taken by the reader by then.
+    struct Watched<'a> {
+        bytes: &'a [u8],
+        taken: Rc<Cell<usize>>,
+        windows: Vec<(usize, usize, usize)>,
+    }
+
+    impl Source for Watched<'_> {
+        fn prefix(&mut self, _len: usize) -> anyhow::Result<&[u8]> {
+            Ok(self.bytes)
+        }
+
+        fn window(&mut self, start: usize, end: usize) -> anyhow::Result<&[u8]> {
+            self.windows.push((start, end, self.taken.get()));
+            Ok(&self.bytes[start..])
+        }
+    }
+
+    /// A reader that takes a few hundred bytes at a time, as a slow one of a pipe does.
+    struct Throttled {
+        taken: Rc<Cell<usize>>,
+        out: Vec<u8>,
+    }
+
+    impl Write for Throttled {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            let len = buf.len().min(300);
+            self.out.extend_from_slice(&buf[..len]);
+            self.taken.set(self.out.len());
+            Ok(len)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn a_slow_reader_holds_decoding_back_a_batch_at_a_time() {
+        let payload: Vec<u8> = (0..64 * BLOCK)
+            .map(|i| u8::try_from(i % 253).unwrap())
+            .collect();
+        let container = pack(
+            &payload,
+            Packing {
+                block_size: Some(BLOCK),
+                ..Packing::new(Codec::Raw)
+            },
+        )
+        .unwrap();
+        let taken = Rc::new(Cell::new(0));
+        let mut source = Watched {
+            bytes: &container,
+            taken: Rc::clone(&taken),
+            windows: Vec::new(),
+        };
+        let mut out = Throttled {
+            taken,
+            out: Vec::new(),
+        };
+        unpack_to(&mut source, None, &mut out, |_, _| {}).unwrap();
+        assert_eq!(out.out, payload);
+
+        // Each batch is read only once the reader has taken every block before it, and
+        // spans that batch alone.
+        let index = Index::parse(&container).unwrap();
+        let batch = rayon::current_num_threads().max(1);
+        let expected: Vec<_> = (0..index.lengths.len())
+            .step_by(batch)
+            .map(|first| {
+                let last = (first + batch).min(index.lengths.len()) - 1;
+                let end = index.offsets[last] + index.lengths[last];
+                (index.offsets[first], end, first * BLOCK)
+            })
+            .collect();
+        assert_eq!(source.windows, expected);
+    }
+}
@@ -0,0 +1,109 @@
+//! Encryption by the gpg on PATH, so that payloads are encrypted to keys an existing
+//! keyring already manages.
+use anyhow::{bail, Context};
+use std::io::Read;
+use std::path::Path;
+use std::process::{Child, Command, Stdio};
+use std::thread::Scope;
+
+/// Encrypts what `input` reads to every one of `recipients`, key IDs or user IDs gpg
+/// can find, writing the message to `out`. The plaintext reaches gpg through a pipe,
+/// never a file of its own.
+pub fn encrypt(
+    mut input: impl Read + Send,
+    recipients: &[String],
+    out: &Path,
+) -> anyhow::Result<()> {
+    let mut command = gpg();
+    command.arg("--encrypt").arg("--output").arg(out);
+    for recipient in recipients {
+        command.arg("--recipient").arg(recipient);
+    }
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("cannot run gpg; is GnuPG installed and on PATH?")?;
+    let mut stdin = child.stdin.take().context("gpg has no stdin")?;
+    let (fed, output) = std::thread::scope(|scope| {
+        let feeder = scope.spawn(move || std::io::copy(&mut input, &mut stdin));
+        let output = child.wait_with_output();
+        let fed = feeder
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic));
+        (fed, output)
+    });
+    let output = output?;
+    if !output.status.success() {
+        bail!(
+            "encrypting with gpg failed ({}): {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    fed.context("cannot pass the payload to gpg")?;
+    Ok(())
+}
+
+/// Decrypts the message `input` reads with whichever secret key gpg holds for it, as a
+/// reader of the plaintext, fed by a thread of `scope`. Only as much is decrypted as is
+/// read before the reader is dropped, which stops gpg.
+pub fn decrypt_reader<'scope>(
+    scope: &'scope Scope<'scope, '_>,
+    mut input: impl Read + Send + 'scope,
+) -> anyhow::Result<Decrypted> {
+    let mut child = gpg()
+        .arg("--decrypt")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .context("cannot run gpg; is GnuPG installed and on PATH?")?;
+    let mut stdin = child.stdin.take().context("gpg has no stdin")?;
+    // Fails once gpg is stopped, which is how it ends early.
+    scope.spawn(move || std::io::copy(&mut input, &mut stdin));
+    Ok(Decrypted(child))
+}
+
+/// The plaintext of [`decrypt_reader`], which reports gpg's error once it ends.
+pub struct Decrypted(Child);
+
+impl Read for Decrypted {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let stdout = self
+            .0
+            .stdout
+            .as_mut()
+            .ok_or(std::io::ErrorKind::BrokenPipe)?;
+        let read = stdout.read(buf)?;
+        if read == 0 && !buf.is_empty() {
+            let status = self.0.wait()?;
+            if !status.success() {
+                let mut message = String::new();
+                if let Some(stderr) = &mut self.0.stderr {
+                    stderr.read_to_string(&mut message)?;
+                }
+                return Err(std::io::Error::other(format!(
+                    "decrypting with gpg failed ({status}): {}",
+                    message.trim()
+                )));
+            }
+        }
+        Ok(read)
+    }
+}
+
+impl Drop for Decrypted {
+    fn drop(&mut self) {
+        // It may have ended already, and nothing more is wanted from it either way.
+        let _ = self.0.kill();
+        let _ = self.0.wait();
+    }
+}
+
+fn gpg() -> Command {
+    let mut command = Command::new("gpg");
+    command.args(["--batch", "--yes", "--quiet"]);
+    command
+}
@@ -0,0 +1,135 @@
+//! `picturer gui`: a window that encodes any file dropped on it and decodes any PNG,
+//! writing the result next to the original.
+
+#[cfg(feature = "gui")]
+mod window {
+    use crate::format::Codec;
+    use crate::{convert, is_png};
+    use eframe::egui;
+    use std::path::PathBuf;
+    use std::sync::mpsc::{channel, Receiver, Sender};
+
+    /// Codecs offered in the window, the default first.
+    const CODECS: [Codec; 4] = [Codec::DEFAULT, Codec::ZSTD, Codec::Zlib(1), Codec::Raw];
+
+    /// A dropped file, and what became of it once its thread is done.
+    struct Job {
+        path: PathBuf,
+        result: Option<Result<String, String>>,
+    }
+
+    struct App {
+        codec: Codec,
+        jobs: Vec<Job>,
+        sender: Sender<(usize, Result<String, String>)>,
+        receiver: Receiver<(usize, Result<String, String>)>,
+    }
+
+    pub fn run() -> anyhow::Result<()> {
+        let (sender, receiver) = channel();
+        let app = App {
+            codec: Codec::DEFAULT,
+            jobs: Vec::new(),
+            sender,
+            receiver,
+        };
+        let options = eframe::NativeOptions {
+            viewport: egui::ViewportBuilder::default()
+                .with_inner_size([420.0, 320.0])
+                .with_drag_and_drop(true),
+            ..Default::default()
+        };
+        eframe::run_native("picturer", options, Box::new(|_| Ok(Box::new(app))))
+            .map_err(|error| anyhow::anyhow!("cannot open a window: {error}"))
+    }
+
+    impl App {
+        /// Converts `path` on its own thread: PNG files are decoded, anything else encoded.
+        fn start(&mut self, path: PathBuf, ctx: &egui::Context) {
+            let decode = is_png(&path);
+            let (index, codec) = (self.jobs.len(), self.codec);
+            let (sender, ctx) = (self.sender.clone(), ctx.clone());
+            let worker = path.clone();
+            std::thread::spawn(move || {
+                let result =
+                    convert(&worker, decode, Some(codec)).map_err(|error| format!("{error:#}"));
+                let _ = sender.send((index, result));
+                ctx.request_repaint();
+            });
+            self.jobs.push(Job { path, result: None });
+        }
+    }
+
+    impl eframe::App for App {
+        fn ui(&mut self, ui: &mut egui::Ui, _frame: &mut eframe::Frame) {
+            while let Ok((index, result)) = self.receiver.try_recv() {
+                self.jobs[index].result = Some(result);
+            }
+            let dropped: Vec<PathBuf> = ui.ctx().input(|input| {
+                input
+                    .raw
+                    .dropped_files
+                    .iter()
+                    .map(|file| file.path().to_path_buf())
+                    .collect()
+            });
+            for path in dropped {
+                self.start(path, ui.ctx());
+            }
+            let hovering = ui.ctx().input(|input| !input.raw.hovered_files.is_empty());
+
+            egui::CentralPanel::default().show(ui, |ui| {
+                ui.horizontal(|ui| {
+                    ui.label("Codec");
+                    egui::ComboBox::from_id_salt("codec")
+                        .selected_text(self.codec.to_string())
+                        .show_ui(ui, |ui| {
+                            for codec in CODECS {
+                                ui.selectable_value(&mut self.codec, codec, codec.to_string());
+                            }
+                        });
+                });
+                ui.separator();
+                ui.vertical_centered(|ui| {
+                    ui.add_space(24.0);
+                    ui.heading(if hovering {
+                        "Release to convert"
+                    } else {
+                        "Drop a file to encode it, or a PNG to decode it"
+                    });
+                    ui.label("The result is written next to the original.");
+                    ui.add_space(24.0);
+                });
+                egui::ScrollArea::vertical().show(ui, |ui| {
+                    for job in self.jobs.iter().rev() {
+                        let name = job.path.file_name().unwrap_or_default().to_string_lossy();
+                        match &job.result {
+                            None => ui.horizontal(|ui| {
+                                ui.spinner();
+                                ui.label(name);
+                            }),
+                            Some(Ok(message)) => ui.horizontal(|ui| {
+                                ui.label(format!("{name}: {message}"));
+                            }),
+                            Some(Err(error)) => ui.horizontal(|ui| {
+                                ui.colored_label(
+                                    ui.visuals().error_fg_color,
+                                    format!("{name}: {error}"),
+                                );
+                            }),
+                        };
+                    }
+                });
+            });
+        }
+    }
+}
+
+#[cfg(not(feature = "gui"))]
+mod window {
+    pub fn run() -> anyhow::Result<()> {
+        anyhow::bail!("picturer was built without the gui feature")
+    }
+}
+
+pub use window::run;
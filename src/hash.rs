@@ -0,0 +1,54 @@
+use crate::Options;
+use sha2::{Digest, Sha256};
+use std::fmt::Write as _;
+use std::io::{self, Write};
+
+#[derive(Clone, Copy, clap::ValueEnum)]
+pub enum Algorithm {
+    Sha256,
+    Blake3,
+}
+
+/// Prints a digest of the payload of `options.in_path` in the format of `sha256sum`,
+/// hashing it as it is decoded rather than writing it out.
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    let mut hasher = match options.algorithm {
+        Algorithm::Sha256 => Hasher::Sha256(Sha256::new()),
+        Algorithm::Blake3 => Hasher::Blake3(Box::new(blake3::Hasher::new())),
+    };
+    crate::decode_into(options, &mut hasher)?;
+    let digest = match hasher {
+        Hasher::Sha256(hasher) => hex(&hasher.finalize()),
+        Hasher::Blake3(hasher) => hex(hasher.finalize().as_bytes()),
+    };
+    println!("{digest}  {}", options.label);
+    Ok(())
+}
+
+enum Hasher {
+    Sha256(Sha256),
+    Blake3(Box<blake3::Hasher>),
+}
+
+impl Write for Hasher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Hasher::Sha256(hasher) => hasher.update(buf),
+            Hasher::Blake3(hasher) => {
+                hasher.update(buf);
+            }
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut s, byte| {
+        let _ = write!(s, "{byte:02x}");
+        s
+    })
+}
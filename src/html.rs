@@ -0,0 +1,29 @@
+use image::{ImageFormat, RgbaImage};
+use std::io::Cursor;
+use std::path::Path;
+
+/// Page with the image inlined and a script that decodes it in the browser.
+const TEMPLATE: &str = include_str!("extract.html");
+
+/// Writes `image` to `out` as a self-extracting page that downloads the payload as `name`.
+/// The script understands raw and zlib blocks only, and single images.
+pub fn write(image: &RgbaImage, name: &str, out: &Path) -> anyhow::Result<()> {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let png = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, png);
+    let page = TEMPLATE
+        .replace("{{PNG}}", &png)
+        .replace("{{TITLE}}", &escape(name))
+        .replace(
+            "{{NAME}}",
+            &serde_json::to_string(name)?.replace("</", "<\\/"),
+        );
+    Ok(std::fs::write(out, page)?)
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
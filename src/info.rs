@@ -0,0 +1,87 @@
+use crate::Options;
+use picturer::format::{Info, Volume};
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Prints what the header of the container in `options.in_path` says about its payload,
+/// decoding nothing but its last block.
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    let bytes = crate::read_input(options)?;
+    let info = Info::read(&bytes, options.dict.as_ref())?;
+    if options.json {
+        return print_json(options, &bytes, info);
+    }
+    println!("{}", options.label);
+    if let Some((volume, _)) = Volume::parse(&bytes)? {
+        println!(
+            "  volume:       {} of {}, payload bytes {} of {}",
+            volume.index + 1,
+            volume.count,
+            volume.offset,
+            volume.total
+        );
+    }
+    if let Some((delta, _)) = picturer::format::Delta::parse(&bytes)? {
+        println!("  patch of:     {}", delta.base);
+    }
+    println!("  format:       version {}", info.version);
+    match info.len {
+        Some(len) => println!("  payload:      {len} bytes"),
+        None => println!("  payload:      unknown length"),
+    }
+    println!(
+        "  stored:       {} bytes, {} in {} blocks of up to {} bytes",
+        info.stored, info.codec, info.blocks, info.block_size
+    );
+    if info.raw_blocks > 0 {
+        println!("  uncompressed: {} blocks", info.raw_blocks);
+    }
+    if let Some(id) = info.dict_id {
+        println!("  dictionary:   {id:08x}");
+    }
+    if !info.stages.is_empty() {
+        println!("  stages:       {}", info.stages.join(", "));
+    }
+    if let Some(content_type) = &info.content_type {
+        println!("  content type: {content_type}");
+    }
+    if let Some(created) = info.created {
+        let time = UNIX_EPOCH + Duration::from_secs(created);
+        println!(
+            "  created:      {}",
+            humantime::format_rfc3339_seconds(time)
+        );
+    }
+    if let Some(id) = &info.id {
+        println!("  id:           {id}");
+    }
+    if let Some(comment) = &info.comment {
+        println!("  comment:      {comment}");
+    }
+    for (key, value) in &info.meta {
+        println!("  meta:         {key}={value}");
+    }
+    if info.meta_hidden {
+        println!("  metadata:     encrypted with the payload");
+    }
+    Ok(())
+}
+
+/// Prints `info` as one JSON object, with the image it came from and any volume or
+/// delta header.
+fn print_json(options: &Options, bytes: &[u8], info: Info) -> anyhow::Result<()> {
+    let mut json = serde_json::to_value(info)?;
+    json["image"] = options.label.clone().into();
+    if let Some((volume, _)) = Volume::parse(bytes)? {
+        json["volume"] = serde_json::json!({
+            "index": volume.index,
+            "count": volume.count,
+            "offset": volume.offset,
+            "total": volume.total,
+        });
+    }
+    if let Some((delta, _)) = picturer::format::Delta::parse(bytes)? {
+        json["patch_of"] = delta.base.into();
+    }
+    println!("{}", serde_json::to_string_pretty(&json)?);
+    Ok(())
+}
@@ -0,0 +1,83 @@
+//! Cleaning up after SIGINT or SIGTERM, or Ctrl-C on Windows: the temporary files and the
+//! outputs being written are removed and the progress line ended before picturer exits,
+//! so an interrupted batch job leaves no half-written image for a later decode to trip
+//! over.
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, MutexGuard, PoisonError};
+
+/// Files written or left in the temporary directory, removed if the run is interrupted.
+static PARTIAL: Mutex<Vec<PathBuf>> = Mutex::new(Vec::new());
+
+/// Whether the progress line is on the terminal, unfinished.
+pub static PROGRESS_LINE: AtomicBool = AtomicBool::new(false);
+
+/// A file being written, removed if the run is interrupted before this is dropped.
+pub struct Partial(PathBuf);
+
+impl Partial {
+    pub fn new(path: &Path) -> Self {
+        lock().push(path.to_owned());
+        Partial(path.to_owned())
+    }
+}
+
+impl Drop for Partial {
+    fn drop(&mut self) {
+        let mut partial = lock();
+        if let Some(at) = partial.iter().rposition(|path| *path == self.0) {
+            partial.swap_remove(at);
+        }
+    }
+}
+
+/// Watches for interruptions from now on, exiting with 128 and the number of the signal,
+/// as a shell reports a process the signal killed. Without a way to watch, an
+/// interruption kills picturer as it would have anyway.
+#[cfg(unix)]
+pub fn install() {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+
+    let Ok(mut signals) = signal_hook::iterator::Signals::new([SIGINT, SIGTERM]) else {
+        return;
+    };
+    std::thread::spawn(move || {
+        if let Some(signal) = signals.forever().next() {
+            interrupted(128 + signal);
+        }
+    });
+}
+
+#[cfg(windows)]
+pub fn install() {
+    use signal_hook::consts::{SIGINT, SIGTERM};
+
+    for signal in [SIGINT, SIGTERM] {
+        // Windows calls console handlers on a thread of their own, not in the middle of
+        // whatever the interrupted thread was doing, so they may take locks and do I/O.
+        let _ =
+            unsafe { signal_hook::low_level::register(signal, move || interrupted(128 + signal)) };
+    }
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn install() {}
+
+/// Removes what is listed as partial, skipping anything but plain files, such as
+/// `/dev/stdout` or a FIFO given as the output, and exits with `code`.
+fn interrupted(code: i32) -> ! {
+    for path in lock().drain(..) {
+        if path.symlink_metadata().is_ok_and(|meta| meta.is_file()) {
+            let _ = std::fs::remove_file(path);
+        }
+    }
+    if PROGRESS_LINE.load(Ordering::Relaxed) {
+        eprintln!();
+    }
+    eprintln!("interrupted");
+    std::process::exit(code)
+}
+
+fn lock() -> MutexGuard<'static, Vec<PathBuf>> {
+    PARTIAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
@@ -0,0 +1,108 @@
+//! Named keys kept under `~/.config/picturer/keys`, made with `picturer key generate`
+//! so that signing and keyfiles need no other tool. Each is 32 random bytes, used as
+//! they are by `--sign-key`, `--keyfile` and `--recipient`, and for its public key by
+//! `--verify-key`; wherever one of them is given a path that does not exist, the key of
+//! that name is used.
+use crate::config;
+use crate::hash::hex;
+use crate::signature;
+use anyhow::{bail, ensure, Context};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+fn dir() -> anyhow::Result<PathBuf> {
+    config::dir()
+        .map(|dir| dir.join("keys"))
+        .context("cannot find the config directory: neither XDG_CONFIG_HOME nor HOME is set")
+}
+
+fn path(name: &str) -> anyhow::Result<PathBuf> {
+    ensure!(
+        !name.is_empty() && !name.starts_with('.') && !name.contains(['/', '\\']),
+        "{name:?} is not a key name: names are not empty, do not start with . and hold no /"
+    );
+    Ok(dir()?.join(format!("{name}.key")))
+}
+
+/// The key named `given`, if `given` is not a path that exists and there is one.
+pub fn named(given: &Path) -> Option<PathBuf> {
+    if given.exists() {
+        return None;
+    }
+    let path = path(given.to_str()?).ok()?;
+    path.exists().then_some(path)
+}
+
+/// `given`, or the key of that name if it is not a path that exists.
+pub fn resolve(given: &Path) -> PathBuf {
+    named(given).unwrap_or_else(|| given.to_owned())
+}
+
+/// Writes a new random key named `name`, readable by its owner only, and prints its
+/// public key.
+pub fn generate(name: &str) -> anyhow::Result<()> {
+    let path = path(name)?;
+    let dir = dir()?;
+    std::fs::create_dir_all(&dir).with_context(|| format!("cannot create {}", dir.display()))?;
+    let mut secret = Zeroizing::new([0; 32]);
+    getrandom::fill(secret.as_mut())?;
+    let mut options = std::fs::OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+    let mut file = match options.open(&path) {
+        Ok(file) => file,
+        Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+            bail!("there is already a key {name}, in {}", path.display())
+        }
+        Err(error) => {
+            return Err(error).with_context(|| format!("cannot write {}", path.display()))
+        }
+    };
+    file.write_all(secret.as_ref())?;
+    file.sync_all()?;
+    eprintln!("generated key {name} in {}", path.display());
+    export(name)
+}
+
+/// Prints the public key of the key named `name`, for `--verify-key` or the
+/// trusted-keys of whoever checks what it signs.
+pub fn export(name: &str) -> anyhow::Result<()> {
+    let path = path(name)?;
+    ensure!(
+        path.exists(),
+        "there is no key {name} in {}",
+        dir()?.display()
+    );
+    let key = signature::signing_key(&path)?;
+    println!("{}", hex(key.verifying_key().as_bytes()));
+    Ok(())
+}
+
+/// Prints the name and public key of every key, by name.
+pub fn list() -> anyhow::Result<()> {
+    let dir = dir()?;
+    let entries = match std::fs::read_dir(&dir) {
+        Ok(entries) => entries,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(error) => return Err(error).with_context(|| format!("cannot read {}", dir.display())),
+    };
+    let mut keys = Vec::new();
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "key") {
+            let name = path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            keys.push((name, signature::signing_key(&path)?.verifying_key()));
+        }
+    }
+    keys.sort_by(|(a, _), (b, _)| a.cmp(b));
+    for (name, key) in keys {
+        println!("{name}  {}", hex(key.as_bytes()));
+    }
+    Ok(())
+}
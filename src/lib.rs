@@ -0,0 +1,371 @@
+//! Bytes stored as the pixels of a PNG image, and read back.
+//!
+//! Without the std feature the crate is `no_std`, needing only `alloc`: it then holds
+//! [`container`], the layout functions here and [`rgba_bytes`], enough to decode an
+//! image whose pixels the caller reads out itself.
+#![cfg_attr(not(feature = "std"), no_std)]
+#![warn(clippy::pedantic)]
+#![allow(clippy::missing_errors_doc)]
+
+extern crate alloc;
+
+#[cfg(feature = "std")]
+pub mod builder;
+#[cfg(feature = "std")]
+pub mod cancel;
+#[cfg(feature = "capi")]
+pub mod capi;
+pub mod container;
+#[cfg(feature = "std")]
+pub mod context;
+pub mod diagnostic;
+#[cfg(feature = "std")]
+pub mod dict;
+#[cfg(feature = "std")]
+pub mod format;
+#[cfg(feature = "std")]
+pub mod pipeline;
+#[cfg(feature = "std")]
+pub mod progress;
+#[cfg(feature = "std")]
+pub mod timings;
+// napi registers nothing in test builds, which would leave the module unused.
+#[cfg(all(feature = "node", not(test)))]
+mod node;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "tokio")]
+mod tasks;
+#[cfg(all(target_arch = "wasm32", feature = "std"))]
+mod wasm;
+
+use alloc::borrow::Cow;
+use anyhow::ensure;
+#[cfg(feature = "std")]
+use anyhow::{bail, Error};
+#[cfg(feature = "std")]
+pub use builder::{Channels, Cipher, Decoder, Encoder};
+#[cfg(feature = "std")]
+pub use cancel::{Cancel, Cancelled};
+#[cfg(feature = "std")]
+pub use context::Context;
+#[cfg(feature = "std")]
+use dict::Dictionary;
+#[cfg(feature = "std")]
+use format::{Codec, Delta, Packing, Volume};
+#[cfg(feature = "std")]
+use image::metadata::Orientation;
+#[cfg(feature = "std")]
+use image::{DynamicImage, ImageBuffer, ImageDecoder, ImageFormat, ImageReader, Limits, RgbaImage};
+#[cfg(feature = "std")]
+use std::io::{BufRead, Cursor, Seek};
+#[cfg(feature = "tokio")]
+pub use tasks::{decode_async, encode_async};
+
+/// Largest width or height of a written image.
+pub const MAX_SIDE: usize = 1 << 14;
+/// Bytes of pixel data in the largest image written, 1 GiB.
+pub const MAX_BYTES: usize = MAX_SIDE * MAX_SIDE * 4;
+/// Bytes of pixel data in the largest image [`layout_opaque`] writes, 3 in each pixel.
+pub const MAX_OPAQUE_BYTES: usize = MAX_SIDE * MAX_SIDE * 3;
+
+#[cfg(feature = "std")]
+/// Decoder limits that let through the largest image picturer writes.
+#[must_use]
+pub fn limits() -> Limits {
+    let mut limits = Limits::default();
+    limits.max_alloc = Some(2 * MAX_BYTES as u64);
+    limits
+}
+
+#[cfg(feature = "std")]
+/// Decodes the image `reader` reads, within [`limits`], with its pixels as they are
+/// stored. An orientation tag a camera or editor added only tells viewers how to turn
+/// the picture, so it is returned rather than applied: a turned copy would hold its data
+/// bytes out of order.
+pub fn read_image<R: BufRead + Seek>(
+    mut reader: ImageReader<R>,
+) -> anyhow::Result<(DynamicImage, Orientation)> {
+    reader.limits(limits());
+    let mut decoder = reader.into_decoder()?;
+    let orientation = decoder.orientation()?;
+    limits().reserve(decoder.total_bytes())?;
+    Ok((DynamicImage::from_decoder(decoder)?, orientation))
+}
+
+#[cfg(feature = "std")]
+pub fn decode(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
+    format::unpack(bytes, None)
+}
+
+#[cfg(feature = "std")]
+pub fn encode(bytes: &[u8], codec: Codec) -> anyhow::Result<RgbaImage> {
+    layout(format::pack(bytes, Packing::new(codec))?)
+}
+
+#[cfg(feature = "std")]
+/// Like [`encode`], but lays the image out in `image`, whose buffer is reused once it
+/// has room, so that encoding many payloads one after another allocates no new images.
+pub fn encode_into(bytes: &[u8], codec: Codec, image: &mut RgbaImage) -> anyhow::Result<()> {
+    let mut buf = std::mem::take(image).into_raw();
+    buf.clear();
+    format::pack_into(&mut buf, bytes, Packing::new(codec))?;
+    *image = layout(buf)?;
+    Ok(())
+}
+
+#[cfg(feature = "std")]
+/// Like [`decode`], but of the pixels of `image` as [`layout`] or [`layout_opaque`] laid
+/// them out, into `out`, which is cleared first and keeps its capacity. Only an opaque
+/// image has its bytes copied out of it.
+pub fn decode_into(image: &RgbaImage, out: &mut Vec<u8>) -> anyhow::Result<()> {
+    out.clear();
+    let pixels = rgba_bytes(image.as_raw());
+    format::unpack_to(&mut &pixels[..], None, out, |_, _| {})
+}
+
+#[cfg(feature = "std")]
+/// Encodes `bytes` as a PNG file in memory. The payload must fit a single image.
+pub fn encode_png(bytes: &[u8], packing: Packing) -> anyhow::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    layout(format::pack(bytes, packing)?)?
+        .write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+#[cfg(feature = "std")]
+/// Decodes a PNG file in memory. Volumes and deltas need the other images they refer
+/// to, so only a self-contained image is accepted.
+pub fn decode_png(png: &[u8], dict: Option<&Dictionary>) -> anyhow::Result<Vec<u8>> {
+    let reader = ImageReader::new(Cursor::new(png)).with_guessed_format()?;
+    let pixels = pixels(canonical(read_image(reader)?.0)?);
+    ensure!(
+        Volume::parse(&pixels)?.is_none(),
+        "image is one volume of a set, which can only be decoded from files"
+    );
+    ensure!(
+        Delta::parse(&pixels)?.is_none(),
+        "image is a delta, which needs its base image to decode"
+    );
+    format::unpack(&pixels, dict)
+}
+
+/// Width and height of the image [`layout`] lays `len` bytes out in: square, or one
+/// row short of square, and never smaller than one pixel, which PNG needs.
+#[must_use]
+pub fn layout_size(len: usize) -> (usize, usize) {
+    grid(len.div_ceil(4))
+}
+
+/// Width and height of the image [`layout_opaque`] lays `len` bytes out in.
+#[must_use]
+pub fn opaque_layout_size(len: usize) -> (usize, usize) {
+    grid(len.div_ceil(3))
+}
+
+fn grid(pixels: usize) -> (usize, usize) {
+    let pixels = pixels.max(1);
+    let mut side = pixels.isqrt();
+    if side * side < pixels {
+        side += 1;
+    }
+    (side, pixels.div_ceil(side))
+}
+
+/// Width and height of the image a container of `len` bytes is laid out in, opaque or
+/// not, if it fits one.
+fn fit(len: usize, opaque: bool) -> anyhow::Result<(usize, usize)> {
+    if opaque {
+        ensure!(
+            len <= MAX_OPAQUE_BYTES,
+            "packed payload of {len} bytes does not fit an opaque {MAX_SIDE}x{MAX_SIDE} image"
+        );
+        Ok(opaque_layout_size(len))
+    } else {
+        ensure!(
+            len <= MAX_BYTES,
+            "packed payload of {len} bytes does not fit a {MAX_SIDE}x{MAX_SIDE} image"
+        );
+        Ok(layout_size(len))
+    }
+}
+
+#[cfg(feature = "std")]
+/// Lays a packed container out as the smallest square-ish RGBA image that holds it.
+pub fn layout(mut buf: Vec<u8>) -> anyhow::Result<RgbaImage> {
+    let (side, rows) = fit(buf.len(), false)?;
+    buf.resize(side * rows * 4, 0);
+    let img = RgbaImage::from_vec(u32::try_from(side)?, u32::try_from(rows)?, buf)
+        .ok_or(Error::msg("buffer too small"))?;
+    Ok(img)
+}
+
+#[cfg(feature = "std")]
+/// Lays a packed container out like [`layout`], but in the red, green and blue of each
+/// pixel, leaving every pixel opaque. Tools that drop an alpha channel nothing uses
+/// then leave the payload intact, and [`pixels`] reads it back either way.
+pub fn layout_opaque(buf: &[u8]) -> anyhow::Result<RgbaImage> {
+    let (width, height, rows) = layout_rows(buf, true)?;
+    let rgba = rows.collect::<Vec<_>>().concat();
+    RgbaImage::from_vec(width, height, rgba).ok_or(Error::msg("buffer too small"))
+}
+
+/// The width and height of the image [`layout`], or with `opaque` [`layout_opaque`],
+/// makes of `buf`, and its RGBA rows one at a time, so that a writer can take them
+/// without the whole padded image being held. Only the last row and opaque rows are
+/// copied.
+pub fn layout_rows(
+    buf: &[u8],
+    opaque: bool,
+) -> anyhow::Result<(u32, u32, impl Iterator<Item = Cow<'_, [u8]>>)> {
+    let (side, rows) = fit(buf.len(), opaque)?;
+    let row_bytes = side * if opaque { 3 } else { 4 };
+    let iter = (0..rows).map(move |row| {
+        let start = (row * row_bytes).min(buf.len());
+        let data = &buf[start..(start + row_bytes).min(buf.len())];
+        if !opaque && data.len() == row_bytes {
+            return Cow::Borrowed(data);
+        }
+        let mut data = data.to_vec();
+        data.resize(row_bytes, 0);
+        if opaque {
+            data = data
+                .chunks_exact(3)
+                .flat_map(|rgb| [rgb[0], rgb[1], rgb[2], u8::MAX])
+                .collect();
+        }
+        Cow::Owned(data)
+    });
+    Ok((u32::try_from(side)?, u32::try_from(rows)?, iter))
+}
+
+#[cfg(feature = "std")]
+/// `image` with 8-bit samples, the form [`pixels`] reads bytes out of, whatever color
+/// type the file was saved in by a tool that kept its pixels. Palettes are looked up
+/// as the image is decoded, and gray pixels stand for equal red, green and blue, which
+/// is all an optimizer turns into gray. Of 16-bit samples the high byte is kept, which
+/// is the 8-bit value whether it was widened by 257 or shifted up. Floating-point samples
+/// hold no exact bytes and are refused.
+pub fn canonical(image: DynamicImage) -> anyhow::Result<DynamicImage> {
+    fn narrow<P: image::Pixel<Subpixel = u8>>(
+        width: u32,
+        height: u32,
+        samples: &[u16],
+    ) -> anyhow::Result<ImageBuffer<P, Vec<u8>>> {
+        let high = samples
+            .iter()
+            .map(|sample| sample.to_be_bytes()[0])
+            .collect();
+        ImageBuffer::from_raw(width, height, high).ok_or(Error::msg("buffer too small"))
+    }
+    let (width, height) = (image.width(), image.height());
+    Ok(match image {
+        DynamicImage::ImageLuma16(image) => {
+            DynamicImage::ImageLuma8(narrow(width, height, image.as_raw())?)
+        }
+        DynamicImage::ImageLumaA16(image) => {
+            DynamicImage::ImageLumaA8(narrow(width, height, image.as_raw())?)
+        }
+        DynamicImage::ImageRgb16(image) => {
+            DynamicImage::ImageRgb8(narrow(width, height, image.as_raw())?)
+        }
+        DynamicImage::ImageRgba16(image) => {
+            DynamicImage::ImageRgba8(narrow(width, height, image.as_raw())?)
+        }
+        DynamicImage::ImageRgb32F(_) | DynamicImage::ImageRgba32F(_) => {
+            bail!("image has floating-point pixels, which no lossless copy of a picturer image has")
+        }
+        image => image,
+    })
+}
+
+#[cfg(feature = "std")]
+/// Whether `image` has no alpha channel, or one that leaves every pixel opaque, as
+/// [`layout_opaque`] writes. The header [`layout`] writes first makes its first pixel
+/// translucent, so the two cannot be mistaken for each other.
+#[must_use]
+pub fn is_opaque(image: &DynamicImage) -> bool {
+    if !image.color().has_alpha() {
+        return true;
+    }
+    match image.as_rgba8() {
+        Some(rgba) => rgba.pixels().all(|pixel| pixel[3] == u8::MAX),
+        None => image.to_rgba8().pixels().all(|pixel| pixel[3] == u8::MAX),
+    }
+}
+
+#[cfg(feature = "std")]
+/// The bytes the pixels of `image`, once [`canonical`], hold: every channel of each
+/// pixel, or only the red, green and blue of an image that [`is_opaque`].
+#[must_use]
+pub fn pixels(image: DynamicImage) -> Vec<u8> {
+    if is_opaque(&image) {
+        image.into_rgb8().into_raw()
+    } else {
+        image.into_rgba8().into_raw()
+    }
+}
+
+/// The bytes 8-bit RGBA pixels hold, as [`pixels`] reads them out of an image: every
+/// channel, or only the red, green and blue if every pixel is opaque. For callers that
+/// decode the image file themselves, as they must without std.
+#[must_use]
+pub fn rgba_bytes(rgba: &[u8]) -> Cow<'_, [u8]> {
+    if rgba.chunks_exact(4).all(|pixel| pixel[3] == u8::MAX) {
+        Cow::Owned(
+            rgba.chunks_exact(4)
+                .flat_map(|pixel| [pixel[0], pixel[1], pixel[2]])
+                .collect(),
+        )
+    } else {
+        Cow::Borrowed(rgba)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn largest_image_is_accepted() {
+        assert_eq!(fit(MAX_BYTES, false).unwrap(), (MAX_SIDE, MAX_SIDE));
+        assert_eq!(fit(MAX_OPAQUE_BYTES, true).unwrap(), (MAX_SIDE, MAX_SIDE));
+    }
+
+    #[test]
+    fn one_byte_more_is_rejected() {
+        assert!(fit(MAX_BYTES + 1, false).is_err());
+        assert!(fit(MAX_OPAQUE_BYTES + 1, true).is_err());
+        assert!(fit(MAX_BYTES, true).is_err());
+    }
+
+    #[test]
+    fn sizes_near_the_limit_stay_within_a_side() {
+        for len in [MAX_BYTES - 4, MAX_BYTES - 1, MAX_BYTES] {
+            let (width, height) = layout_size(len);
+            assert!(width <= MAX_SIDE && height <= MAX_SIDE);
+            assert!(width * height * 4 >= len);
+        }
+        assert_eq!(layout_size(MAX_BYTES + 1).0, MAX_SIDE + 1);
+    }
+
+    #[test]
+    fn empty_payload_is_laid_out_in_one_pixel() {
+        assert_eq!(layout_size(0), (1, 1));
+        assert_eq!(opaque_layout_size(0), (1, 1));
+    }
+
+    #[cfg(feature = "std")]
+    #[test]
+    fn empty_payload_round_trips_to_nothing() {
+        for codec in [Codec::Raw, Codec::DEFAULT] {
+            let png = encode_png(&[], Packing::new(codec)).unwrap();
+            let image = image::load_from_memory(&png).unwrap();
+            assert!(image.width() >= 1 && image.height() >= 1);
+            assert_eq!(decode_png(&png, None).unwrap(), b"");
+
+            let image = encode(&[], codec).unwrap();
+            assert_eq!(decode(image.as_raw()).unwrap(), b"");
+        }
+    }
+}
@@ -0,0 +1,140 @@
+//! `picturer list`: the files of an image made from a directory or with `--add`, read
+//! from the table at the start of its payload without decoding the rest. An encrypted
+//! payload is decrypted only as far as the table, so that its files are browsed without
+//! their contents being decrypted.
+use crate::{age, archive, entries, gpg, stream, Options};
+use anyhow::{bail, ensure};
+use picturer::format::{self, Source};
+use std::io::{BufRead, BufReader, Cursor, Read};
+use std::thread::Scope;
+
+/// Payload bytes decoded at a time when the payload is read as a message to decrypt.
+const STEP: u64 = 1 << 20;
+/// Leading bytes of an image read for the header of its container.
+const HEADER_PROBE: usize = 1 << 20;
+
+/// Prints the path of every file of the image in `options.in_path`, one per line.
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    std::thread::scope(|scope| {
+        let mut payload = open(options, scope)?;
+        for path in list(options, &mut payload)? {
+            println!("{path}");
+        }
+        Ok(())
+    })
+}
+
+fn list(options: &Options, payload: &mut Payload) -> anyhow::Result<Vec<String>> {
+    let prefix = payload.prefix(usize::try_from(entries::PREFIX_LEN)?)?;
+    if entries::is_entries(&prefix) {
+        let header = payload.prefix(usize::try_from(entries::header_len(&prefix)?)?)?;
+        return Ok(entries::parse(&header)?
+            .into_iter()
+            .map(|entry| entry.name)
+            .collect());
+    }
+    if prefix.starts_with(b"age-encryption") {
+        bail!(
+            "the payload of {} is encrypted with age; give the --age-identity to list it",
+            options.label
+        );
+    }
+    ensure!(
+        archive::is_archive(&prefix),
+        "{} holds a single file, not a directory or named entries",
+        options.label
+    );
+    // The table does not say how long it is, so more is read until it parses.
+    let mut len = 1 << 16;
+    loop {
+        let bytes = payload.prefix(len)?;
+        match archive::list(&bytes) {
+            Ok(paths) => return Ok(paths),
+            Err(_) if bytes.len() == len => len = len.saturating_mul(4),
+            Err(error) => return Err(error),
+        }
+    }
+}
+
+/// Where the leading bytes of the payload are read from.
+enum Payload<'a> {
+    /// The image, decoding only the blocks the bytes asked for lie in.
+    Image(&'a Options),
+    /// The plaintext of an encrypted payload.
+    Decrypted(stream::ReadStream<Box<dyn Read + 'a>>),
+    /// The plaintext of a payload encrypted with `--hide-metadata`, which is a container
+    /// of its own, stored raw, holding the metadata and the payload.
+    Hidden(stream::ReadStream<Box<dyn Read + 'a>>),
+}
+
+impl Payload<'_> {
+    fn prefix(&mut self, len: usize) -> anyhow::Result<Vec<u8>> {
+        Ok(match self {
+            Payload::Image(options) => {
+                let mut bytes = Vec::new();
+                crate::decode_range(options, 0, Some(len as u64), &mut bytes)?;
+                bytes
+            }
+            Payload::Decrypted(plaintext) => plaintext.prefix(len)?.to_vec(),
+            Payload::Hidden(plaintext) => {
+                format::unpack_range(plaintext, 0, Some(len as u64), None)?
+            }
+        })
+    }
+}
+
+/// Reads the payload as it is, or with `--gpg-decrypt`, `--age-identity` or encrypted
+/// metadata, as the plaintext gpg or age decrypt from it.
+fn open<'a>(options: &'a Options, scope: &'a Scope<'a, '_>) -> anyhow::Result<Payload<'a>> {
+    let hidden = if options.secrets.is_empty() {
+        let mut pixels = stream::PixelStream::open(&options.in_path)?;
+        format::meta_hidden(pixels.prefix(HEADER_PROBE)?)
+    } else {
+        format::meta_hidden(&crate::read_input(options)?)
+    };
+    if !hidden && !options.gpg_decrypt && options.age_identities.is_empty() {
+        return Ok(Payload::Image(options));
+    }
+    let mut message = BufReader::new(Message {
+        options,
+        offset: 0,
+        buf: Cursor::new(Vec::new()),
+    });
+    let plaintext: Box<dyn Read + 'a> = if !options.age_identities.is_empty() {
+        Box::new(age::decrypt_reader(message, &options.age_identities)?)
+    } else if options.gpg_decrypt || !message.fill_buf()?.starts_with(b"age-encryption.org/") {
+        // gpg asks for the passphrase of the key itself, if it has one.
+        Box::new(gpg::decrypt_reader(scope, message)?)
+    } else {
+        bail!(
+            "the payload of {} is encrypted with age; give the --age-identity to list it",
+            options.label
+        )
+    };
+    let plaintext = stream::ReadStream::new(plaintext);
+    Ok(if hidden {
+        Payload::Hidden(plaintext)
+    } else {
+        Payload::Decrypted(plaintext)
+    })
+}
+
+/// The encrypted payload of an image, decoded [`STEP`] bytes at a time as it is read.
+struct Message<'a> {
+    options: &'a Options,
+    offset: u64,
+    buf: Cursor<Vec<u8>>,
+}
+
+impl Read for Message<'_> {
+    fn read(&mut self, out: &mut [u8]) -> std::io::Result<usize> {
+        if self.buf.position() == self.buf.get_ref().len() as u64 {
+            let mut bytes = Vec::new();
+            crate::decode_range(self.options, self.offset, Some(STEP), &mut bytes)
+                .map_err(std::io::Error::other)?;
+            self.offset += bytes.len() as u64;
+            self.buf = Cursor::new(bytes);
+        }
+        self.buf.read(out)
+    }
+}
@@ -1,119 +1,2785 @@
 #![warn(clippy::pedantic)]
 
-use anyhow::{bail, Error};
-use flate2::bufread::{ZlibDecoder, ZlibEncoder};
-use image::{GenericImageView, ImageFormat, Rgba, RgbaImage};
-use std::env::args;
-use std::fmt::Write as WriteFmt;
+mod age;
+mod analyze;
+mod animation;
+mod archive;
+mod armor;
+mod audit;
+mod backup;
+mod barcode;
+mod beam;
+mod bench;
+mod checksum;
+mod cli;
+mod clipboard;
+mod config;
+mod copies;
+mod delta;
+mod entries;
+mod expand;
+mod fetch;
+mod gpg;
+mod gui;
+mod hash;
+mod html;
+mod info;
+mod interrupt;
+mod keys;
+mod list;
+mod manifest;
+mod member;
+mod migrate;
+mod mime;
+mod mosaic;
+mod pdf;
+mod pq;
+mod preview;
+mod raster;
+mod resample;
+mod robust;
+mod scan;
+mod selftest;
+mod serve;
+mod signature;
+mod space;
+mod spill;
+mod stego;
+mod stream;
+mod svg;
+mod target;
+mod tile;
+mod tui;
+mod upload;
+mod vectors;
+mod verify;
+mod video;
+mod volume;
+
+use anyhow::{bail, ensure, Context};
+use clap::error::ErrorKind;
+use clap::{CommandFactory, Parser, ValueEnum};
+use cli::{Cli, Command, DictCommand, Format, KeyCommand, PadTo, PngLevel};
+use dict::Dictionary;
+use format::{Codec, Delta, Packing, Source, Volume};
+use image::codecs::jpeg::JpegEncoder;
+use image::metadata::Orientation;
+use image::{DynamicImage, ImageFormat, ImageReader, RgbaImage};
+use picturer::progress::{self, Progress};
+use picturer::timings::{self, Stage};
+use picturer::{decode, diagnostic, dict, encode, format, layout, MAX_BYTES};
+use sha2::{Digest, Sha256};
+use std::borrow::Cow;
+use std::collections::BTreeMap;
 use std::fs::File;
-use std::io::{Read, Write};
-use std::path::PathBuf;
+use std::io::{BufWriter, IsTerminal, Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use std::sync::{Mutex, PoisonError};
 
-fn usage_err(name: &str, msg: &str) -> String {
-    let mut s = String::new();
-    let _ = writeln!(s, "USAGE: {name} <-e|-d> <in.file> [out.file]");
-    let _ = writeln!(s, "Modes:");
-    let _ = writeln!(s, "-e: Encode bytes as color to png");
-    let _ = writeln!(s, "-d: Decode png data back to bytes");
-    let _ = writeln!(s, "ERROR: {msg}");
-    s
-}
+/// Deepest chain of delta images followed when decoding.
+const MAX_DELTA_CHAIN: usize = 256;
+/// Bytes a payload must have for decoding it to show its progress.
+const PROGRESS_MIN: u64 = 64 << 20;
 
+#[derive(PartialEq, Eq)]
 enum Mode {
     Encode,
     Decode,
+    Verify,
+    Hash,
+    Info,
+    List,
+}
+
+/// Exits with `message` the way clap reports the errors it catches itself.
+fn usage_error(kind: ErrorKind, message: impl std::fmt::Display) -> ! {
+    Cli::command().error(kind, message).exit()
+}
+
+fn main() -> ExitCode {
+    interrupt::install();
+    progress::set_hook(show_progress);
+    match run() {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(error) => {
+            report(&error);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+/// Prints `error` with the errors that caused it under it and, if any of them has a
+/// code, the code and what to try instead.
+fn report(error: &anyhow::Error) {
+    let found = diagnostic::find(error);
+    match found {
+        Some(found) => eprintln!("error[{}]: {error}", found.code.name()),
+        None => eprintln!("error: {error}"),
+    }
+    for cause in error.chain().skip(1) {
+        eprintln!("  caused by: {cause}");
+    }
+    if let Some(found) = found {
+        eprintln!("  help: {}", found.code.help());
+    }
+}
+
+fn run() -> anyhow::Result<()> {
+    #[cfg(windows)]
+    if let Some(path) = dropped() {
+        return convert_dropped(&path);
+    }
+    let config = config::Config::load;
+    let (mode, options) = match Cli::parse().command {
+        Command::Encode(args) => (Mode::Encode, Options::encode(*args, config()?)?),
+        Command::Decode(args) => (Mode::Decode, Options::decode(args, config()?)?),
+        Command::Verify(args) => (Mode::Verify, Options::verify(args, config()?)?),
+        Command::Hash(args) => (Mode::Hash, Options::hash(args, config()?)?),
+        Command::Info(args) => (Mode::Info, Options::info(args, config()?)?),
+        Command::List(args) => (Mode::List, Options::list(args, config()?)?),
+        Command::Append { image, more, dict } => {
+            let dict = dict.as_deref().map(Dictionary::load).transpose()?;
+            return append(&image, &more, dict.as_ref());
+        }
+        Command::Scan { id, dir } => return scan::run(&id, &dir),
+        Command::Audit(args) => return audit::run(&args),
+        Command::Analyze(args) => return analyze::run(&args),
+        Command::Migrate(args) => return migrate::run(&args),
+        Command::Selftest { seed } => return selftest::run(seed),
+        Command::Vectors(command) => return vectors::run(&command),
+        Command::Backup(args) => return backup::backup(&args),
+        Command::Restore {
+            out,
+            store,
+            at,
+            allow_symlinks,
+        } => return backup::restore(&store, at, &out, allow_symlinks),
+        Command::Bench { input } => return bench::run(input.map(std::fs::read).transpose()?),
+        Command::Serve(args) => {
+            format::set_max_output(args.max_output_size.unwrap_or(serve::DEFAULT_MAX_OUTPUT));
+            return serve::run(serve::Config {
+                listen: args.listen,
+                limit: args.max_size,
+                workers: args
+                    .jobs
+                    .map_or_else(rayon::current_num_threads, usize::from),
+                tokens: args.tokens,
+            });
+        }
+        Command::Dict(DictCommand::Train { samples, out, size }) => {
+            return train_dict(&samples, &out, size)
+        }
+        Command::Key(KeyCommand::Generate { name }) => return keys::generate(&name),
+        Command::Key(KeyCommand::Export { name }) => return keys::export(&name),
+        Command::Key(KeyCommand::List) => return keys::list(),
+        Command::Key(KeyCommand::GeneratePq { output }) => return pq::generate(output.as_deref()),
+        Command::Join(args) => return join(&args, &config()?),
+        Command::Completions { shell } => {
+            clap_complete::generate(
+                shell,
+                &mut Cli::command(),
+                "picturer",
+                &mut std::io::stdout(),
+            );
+            return Ok(());
+        }
+        Command::Man { dir } => return man(dir.as_deref()),
+        Command::Presets => {
+            print_presets();
+            return Ok(());
+        }
+        Command::Capacity(args) => return capacity(&args),
+        Command::Beam(args) => return beam::send(&args),
+        Command::Receive(args) => return beam::receive(&args),
+        Command::Tui => return tui::run(),
+        Command::Gui => return gui::run(),
+    };
+    execute(&mode, options)
 }
 
-fn main() -> anyhow::Result<()> {
-    macro_rules! fail {
-        ($name:expr, $msg:expr) => {
-            return Err(anyhow::Error::msg(usage_err($name, $msg)))
+/// Stages the input and output `mode` needs, as `options` give them, then runs it.
+fn execute(mode: &Mode, mut options: Options) -> anyhow::Result<()> {
+    format::set_max_output(options.max_output);
+    if options.timings {
+        timings::enable();
+    }
+    let _input = options.stage_input(mode)?;
+    let _members = options.stage_member(mode)?;
+    let _unwrapped = options.stage_wrapped(mode)?;
+    let encrypted = options.stage_encrypted()?;
+    if *mode == Mode::Encode && encrypted.is_none() {
+        options.detect_type()?;
+    }
+    let output = options.stage_output(mode)?;
+    match mode {
+        Mode::Encode => encode_and_hand_on(&options)?,
+        Mode::Decode => check_and_decode(&mut options)?,
+        Mode::Verify => verify::run(&options)?,
+        Mode::Hash => hash::run(&options)?,
+        Mode::Info => info::run(&options)?,
+        Mode::List => list::run(&options)?,
+    }
+    match output {
+        Some(output) if options.data_uri => print_data_uri(&output.path)?,
+        Some(output) if options.to_stdout => {
+            write_stdout(&output.path, &mut std::io::stdout().lock())?;
+        }
+        Some(output) => clipboard::write(&output.path, *mode == Mode::Encode)?,
+        None => {}
+    }
+    if options.timings {
+        print_timings();
+    }
+    Ok(())
+}
+
+/// `--timings`: each stage that ran, with the time it took and the bytes it went
+/// through per second.
+fn print_timings() {
+    eprintln!(
+        "{:<12} {:>10} {:>14} {:>12}",
+        "stage", "time", "bytes", "MB/s"
+    );
+    for (stage, time, bytes) in timings::spent() {
+        #[allow(clippy::cast_precision_loss)]
+        let rate = bytes as f64 / time.as_secs_f64() / 1e6;
+        eprintln!(
+            "{:<12} {:>9.3}s {bytes:>14} {rate:>12.1}",
+            stage.name(),
+            time.as_secs_f64()
+        );
+    }
+}
+
+/// Checks the signature of and previews the input, decodes it, then decodes what it
+/// held and unpacks it as the options ask.
+fn check_and_decode(options: &mut Options) -> anyhow::Result<()> {
+    if !options.verify_keys.is_empty() {
+        signature::check(
+            options.given_path(),
+            &options.verify_keys,
+            options.require_signature,
+        )?;
+    }
+    if options.preview {
+        preview::show(&options.in_path)?;
+    }
+    let mut written = decode_file(options)?;
+    if options.recursive {
+        written = decode_nested(options, written)?;
+    }
+    if options.auto_extract && written.is_file() {
+        expand::run(
+            &written,
+            options.tree_dir.as_deref(),
+            options.allow_symlinks,
+        )?;
+    }
+    Ok(())
+}
+
+/// Encodes the input, then previews, armors, writes checksums and signatures of and
+/// uploads the images written, as the options ask.
+fn encode_and_hand_on(options: &Options) -> anyhow::Result<()> {
+    let template = options
+        .upload
+        .as_deref()
+        .map(upload::template)
+        .transpose()?;
+    let mut written = encode_file(options)?;
+    if options.preview
+        && matches!(
+            options.format,
+            Format::Png | Format::Jpeg | Format::DataMatrix | Format::Aztec
+        )
+    {
+        for path in &written {
+            preview::show(path)?;
+        }
+    }
+    if options.armor {
+        ensure!(
+            written.len() == 1,
+            "--armor needs a payload that fits one image"
+        );
+        written = written
+            .iter()
+            .map(|path| armor::wrap(path))
+            .collect::<anyhow::Result<_>>()?;
+    }
+    if options.copies > 1 {
+        ensure!(
+            written.len() == 1,
+            "--copies needs a payload that fits one image"
+        );
+        let copies = copies::write(&written[0], options.copies)?;
+        written.extend(copies);
+    }
+    for path in &written {
+        if options.write_checksum {
+            checksum::write(path)?;
+        }
+        if let Some(key) = &options.sign_key {
+            signature::write(path, key)?;
+        }
+    }
+    if let Some(template) = template {
+        upload::run(template, &written)?;
+    }
+    Ok(())
+}
+
+/// Prints the image at `path` as a `data:` URI, ready to paste into HTML, CSS or Markdown.
+fn print_data_uri(path: &Path) -> anyhow::Result<()> {
+    ensure!(
+        path.is_file(),
+        "--data-uri needs a payload that fits one image"
+    );
+    let bytes = std::fs::read(path)?;
+    let mime = image::guess_format(&bytes).map_or("image/png", |format| format.to_mime_type());
+    let data = base64::Engine::encode(&base64::engine::general_purpose::STANDARD, bytes);
+    println!("data:{mime};base64,{data}");
+    Ok(())
+}
+
+/// Copies the image or payload at `path` to `stdout` as it is. Rust writes bytes to
+/// standard output untranslated, on Windows too, where no text mode turns `\n` into
+/// `\r\n`.
+fn write_stdout(path: &Path, stdout: &mut impl Write) -> anyhow::Result<()> {
+    ensure!(
+        path.is_file(),
+        "only a single image or file can go to --stdout"
+    );
+    std::io::copy(&mut File::open(path)?, stdout)?;
+    Ok(stdout.flush()?)
+}
+
+/// What the mode needs from the command line.
+#[allow(clippy::struct_excessive_bools)]
+struct Options {
+    in_path: PathBuf,
+    out_path: Option<PathBuf>,
+    offset: u64,
+    length: Option<u64>,
+    /// Whether a range is widened to whole records, given with `--whole-records`.
+    whole_records: bool,
+    /// Bytes that end each record, given with `--split-on`, for blocks to end on.
+    split_on: Option<Vec<u8>>,
+    base: Option<PathBuf>,
+    codec: Codec,
+    dict: Option<Dictionary>,
+    /// Files given with `--add name=path`, packed as named entries instead of `in_path`.
+    entries: Vec<(String, PathBuf)>,
+    /// Entry to decode, given with `--name`.
+    name: Option<String>,
+    /// Whether to write a manifest when splitting into volumes.
+    manifest: bool,
+    /// Whether each PNG written says how to decode it, given with `--recovery-note`.
+    recovery_note: bool,
+    /// File that `verify` compares the decoded payload with.
+    against: Option<PathBuf>,
+    /// Whether `verify` checks the image against its checksum file, given with `--check`.
+    check: bool,
+    /// Whether `-e` writes a checksum file next to each image, given with
+    /// `--write-checksum`.
+    write_checksum: bool,
+    /// Key `-e` signs each image with, given with `--sign-key`.
+    sign_key: Option<ed25519_dalek::SigningKey>,
+    /// Keys `-d` checks the signature of the input against, given with `--verify-key`
+    /// and, with `--require-signature`, in the config file.
+    verify_keys: Vec<ed25519_dalek::VerifyingKey>,
+    /// Whether `-d` refuses an input without a signature, given with
+    /// `--require-signature`.
+    require_signature: bool,
+    /// Digest printed by `hash`.
+    algorithm: hash::Algorithm,
+    /// Where outputs go when no output path is given: next to the input, or in the
+    /// current directory under the last segment of a URL.
+    out_base: PathBuf,
+    /// Directory that replaces the directory of `out_base`, given with `--out-dir`.
+    out_dir: Option<PathBuf>,
+    /// Directory `-d` extracts a tree into as it is, given with `--output-dir`.
+    tree_dir: Option<PathBuf>,
+    /// Whether outputs named after the input are numbered rather than overwrite a file,
+    /// given with `--auto-rename`.
+    auto_rename: bool,
+    /// The numbered paths `out_path` gave for each extension, so that asking again gives
+    /// the same one once it has been written.
+    renamed: Mutex<BTreeMap<String, PathBuf>>,
+    /// Largest download accepted when the input is a URL.
+    max_download: u64,
+    /// Most payload bytes an image may decode to, given with `--max-output-size`.
+    max_output: u64,
+    /// Bytes a second downloads are held to, given with `--limit-rate`.
+    limit_rate: Option<u64>,
+    /// How messages name the input: its path, or the URL it was downloaded from.
+    label: String,
+    /// Command template or preset that `-e` hands the written images to.
+    upload: Option<String>,
+    /// Whether the input comes from the clipboard rather than `in_path`.
+    clipboard: bool,
+    /// Whether the output goes to the clipboard rather than `out_path`.
+    to_clipboard: bool,
+    /// Whether the output goes to standard output, with `--stdout`.
+    to_stdout: bool,
+    /// Whether to draw the written or decoded image in the terminal.
+    preview: bool,
+    /// Whether `-e` prints the image as a `data:` URI instead of keeping it.
+    data_uri: bool,
+    /// Whether `-e` writes the images as base64 text, given with `--armor`.
+    armor: bool,
+    /// How many identical images `-e` writes, given with `--copies`.
+    copies: u8,
+    /// The armored, ANSI art, SVG or PDF file given, once `in_path` points at the
+    /// image it held.
+    wrapper: Option<PathBuf>,
+    /// Whether `-e` writes a robust image, which survives re-compression.
+    robust: bool,
+    /// How hard `-e` deflates the PNG it writes, given with `--png-level`.
+    png_level: PngLevel,
+    /// Whether `-e` stores 3 bytes a pixel and leaves every pixel opaque, given with
+    /// `--opaque`.
+    opaque: bool,
+    /// Largest image `-e` writes, given with `--target-size`.
+    target_size: Option<u64>,
+    /// What `-e` pads each image up to, given with `--pad-to`.
+    pad_to: Option<PadTo>,
+    /// Width and height of the tiles `-e` cuts the image into, given with `--tile`.
+    tile: Option<(u32, u32)>,
+    /// How far a channel of a robust image may be from a level and still be read as it.
+    tolerance: u8,
+    /// How many volumes of a set `-d` decodes at once, given with `--jobs`.
+    jobs: usize,
+    /// Whether the input is a zip or tar file to find the image in, given with
+    /// `--from-archive`.
+    from_archive: bool,
+    /// Images `-e` hides the payload in, given with `--carrier`.
+    carriers: Vec<PathBuf>,
+    /// Low bits of each carrier channel the payload takes.
+    density: u8,
+    /// When `-e` records the payload was encoded, in seconds since the Unix epoch.
+    created: Option<u64>,
+    /// ID `-e` stores for the payload, random unless `--deterministic` leaves it to be
+    /// derived from the payload.
+    id: Option<[u8; 16]>,
+    /// Text `-e` stores to describe the payload, given with `--comment`.
+    comment: Option<String>,
+    /// Key/value pairs `-e` stores to describe the payload, given with `--meta`.
+    meta: BTreeMap<String, String>,
+    /// Whether `-e` encrypts the metadata with the payload, given with `--hide-metadata`,
+    /// and so leaves it out of the header.
+    hide_metadata: bool,
+    /// Whether `info` prints JSON, given with `--json`.
+    json: bool,
+    /// Passphrases and keyfiles of deniable carriers, to write or read.
+    secrets: Vec<stego::Secret>,
+    /// Keys `-e` encrypts the payload to with gpg, given with `--gpg-recipient`.
+    gpg_recipients: Vec<String>,
+    /// Whether `-d` decrypts the payload with gpg.
+    gpg_decrypt: bool,
+    /// Keys `-e` encrypts the payload to with age, given with `--age-recipient` and
+    /// `--recipient-ssh`.
+    age_recipients: age::Recipients,
+    /// Identities `-d` decrypts the payload with using age, given with `--age-identity`.
+    age_identities: age::Identities,
+    /// Whether `-d` decodes a payload that is a picturer image in turn, given with
+    /// `--recursive`.
+    recursive: bool,
+    /// Whether `-d` unpacks a decoded tar or zip file, given with `--auto-extract`.
+    auto_extract: bool,
+    /// The image given, once `in_path` is one nested in it, which no output may
+    /// overwrite either.
+    outermost: Option<PathBuf>,
+    /// MIME type of the input, stored for decoding to name its output by.
+    content_type: Option<String>,
+    /// Whether `-d` names outputs of images with no content type after their bytes.
+    sniff: bool,
+    /// How a directory input, or the original `verify` compares with, is packed.
+    tree: cli::Tree,
+    /// Whether the same input must always give the same image, given with
+    /// `--deterministic`.
+    deterministic: bool,
+    /// Whether `-d` recreates the links of an extracted directory.
+    allow_symlinks: bool,
+    /// Raw bytes per block `-e` compresses, given with `--chunk-size`.
+    chunk_size: Option<usize>,
+    /// Whether `-e` stores an input image as its pixels, given with `--wrap`.
+    wrap: bool,
+    /// Whether to print the time each stage took, given with `--timings`.
+    timings: bool,
+    format: Format,
+}
+
+impl Options {
+    /// The flags every mode shares, with the rest left at their defaults or as `config`
+    /// sets them.
+    fn new(
+        paths: Vec<PathBuf>,
+        common: cli::Common,
+        entries: Vec<(String, PathBuf)>,
+        staged: bool,
+        config: config::Config,
+    ) -> anyhow::Result<Self> {
+        let (in_path, out_path) = Self::paths(paths, common.clipboard, staged, &entries);
+        if let Some(dir) = &common.temp_dir {
+            fetch::set_temp_dir(dir)?;
+        }
+        Ok(Options {
+            out_base: in_path.clone(),
+            out_dir: config.out_dir,
+            tree_dir: None,
+            auto_rename: false,
+            renamed: Mutex::default(),
+            label: in_path.display().to_string(),
+            upload: None,
+            clipboard: common.clipboard,
+            to_clipboard: false,
+            to_stdout: false,
+            preview: false,
+            data_uri: false,
+            armor: false,
+            copies: 1,
+            wrapper: None,
+            robust: false,
+            png_level: PngLevel::for_codec(Codec::DEFAULT),
+            opaque: false,
+            target_size: None,
+            pad_to: None,
+            tile: None,
+            tolerance: robust::TOLERANCE,
+            from_archive: false,
+            jobs: rayon::current_num_threads(),
+            carriers: Vec::new(),
+            density: stego::DENSITY,
+            secrets: Vec::new(),
+            gpg_recipients: Vec::new(),
+            gpg_decrypt: false,
+            age_recipients: Vec::new(),
+            age_identities: Vec::new(),
+            recursive: false,
+            auto_extract: false,
+            outermost: None,
+            content_type: None,
+            created: None,
+            id: None,
+            comment: None,
+            meta: BTreeMap::new(),
+            hide_metadata: false,
+            json: false,
+            sniff: true,
+            tree: cli::Tree::default(),
+            deterministic: false,
+            allow_symlinks: false,
+            chunk_size: None,
+            wrap: false,
+            timings: common.timings,
+            format: Format::Png,
+            max_download: config.max_download.unwrap_or(fetch::DEFAULT_LIMIT),
+            max_output: config.max_output_size.unwrap_or(format::DEFAULT_MAX_OUTPUT),
+            limit_rate: config.limit_rate,
+            in_path,
+            out_path,
+            offset: 0,
+            length: None,
+            whole_records: false,
+            split_on: None,
+            base: common.base,
+            codec: Codec::DEFAULT,
+            dict: common.dict.as_deref().map(Dictionary::load).transpose()?,
+            entries,
+            name: None,
+            manifest: config.manifest,
+            recovery_note: false,
+            against: None,
+            check: false,
+            write_checksum: false,
+            sign_key: None,
+            verify_keys: Vec::new(),
+            require_signature: false,
+            algorithm: hash::Algorithm::Sha256,
+        })
+    }
+
+    /// Exits with a usage error if a flag that shapes the PNG is given with another format.
+    fn check_png_only(args: &cli::EncodeArgs) {
+        let png_only = [
+            ("--opaque", args.opaque),
+            ("--tile", args.tile.is_some()),
+            ("--target-size", args.target_size.is_some()),
+            ("--pad-to", args.pad_to.is_some()),
+        ];
+        if let Some((flag, _)) = png_only.iter().find(|(_, given)| *given) {
+            if args.format != Format::Png {
+                usage_error(
+                    ErrorKind::ArgumentConflict,
+                    format!("{flag} needs --format png"),
+                )
+            }
+        }
+    }
+
+    /// `--codec` and `--dict` go together: giving either, or a `--preset`, leaves both as
+    /// the config sets them unused, so a config dictionary never clashes with a codec on
+    /// the command line.
+    fn encode(mut args: cli::EncodeArgs, mut config: config::Config) -> anyhow::Result<Self> {
+        if let Some(preset) = args.preset {
+            args.manifest |= preset.manifest();
+            args.recovery_note |= preset.recovery_note();
+            args.robust |= preset.robust();
+            if args.common.dict.is_none() {
+                args.codec = args.codec.or(Some(preset.codec()));
+            }
+        }
+        if args.codec.is_none() && args.common.dict.is_none() {
+            args.codec = config.codec;
+            args.common.dict = config.dict.take();
+        }
+        let codec = match (args.codec, &args.common.dict) {
+            (Some(Codec::Zstd(level)), _) => Codec::Zstd(level),
+            (Some(codec), Some(_)) => usage_error(
+                ErrorKind::ArgumentConflict,
+                format!("--dict needs zstd, not {codec}"),
+            ),
+            (Some(codec), None) => codec,
+            (None, Some(_)) => Codec::ZSTD,
+            (None, None) => Codec::DEFAULT,
+        };
+        let staged = args.output.to_clipboard || args.output.stdout || args.data_uri;
+        Self::check_png_only(&args);
+        let options = Self::new(args.paths, args.common, args.add, staged, config)?;
+        Ok(Options {
+            codec,
+            manifest: args.manifest || options.manifest,
+            recovery_note: args.recovery_note,
+            upload: args.upload,
+            gpg_recipients: args.gpg_recipients,
+            age_recipients: args
+                .age_recipients
+                .iter()
+                .map(|given| age::recipients(given))
+                .chain(
+                    args.ssh_recipients
+                        .iter()
+                        .map(|path| age::ssh_recipients(path)),
+                )
+                .collect::<anyhow::Result<Vec<_>>>()?
+                .into_iter()
+                .flatten()
+                .collect(),
+            format: args.format,
+            data_uri: args.data_uri,
+            armor: args.armor,
+            copies: args.copies,
+            split_on: args.split_on,
+            created: timestamp(args.no_timestamp, args.deterministic)?,
+            id: if args.deterministic {
+                None
+            } else {
+                Some(random_id()?)
+            },
+            comment: args.comment,
+            meta: args.meta.into_iter().collect(),
+            hide_metadata: args.hide_metadata,
+            tree: args.tree,
+            deterministic: args.deterministic,
+            robust: args.robust,
+            png_level: args.png_level.unwrap_or(PngLevel::for_codec(codec)),
+            chunk_size: args.chunk_size.map(usize::try_from).transpose()?,
+            opaque: args.opaque,
+            target_size: args.target_size,
+            pad_to: args.pad_to,
+            wrap: args.wrap,
+            tile: args.tile,
+            write_checksum: args.write_checksum,
+            sign_key: args
+                .sign_key
+                .as_deref()
+                .map(signature::signing_key)
+                .transpose()?,
+            carriers: args.hiding.carriers,
+            density: args.hiding.density,
+            secrets: stego::Secret::given(
+                args.hiding.passphrase,
+                args.hiding.keyfile.as_deref(),
+                &args.hiding.recipients,
+                true,
+            )?,
+            preview: args.output.preview,
+            to_clipboard: args.output.to_clipboard,
+            to_stdout: args.output.stdout,
+            auto_rename: args.output.auto_rename,
+            out_dir: args.output.out_dir.or(options.out_dir.clone()),
+            ..options
+        })
+    }
+
+    fn decode(mut args: cli::DecodeArgs, mut config: config::Config) -> anyhow::Result<Self> {
+        args.common.dict = args.common.dict.or(config.dict.take());
+        if args.require_signature {
+            args.verify_key.append(&mut config.trusted_keys);
+            if args.verify_key.is_empty() {
+                usage_error(
+                    ErrorKind::MissingRequiredArgument,
+                    "--require-signature needs --verify-key or trusted-keys in the config file",
+                )
+            }
+        }
+        let verify_keys = args
+            .verify_key
+            .iter()
+            .map(|key| signature::verifying_key(key))
+            .collect::<anyhow::Result<_>>()?;
+        let staged = args.output.to_clipboard || args.output.stdout;
+        let options = Self::new(args.paths, args.common, Vec::new(), staged, config)?;
+        Ok(Options {
+            jobs: args
+                .jobs
+                .map_or_else(rayon::current_num_threads, usize::from),
+            offset: args.offset,
+            length: args.length,
+            whole_records: args.whole_records,
+            name: args.name,
+            gpg_decrypt: args.gpg_decrypt,
+            age_identities: age::identities(&args.age_identities)?,
+            sniff: !args.no_sniff,
+            tree_dir: args.output_dir.clone(),
+            allow_symlinks: args.allow_symlinks,
+            recursive: args.recursive,
+            auto_extract: args.auto_extract,
+            verify_keys,
+            require_signature: args.require_signature,
+            tolerance: args.recovery.tolerance,
+            from_archive: args.recovery.from_archive,
+            secrets: stego::Secret::given(
+                args.recovery.passphrase,
+                args.recovery.keyfile.as_deref(),
+                &[],
+                false,
+            )?,
+            max_download: args.remote.max_download.unwrap_or(options.max_download),
+            max_output: args.remote.max_output_size.unwrap_or(options.max_output),
+            limit_rate: args.remote.limit_rate.or(options.limit_rate),
+            preview: args.output.preview,
+            to_clipboard: args.output.to_clipboard,
+            to_stdout: args.output.stdout,
+            auto_rename: args.output.auto_rename,
+            out_dir: args
+                .output_dir
+                .or(args.output.out_dir)
+                .or(options.out_dir.clone()),
+            ..options
+        })
+    }
+
+    fn verify(mut args: cli::VerifyArgs, mut config: config::Config) -> anyhow::Result<Self> {
+        args.common.dict = args.common.dict.or(config.dict.take());
+        let options = Self::new(args.paths, args.common, Vec::new(), true, config)?;
+        Ok(Options {
+            against: args.against,
+            check: args.check,
+            tree: args.tree,
+            deterministic: args.deterministic,
+            codec: args.codec.unwrap_or(Codec::DEFAULT),
+            tolerance: args.recovery.tolerance,
+            from_archive: args.recovery.from_archive,
+            secrets: stego::Secret::given(
+                args.recovery.passphrase,
+                args.recovery.keyfile.as_deref(),
+                &[],
+                false,
+            )?,
+            max_download: args.remote.max_download.unwrap_or(options.max_download),
+            max_output: args.remote.max_output_size.unwrap_or(options.max_output),
+            limit_rate: args.remote.limit_rate.or(options.limit_rate),
+            ..options
+        })
+    }
+
+    fn hash(mut args: cli::HashArgs, mut config: config::Config) -> anyhow::Result<Self> {
+        args.common.dict = args.common.dict.or(config.dict.take());
+        let options = Self::new(args.paths, args.common, Vec::new(), true, config)?;
+        Ok(Options {
+            algorithm: args.algo,
+            tolerance: args.recovery.tolerance,
+            from_archive: args.recovery.from_archive,
+            secrets: stego::Secret::given(
+                args.recovery.passphrase,
+                args.recovery.keyfile.as_deref(),
+                &[],
+                false,
+            )?,
+            max_download: args.remote.max_download.unwrap_or(options.max_download),
+            max_output: args.remote.max_output_size.unwrap_or(options.max_output),
+            limit_rate: args.remote.limit_rate.or(options.limit_rate),
+            ..options
+        })
+    }
+
+    fn info(mut args: cli::InfoArgs, mut config: config::Config) -> anyhow::Result<Self> {
+        args.common.dict = args.common.dict.or(config.dict.take());
+        let options = Self::new(args.paths, args.common, Vec::new(), true, config)?;
+        Ok(Options {
+            tolerance: args.recovery.tolerance,
+            from_archive: args.recovery.from_archive,
+            secrets: stego::Secret::given(
+                args.recovery.passphrase,
+                args.recovery.keyfile.as_deref(),
+                &[],
+                false,
+            )?,
+            max_download: args.remote.max_download.unwrap_or(options.max_download),
+            max_output: args.remote.max_output_size.unwrap_or(options.max_output),
+            limit_rate: args.remote.limit_rate.or(options.limit_rate),
+            json: args.json,
+            ..options
+        })
+    }
+
+    fn list(mut args: cli::ListArgs, mut config: config::Config) -> anyhow::Result<Self> {
+        args.common.dict = args.common.dict.or(config.dict.take());
+        let options = Self::new(args.paths, args.common, Vec::new(), true, config)?;
+        Ok(Options {
+            gpg_decrypt: args.gpg_decrypt,
+            age_identities: age::identities(&args.age_identities)?,
+            tolerance: args.recovery.tolerance,
+            from_archive: args.recovery.from_archive,
+            secrets: stego::Secret::given(
+                args.recovery.passphrase,
+                args.recovery.keyfile.as_deref(),
+                &[],
+                false,
+            )?,
+            max_download: args.remote.max_download.unwrap_or(options.max_download),
+            max_output: args.remote.max_output_size.unwrap_or(options.max_output),
+            limit_rate: args.remote.limit_rate.or(options.limit_rate),
+            ..options
+        })
+    }
+
+    /// Input and output paths from the positional arguments. With `--clipboard` or
+    /// `--add` there is no input path, so the only one is the output; with a `staged`
+    /// output, or none at all, there is no output path.
+    fn paths(
+        paths: Vec<PathBuf>,
+        clipboard: bool,
+        staged: bool,
+        entries: &[(String, PathBuf)],
+    ) -> (PathBuf, Option<PathBuf>) {
+        let mut paths = paths.into_iter();
+        let (in_path, out_path) = if clipboard {
+            (PathBuf::from("clipboard"), paths.next())
+        } else if entries.is_empty() {
+            let Some(in_path) = paths.next() else {
+                usage_error(ErrorKind::MissingRequiredArgument, "missing input file")
+            };
+            (in_path, paths.next())
+        } else {
+            let Some(out_path) = paths.next() else {
+                usage_error(
+                    ErrorKind::MissingRequiredArgument,
+                    "--add needs an output image",
+                )
+            };
+            (PathBuf::new(), Some(out_path))
+        };
+        if paths.next().is_some() || (staged && out_path.is_some()) {
+            usage_error(ErrorKind::TooManyValues, "too many arguments")
+        }
+        (in_path, out_path)
+    }
+
+    /// Writes `container` to `path` as an image, opaque if `--opaque` asks for it, with
+    /// the note of `--recovery-note`.
+    fn write(&self, container: &[u8], path: &Path) -> anyhow::Result<()> {
+        let note = self.recovery_note.then(|| self.note(container));
+        let note = note.as_deref();
+        let padded = self.pad(container)?;
+        let container = padded.as_deref().unwrap_or(container);
+        if let Some(size) = self.tile {
+            let image = tile::layout(container, size)?;
+            let rows = image_rows(&image);
+            return write_rows(
+                path,
+                image.width(),
+                image.height(),
+                rows,
+                self.png_level,
+                note,
+            );
+        }
+        let (width, height, rows) = picturer::layout_rows(container, self.opaque)?;
+        write_rows(path, width, height, rows, self.png_level, note)
+    }
+
+    /// `container` followed by the random bytes `--pad-to` fills it up with, if any.
+    /// Decoding reads only as far as the container's own table says, so they are
+    /// skipped. A deterministic image is padded with bytes hashed from the container.
+    fn pad(&self, container: &[u8]) -> anyhow::Result<Option<Vec<u8>>> {
+        let Some(pad_to) = self.pad_to else {
+            return Ok(None);
+        };
+        let max = if self.opaque {
+            picturer::MAX_OPAQUE_BYTES
+        } else {
+            picturer::MAX_BYTES
         };
+        let len = pad_to.bucket(container.len() as u64, max as u64);
+        let mut padded = container.to_vec();
+        padded.resize(usize::try_from(len)?, 0);
+        let fill = &mut padded[container.len()..];
+        if self.deterministic {
+            blake3::Hasher::new()
+                .update(container)
+                .finalize_xof()
+                .fill(fill);
+        } else {
+            getrandom::fill(fill)?;
+        }
+        Ok(Some(padded))
+    }
+
+    /// What `--recovery-note` stores in an image of `container`: enough of the format to
+    /// get the payload back should picturer no longer be at hand.
+    fn note(&self, container: &[u8]) -> String {
+        let channels = if self.opaque {
+            "red, green and blue of each pixel, skipping alpha"
+        } else {
+            "red, green, blue and alpha of each pixel"
+        };
+        let mut note = format!(
+            "This image holds a file, stored by picturer {} in its pixels. Decode it with \
+             picturer decode IMAGE.\n\nWithout picturer: read the {channels}, row by row \
+             from the top left, as bytes.",
+            env!("CARGO_PKG_VERSION")
+        );
+        if self.tile.is_some() {
+            note.push_str(
+                " The image is cut into tiles, each starting with a header that begins \
+                 with PTIL; joined in the order their headers number them, they hold the \
+                 bytes that follow.",
+            );
+        }
+        if Volume::parse(container).is_ok_and(|volume| volume.is_some()) {
+            note.push_str(
+                " The bytes start with PICV and say which volume of a set this is and \
+                 where its part of the file goes; the part follows.",
+            );
+        }
+        if self.pad_to.is_some() {
+            note.push_str(" Random bytes may follow the container to pad the image out.");
+        }
+        note.push_str(
+            " A container starts with PICT, a version byte and a codec byte (0 raw, 1 zlib, \
+             2 zstd), then the block size (8 bytes), the length of a table of fields (4) \
+             and the table, the number of blocks (4) and the length of each (8 each), then \
+             the blocks, each compressed alone unless a field marks it stored raw. Integers \
+             are little-endian. Decompressed and joined, the blocks are the file.",
+        );
+        note
     }
-    let mut args = args();
-    let name = args.next().unwrap();
-    let mode = match args.next() {
-        Some(e) if e == "-e" => Mode::Encode,
-        Some(d) if d == "-d" => Mode::Decode,
-        Some(p) => fail!(&name, &format!("invalid mode {p}")),
-        _ => fail!(&name, "expected a mode and an input file."),
+
+    /// Most payload bytes a single image holds before it is split into volumes.
+    fn capacity(&self) -> u64 {
+        volume::capacity(self.opaque)
+    }
+
+    fn packing(&self) -> Packing<'_> {
+        Packing {
+            codec: self.codec,
+            dict: self.dict.as_ref(),
+            pipeline: None,
+            content_type: self.content_type.as_deref(),
+            created: self.created,
+            comment: self.comment.as_deref(),
+            meta: Some(&self.meta).filter(|meta| !meta.is_empty()),
+            meta_hidden: self.encrypts(),
+            block_size: self
+                .chunk_size
+                .or(self.deterministic.then_some(format::BLOCK_SIZE)),
+            id: self.id,
+            split_on: self.split_on.as_deref(),
+            context: None,
+            cancel: None,
+        }
+    }
+
+    fn is_range(&self) -> bool {
+        self.offset != 0 || self.length.is_some()
+    }
+
+    /// The output path given on the command line, or the input path with `extension`,
+    /// moved into `--out-dir` if one was given and, with `--auto-rename`, numbered if it
+    /// exists. With no extension it is the directory a tree is extracted into, which
+    /// `--output-dir` gives as it is.
+    fn out_path(&self, extension: &str) -> PathBuf {
+        if let Some(path) = self
+            .out_path
+            .clone()
+            .or_else(|| self.tree_dir.clone().filter(|_| extension.is_empty()))
+        {
+            return path;
+        }
+        let path = match &self.out_dir {
+            Some(dir) => dir
+                .join(self.out_base.file_name().unwrap_or_default())
+                .with_extension(extension),
+            None => self.out_base.with_extension(extension),
+        };
+        if !self.auto_rename {
+            return path;
+        }
+        self.renamed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .entry(extension.to_owned())
+            .or_insert_with(|| unused(path))
+            .clone()
+    }
+
+    /// Like `out_path("bin")`, with the extension of the payload's content type instead
+    /// if one is stored or, unless `--no-sniff`, its leading bytes `payload` give one
+    /// away, and it would not overwrite the
+    /// input, as a PNG decoded next to its image would. The stem of `arch.tar.png`
+    /// already ends in `.tar`, so it decodes to `arch.tar.gz`.
+    fn typed_out_path(&self, content_type: Option<&str>, payload: &[u8]) -> PathBuf {
+        let extension = match content_type {
+            Some(mime) => mime::extension(mime, payload),
+            None if self.sniff => mime::sniff(payload),
+            None => None,
+        };
+        let extension = extension.unwrap_or("bin");
+        let stem = Path::new(self.out_base.file_stem().unwrap_or_default());
+        let path = match extension.split_once('.') {
+            Some((inner, outer)) if stem.extension().is_some_and(|ext| ext == inner) => {
+                self.out_path(outer)
+            }
+            _ => self.out_path(extension),
+        };
+        let output = std::fs::canonicalize(&path).ok();
+        let overwrites =
+            |input: &Path| output.is_some() && std::fs::canonicalize(input).ok() == output;
+        if overwrites(&self.in_path) || self.outermost.as_deref().is_some_and(overwrites) {
+            return self.out_path("bin");
+        }
+        path
+    }
+
+    /// Saves the clipboard, or copies the input if it is a pipe or a URL to download, and
+    /// points `in_path` at the copy, which lasts until the returned guard is dropped.
+    /// Decoding reads its input more than once, which a pipe cannot do.
+    fn stage_input(&mut self, mode: &Mode) -> anyhow::Result<Option<fetch::TempFile>> {
+        if self.clipboard {
+            let file = clipboard::read(*mode == Mode::Encode)?;
+            self.in_path.clone_from(&file.path);
+            "clipboard".clone_into(&mut self.label);
+            return Ok(Some(file));
+        }
+        if *mode == Mode::Encode {
+            return Ok(None);
+        }
+        if is_pipe(&self.in_path) {
+            let name = self.in_path.file_name().unwrap_or("stdin".as_ref());
+            let copy = fetch::TempFile::new(&name.to_string_lossy())?;
+            std::io::copy(
+                &mut File::open(&self.in_path)?,
+                &mut File::create(&copy.path)?,
+            )?;
+            self.out_base = PathBuf::from(name);
+            self.in_path.clone_from(&copy.path);
+            return Ok(Some(copy));
+        }
+        if !fetch::is_url(&self.in_path) {
+            return Ok(None);
+        }
+        let url = self.in_path.to_string_lossy().into_owned();
+        if let Some(rate) = self.limit_rate {
+            fetch::set_rate_limit(rate);
+        }
+        let download = fetch::download(&url, self.max_download)?;
+        self.out_base = PathBuf::from(fetch::file_name(&url));
+        self.in_path.clone_from(&download.path);
+        self.label = url;
+        Ok(Some(download))
+    }
+
+    /// Copies the image out of the zip or tar file given as `ARCHIVE::MEMBER`, or with
+    /// `--from-archive` as the input, with the rest of its volume set, and points
+    /// `in_path` at it, which lasts until the returned guards are dropped.
+    fn stage_member(&mut self, mode: &Mode) -> anyhow::Result<Vec<fetch::TempFile>> {
+        if *mode == Mode::Encode {
+            return Ok(Vec::new());
+        }
+        let (member, files) = if self.from_archive {
+            member::scan(&self.in_path)?
+        } else if let Some((archive, name)) = member::split(&self.in_path) {
+            member::extract(&archive, &name)?
+        } else {
+            return Ok(Vec::new());
+        };
+        if self.from_archive {
+            self.label = format!("{}::{member}", self.label);
+        }
+        self.out_base = PathBuf::from(Path::new(&member).file_name().unwrap_or_default());
+        self.in_path.clone_from(&files[0].path);
+        Ok(files)
+    }
+
+    /// The file given as input, whose signature and checksum sit next to it: the one
+    /// that [`is_wrapped`] rather than the image it held.
+    fn given_path(&self) -> &Path {
+        self.wrapper.as_deref().unwrap_or(&self.in_path)
+    }
+
+    /// Points `in_path` at the image an input that [`is_wrapped`] holds, which lasts
+    /// until the returned guard is dropped, keeping the file given in `wrapper`.
+    fn stage_wrapped(&mut self, mode: &Mode) -> anyhow::Result<Option<fetch::TempFile>> {
+        if *mode == Mode::Encode || !self.in_path.is_file() {
+            return Ok(None);
+        }
+        let image = fetch::TempFile::new("unwrapped.png")?;
+        if !unwrap_image(&self.in_path, &image.path)? {
+            return Ok(None);
+        }
+        if self
+            .out_base
+            .extension()
+            .is_some_and(|extension| extension == "asc")
+        {
+            self.out_base = self.out_base.with_extension("");
+        }
+        self.wrapper = Some(std::mem::replace(&mut self.in_path, image.path.clone()));
+        Ok(Some(image))
+    }
+
+    /// With `--gpg-recipient` or `--age-recipient`, encrypts the payload and points
+    /// `in_path` at the message, which lasts until the returned guard is dropped. The
+    /// payload is first stored raw in a container of its own that holds the metadata, so
+    /// that what the header shows of it is checked on decoding against what was
+    /// encrypted. With `--hide-metadata`, the header then leaves the metadata out.
+    fn stage_encrypted(&mut self) -> anyhow::Result<Option<fetch::TempFile>> {
+        if !self.encrypts() {
+            return Ok(None);
+        }
+        let message = fetch::TempFile::new("payload.enc")?;
+        self.detect_type()?;
+        let payload = if self.entries.is_empty() && !self.wrap && is_pipe(&self.in_path) {
+            // The message is split into volumes, so a pipe need not fit one image.
+            let mut payload = Vec::new();
+            File::open(&self.in_path)?.read_to_end(&mut payload)?;
+            payload
+        } else {
+            whole_payload(self)?
+        };
+        let packing = Packing {
+            codec: Codec::Raw,
+            dict: None,
+            meta_hidden: false,
+            ..self.packing()
+        };
+        let packed = format::pack(&payload, packing)?;
+        drop(payload);
+        self.encrypt(packed.as_slice(), &message.path)?;
+        self.entries.clear();
+        if self.hide_metadata {
+            self.content_type = None;
+            self.created = None;
+            self.comment = None;
+            self.meta.clear();
+        }
+        self.in_path.clone_from(&message.path);
+        Ok(Some(message))
+    }
+
+    /// Whether the payload is encrypted with gpg or age before it is encoded.
+    fn encrypts(&self) -> bool {
+        !self.gpg_recipients.is_empty() || !self.age_recipients.is_empty()
+    }
+
+    /// Encrypts what `input` reads into `out`, handing it over in memory or through a
+    /// pipe so that the plaintext is never written to a file of its own.
+    fn encrypt(&self, input: impl Read + Send, out: &Path) -> anyhow::Result<()> {
+        if self.age_recipients.is_empty() {
+            gpg::encrypt(input, &self.gpg_recipients, out)
+        } else {
+            age::encrypt(input, &self.age_recipients, out)
+        }
+    }
+
+    /// Records what kind of file the input is. Directories and `--add` entries are left
+    /// untyped, as decoding extracts them, and so are pipes, which cannot be read twice.
+    fn detect_type(&mut self) -> anyhow::Result<()> {
+        if self.entries.is_empty() && self.in_path.is_file() {
+            self.content_type = mime::detect(&self.in_path)?;
+        }
+        Ok(())
+    }
+
+    /// With `--to-clipboard`, `--stdout` or `--data-uri`, points `out_path` at a file to
+    /// move to the clipboard or print once it has been written. Binary output is refused
+    /// a terminal, which would show garbage, or on Windows fail on bytes that are not UTF-8.
+    fn stage_output(&mut self, mode: &Mode) -> anyhow::Result<Option<fetch::TempFile>> {
+        if !self.to_clipboard && !self.to_stdout && !self.data_uri {
+            return Ok(None);
+        }
+        ensure!(
+            !self.to_stdout || !std::io::stdout().is_terminal(),
+            "--stdout writes binary data; redirect it to a file or a pipe"
+        );
+        let file = fetch::TempFile::new(if *mode == Mode::Encode {
+            "out.png"
+        } else {
+            "out.bin"
+        })?;
+        self.out_path = Some(file.path.clone());
+        Ok(Some(file))
+    }
+}
+
+/// `path`, or if it or a file written beside it is there, the first of `out-1.png`,
+/// `out-2.png` and so on that is free.
+fn unused(path: PathBuf) -> PathBuf {
+    if !taken(&path) {
+        return path;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path
+        .extension()
+        .map(|extension| format!(".{}", extension.to_string_lossy()))
+        .unwrap_or_default();
+    (1..=u32::MAX)
+        .map(|number| path.with_file_name(format!("{stem}-{number}{extension}")))
+        .find(|path| !taken(path))
+        .unwrap_or(path)
+}
+
+/// Whether anything is at `path`, or at a name an encode to it writes as well: its
+/// manifest, or a volume of a set, `out.001.png` and on.
+fn taken(path: &Path) -> bool {
+    let exists = |path: &Path| path.symlink_metadata().is_ok();
+    if exists(path) || exists(&manifest::Manifest::path(path)) {
+        return true;
+    }
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let extension = path.extension().unwrap_or_default().to_string_lossy();
+    let is_volume = |name: &str| {
+        name.strip_prefix(&format!("{stem}."))
+            .and_then(|rest| rest.strip_suffix(&format!(".{extension}")))
+            .is_some_and(|index| !index.is_empty() && index.bytes().all(|b| b.is_ascii_digit()))
     };
-    let Some(in_path) = args.next().map(PathBuf::from) else {
-        fail!(&name, "missing input file.")
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
     };
-    let out_path = args.next().map_or_else(
-        || match mode {
-            Mode::Encode => in_path.with_extension("png"),
-            Mode::Decode => in_path.with_extension("bin"),
+    std::fs::read_dir(dir).is_ok_and(|entries| {
+        entries
+            .flatten()
+            .any(|entry| entry.file_name().to_str().is_some_and(is_volume))
+    })
+}
+
+/// The path given as the only argument, as Explorer does when a file is dropped on the
+/// executable, unless it names a mode. Only Windows has this convention; elsewhere a
+/// lone path is a mistake that clap explains.
+#[cfg(windows)]
+fn dropped() -> Option<PathBuf> {
+    let mut args = std::env::args_os().skip(1);
+    let (Some(arg), None) = (args.next(), args.next()) else {
+        return None;
+    };
+    let is_mode = arg
+        .to_str()
+        .is_some_and(|arg| arg.starts_with('-') || Cli::command().find_subcommand(arg).is_some());
+    let path = PathBuf::from(arg);
+    (!is_mode && path.exists()).then_some(path)
+}
+
+/// Decodes a dropped PNG and encodes anything else. A console opened just for this
+/// closes as soon as picturer exits, so a failure waits for Enter first.
+#[cfg(windows)]
+fn convert_dropped(path: &Path) -> anyhow::Result<()> {
+    match convert(path, is_png(path), None) {
+        Ok(message) => {
+            eprintln!("{message}");
+            Ok(())
+        }
+        Err(error) if std::io::stdin().is_terminal() => {
+            report(&error);
+            eprintln!("\nPress Enter to close.");
+            let _ = std::io::stdin().read_line(&mut String::new());
+            std::process::exit(1)
+        }
+        Err(error) => Err(error),
+    }
+}
+
+/// Whether `path` looks like an image to decode rather than a file to encode.
+#[cfg(any(windows, feature = "gui", feature = "tui"))]
+fn is_png(path: &Path) -> bool {
+    path.extension()
+        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"))
+}
+
+/// Encodes or decodes `path` next to itself, as `-e` and `-d` do with no output path,
+/// and says what was written. Without a `codec`, the config file picks one.
+#[cfg(any(windows, feature = "gui", feature = "tui"))]
+fn convert(path: &Path, decode: bool, codec: Option<Codec>) -> anyhow::Result<String> {
+    let config = config::Config::load()?;
+    let paths = vec![path.to_path_buf()];
+    if decode {
+        let options = Options::decode(
+            cli::DecodeArgs {
+                paths,
+                ..Default::default()
+            },
+            config,
+        )?;
+        format::set_max_output(options.max_output);
+        let written = decode_file(&options)?;
+        return Ok(format!("Wrote {}", written.display()));
+    }
+    let options = Options::encode(
+        cli::EncodeArgs {
+            paths,
+            codec,
+            ..Default::default()
         },
-        PathBuf::from,
+        config,
+    )?;
+    let written = encode_file(&options)?;
+    Ok(match written.as_slice() {
+        [one] => format!("Wrote {}", one.display()),
+        many => format!("Wrote {} volumes", many.len()),
+    })
+}
+
+/// What `-e` records as the time of encoding: `SOURCE_DATE_EPOCH` if it is set, as
+/// reproducible builds expect, or else now, unless `--no-timestamp` or `--deterministic`
+/// leave it out.
+fn timestamp(no_timestamp: bool, deterministic: bool) -> anyhow::Result<Option<u64>> {
+    if no_timestamp {
+        return Ok(None);
+    }
+    if let Ok(epoch) = std::env::var("SOURCE_DATE_EPOCH") {
+        let epoch = epoch
+            .trim()
+            .parse()
+            .with_context(|| format!("SOURCE_DATE_EPOCH={epoch} is not a count of seconds"))?;
+        return Ok(Some(epoch));
+    }
+    if deterministic {
+        return Ok(None);
+    }
+    let now = std::time::SystemTime::now().duration_since(std::time::UNIX_EPOCH)?;
+    Ok(Some(now.as_secs()))
+}
+
+/// A random version 4 UUID, shared by every volume of the payload.
+fn random_id() -> anyhow::Result<[u8; 16]> {
+    let mut id = [0; 16];
+    getrandom::fill(&mut id)?;
+    id[6] = (id[6] & 0x0f) | 0x40;
+    id[8] = (id[8] & 0x3f) | 0x80;
+    Ok(id)
+}
+
+/// Encodes the input as the options ask, returning the images written.
+fn encode_file(options: &Options) -> anyhow::Result<Vec<PathBuf>> {
+    if !options.carriers.is_empty() {
+        return encode_hidden(options);
+    }
+    if options.format == Format::Pdf {
+        return encode_pdf(options);
+    }
+    if matches!(options.format, Format::DataMatrix | Format::Aztec) {
+        return encode_barcode(options);
+    }
+    if options.format == Format::Mkv {
+        return encode_video(options);
+    }
+    if options.robust || options.format == Format::Jpeg {
+        return encode_robust(options);
+    }
+    if options.format == Format::Html {
+        return encode_html(options);
+    }
+    if options.format == Format::AnsiArt {
+        return encode_mosaic(options);
+    }
+    if options.format == Format::Svg {
+        return encode_svg(options);
+    }
+    let out_path = options.out_path("png");
+    let payload = if !options.entries.is_empty() {
+        Some(entries::pack(&options.entries)?)
+    } else if options.in_path.is_dir() {
+        Some(archive::pack(
+            &options.in_path,
+            &options.tree,
+            options.deterministic,
+            options.codec,
+        )?)
+    } else if options.wrap {
+        Some(raster::wrap(&options.in_path)?)
+    } else {
+        None
+    };
+    if let Some(base) = &options.base {
+        let target = match payload {
+            Some(payload) => payload,
+            None => std::fs::read(&options.in_path)?,
+        };
+        let base_payload = decode_image(base, &read_single(base)?, None, options.dict.as_ref(), 0)?;
+        let patch = delta::diff(&base_payload, &target);
+        let mut buf = Delta {
+            base: base_reference(base, &out_path)?,
+            base_hash: Sha256::digest(&base_payload).into(),
+        }
+        .header()?;
+        format::pack_into(&mut buf, &patch, options.packing())?;
+        options.write(&buf, &out_path)?;
+        return Ok(vec![out_path]);
+    }
+    if let Some(payload) = payload {
+        return write_payload(payload.as_slice(), payload.len() as u64, &out_path, options);
+    }
+    let file = File::open(&options.in_path)?;
+    if is_pipe(&options.in_path) {
+        return encode_stream(file, &out_path, options);
+    }
+    let total = file.metadata()?.len();
+    write_payload(file, total, &out_path, options)
+}
+
+/// Writes the input as a single robust image, a PNG or a JPEG; robust payloads are never
+/// split into volumes.
+fn encode_robust(options: &Options) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        options.base.is_none() && matches!(options.format, Format::Png | Format::Jpeg),
+        "--robust cannot be combined with --base or a --format other than png or jpeg"
     );
-    match mode {
-        Mode::Encode => {
-            let mut buffer = Vec::new();
-            File::open(&in_path)?.read_to_end(&mut buffer)?;
-            Ok(encode(&buffer)?.write_to(&mut File::create(out_path)?, ImageFormat::Png)?)
+    let image = robust::encode(&format::pack(&whole_payload(options)?, options.packing())?)?;
+    let out_path;
+    if options.format == Format::Jpeg {
+        out_path = options.out_path("jpg");
+        let _partial = interrupt::Partial::new(&out_path);
+        let out = BufWriter::new(File::create(&out_path)?);
+        JpegEncoder::new_with_quality(out, robust::JPEG_QUALITY).encode_image(&image)?;
+    } else {
+        out_path = options.out_path("png");
+        let _partial = interrupt::Partial::new(&out_path);
+        let mut out = BufWriter::new(File::create(&out_path)?);
+        image.write_to(&mut out, ImageFormat::Png)?;
+        out.flush()?;
+    }
+    Ok(vec![out_path])
+}
+
+/// Hides the input in the `--carrier` images, as a PNG copy of each.
+fn encode_hidden(options: &Options) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        options.base.is_none() && !options.robust && options.format == Format::Png,
+        "--carrier cannot be combined with --base, --robust or a --format other than png"
+    );
+    let container = format::pack(&whole_payload(options)?, options.packing())?;
+    stego::embed(
+        &container,
+        &options.carriers,
+        options.density,
+        &options.secrets,
+        &options.out_path("png"),
+        options.deterministic,
+    )
+}
+
+/// The input read into memory, packing a directory or `--add` entries first, for
+/// layouts that are never split into volumes. A pipe is read no further than one image
+/// holds, so that one that never ends fails rather than filling memory.
+fn whole_payload(options: &Options) -> anyhow::Result<Vec<u8>> {
+    if !options.entries.is_empty() {
+        return entries::pack(&options.entries);
+    }
+    if options.in_path.is_dir() {
+        let (tree, deterministic) = (&options.tree, options.deterministic);
+        return archive::pack(&options.in_path, tree, deterministic, options.codec);
+    }
+    if options.wrap {
+        return raster::wrap(&options.in_path);
+    }
+    let mut payload = Vec::new();
+    if !is_pipe(&options.in_path) {
+        File::open(&options.in_path)?.read_to_end(&mut payload)?;
+        return Ok(payload);
+    }
+    let limit = options.capacity();
+    File::open(&options.in_path)?
+        .take(limit + 1)
+        .read_to_end(&mut payload)?;
+    ensure!(
+        payload.len() as u64 <= limit,
+        "{} gives more than the {limit} bytes one image holds, and this layout is never \
+         split into volumes",
+        options.in_path.display()
+    );
+    Ok(payload)
+}
+
+/// Encodes input of unknown length, such as a pipe. What fits one image is encoded
+/// from memory; anything longer is spooled to a temporary file to size the volume set.
+fn encode_stream(
+    mut input: impl Read,
+    out: &Path,
+    options: &Options,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let mut buffer = Vec::new();
+    (&mut input)
+        .take(options.capacity() + 1)
+        .read_to_end(&mut buffer)?;
+    if buffer.len() as u64 <= options.capacity() {
+        return write_payload(buffer.as_slice(), buffer.len() as u64, out, options);
+    }
+    let spool = fetch::TempFile::new("spool")?;
+    let mut file = std::fs::OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&spool.path)?;
+    file.write_all(&buffer)?;
+    drop(buffer);
+    std::io::copy(&mut input, &mut file)?;
+    let total = file.stream_position()?;
+    file.rewind()?;
+    write_payload(std::io::BufReader::new(file), total, out, options)
+}
+
+/// Writes the input as a self-extracting HTML page. Its script decodes only what a
+/// browser can unpack unaided: one image of raw or zlib blocks holding a plain file.
+fn encode_html(options: &Options) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        options.base.is_none()
+            && options.entries.is_empty()
+            && options.dict.is_none()
+            && !options.to_clipboard
+            && !options.data_uri
+            && !matches!(options.codec, Codec::Zstd(_))
+            && !options.in_path.is_dir(),
+        "--format html needs a single file and the raw or zlib codec, written to a file"
+    );
+    let payload = std::fs::read(&options.in_path)?;
+    ensure!(
+        payload.len() as u64 <= volume::CAPACITY,
+        "--format html needs a payload that fits one image"
+    );
+    let name = options
+        .out_base
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let out_path = options.out_path("html");
+    html::write(
+        &layout(format::pack(&payload, options.packing())?)?,
+        &name,
+        &out_path,
+    )?;
+    Ok(vec![out_path])
+}
+
+/// Writes the input as ANSI art of an opaque image, since a terminal shows no alpha.
+fn encode_mosaic(options: &Options) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        options.base.is_none() && !options.to_clipboard && !options.data_uri,
+        "--format ansi-art cannot be combined with --base, and is written to a file"
+    );
+    let container = format::pack(&whole_payload(options)?, options.packing())?;
+    let image = DynamicImage::from(picturer::layout_opaque(&container)?).into_rgb8();
+    let out_path = options.out_path("ans");
+    mosaic::write(&image, &out_path)?;
+    Ok(vec![out_path])
+}
+
+/// Writes the input as an SVG of an opaque image, since rasterizers blend what is not.
+fn encode_svg(options: &Options) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        options.base.is_none() && !options.to_clipboard && !options.data_uri,
+        "--format svg cannot be combined with --base, and is written to a file"
+    );
+    let container = format::pack(&whole_payload(options)?, options.packing())?;
+    let image = DynamicImage::from(picturer::layout_opaque(&container)?).into_rgb8();
+    let out_path = options.out_path("svg");
+    svg::write(&image, &out_path)?;
+    Ok(vec![out_path])
+}
+
+/// Writes the input as a Data Matrix or Aztec symbol, saved as a PNG.
+fn encode_barcode(options: &Options) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        options.base.is_none() && !options.robust,
+        "--format data-matrix and aztec cannot be combined with --base or --robust"
+    );
+    let container = format::pack(&whole_payload(options)?, options.packing())?;
+    let out_path = options.out_path("png");
+    barcode::encode(&container, options.format)?.save_with_format(&out_path, ImageFormat::Png)?;
+    Ok(vec![out_path])
+}
+
+/// Writes the input as a video of volumes, reading it a frame at a time.
+fn encode_video(options: &Options) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        options.base.is_none() && !options.robust && !options.to_clipboard && !options.data_uri,
+        "--format mkv cannot be combined with --base or --robust, and is written to a file"
+    );
+    let out_path = options.out_path("mkv");
+    if !options.entries.is_empty() || options.in_path.is_dir() {
+        let payload = whole_payload(options)?;
+        return video::encode(
+            payload.as_slice(),
+            payload.len() as u64,
+            &out_path,
+            options.packing(),
+        );
+    }
+    let file = File::open(&options.in_path)?;
+    let total = file.metadata()?.len();
+    video::encode(
+        std::io::BufReader::new(file),
+        total,
+        &out_path,
+        options.packing(),
+    )
+}
+
+/// Writes the input as a PDF page of an opaque or `--robust` image, under lines that say
+/// what it holds and how to decode it.
+fn encode_pdf(options: &Options) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        options.base.is_none() && !options.to_clipboard && !options.data_uri,
+        "--format pdf cannot be combined with --base, and is written to a file"
+    );
+    let payload = whole_payload(options)?;
+    let container = format::pack(&payload, options.packing())?;
+    let image = if options.robust {
+        robust::encode(&container)?
+    } else {
+        DynamicImage::from(picturer::layout_opaque(&container)?).into_rgb8()
+    };
+    let name = options
+        .out_base
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy();
+    let out_path = options.out_path("pdf");
+    let pdf_name = out_path.file_name().unwrap_or_default().to_string_lossy();
+    let digest = hash::hex(&Sha256::digest(&payload));
+    let lines = |dpi: u32| {
+        let mut lines = vec![
+            format!("picturer image of {name}, {} bytes", payload.len()),
+            format!("SHA-256 {digest}"),
+            String::new(),
+            format!("The image below holds it at {dpi} DPI, a pixel to each 1/{dpi} inch square."),
+            "To get it back from this PDF, decode the PDF, or the image extracted unchanged:"
+                .to_owned(),
+            format!("    picturer decode {pdf_name}"),
+            format!("    pdfimages -png {pdf_name} page && picturer decode page-000.png"),
+        ];
+        if options.robust {
+            lines.extend([
+                "It was written with --robust, in blocks with error correction, so a scan of a"
+                    .to_owned(),
+                format!("printout at {dpi} DPI or more may decode too: picturer decode scan.png"),
+            ]);
+        } else {
+            lines.extend([
+                "Each pixel holds 3 bytes, in its red, green and blue, row by row from the top"
+                    .to_owned(),
+                "left, so a scan of a printout will not decode; encode with --robust for paper."
+                    .to_owned(),
+            ]);
         }
-        Mode::Decode => {
-            let bytes: Vec<u8> = image::open(in_path)?
-                .pixels()
-                .flat_map(|(_, _, Rgba(c))| c)
-                .collect();
+        lines
+    };
+    let dpi = pdf::dpi(&image, lines(pdf::DPI).len());
+    pdf::write(&image, dpi, &lines(dpi), &name, &out_path)?;
+    Ok(vec![out_path])
+}
+
+/// Encodes `total` bytes from `input` to `out`, as a volume set if they do not fit one image.
+fn write_payload(
+    input: impl Read,
+    total: u64,
+    out: &Path,
+    options: &Options,
+) -> anyhow::Result<Vec<PathBuf>> {
+    if let Some(target) = options.target_size {
+        return target::encode(input, total, out, target, options);
+    }
+    let packing = options.packing();
+    if total > options.capacity() {
+        ensure!(
+            options.tile.is_none(),
+            "--tile needs a payload that fits one image"
+        );
+        return volume::encode(
+            input,
+            total,
+            out,
+            packing,
+            options.manifest,
+            options.capacity(),
+            |container, path| options.write(container, path),
+        );
+    }
+    if options.manifest {
+        eprintln!("not writing a manifest: the payload fits one image");
+    }
+    let started = timings::start();
+    let payload = spill::read(input, total, "spill-payload")?;
+    timings::record(Stage::Read, started, payload.len() as u64);
+    let container = spill::keep(format::pack(&payload, packing)?, "spill-container")?;
+    drop(payload);
+    options.write(&container, out)?;
+    Ok(vec![out.to_path_buf()])
+}
 
-            File::create(out_path)?.write_all(&decode(&bytes)?)?;
+/// Decodes `written` again, in place of itself, for as long as it is a picturer image,
+/// and reports how many images deep the payload it ends at was. Only the outermost image
+/// is decrypted and checked, as flags for it say nothing of those inside it.
+fn decode_nested(options: &mut Options, mut written: PathBuf) -> anyhow::Result<PathBuf> {
+    options.gpg_decrypt = false;
+    options.age_identities.clear();
+    options.secrets.clear();
+    options.base = None;
+    options.outermost = Some(options.in_path.clone());
+    let mut depth = 1;
+    while written.is_file() && holds_container(&written)? {
+        let mut path = written.clone().into_os_string();
+        path.push(".nested");
+        let nested = fetch::TempFile::at(PathBuf::from(path));
+        std::fs::rename(&written, &nested.path)?;
+        options.in_path.clone_from(&nested.path);
+        written = decode_file(options)?;
+        depth += 1;
+    }
+    eprintln!(
+        "{} was {depth} image{} deep",
+        written.display(),
+        if depth == 1 { "" } else { "s" }
+    );
+    Ok(written)
+}
 
-            Ok(())
+/// Whether the file at `path` is a PNG whose pixels start with a container.
+fn holds_container(path: &Path) -> anyhow::Result<bool> {
+    let mut head = [0; 8];
+    let read = File::open(path)?.read(&mut head)?;
+    if image::guess_format(&head[..read]).ok() != Some(ImageFormat::Png) {
+        return Ok(false);
+    }
+    let Ok(mut pixels) = stream::PixelStream::open(path) else {
+        return Ok(false);
+    };
+    Ok(format::is_container(pixels.prefix(Volume::HEADER_LEN)?))
+}
+
+/// Decodes the input as the options ask, returning the file or directory written.
+fn decode_file(options: &Options) -> anyhow::Result<PathBuf> {
+    let Options {
+        in_path,
+        base,
+        dict,
+        ..
+    } = options;
+    let dict = dict.as_ref();
+    if let Some(dir) = &options.tree_dir {
+        std::fs::create_dir_all(dir)?;
+    }
+    if options.gpg_decrypt || !options.age_identities.is_empty() {
+        return decode_decrypted(options);
+    }
+    if let Some(name) = &options.name {
+        return decode_entry(options, name);
+    }
+    if options.is_range() {
+        let out_path = options.out_path("bin");
+        let _partial = interrupt::Partial::new(&out_path);
+        decode_range(
+            options,
+            options.offset,
+            options.length,
+            &mut File::create(&out_path)?,
+        )?;
+        return Ok(out_path);
+    }
+    let out_path = options.out_path("");
+    if is_pipe(&out_path) && options.secrets.is_empty() && decode_streamed(options, &out_path)? {
+        return Ok(out_path);
+    }
+    if is_framed(in_path)? {
+        let out_path = options.out_path("bin");
+        let partial = interrupt::Partial::new(&out_path);
+        let mut out = BufWriter::new(File::create(&out_path)?);
+        decode_frames(in_path, &mut out, dict)?;
+        out.flush()?;
+        drop((out, partial));
+        let written = extract_joined(&out_path, &options.out_path(""), options.allow_symlinks)?;
+        return retype(options, written, None);
+    }
+    let bytes = read_input(options)?;
+    let content_type = format::content_type(&bytes);
+    if let Some((first, _)) = Volume::parse(&bytes)? {
+        let out_path = options.out_path("bin");
+        format::check_output(first.total)?;
+        space::ensure_free(&out_path, first.total)?;
+        let partial = interrupt::Partial::new(&out_path);
+        if is_pipe(&out_path) {
+            volume::decode(in_path, first, &mut File::create(&out_path)?, dict)?;
+        } else {
+            volume::decode_to(in_path, first, &out_path, dict, options.jobs)?;
         }
+        drop(partial);
+        let written = extract_joined(&out_path, &options.out_path(""), options.allow_symlinks)?;
+        return retype(options, written, content_type.as_deref());
+    }
+
+    let payload = decode_image(in_path, &bytes, base.as_deref(), dict, 0)?;
+    let unpacks = archive::is_archive(&payload) || entries::is_entries(&payload);
+    if unpacks && !is_pipe(&options.out_path("")) {
+        extract(&payload, &options.out_path(""), options.allow_symlinks)?;
+        return Ok(options.out_path(""));
     }
+    if raster::is_raster(&payload) && !is_pipe(&options.out_path("")) {
+        let out_path = options.typed_out_path(content_type.as_deref().or(Some("image/png")), &[]);
+        raster::unwrap(&payload, &out_path)?;
+        return Ok(out_path);
+    }
+    let out_path = options.typed_out_path(content_type.as_deref(), &payload);
+    space::ensure_free(&out_path, payload.len() as u64)?;
+    let started = timings::start();
+    let _partial = interrupt::Partial::new(&out_path);
+    File::create(&out_path)?.write_all(&payload)?;
+    timings::record(Stage::Write, started, payload.len() as u64);
+    Ok(out_path)
 }
 
-fn decode(bytes: &[u8]) -> anyhow::Result<Vec<u8>> {
-    let is_compressed: bool = {
-        if bytes.is_empty() {
-            bail!("input file is empty")
+/// Decodes a single image into the pipe `out` as its rows are read, so that whatever
+/// reads the pipe can start before the whole image is decoded. Returns `false`, having
+/// written nothing, for anything but a plain container, which is then read whole.
+fn decode_streamed(options: &Options, out: &Path) -> anyhow::Result<bool> {
+    let mut pixels = stream::PixelStream::open(&options.in_path)?;
+    let head = pixels.prefix(Volume::HEADER_LEN)?;
+    if !format::is_container(head) || Volume::parse(head)?.is_some() || Delta::is_delta(head) {
+        return Ok(false);
+    }
+    let _partial = interrupt::Partial::new(out);
+    let mut out = File::create(out)?;
+    format::unpack_to(&mut pixels, options.dict.as_ref(), &mut out, |_, _| {})?;
+    Ok(true)
+}
+
+/// Renames a payload decoded to `.bin` after its content type, unless it was extracted
+/// or written to a pipe, and writes the image a `--wrap` payload holds in its place.
+fn retype(options: &Options, path: PathBuf, content_type: Option<&str>) -> anyhow::Result<PathBuf> {
+    if !path.is_file() {
+        return Ok(path);
+    }
+    let mut head = Vec::new();
+    File::open(&path)?.take(8192).read_to_end(&mut head)?;
+    if raster::is_raster(&head) {
+        let typed = options.typed_out_path(content_type.or(Some("image/png")), &[]);
+        raster::unwrap(&std::fs::read(&path)?, &typed)?;
+        if typed != path {
+            std::fs::remove_file(&path)?;
+        }
+        return Ok(typed);
+    }
+    if content_type.is_none() && !options.sniff {
+        return Ok(path);
+    }
+    let typed = options.typed_out_path(content_type, &head);
+    if typed != path {
+        std::fs::rename(&path, &typed)?;
+    }
+    Ok(typed)
+}
+
+/// Decodes the payload, decrypts it with gpg or age into the output, and extracts it
+/// there if it is an archive or a set of named entries, once any metadata the header
+/// shows is checked against what was encrypted with the payload. The message is held in
+/// memory and the plaintext streamed from gpg or age, so neither is left in a temporary
+/// file.
+fn decode_decrypted(options: &Options) -> anyhow::Result<PathBuf> {
+    let mut message = Vec::new();
+    decode_into(options, &mut message)?;
+    let out_path = options.out_path("bin");
+    let pixels = read_input(options)?;
+    let hidden = format::meta_hidden(&pixels);
+    let public = if hidden {
+        format::metadata(&pixels)?
+    } else {
+        Vec::new()
+    };
+    drop(pixels);
+    let content_type = std::thread::scope(|scope| -> anyhow::Result<_> {
+        let mut plaintext: Box<dyn Read> = if options.age_identities.is_empty() {
+            Box::new(gpg::decrypt_reader(scope, message.as_slice())?)
+        } else {
+            Box::new(age::decrypt_reader(
+                message.as_slice(),
+                &options.age_identities,
+            )?)
         };
-        bytes[0] != 0
+        if hidden {
+            let mut inner = Vec::new();
+            plaintext.read_to_end(&mut inner)?;
+            ensure!(
+                public.is_empty() || public == format::metadata(&inner)?,
+                "the metadata in the header of {} was changed since it was encoded: it is \
+                 not what was encrypted with the payload",
+                options.label
+            );
+            std::fs::write(&out_path, format::unpack(&inner, None)?)?;
+            return Ok(format::content_type(&inner));
+        }
+        let mut out = BufWriter::new(File::create(&out_path)?);
+        std::io::copy(&mut plaintext, &mut out)?;
+        out.flush()?;
+        Ok(None)
+    })?;
+    let written = extract_joined(&out_path, &options.out_path(""), options.allow_symlinks)?;
+    retype(options, written, content_type.as_deref())
+}
+
+/// Decodes the whole payload of `options.in_path` into `out`, whether it is a single
+/// image, a delta, a volume set or the frames of a video or animation.
+fn decode_into(options: &Options, out: &mut impl Write) -> anyhow::Result<()> {
+    let dict = options.dict.as_ref();
+    if is_framed(&options.in_path)? {
+        return decode_frames(&options.in_path, out, dict);
+    }
+    let pixels = read_input(options)?;
+    if let Some((first, _)) = Volume::parse(&pixels)? {
+        drop(pixels);
+        return volume::decode(&options.in_path, first, out, dict);
+    }
+    let payload = decode_image(&options.in_path, &pixels, options.base.as_deref(), dict, 0)?;
+    let started = timings::start();
+    out.write_all(&payload)?;
+    timings::record(Stage::Write, started, payload.len() as u64);
+    Ok(())
+}
+
+/// Reassembles the volume set listed in the manifest into the payload, after checking
+/// every volume against the hash recorded for it, downloading them first if the
+/// manifest is a URL.
+fn join(args: &cli::JoinArgs, config: &config::Config) -> anyhow::Result<()> {
+    let manifest = &args.manifest;
+    let url = manifest.to_str().filter(|_| fetch::is_url(manifest));
+    let (_downloads, paths, name) = if let Some(url) = url {
+        if let Some(rate) = args.limit_rate.or(config.limit_rate) {
+            fetch::set_rate_limit(rate);
+        }
+        let limit = args.max_download.or(config.max_download);
+        let (downloads, paths) = download_volumes(url, limit.unwrap_or(fetch::DEFAULT_LIMIT))?;
+        (downloads, paths, PathBuf::from(fetch::file_name(url)))
+    } else {
+        let paths = manifest::Manifest::load(manifest)?.verify(manifest)?;
+        (Vec::new(), paths, manifest.clone())
+    };
+    let default = || name.with_extension("").with_extension("bin");
+    let out = args.out.clone().unwrap_or_else(default);
+    let Some(first) = paths.first() else {
+        bail!("manifest lists no volumes")
     };
-    let Some((length, data)) = bytes[1..].split_first_chunk() else {
-        bail!("input file is invalid")
+    let mut pixels = stream::PixelStream::open(first)?;
+    let Some((first, _)) = Volume::parse(pixels.prefix(Volume::HEADER_LEN)?)? else {
+        bail!("{} is not part of a volume set", first.display())
     };
-    let length = usize::try_from(u64::from_le_bytes(*length))?;
-    let buf = &data[..length];
-    if is_compressed {
-        Ok(ZlibDecoder::new(buf)
-            .bytes()
-            .collect::<Result<Vec<u8>, _>>()?)
+    let partial = interrupt::Partial::new(&out);
+    let jobs = args
+        .jobs
+        .map_or_else(rayon::current_num_threads, usize::from);
+    volume::decode_files_to(&paths, first, &out, None, jobs)?;
+    drop(partial);
+    extract_joined(&out, &out.with_extension(""), args.allow_symlinks).map(drop)
+}
+
+/// Downloads the manifest at `url` and all the volumes it lists, each no longer than
+/// `limit` bytes, and checks them as [`join`] does those on disk.
+fn download_volumes(url: &str, limit: u64) -> anyhow::Result<(Vec<fetch::TempFile>, Vec<PathBuf>)> {
+    let manifest = manifest::Manifest::load(&fetch::download(url, manifest::MAX_LEN)?.path)?;
+    manifest.check()?;
+    let urls: Vec<_> = manifest
+        .volumes
+        .iter()
+        .map(|entry| fetch::resolve(url, &entry.file))
+        .collect();
+    let downloads = fetch::download_all(&urls, limit)?;
+    let paths: Vec<_> = downloads
+        .iter()
+        .map(|download| download.path.clone())
+        .collect();
+    manifest.check_files(&paths)?;
+    Ok((downloads, paths))
+}
+
+/// Replaces a payload decoded from volumes with the tree it holds, if it is an archive
+/// or a set of named entries, returning whichever is left.
+fn extract_joined(path: &Path, dir: &Path, allow_links: bool) -> anyhow::Result<PathBuf> {
+    if is_pipe(path) {
+        return Ok(path.to_path_buf());
+    }
+    let mut magic = Vec::new();
+    File::open(path)?.take(64).read_to_end(&mut magic)?;
+    if archive::is_archive(&magic) || entries::is_entries(&magic) {
+        let payload = std::fs::read(path)?;
+        std::fs::remove_file(path)?;
+        extract(&payload, dir, allow_links)?;
+        return Ok(dir.to_path_buf());
+    }
+    Ok(path.to_path_buf())
+}
+
+/// Unpacks an archive or a set of named entries into the directory `out`, recreating
+/// the links of an archive only with `allow_links`.
+fn extract(payload: &[u8], out: &Path, allow_links: bool) -> anyhow::Result<()> {
+    if entries::is_entries(payload) {
+        entries::extract(payload, out)
     } else {
-        Ok(buf.to_owned())
+        archive::extract(payload, out, allow_links)
     }
 }
 
-fn encode(bytes: &[u8]) -> anyhow::Result<RgbaImage> {
-    let is_compressed: bool;
-    let compressed: Result<Vec<u8>, _> = ZlibEncoder::new(bytes, flate2::Compression::best())
-        .bytes()
-        .collect();
-    let bytes = match compressed {
-        Ok(compressed) => {
-            is_compressed = true;
-            compressed
-        }
-        Err(err) => {
-            is_compressed = false;
-            eprintln!("Compression failed: {err:?}. Encoding raw bytes...");
-            bytes.to_owned()
-        }
-    };
-    let mut buf = Vec::new();
-    buf.push(is_compressed.into());
-    buf.extend((bytes.len() as u64).to_le_bytes());
-    buf.extend(bytes);
-    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
-    let w = f64::from(u32::try_from(buf.len())?).sqrt().ceil() as u32;
-    let width = w + (4 - w % 4);
-    let height = width / 4 - 1;
-    buf.resize((width * height * 4) as usize, 0);
-    let img =
-        RgbaImage::from_vec(width / 2, height * 2, buf).ok_or(Error::msg("buffer too small"))?;
-    Ok(img)
+/// Writes `length` bytes of the payload starting at `offset` to `out`,
+/// decoding as little of the image as its format allows.
+fn decode_range(
+    options: &Options,
+    offset: u64,
+    length: Option<u64>,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let Options {
+        in_path,
+        base,
+        dict,
+        ..
+    } = options;
+    let dict = dict.as_ref();
+    // Nothing in a deniable carrier says it is one, so with a secret it is read whole.
+    let mut pixels = if options.secrets.is_empty() {
+        stream::PixelStream::open(in_path)?
+    } else {
+        stream::PixelStream::Full(read_input(options)?)
+    };
+    if let Some((first, _)) = Volume::parse(pixels.prefix(Volume::HEADER_LEN)?)? {
+        ensure!(
+            !options.whole_records,
+            "--whole-records cannot read a set of volumes"
+        );
+        return volume::decode_range(in_path, first, offset, length, out, dict);
+    }
+    let bytes = if options.whole_records {
+        ensure!(
+            !Delta::is_delta(pixels.prefix(Delta::MAGIC.len())?),
+            "--whole-records cannot read a delta"
+        );
+        format::unpack_records(&mut pixels, offset, length, dict)?
+    } else if Delta::is_delta(pixels.prefix(Delta::MAGIC.len())?) {
+        let pixels = pixels.prefix(usize::MAX)?;
+        let payload = decode_image(in_path, pixels, base.as_deref(), dict, 0)?;
+        format::slice(payload, offset, length)?
+    } else {
+        format::unpack_range(&mut pixels, offset, length, dict)?
+    };
+    Ok(out.write_all(&bytes)?)
+}
+
+/// Writes the entry called `name` (or the `--offset`/`--length` range of it) to the
+/// output, or next to the image under its own name, reading only the table and its bytes.
+fn decode_entry(options: &Options, name: &str) -> anyhow::Result<PathBuf> {
+    let mut prefix = Vec::new();
+    decode_range(options, 0, Some(entries::PREFIX_LEN), &mut prefix)?;
+    ensure!(
+        entries::is_entries(&prefix),
+        "{} does not hold named entries",
+        options.in_path.display()
+    );
+    let mut header = Vec::new();
+    decode_range(options, 0, Some(entries::header_len(&prefix)?), &mut header)?;
+    let table = entries::parse(&header)?;
+    let Some(entry) = table.iter().find(|entry| entry.name == name) else {
+        let names: Vec<&str> = table.iter().map(|entry| entry.name.as_str()).collect();
+        bail!(
+            "no entry named {name}; the image holds {}",
+            names.join(", ")
+        )
+    };
+    let offset = options.offset.min(entry.len);
+    let length = options
+        .length
+        .map_or(entry.len - offset, |length| length.min(entry.len - offset));
+    let out_path = options
+        .out_path
+        .clone()
+        .unwrap_or_else(|| match &options.out_dir {
+            Some(dir) => dir.join(name),
+            None => options.out_base.with_file_name(name),
+        });
+    let _partial = interrupt::Partial::new(&out_path);
+    let mut out = BufWriter::new(File::create(&out_path)?);
+    decode_range(options, entry.offset + offset, Some(length), &mut out)?;
+    out.flush()?;
+    Ok(out_path)
+}
+
+/// Decodes the pixels of a single image read from `path`. When the image is a delta,
+/// its base is decoded first, from `base` if given or else from the path stored in it.
+/// `dict` is offered to every image in the chain that was compressed with one. Images
+/// from before the block container are still decoded, with a warning to migrate them.
+fn decode_image(
+    path: &Path,
+    pixels: &[u8],
+    base: Option<&Path>,
+    dict: Option<&Dictionary>,
+    depth: usize,
+) -> anyhow::Result<Vec<u8>> {
+    let Some((delta, container)) = Delta::parse(pixels)? else {
+        if !format::is_container(pixels) && format::legacy_len(pixels).is_some() {
+            eprintln!(
+                "warning: {} is in the layout from before the block container; re-encode it \
+                 with picturer migrate {0} NEW.png",
+                path.display()
+            );
+        }
+        return format::unpack(pixels, dict);
+    };
+    ensure!(
+        depth < MAX_DELTA_CHAIN,
+        "delta chain is longer than {MAX_DELTA_CHAIN} images"
+    );
+    let base = base.map_or_else(
+        || path.parent().unwrap_or(Path::new("")).join(&delta.base),
+        Path::to_path_buf,
+    );
+    let base_payload = decode_image(&base, &read_single(&base)?, None, dict, depth + 1)?;
+    ensure!(
+        Sha256::digest(&base_payload)[..] == delta.base_hash,
+        "{} is not the image this delta was made against",
+        base.display()
+    );
+    delta::apply(&base_payload, &format::unpack(container, dict)?)
+}
+
+/// Shows on a terminal how much of a payload of at least [`PROGRESS_MIN`] bytes has been
+/// decoded, as the library reports it.
+fn show_progress(progress: Progress) {
+    let (Stage::Decompress, done, Some(total)) = (progress.stage, progress.done, progress.total)
+    else {
+        return;
+    };
+    if total < PROGRESS_MIN {
+        return;
+    }
+    if std::io::stderr().is_terminal() {
+        eprint!("\rdecoded {}% of {total} bytes", done * 100 / total);
+        if done == total {
+            eprintln!();
+        }
+        interrupt::PROGRESS_LINE.store(done < total, std::sync::atomic::Ordering::Relaxed);
+    }
+}
+
+/// Reads a delta base, which cannot be a volume set.
+fn read_single(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let pixels =
+        read_pixels(path).with_context(|| format!("cannot read delta base {}", path.display()))?;
+    ensure!(
+        Volume::parse(&pixels)?.is_none(),
+        "delta base {} is part of a volume set",
+        path.display()
+    );
+    Ok(pixels)
+}
+
+/// How a delta written to `out` refers to `base`: by file name when both are in the
+/// same directory, so the pair can be moved together, and by absolute path otherwise.
+fn base_reference(base: &Path, out: &Path) -> anyhow::Result<String> {
+    let base = base.canonicalize()?;
+    let out_dir = out
+        .parent()
+        .filter(|dir| !dir.as_os_str().is_empty())
+        .unwrap_or(Path::new("."))
+        .canonicalize()?;
+    let reference = match base.strip_prefix(out_dir) {
+        Ok(name) if name.components().count() == 1 => name,
+        _ => &base,
+    };
+    reference
+        .to_str()
+        .map(str::to_owned)
+        .context("delta base path is not valid UTF-8")
+}
+
+/// `dict train`: writes a dictionary trained on `samples` to `out`.
+fn train_dict(samples: &[PathBuf], out: &Path, size: usize) -> anyhow::Result<()> {
+    let bytes = dict::train(samples, size)?;
+    std::fs::write(out, &bytes)?;
+    eprintln!("wrote {} ({} bytes)", out.display(), bytes.len());
+    Ok(())
+}
+
+/// `presets`: what each `--preset` sets.
+fn print_presets() {
+    for preset in cli::Preset::value_variants() {
+        let name = preset.to_possible_value().expect("no preset is hidden");
+        let manifest = if preset.manifest() { " --manifest" } else { "" };
+        let note = if preset.recovery_note() {
+            " --recovery-note"
+        } else {
+            ""
+        };
+        let robust = if preset.robust() { " --robust" } else { "" };
+        println!(
+            "{:<10} --codec {}{manifest}{note}{robust}\n           {}",
+            name.get_name(),
+            preset.codec(),
+            name.get_help().map(ToString::to_string).unwrap_or_default()
+        );
+    }
+}
+
+/// `capacity`: what each carrier, and all of them together, can hide.
+fn capacity(args: &cli::CapacityArgs) -> anyhow::Result<()> {
+    let cli::Hiding {
+        carriers,
+        density,
+        passphrase,
+        keyfile,
+        recipients,
+    } = &args.hiding;
+    let secrets =
+        usize::from(passphrase.is_some()) + usize::from(keyfile.is_some()) + recipients.len();
+    if carriers.is_empty() {
+        usage_error(
+            ErrorKind::MissingRequiredArgument,
+            "capacity needs at least one --carrier",
+        )
+    }
+    let mut total = 0;
+    for carrier in carriers {
+        let (width, height, bytes) = stego::capacity(carrier, *density, secrets)?;
+        println!("{}: {width}x{height}, {bytes} bytes", carrier.display());
+        total += bytes;
+    }
+    // The block size is picked for the payload, which is less than the carriers hold.
+    let mut block_size = format::auto_block_size(total);
+    let payload = loop {
+        let payload = format::raw_capacity(total, args.dict.is_some(), block_size);
+        let picked = format::auto_block_size(payload);
+        if picked == block_size {
+            break payload;
+        }
+        block_size = picked;
+    };
+    let settings = if secrets > 0 {
+        format!("opened by {secrets} passphrases or keyfiles")
+    } else {
+        format!("at --density {density}")
+    };
+    println!(
+        "holds a payload of {payload} bytes {settings} if it does not compress, and more \
+         if it does"
+    );
+    Ok(())
+}
+
+/// `man`: renders the man page, or with `dir` one page per subcommand, from the same
+/// definitions clap parses the command line with.
+fn man(dir: Option<&Path>) -> anyhow::Result<()> {
+    let command = Cli::command();
+    match dir {
+        Some(dir) => {
+            std::fs::create_dir_all(dir)?;
+            Ok(clap_mangen::generate_to(command, dir)?)
+        }
+        None => Ok(clap_mangen::Man::new(command).render(&mut std::io::stdout())?),
+    }
+}
+
+fn append(image: &Path, more: &Path, dict: Option<&Dictionary>) -> anyhow::Result<()> {
+    let pixels = read_pixels(image)?;
+    ensure!(
+        Volume::parse(&pixels)?.is_none(),
+        "appending to a volume set is not supported"
+    );
+    let container = format::append(&pixels, &std::fs::read(more)?, dict)?;
+    let mut tmp = image.as_os_str().to_owned();
+    tmp.push(".tmp");
+    let opaque = picturer::is_opaque(&read_image(image)?);
+    write_container(&container, Path::new(&tmp), opaque, PngLevel::Default)?;
+    Ok(std::fs::rename(tmp, image)?)
+}
+
+/// Whether the file at `path` is no image but holds one: armored, ANSI art, an SVG or a
+/// PDF.
+fn is_wrapped(path: &Path) -> anyhow::Result<bool> {
+    Ok(armor::is_armored(path)?
+        || mosaic::is_mosaic(path)?
+        || svg::is_svg(path)?
+        || pdf::is_pdf(path)?)
+}
+
+/// Whether the file at `path` holds a volume set as its frames: a video, or an animated
+/// PNG or GIF.
+fn is_framed(path: &Path) -> anyhow::Result<bool> {
+    Ok(video::is_video(path)? || animation::is_animated(path)?)
+}
+
+/// Decodes the volume set the frames of the file at `path`, which [`is_framed`], hold
+/// into `out`.
+fn decode_frames(
+    path: &Path,
+    out: &mut impl Write,
+    dict: Option<&Dictionary>,
+) -> anyhow::Result<()> {
+    if video::is_video(path)? {
+        video::decode(path, out, dict)
+    } else {
+        animation::decode(path, out, dict)
+    }
+}
+
+/// Writes the image the file at `path` holds to `out` as a PNG if it [`is_wrapped`],
+/// returning whether it was.
+fn unwrap_image(path: &Path, out: &Path) -> anyhow::Result<bool> {
+    let image = if armor::is_armored(path)? {
+        return armor::unwrap(path, out).map(|()| true);
+    } else if mosaic::is_mosaic(path)? {
+        mosaic::read(path)?
+    } else if svg::is_svg(path)? {
+        svg::read(path)?
+    } else if pdf::is_pdf(path)? {
+        pdf::read(path)?
+    } else {
+        return Ok(false);
+    };
+    image.save_with_format(out, ImageFormat::Png)?;
+    Ok(true)
+}
+
+/// Whether `path` is something other than a file or directory, such as a FIFO, whose
+/// length is unknown and which can only be read once.
+fn is_pipe(path: &Path) -> bool {
+    std::fs::metadata(path).is_ok_and(|metadata| !metadata.is_file() && !metadata.is_dir())
+}
+
+/// The bytes held by the image at `path`: its pixels, for a robust image the container
+/// recovered from its blocks, or for a carrier the container hidden in its set. An image
+/// scaled up by a whole factor, as a rasterized SVG is, is read at its own size; others
+/// that look resized or re-compressed are refused rather than decoded to garbage.
+fn read_pixels(path: &Path) -> anyhow::Result<Vec<u8>> {
+    read_pixels_within(path, robust::TOLERANCE, &[])
+}
+
+/// [`read_pixels`] of the input, reading a robust image with `--tolerance` and a
+/// carrier with `--passphrase` or `--keyfile`. With copies of it beside it, written with
+/// `--copies`, every byte is the one most copies read.
+fn read_input(options: &Options) -> anyhow::Result<Vec<u8>> {
+    let read = |path: &Path| read_pixels_within(path, options.tolerance, &options.secrets);
+    let pixels = read(&options.in_path)?;
+    let others = copies::others(&options.in_path);
+    if others.is_empty() {
+        return Ok(pixels);
+    }
+    let mut read_copies = vec![pixels];
+    for path in others {
+        match read(&path) {
+            Ok(pixels) if pixels.len() == read_copies[0].len() => read_copies.push(pixels),
+            Ok(_) => eprintln!(
+                "skipping {}, which holds fewer or more bytes",
+                path.display()
+            ),
+            Err(err) => eprintln!("skipping {}: {err:#}", path.display()),
+        }
+    }
+    let (pixels, vote) = copies::vote(&read_copies);
+    if vote.differing > 0 {
+        eprintln!(
+            "voted between {} copies: {} bytes differed, {} of them with no majority",
+            read_copies.len(),
+            vote.differing,
+            vote.tied
+        );
+    }
+    Ok(pixels)
+}
+
+fn read_pixels_within(
+    path: &Path,
+    tolerance: u8,
+    secrets: &[stego::Secret],
+) -> anyhow::Result<Vec<u8>> {
+    let (bytes, robust) = read_reporting(path, tolerance, secrets)?;
+    if let Some((transform, report)) = robust {
+        if !transform.is_native() {
+            eprintln!("found a robust image {transform}");
+        }
+        eprintln!("robust image: {report}");
+        if report.margin_used() >= robust::RENEW_AT {
+            eprintln!("  it is close to unrecoverable; encode a fresh copy from this payload");
+        }
+    }
+    Ok(bytes)
+}
+
+/// How a robust image was found turned, and what it needed corrected.
+type Corrected = (robust::Transform, robust::Report);
+
+/// Like [`read_pixels_within`], but returning what a robust image needed corrected
+/// instead of printing it.
+fn read_reporting(
+    path: &Path,
+    tolerance: u8,
+    secrets: &[stego::Secret],
+) -> anyhow::Result<(Vec<u8>, Option<Corrected>)> {
+    let (image, orientation) = read_oriented(path)?;
+    let image = picturer::canonical(image)?;
+    if let Some(container) = stego::extract(path, &image, secrets)? {
+        return Ok((container, None));
+    }
+    if let Some(container) = tile::decode(&image.to_rgba8())? {
+        return Ok((container, None));
+    }
+    if let Some((container, transform, report)) = robust::decode(&image, tolerance)? {
+        return Ok((container, Some((transform, report))));
+    }
+    let checked = resample::check(path, &image);
+    if checked.is_err() {
+        if let Some(container) = barcode::decode(&image).filter(|bytes| format::is_container(bytes))
+        {
+            return Ok((container, None));
+        }
+        if let Some((small, scale)) = resample::unscale(&image) {
+            if resample::check(path, &small).is_ok() {
+                eprintln!(
+                    "read the image at 1/{scale} of its size, as it was scaled up by {scale}"
+                );
+                return Ok((picturer::pixels(small), None));
+            }
+        }
+    }
+    if checked.is_err() && orientation != Orientation::NoTransforms {
+        // A tool that turned the pixels may have tagged the file to turn them back.
+        let mut turned = image.clone();
+        turned.apply_orientation(orientation);
+        if resample::check(path, &turned).is_ok() {
+            eprintln!("read the image as its orientation tag turns it");
+            return Ok((picturer::pixels(turned), None));
+        }
+    }
+    if secrets.is_empty() {
+        checked?;
+    } else {
+        // A deniable carrier no secret opens looks like any other photo.
+        checked.with_context(|| {
+            format!(
+                "{} is not a carrier the passphrase or keyfile opens",
+                path.display()
+            )
+        })?;
+    }
+    Ok((picturer::pixels(image), None))
+}
+
+fn read_image(path: &Path) -> anyhow::Result<DynamicImage> {
+    Ok(read_oriented(path)?.0)
+}
+
+/// The image at `path` with its pixels as stored, and the orientation tag it carries.
+fn read_oriented(path: &Path) -> anyhow::Result<(DynamicImage, Orientation)> {
+    let started = timings::start();
+    let read = picturer::read_image(ImageReader::open(path)?.with_guessed_format()?)?;
+    timings::record(Stage::PngRead, started, read.0.as_bytes().len() as u64);
+    Ok(read)
+}
+
+fn write_png(img: &RgbaImage, path: &Path, level: PngLevel) -> anyhow::Result<()> {
+    write_rows(
+        path,
+        img.width(),
+        img.height(),
+        image_rows(img),
+        level,
+        None,
+    )
+}
+
+fn image_rows(img: &RgbaImage) -> impl Iterator<Item = Cow<'_, [u8]>> {
+    img.as_raw()
+        .chunks_exact(img.width() as usize * 4)
+        .map(Cow::Borrowed)
+}
+
+/// Writes `container` to `path` as the image [`picturer::layout`], or with `opaque`
+/// [`picturer::layout_opaque`], makes of it, a row at a time rather than from a padded
+/// copy.
+fn write_container(
+    container: &[u8],
+    path: &Path,
+    opaque: bool,
+    level: PngLevel,
+) -> anyhow::Result<()> {
+    let (width, height, rows) = picturer::layout_rows(container, opaque)?;
+    write_rows(path, width, height, rows, level, None)
+}
+
+/// Writes the PNG of `rows`, with `note`, if any, as a tEXt chunk before the pixels.
+fn write_rows<'a>(
+    path: &Path,
+    width: u32,
+    height: u32,
+    rows: impl Iterator<Item = Cow<'a, [u8]>>,
+    level: PngLevel,
+    note: Option<&str>,
+) -> anyhow::Result<()> {
+    // Packed payloads barely compress again, so the file is about as large as its
+    // pixels, with a filter byte per row and some framing.
+    let pixels = u64::from(width) * u64::from(height) * 4;
+    space::ensure_free(path, pixels + pixels / 1024 + u64::from(height) + 4096)?;
+    // Filtering and deflating run in chunks across rayon's threads, which for a large
+    // image is most of the time spent encoding.
+    let mut header = mtpng::Header::new();
+    header.set_size(width, height)?;
+    header.set_color(mtpng::ColorType::TruecolorAlpha, 8)?;
+    let mut options = mtpng::encoder::Options::new();
+    options.set_compression_level(level.compression())?;
+    // Each chunk is written as its own IDAT once deflated, rather than all gathered in
+    // memory, so a slow reader of `path` holds the encoder back instead.
+    options.set_streaming(true)?;
+    let started = timings::start();
+    let _partial = interrupt::Partial::new(path);
+    let mut encoder = mtpng::encoder::Encoder::new(BufWriter::new(File::create(path)?), &options);
+    encoder.write_header(&header)?;
+    if let Some(note) = note {
+        let mut text = b"Description\0".to_vec();
+        text.extend(note.bytes());
+        encoder.write_chunk(b"tEXt", &text)?;
+    }
+    for row in rows {
+        encoder.write_image_rows(&row)?;
+    }
+    encoder.finish()?.flush()?;
+    timings::record(Stage::PngWrite, started, pixels);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs picturer with `args` as given on the command line, without a config file.
+    fn picturer(args: &[&str]) -> anyhow::Result<()> {
+        let cli = Cli::try_parse_from(std::iter::once("picturer").chain(args.iter().copied()))?;
+        let config = config::Config::default();
+        let (mode, options) = match cli.command {
+            Command::Encode(args) => (Mode::Encode, Options::encode(*args, config)?),
+            Command::Decode(args) => (Mode::Decode, Options::decode(args, config)?),
+            _ => bail!("only encode and decode are run here"),
+        };
+        execute(&mode, options)
+    }
+
+    fn path(path: &Path) -> &str {
+        path.to_str().unwrap()
+    }
+
+    /// Bytes that compress a little, so that both codecs and raw blocks are exercised.
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| u8::try_from(i * 7 % 251).unwrap())
+            .collect()
+    }
+
+    #[cfg(unix)]
+    fn fifo(path: &Path) {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = std::ffi::CString::new(path.as_os_str().as_bytes()).unwrap();
+        assert_eq!(unsafe { libc::mkfifo(name.as_ptr(), 0o600) }, 0);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn default_args_are_those_of_no_flags() {
+        let command = |args: &[&str]| Cli::try_parse_from(args).unwrap().command;
+        let Command::Encode(parsed) = command(&["picturer", "-e", "in"]) else {
+            unreachable!()
+        };
+        let built = cli::EncodeArgs::default();
+        assert!(built.format == parsed.format);
+        assert_eq!(built.copies, parsed.copies);
+        assert_eq!(built.hiding.density, parsed.hiding.density);
+        let Command::Decode(parsed) = command(&["picturer", "-d", "in"]) else {
+            unreachable!()
+        };
+        let built = cli::DecodeArgs::default();
+        assert_eq!(built.offset, parsed.offset);
+        assert_eq!(built.recovery.tolerance, parsed.recovery.tolerance);
+    }
+
+    #[test]
+    fn round_trips_through_fifos() {
+        let dir = tempfile::tempdir().unwrap();
+        let bytes = payload(300_000);
+        let input = dir.path().join("in.fifo");
+        let image = dir.path().join("image.png");
+        fifo(&input);
+        let writer = std::thread::spawn({
+            let (input, bytes) = (input.clone(), bytes.clone());
+            move || std::fs::write(input, bytes)
+        });
+        picturer(&["encode", path(&input), path(&image)]).unwrap();
+        writer.join().unwrap().unwrap();
+
+        let piped = dir.path().join("image.fifo");
+        let output = dir.path().join("out.fifo");
+        fifo(&piped);
+        fifo(&output);
+        let writer = std::thread::spawn({
+            let (piped, png) = (piped.clone(), std::fs::read(&image).unwrap());
+            move || std::fs::write(piped, png)
+        });
+        let reader = std::thread::spawn({
+            let output = output.clone();
+            move || std::fs::read(output)
+        });
+        picturer(&["decode", path(&piped), path(&output)]).unwrap();
+        writer.join().unwrap().unwrap();
+        assert_eq!(reader.join().unwrap().unwrap(), bytes);
+    }
+
+    #[test]
+    fn output_dir_holds_the_tree_as_it_was() {
+        let dir = tempfile::tempdir().unwrap();
+        let tree = dir.path().join("tree");
+        std::fs::create_dir_all(tree.join("sub")).unwrap();
+        std::fs::write(tree.join("top.txt"), b"top").unwrap();
+        std::fs::write(tree.join("sub/inner.bin"), payload(5000)).unwrap();
+        let image = dir.path().join("tree.png");
+        picturer(&["encode", path(&tree), path(&image)]).unwrap();
+
+        let out = dir.path().join("extracted");
+        picturer(&["decode", path(&image), "--output-dir", path(&out)]).unwrap();
+        assert_eq!(std::fs::read(out.join("top.txt")).unwrap(), b"top");
+        assert_eq!(
+            std::fs::read(out.join("sub/inner.bin")).unwrap(),
+            payload(5000)
+        );
+    }
+
+    #[test]
+    fn output_dir_refuses_a_malicious_archive() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("deep/out");
+        for (index, evil) in ["../../escaped", "/tmp/picturer-escaped"]
+            .iter()
+            .enumerate()
+        {
+            let hostile = dir.path().join(format!("hostile{index}"));
+            std::fs::write(
+                &hostile,
+                archive::tests::files(&[("fine", b"fine"), (evil, b"evil")]),
+            )
+            .unwrap();
+            let image = dir.path().join(format!("hostile{index}.png"));
+            picturer(&["encode", path(&hostile), path(&image)]).unwrap();
+
+            let error =
+                picturer(&["decode", path(&image), "--output-dir", path(&out)]).unwrap_err();
+            assert!(
+                format!("{error:#}").contains("not a plain relative path"),
+                "{error:#}"
+            );
+            assert!(!out.join("fine").exists());
+        }
+        assert!(!dir.path().join("escaped").exists());
+        assert!(!Path::new("/tmp/picturer-escaped").exists());
+    }
+
+    #[test]
+    fn empty_file_round_trips_to_an_empty_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("empty");
+        std::fs::write(&input, b"").unwrap();
+        let image = dir.path().join("empty.png");
+        picturer(&["encode", path(&input), path(&image)]).unwrap();
+        assert!(image::open(&image).unwrap().width() >= 1);
+
+        let output = dir.path().join("decoded");
+        picturer(&["decode", path(&image), path(&output)]).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), b"");
+    }
+
+    #[test]
+    fn stdout_gets_binary_bytes_as_they_are() {
+        let dir = tempfile::tempdir().unwrap();
+        let file = dir.path().join("payload");
+        let bytes = b"line\nnext\r\n\x1a\xff\xfe\x00\n";
+        std::fs::write(&file, bytes).unwrap();
+        let mut out = Vec::new();
+        write_stdout(&file, &mut out).unwrap();
+        assert_eq!(out, bytes);
+        assert!(write_stdout(dir.path(), &mut Vec::new()).is_err());
+    }
+
+    #[test]
+    fn non_ascii_paths_keep_their_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("données-日本語.bin");
+        let bytes = b"\r\n\n\r\xe9t\xc3\xa9";
+        std::fs::write(&input, bytes).unwrap();
+        let images = dir.path().join("画像");
+        std::fs::create_dir(&images).unwrap();
+        picturer(&["encode", path(&input), "--out-dir", path(&images)]).unwrap();
+        let image = images.join("données-日本語.png");
+        assert!(image.is_file());
+
+        let output = dir.path().join("sortie-é.bin");
+        picturer(&["decode", path(&image), path(&output)]).unwrap();
+        assert_eq!(std::fs::read(&output).unwrap(), bytes);
+    }
+
+    #[test]
+    fn auto_rename_steps_past_volumes_and_manifests() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out.png");
+        assert_eq!(unused(out.clone()), out);
+
+        std::fs::write(dir.path().join("out.001.png"), b"").unwrap();
+        assert_eq!(unused(out.clone()), dir.path().join("out-1.png"));
+        std::fs::write(dir.path().join("out-1.manifest.json"), b"").unwrap();
+        assert_eq!(unused(out.clone()), dir.path().join("out-2.png"));
+        std::fs::write(dir.path().join("out-2.0002.png"), b"").unwrap();
+        assert_eq!(unused(out.clone()), dir.path().join("out-3.png"));
+        // Names that only look alike are not part of a set.
+        std::fs::write(dir.path().join("out-3.x.png"), b"").unwrap();
+        std::fs::write(dir.path().join("out-3.001.jpg"), b"").unwrap();
+        assert_eq!(unused(out), dir.path().join("out-3.png"));
+    }
+
+    #[test]
+    fn auto_rename_leaves_an_earlier_set_alone() {
+        let dir = tempfile::tempdir().unwrap();
+        let input = dir.path().join("in.bin");
+        std::fs::write(&input, payload(1000)).unwrap();
+        let volume = dir.path().join("in.001.png");
+        let manifest = dir.path().join("in.manifest.json");
+        std::fs::write(&volume, b"volume").unwrap();
+        std::fs::write(&manifest, b"manifest").unwrap();
+        picturer(&["encode", path(&input), "--auto-rename"]).unwrap();
+
+        assert!(!dir.path().join("in.png").exists());
+        assert!(dir.path().join("in-1.png").is_file());
+        assert_eq!(std::fs::read(&volume).unwrap(), b"volume");
+        assert_eq!(std::fs::read(&manifest).unwrap(), b"manifest");
+    }
 }
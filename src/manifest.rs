@@ -0,0 +1,119 @@
+use crate::hash::hex;
+use anyhow::{ensure, Context};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Largest manifest downloaded by `picturer join` from a URL.
+pub const MAX_LEN: u64 = 16 << 20;
+
+/// JSON description of a volume set, written next to its volumes with `--manifest`.
+#[derive(Serialize, Deserialize)]
+pub struct Manifest {
+    /// Payload bytes in the whole set.
+    pub total: u64,
+    pub volumes: Vec<Entry>,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+pub struct Entry {
+    /// File name, relative to the manifest.
+    pub file: String,
+    /// 1-based, matching the number in the file name.
+    pub sequence: u32,
+    /// Of the PNG file as written.
+    pub sha256: String,
+    /// Range of the payload held by this volume.
+    pub offset: u64,
+    pub length: u64,
+    /// Of the payload range, so an interrupted encode can tell which volumes it can keep.
+    #[serde(default)]
+    pub payload_sha256: String,
+}
+
+impl Manifest {
+    /// `out.png` gets `out.manifest.json`.
+    pub fn path(out: &Path) -> PathBuf {
+        out.with_extension("manifest.json")
+    }
+
+    /// Replaces the manifest at `path` in one step, so a crash never leaves half of it.
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let mut tmp = path.as_os_str().to_owned();
+        tmp.push(".tmp");
+        fs::write(&tmp, serde_json::to_string_pretty(self)? + "\n")?;
+        Ok(fs::rename(tmp, path)?)
+    }
+
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let text = fs::read_to_string(path)
+            .with_context(|| format!("cannot read manifest {}", path.display()))?;
+        serde_json::from_str(&text).with_context(|| format!("{} is not a manifest", path.display()))
+    }
+
+    /// Checks every listed volume against its hash and returns their paths in order.
+    pub fn verify(&self, manifest: &Path) -> anyhow::Result<Vec<PathBuf>> {
+        self.check()?;
+        let dir = manifest.parent().unwrap_or(Path::new(""));
+        let paths: Vec<_> = self
+            .volumes
+            .iter()
+            .map(|entry| dir.join(&entry.file))
+            .collect();
+        self.check_files(&paths)?;
+        Ok(paths)
+    }
+
+    /// Checks that the volumes add up to the payload and follow one another in order.
+    pub fn check(&self) -> anyhow::Result<()> {
+        ensure!(
+            self.volumes.iter().map(|entry| entry.length).sum::<u64>() == self.total,
+            "manifest volumes do not add up to {} bytes",
+            self.total
+        );
+        let mut expected_offset = 0;
+        for (index, entry) in self.volumes.iter().enumerate() {
+            ensure!(
+                entry.sequence as usize == index + 1 && entry.offset == expected_offset,
+                "manifest lists {} out of order",
+                entry.file
+            );
+            expected_offset += entry.length;
+        }
+        Ok(())
+    }
+
+    /// Checks `paths`, one for every listed volume in order, against their hashes.
+    pub fn check_files(&self, paths: &[PathBuf]) -> anyhow::Result<()> {
+        ensure!(
+            paths.len() == self.volumes.len(),
+            "manifest lists {} volumes, not {}",
+            self.volumes.len(),
+            paths.len()
+        );
+        for (entry, path) in self.volumes.iter().zip(paths) {
+            ensure!(
+                hex(&hash_file(path)?) == entry.sha256,
+                "{} does not match the SHA-256 in the manifest",
+                path.display()
+            );
+        }
+        Ok(())
+    }
+}
+
+/// SHA-256 of a file, read in pieces.
+pub fn hash_file(path: &Path) -> anyhow::Result<[u8; 32]> {
+    let mut file =
+        fs::File::open(path).with_context(|| format!("cannot read volume {}", path.display()))?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 1 << 20];
+    loop {
+        match file.read(&mut buf)? {
+            0 => return Ok(hasher.finalize().into()),
+            len => hasher.update(&buf[..len]),
+        }
+    }
+}
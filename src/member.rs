@@ -0,0 +1,188 @@
+//! Images read straight out of zip and tar files, named as `archive.zip::images/data.png`
+//! or found in one with `--from-archive`, so that nothing has to be extracted first.
+//! The members are copied to temporary files, along with the other volumes of a set,
+//! which decoding then finds beside the first as it would in a directory.
+use crate::fetch::TempFile;
+use crate::{mime, read_pixels, volume};
+use anyhow::{bail, Context};
+use picturer::format::{self, Volume};
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Leading bytes of a PNG, JPEG and GIF file, which are all a member is looked at for
+/// before it is copied out.
+pub const IMAGE_MAGIC: [&[u8]; 3] = [b"\x89PNG", b"\xff\xd8\xff", b"GIF8"];
+
+/// The archive and the member of it that `given` names as `ARCHIVE::MEMBER`, if what
+/// comes before a `::` is a file.
+pub fn split(given: &Path) -> Option<(PathBuf, String)> {
+    let given = given.to_str()?;
+    given.match_indices("::").find_map(|(at, _)| {
+        let (archive, member) = (&given[..at], &given[at + 2..]);
+        (!member.is_empty() && Path::new(archive).is_file())
+            .then(|| (PathBuf::from(archive), member.to_owned()))
+    })
+}
+
+/// Copies member `name` of `archive` out, along with the other volumes of the set it is
+/// one of, if the archive holds them. The image comes first.
+pub fn extract(archive: &Path, name: &str) -> anyhow::Result<(String, Vec<TempFile>)> {
+    let wanted = normal(name);
+    let mut image = None;
+    each(archive, |member, reader| {
+        if normal(member) != wanted {
+            return Ok(true);
+        }
+        image = Some(copy(member, reader)?);
+        Ok(false)
+    })?;
+    let image = image.with_context(|| format!("{} holds no {name}", archive.display()))?;
+    let Some((index, count)) = volume::position(&image.path)? else {
+        return Ok((wanted.to_owned(), vec![image]));
+    };
+    let others: BTreeSet<String> = volume::siblings(Path::new(wanted), index, count)
+        .iter()
+        .map(|path| path.to_string_lossy().into_owned())
+        .collect();
+    let mut files = vec![image];
+    each(archive, |member, reader| {
+        if others.contains(normal(member)) {
+            files.push(copy(member, reader)?);
+        }
+        Ok(true)
+    })?;
+    Ok((wanted.to_owned(), files))
+}
+
+/// Copies out the images picturer wrote that `archive` holds, with the name of the first:
+/// one, or the volumes of one set, the first of which comes first. Any more than that is
+/// an error naming them, as which to decode is for the user to say.
+pub fn scan(archive: &Path) -> anyhow::Result<(String, Vec<TempFile>)> {
+    let mut found: Vec<(String, TempFile)> = Vec::new();
+    each(archive, |member, reader| {
+        let mut head = Vec::new();
+        reader.take(8).read_to_end(&mut head)?;
+        if !IMAGE_MAGIC.iter().any(|magic| head.starts_with(magic)) {
+            return Ok(true);
+        }
+        if let Some((other, _)) = found.iter().find(|(other, _)| name(other) == name(member)) {
+            bail!(
+                "{} holds both {other} and {member}; name one as {}::MEMBER",
+                archive.display(),
+                archive.display()
+            );
+        }
+        let file = copy(member, &mut head.as_slice().chain(reader))?;
+        if holds_payload(&file.path) {
+            found.push((normal(member).to_owned(), file));
+        }
+        Ok(true)
+    })?;
+    found.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let Some((first, _)) = found.first() else {
+        bail!("{} holds no image picturer wrote", archive.display());
+    };
+    let base = volume::base(Path::new(first));
+    let one_set = found.iter().all(|(member, file)| {
+        volume::base(Path::new(member)) == base
+            && volume::position(&file.path).is_ok_and(|position| position.is_some())
+    });
+    if found.len() > 1 && !one_set {
+        let names: Vec<_> = found.iter().map(|(member, _)| member.as_str()).collect();
+        bail!(
+            "{} holds {} images picturer wrote: {}; name one as {}::MEMBER",
+            archive.display(),
+            found.len(),
+            names.join(", "),
+            archive.display()
+        );
+    }
+    let first = first.clone();
+    Ok((first, found.into_iter().map(|(_, file)| file).collect()))
+}
+
+/// Whether the image at `path` holds a container or a volume of one.
+fn holds_payload(path: &Path) -> bool {
+    read_pixels(path).is_ok_and(|bytes| {
+        format::is_container(&bytes)
+            || format::legacy_len(&bytes).is_some()
+            || Volume::parse(&bytes).is_ok_and(|volume| volume.is_some())
+    })
+}
+
+/// Calls `visit` with the name and contents of each file `archive` holds, a zip, tar or
+/// gzipped tar file, until it returns false.
+fn each(
+    archive: &Path,
+    mut visit: impl FnMut(&str, &mut dyn Read) -> anyhow::Result<bool>,
+) -> anyhow::Result<()> {
+    let mut head = Vec::new();
+    File::open(archive)
+        .with_context(|| format!("cannot read {}", archive.display()))?
+        .take(8192)
+        .read_to_end(&mut head)?;
+    let file = BufReader::new(File::open(archive)?);
+    let read = match mime::sniff(&head) {
+        Some("zip") => unzip(file, &mut visit),
+        Some("tar.gz") => untar(flate2::read::GzDecoder::new(file), &mut visit),
+        Some("tar") => untar(file, &mut visit),
+        _ => bail!("{} is not a zip or tar file", archive.display()),
+    };
+    read.with_context(|| format!("cannot read {}", archive.display()))
+}
+
+fn unzip(
+    reader: impl Read + std::io::Seek,
+    visit: &mut impl FnMut(&str, &mut dyn Read) -> anyhow::Result<bool>,
+) -> anyhow::Result<()> {
+    let mut zip = zip::ZipArchive::new(reader)?;
+    for index in 0..zip.len() {
+        let mut file = zip.by_index(index)?;
+        if !file.is_file() {
+            continue;
+        }
+        let name = file.name()?.into_owned();
+        if !visit(&name, &mut file)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+fn untar(
+    reader: impl Read,
+    visit: &mut impl FnMut(&str, &mut dyn Read) -> anyhow::Result<bool>,
+) -> anyhow::Result<()> {
+    let mut tar = tar::Archive::new(reader);
+    for entry in tar.entries()? {
+        let mut entry = entry?;
+        if !entry.header().entry_type().is_file() {
+            continue;
+        }
+        let name = entry.path()?.to_string_lossy().into_owned();
+        if !visit(&name, &mut entry)? {
+            break;
+        }
+    }
+    Ok(())
+}
+
+/// Copies a member to a temporary file of its name, less the directories it is in.
+fn copy(member: &str, reader: &mut dyn Read) -> anyhow::Result<TempFile> {
+    let file = TempFile::new(name(member))?;
+    std::io::copy(reader, &mut File::create(&file.path)?)
+        .with_context(|| format!("cannot copy out {member}"))?;
+    Ok(file)
+}
+
+/// `member` as it is compared: without a leading `./` or `/`.
+fn normal(member: &str) -> &str {
+    member.trim_start_matches("./").trim_start_matches('/')
+}
+
+/// The file name of `member`.
+fn name(member: &str) -> &str {
+    member.rsplit(['/', '\\']).next().unwrap_or(member)
+}
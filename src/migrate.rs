@@ -0,0 +1,58 @@
+//! `picturer migrate`: decodes an image and encodes its payload again in the container
+//! format this picturer writes, so images from before the block container, which had no
+//! header but a compression flag and a length, stay readable as the format moves on.
+use crate::cli::{MigrateArgs, PngLevel};
+use crate::dict::Dictionary;
+use crate::robust;
+use anyhow::ensure;
+use image::ImageFormat;
+use picturer::format::{self, Codec, Delta, Info, Packing, Volume};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+
+/// Writes the payload of `args.old` to `args.new`, keeping the type, time, comment,
+/// metadata and ID a container stored. The dictionary, if any, is only for reading.
+pub fn run(args: &MigrateArgs) -> anyhow::Result<()> {
+    let pixels = crate::read_pixels(&args.old)?;
+    ensure!(
+        Volume::parse(&pixels)?.is_none() && Delta::parse(&pixels)?.is_none(),
+        "{} is a volume or a patch, which is migrated by decoding it and encoding the \
+         payload again",
+        args.old.display()
+    );
+    let dict = args.dict.as_deref().map(Dictionary::load).transpose()?;
+    let payload = format::unpack(&pixels, dict.as_ref())?;
+    let codec = args.codec.unwrap_or(Codec::DEFAULT);
+    let container = if format::is_container(&pixels) {
+        let info = Info::read(&pixels, dict.as_ref())?;
+        format::pack(
+            &payload,
+            Packing {
+                content_type: info.content_type.as_deref(),
+                created: info.created,
+                comment: info.comment.as_deref(),
+                meta: Some(&info.meta).filter(|meta| !meta.is_empty()),
+                meta_hidden: info.meta_hidden,
+                id: format::payload_id(&pixels),
+                ..Packing::new(codec)
+            },
+        )?
+    } else {
+        eprintln!("{} is from before the block container", args.old.display());
+        format::pack(&payload, Packing::new(codec))?
+    };
+    if args.robust {
+        let mut out = BufWriter::new(File::create(&args.new)?);
+        robust::encode(&container)?.write_to(&mut out, ImageFormat::Png)?;
+        out.flush()?;
+    } else {
+        let opaque = picturer::is_opaque(&crate::read_image(&args.old)?);
+        crate::write_container(&container, &args.new, opaque, PngLevel::Default)?;
+    }
+    eprintln!(
+        "migrated {} bytes of payload to {}",
+        payload.len(),
+        args.new.display()
+    );
+    Ok(())
+}
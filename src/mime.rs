@@ -0,0 +1,79 @@
+//! Content types of payloads, recorded when they are encoded so that decoding can give
+//! the output a fitting extension instead of `.bin`.
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Bytes of a file looked at to tell its type.
+const SNIFF_LEN: u64 = 8192;
+
+/// Types the bytes of a file do not give away, known by their extensions instead; the
+/// first extension of each is the one decoded outputs are given.
+const BY_NAME: &[(&str, &[&str])] = &[
+    ("application/x-compressed-tar", &["tar.gz", "tgz"]),
+    ("text/plain", &["txt", "text", "log"]),
+    ("text/markdown", &["md", "markdown"]),
+    ("text/csv", &["csv"]),
+    ("application/json", &["json"]),
+    ("application/toml", &["toml"]),
+    ("application/yaml", &["yaml", "yml"]),
+    ("text/css", &["css"]),
+    ("text/javascript", &["js", "mjs"]),
+    ("image/svg+xml", &["svg"]),
+];
+
+/// The content type of the file at `path`, from its leading bytes or else its name, or
+/// `None` if neither tells.
+pub fn detect(path: &Path) -> anyhow::Result<Option<String>> {
+    let mut head = Vec::new();
+    File::open(path)?.take(SNIFF_LEN).read_to_end(&mut head)?;
+    if let Some(mime) = sniff_type(&head) {
+        return Ok(Some(mime.to_owned()));
+    }
+    let name = path
+        .file_name()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .to_lowercase();
+    let by_name = BY_NAME.iter().find(|(_, extensions)| {
+        extensions
+            .iter()
+            .any(|extension| name.ends_with(&format!(".{extension}")))
+    });
+    Ok(by_name.map(|(mime, _)| (*mime).to_owned()))
+}
+
+/// The content type `head`, the leading bytes of a payload, gives away.
+fn sniff_type(head: &[u8]) -> Option<&'static str> {
+    let mime = infer::get(head)?.mime_type();
+    if mime == "application/gzip" && is_tar_gz(head) {
+        return Some(BY_NAME[0].0);
+    }
+    Some(mime)
+}
+
+/// The extension for a payload with no stored content type that its leading bytes
+/// `head` give away.
+pub fn sniff(head: &[u8]) -> Option<&'static str> {
+    extension(sniff_type(head)?, head)
+}
+
+/// Whether the gzip stream starting `head` holds a tar archive.
+fn is_tar_gz(head: &[u8]) -> bool {
+    let mut tar = Vec::new();
+    let _ = flate2::read::GzDecoder::new(head)
+        .take(512)
+        .read_to_end(&mut tar);
+    infer::archive::is_tar(&tar)
+}
+
+/// The extension for a payload of content type `mime`, whose bytes are `payload`, or
+/// `None` for a type with none known.
+pub fn extension(mime: &str, payload: &[u8]) -> Option<&'static str> {
+    if let Some((_, extensions)) = BY_NAME.iter().find(|(known, _)| *known == mime) {
+        return extensions.first().copied();
+    }
+    infer::get(payload)
+        .filter(|kind| kind.mime_type() == mime)
+        .map(|kind| kind.extension())
+}
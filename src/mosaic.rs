@@ -0,0 +1,162 @@
+//! Images written as colored half-block characters with `--format ansi-art`, two pixels
+//! to a character, so that a small payload such as a key can be shown in a terminal and
+//! decoded again from the text that terminal captured.
+use anyhow::{bail, ensure, Context};
+use image::{Rgb, RgbImage};
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Widest image written, in characters, beyond which the art no longer fits a terminal.
+pub const MAX_COLUMNS: u32 = 160;
+
+/// Writes `image` to `out` as ANSI art, the way the ANSI preview draws it unscaled.
+pub fn write(image: &RgbImage, out: &Path) -> anyhow::Result<()> {
+    ensure!(
+        image.width() <= MAX_COLUMNS,
+        "--format ansi-art is for small payloads: the art would be {} columns wide, more \
+         than {MAX_COLUMNS}",
+        image.width()
+    );
+    std::fs::write(out, crate::preview::ansi(image))
+        .with_context(|| format!("cannot write {}", out.display()))
+}
+
+/// Whether the file at `path` starts like ANSI art: truecolor escapes and half blocks.
+pub fn is_mosaic(path: &Path) -> anyhow::Result<bool> {
+    let mut start = Vec::new();
+    File::open(path)?.take(1024).read_to_end(&mut start)?;
+    let text = String::from_utf8_lossy(&start);
+    Ok(text.contains("\x1b[38;2;") && text.contains(['▀', '▄']))
+}
+
+/// The image the ANSI art at `path` draws. Colors are followed as a terminal follows
+/// them, so that art captured again, with repeated escapes dropped or cells drawn as
+/// lower half or full blocks instead, reads the same; lines without colored cells, such
+/// as a prompt, are skipped.
+pub fn read(path: &Path) -> anyhow::Result<RgbImage> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("{} is not text", path.display()))?;
+    let mut state = Colors::default();
+    let mut rows = Vec::new();
+    for line in text.lines() {
+        let cells = state.cells(line)?;
+        if !cells.is_empty() {
+            rows.push(cells);
+        }
+    }
+    let Some(width) = rows.first().map(Vec::len) else {
+        bail!("{} draws no colored cells", path.display())
+    };
+    ensure!(
+        rows.iter().all(|row| row.len() == width),
+        "{} is not a rectangle of cells; its lines are of different widths",
+        path.display()
+    );
+    // Only the last line may leave the lower halves uncolored, for an odd height.
+    let odd = rows[rows.len() - 1]
+        .iter()
+        .all(|(_, lower)| lower.is_none());
+    let height = rows.len() * 2 - usize::from(odd);
+    let mut image = RgbImage::new(u32::try_from(width)?, u32::try_from(height)?);
+    for (y, row) in rows.iter().enumerate() {
+        for (x, cell) in row.iter().enumerate() {
+            let (x, y) = (u32::try_from(x)?, u32::try_from(y * 2)?);
+            let pixels = match *cell {
+                (Some(upper), Some(lower)) => [Some(upper), Some(lower)],
+                (Some(upper), None) if odd && y + 1 == image.height() => [Some(upper), None],
+                _ => bail!("{} has a cell without both of its colors", path.display()),
+            };
+            for (dy, pixel) in (0..).zip(pixels) {
+                if let Some(pixel) = pixel {
+                    image.put_pixel(x, y + dy, pixel);
+                }
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// The foreground and background color last set by escapes.
+#[derive(Default)]
+struct Colors {
+    fg: Option<Rgb<u8>>,
+    bg: Option<Rgb<u8>>,
+}
+
+type Cell = (Option<Rgb<u8>>, Option<Rgb<u8>>);
+
+impl Colors {
+    /// The upper and lower color of each cell `line` draws.
+    fn cells(&mut self, line: &str) -> anyhow::Result<Vec<Cell>> {
+        let mut cells = Vec::new();
+        let mut chars = line.chars();
+        while let Some(char) = chars.next() {
+            match char {
+                '\x1b' => {
+                    if chars.next() != Some('[') {
+                        continue;
+                    }
+                    let mut params = String::new();
+                    for char in chars.by_ref() {
+                        if char.is_ascii_digit() || char == ';' {
+                            params.push(char);
+                        } else {
+                            if char == 'm' {
+                                self.apply(&params)?;
+                            }
+                            break;
+                        }
+                    }
+                }
+                '▀' => cells.push((self.fg, self.bg)),
+                '▄' => cells.push((self.bg, self.fg)),
+                '█' => cells.push((self.fg, self.fg)),
+                ' ' if self.bg.is_some() => cells.push((self.bg, self.bg)),
+                _ => {}
+            }
+        }
+        Ok(cells)
+    }
+
+    /// Follows a select graphic rendition escape with parameters `params`.
+    fn apply(&mut self, params: &str) -> anyhow::Result<()> {
+        let params = params
+            .split(';')
+            .map(|param| {
+                if param.is_empty() {
+                    Ok(0)
+                } else {
+                    param.parse()
+                }
+            })
+            .collect::<Result<Vec<u16>, _>>()
+            .context("ANSI art holds a malformed color escape")?;
+        let mut params = params.iter().copied();
+        while let Some(param) = params.next() {
+            match param {
+                0 => *self = Colors::default(),
+                39 => self.fg = None,
+                49 => self.bg = None,
+                38 | 48 => {
+                    let (Some(2), Some(r), Some(g), Some(b)) =
+                        (params.next(), params.next(), params.next(), params.next())
+                    else {
+                        bail!("ANSI art holds a color that is not 24-bit, which loses data")
+                    };
+                    let channel = |channel| {
+                        u8::try_from(channel).context("ANSI art holds a color out of range")
+                    };
+                    let color = Some(Rgb([channel(r)?, channel(g)?, channel(b)?]));
+                    if param == 38 {
+                        self.fg = color;
+                    } else {
+                        self.bg = color;
+                    }
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
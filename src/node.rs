@@ -0,0 +1,62 @@
+//! Node.js addon, built with `npm run build` from `package.json`. The work runs on the
+//! libuv thread pool, so neither call blocks the event loop.
+use crate::format::{Codec, Packing};
+use napi::bindgen_prelude::{AsyncTask, Buffer};
+use napi::{Env, Task};
+use napi_derive::napi;
+
+#[allow(clippy::needless_pass_by_value)]
+fn error(error: anyhow::Error) -> napi::Error {
+    napi::Error::from_reason(format!("{error:#}"))
+}
+
+pub struct Encode {
+    data: Buffer,
+    codec: Codec,
+}
+
+impl Task for Encode {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> napi::Result<Vec<u8>> {
+        crate::encode_png(&self.data, Packing::new(self.codec)).map_err(error)
+    }
+
+    fn resolve(&mut self, _env: Env, png: Vec<u8>) -> napi::Result<Buffer> {
+        Ok(png.into())
+    }
+}
+
+pub struct Decode {
+    png: Buffer,
+}
+
+impl Task for Decode {
+    type Output = Vec<u8>;
+    type JsValue = Buffer;
+
+    fn compute(&mut self) -> napi::Result<Vec<u8>> {
+        crate::decode_png(&self.png, None).map_err(error)
+    }
+
+    fn resolve(&mut self, _env: Env, data: Vec<u8>) -> napi::Result<Buffer> {
+        Ok(data.into())
+    }
+}
+
+/// Encodes `data` as a PNG file with `codec` (`raw`, `zlib[-0..9]` or `zstd[-1..22]`).
+#[napi(ts_return_type = "Promise<Buffer>")]
+pub fn encode(data: Buffer, codec: Option<String>) -> napi::Result<AsyncTask<Encode>> {
+    let codec = match codec {
+        Some(codec) => codec.parse().map_err(error)?,
+        None => Codec::DEFAULT,
+    };
+    Ok(AsyncTask::new(Encode { data, codec }))
+}
+
+/// Decodes a PNG file written by picturer back into the bytes it holds.
+#[napi(ts_return_type = "Promise<Buffer>")]
+pub fn decode(png: Buffer) -> AsyncTask<Decode> {
+    AsyncTask::new(Decode { png })
+}
@@ -0,0 +1,201 @@
+//! Single-page PDFs written with `--format pdf`: the image at a stated DPI under lines
+//! that say what it holds and how to get it back, for archiving on paper or as PDF.
+//! Decoding reads the image back from PDFs written this way.
+use anyhow::{bail, ensure, Context};
+use flate2::read::ZlibDecoder;
+use flate2::write::ZlibEncoder;
+use flate2::Compression;
+use image::RgbImage;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::Path;
+
+/// A4, in points.
+const PAGE: (u32, u32) = (595, 842);
+const MARGIN: u32 = 48;
+const FONT_SIZE: u32 = 9;
+const LEADING: u32 = 13;
+/// Resolution images are placed at unless they need a higher one to fit the page.
+pub const DPI: u32 = 150;
+
+/// The resolution `image` is placed at under `lines` of text: [`DPI`], or the lowest
+/// whole one that fits it on the page.
+pub fn dpi(image: &RgbImage, lines: usize) -> u32 {
+    let text = u32::try_from(lines)
+        .unwrap_or(u32::MAX)
+        .saturating_mul(LEADING)
+        + LEADING;
+    let room = (
+        PAGE.0 - 2 * MARGIN,
+        (PAGE.1 - 2 * MARGIN).saturating_sub(text).max(LEADING),
+    );
+    let needed = |pixels: u32, points: u32| (u64::from(pixels) * 72).div_ceil(u64::from(points));
+    let needed = needed(image.width(), room.0).max(needed(image.height(), room.1));
+    u32::try_from(needed).unwrap_or(u32::MAX).max(DPI)
+}
+
+/// Writes a page to `out` with `lines` of text at the top and `image` under them at
+/// `dpi`, its pixels deflated as they are, so that they can be extracted unchanged.
+pub fn write(
+    image: &RgbImage,
+    dpi: u32,
+    lines: &[String],
+    title: &str,
+    out: &Path,
+) -> anyhow::Result<()> {
+    let mut pixels = ZlibEncoder::new(Vec::new(), Compression::best());
+    pixels.write_all(image.as_raw())?;
+    let pixels = pixels.finish()?;
+    let points = |side: u32| f64::from(side) * 72.0 / f64::from(dpi);
+    let (width, height) = (points(image.width()), points(image.height()));
+    let left = (f64::from(PAGE.0) - width) / 2.0;
+    let top = PAGE.1 - MARGIN;
+    let text_height = u32::try_from(lines.len())? * LEADING;
+    let bottom = f64::from(top - text_height - LEADING) - height;
+    let mut contents = format!(
+        "BT /F1 {FONT_SIZE} Tf {LEADING} TL {MARGIN} {} Td\n",
+        top - FONT_SIZE
+    );
+    for line in lines {
+        let _ = writeln!(contents, "({}) Tj T*", escape(line));
+    }
+    let _ = writeln!(
+        contents,
+        "ET\nq {width:.3} 0 0 {height:.3} {left:.3} {bottom:.3} cm /Im1 Do Q"
+    );
+
+    let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::new();
+    let mut object = |pdf: &mut Vec<u8>, body: &[u8]| {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", offsets.len()).as_bytes());
+        pdf.extend_from_slice(body);
+        pdf.extend_from_slice(b"\nendobj\n");
+    };
+    object(&mut pdf, b"<< /Type /Catalog /Pages 2 0 R >>");
+    object(&mut pdf, b"<< /Type /Pages /Kids [3 0 R] /Count 1 >>");
+    object(
+        &mut pdf,
+        format!(
+            "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] /Resources << /Font << /F1 4 \
+             0 R >> /XObject << /Im1 5 0 R >> >> /Contents 6 0 R >>",
+            PAGE.0, PAGE.1
+        )
+        .as_bytes(),
+    );
+    object(
+        &mut pdf,
+        b"<< /Type /Font /Subtype /Type1 /BaseFont /Courier /Encoding /WinAnsiEncoding >>",
+    );
+    let mut image_object = format!(
+        "<< /Type /XObject /Subtype /Image /Width {} /Height {} /ColorSpace /DeviceRGB \
+         /BitsPerComponent 8 /Interpolate false /Filter /FlateDecode /Length {} >>\nstream\n",
+        image.width(),
+        image.height(),
+        pixels.len()
+    )
+    .into_bytes();
+    image_object.extend_from_slice(&pixels);
+    image_object.extend_from_slice(b"\nendstream");
+    object(&mut pdf, &image_object);
+    object(
+        &mut pdf,
+        format!(
+            "<< /Length {} >>\nstream\n{contents}endstream",
+            contents.len()
+        )
+        .as_bytes(),
+    );
+    object(
+        &mut pdf,
+        format!("<< /Title ({}) /Producer (picturer) >>", escape(title)).as_bytes(),
+    );
+    let xref = pdf.len();
+    let mut trailer = format!("xref\n0 {}\n0000000000 65535 f \n", offsets.len() + 1);
+    for offset in &offsets {
+        let _ = writeln!(trailer, "{offset:010} 00000 n ");
+    }
+    let _ = write!(
+        trailer,
+        "trailer\n<< /Size {} /Root 1 0 R /Info {} 0 R >>\nstartxref\n{xref}\n%%EOF\n",
+        offsets.len() + 1,
+        offsets.len()
+    );
+    pdf.extend_from_slice(trailer.as_bytes());
+    std::fs::write(out, pdf).with_context(|| format!("cannot write {}", out.display()))
+}
+
+/// Whether the file at `path` is a PDF.
+pub fn is_pdf(path: &Path) -> anyhow::Result<bool> {
+    let mut start = [0; 5];
+    let read = File::open(path)?.read(&mut start)?;
+    Ok(start[..read] == *b"%PDF-")
+}
+
+/// The image on the page of the PDF at `path`, if it is stored as [`write`] stores it:
+/// 8-bit RGB, deflated or not. To read one that was rewritten since, extract the image
+/// with a PDF tool that keeps its pixels, such as `pdfimages -png`.
+pub fn read(path: &Path) -> anyhow::Result<RgbImage> {
+    let pdf = std::fs::read(path)?;
+    let Some(start) = find(&pdf, b"/Subtype /Image") else {
+        bail!(
+            "{} holds no image picturer can find; extract it with pdfimages -png and \
+             decode that instead",
+            path.display()
+        )
+    };
+    let dict_start = pdf[..start]
+        .windows(2)
+        .rposition(|pair| pair == b"<<")
+        .context("the PDF image has no dictionary")?;
+    let dict_end =
+        start + find(&pdf[start..], b">>").context("the PDF image dictionary is cut short")?;
+    let dict = String::from_utf8_lossy(&pdf[dict_start..dict_end]);
+    let value = |name: &str| -> anyhow::Result<u32> {
+        dict.split_once(&format!("/{name} "))
+            .and_then(|(_, rest)| rest.split_whitespace().next()?.parse().ok())
+            .with_context(|| format!("the PDF image gives no /{name}, or not directly"))
+    };
+    let (width, height, len) = (value("Width")?, value("Height")?, value("Length")?);
+    ensure!(
+        dict.contains("/DeviceRGB") && value("BitsPerComponent")? == 8,
+        "{} holds an image that is not 8-bit RGB, which picturer does not write; extract \
+         it with pdfimages -png and decode that instead",
+        path.display()
+    );
+    let stream =
+        dict_end + find(&pdf[dict_end..], b"stream").context("the PDF image has no stream")?;
+    let data = stream + b"stream".len();
+    let data = data + usize::from(pdf.get(data) == Some(&b'\r')) + 1;
+    let data = pdf
+        .get(data..data + usize::try_from(len)?)
+        .context("the PDF image stream is cut short")?;
+    let mut pixels = Vec::new();
+    if dict.contains("/FlateDecode") {
+        ZlibDecoder::new(data)
+            .take(u64::from(width) * u64::from(height) * 3)
+            .read_to_end(&mut pixels)
+            .context("the PDF image stream is damaged")?;
+    } else {
+        pixels = data.to_vec();
+    }
+    RgbImage::from_raw(width, height, pixels).context("the PDF image stream holds too few pixels")
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+/// `text` as a PDF string, with anything a Courier font has no glyph for as `?`.
+fn escape(text: &str) -> String {
+    text.chars()
+        .map(|char| match char {
+            '(' | ')' | '\\' => format!("\\{char}"),
+            ' '..='~' => char.to_string(),
+            _ => "?".to_owned(),
+        })
+        .collect()
+}
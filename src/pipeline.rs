@@ -0,0 +1,338 @@
+//! Stages that every compressed block of a container passes through before it is
+//! stored, such as encryption or error correction, and the registry readers find them
+//! in. The container stores the name and settings of each stage, so any reader that
+//! knows the names can undo them; stages from other crates are made known with
+//! [`register`].
+use crate::diagnostic::{self, Code};
+use crate::format::{self, Reader};
+use anyhow::{bail, ensure};
+use std::borrow::Cow;
+use std::sync::{PoisonError, RwLock};
+
+/// One transform of a block, undone by [`Stage::reverse`].
+pub trait Stage: Send + Sync {
+    /// Name the stage is stored and registered under.
+    fn name(&self) -> &str;
+
+    /// Settings a reader needs to rebuild the stage, passed to its [`Factory`].
+    fn params(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    fn apply(&self, block: Vec<u8>) -> anyhow::Result<Vec<u8>>;
+
+    fn reverse(&self, block: Vec<u8>) -> anyhow::Result<Vec<u8>>;
+}
+
+/// Rebuilds a stage from the settings its [`Stage::params`] stored.
+pub type Factory = fn(params: &[u8]) -> anyhow::Result<Box<dyn Stage>>;
+
+static REGISTRY: RwLock<Vec<(&'static str, Factory)>> = RwLock::new(Vec::new());
+
+/// Lets containers with stages named `name` be read, by rebuilding them with
+/// `factory`. A name registered again replaces the earlier one; the built-in names
+/// cannot be replaced.
+pub fn register(name: &'static str, factory: Factory) {
+    let mut registry = REGISTRY.write().unwrap_or_else(PoisonError::into_inner);
+    registry.retain(|(registered, _)| *registered != name);
+    registry.push((name, factory));
+}
+
+fn build(name: &str, params: &[u8]) -> anyhow::Result<Box<dyn Stage>> {
+    if name == Checksum::NAME {
+        return Checksum::build(params);
+    }
+    if name == ErrorCorrection::NAME {
+        return ErrorCorrection::build(params);
+    }
+    let registry = REGISTRY.read().unwrap_or_else(PoisonError::into_inner);
+    let Some((_, factory)) = registry.iter().find(|(registered, _)| *registered == name) else {
+        bail!("image needs pipeline stage {name}, which is not registered")
+    };
+    factory(params)
+}
+
+/// Stages applied in order after compression, and undone in reverse before
+/// decompression.
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<Box<dyn Stage>>,
+}
+
+impl Pipeline {
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `stage` after the others.
+    #[must_use]
+    pub fn then(mut self, stage: impl Stage + 'static) -> Self {
+        self.stages.push(Box::new(stage));
+        self
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.stages.is_empty()
+    }
+
+    /// Names of the stages, in the order they are applied.
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.stages.iter().map(|stage| stage.name())
+    }
+
+    /// What the container header stores: for each stage, the length of its name as a
+    /// `u8`, the name, the length of its settings as a `u32`, and the settings.
+    pub(crate) fn descriptor(&self) -> anyhow::Result<Vec<u8>> {
+        let mut buf = Vec::new();
+        for stage in &self.stages {
+            let name = stage.name();
+            let params = stage.params();
+            buf.push(u8::try_from(name.len())?);
+            buf.extend(name.as_bytes());
+            buf.extend(u32::try_from(params.len())?.to_le_bytes());
+            buf.extend(params);
+        }
+        Ok(buf)
+    }
+
+    /// Names of the stages a [`Pipeline::descriptor`] describes, whether or not they are
+    /// registered.
+    pub(crate) fn stage_names(bytes: &[u8]) -> anyhow::Result<Vec<String>> {
+        let mut names = Vec::new();
+        let mut reader = Reader(bytes);
+        while !reader.0.is_empty() {
+            let len = usize::from(reader.u8()?);
+            names.push(String::from_utf8_lossy(reader.take(len)?).into_owned());
+            let len = usize::try_from(reader.u32()?)?;
+            reader.take(len)?;
+        }
+        Ok(names)
+    }
+
+    /// Rebuilds the pipeline a [`Pipeline::descriptor`] describes.
+    pub(crate) fn from_descriptor(bytes: &[u8]) -> anyhow::Result<Self> {
+        let mut pipeline = Pipeline::new();
+        let mut reader = Reader(bytes);
+        while !reader.0.is_empty() {
+            let len = usize::from(reader.u8()?);
+            let name = std::str::from_utf8(reader.take(len)?)?;
+            let len = usize::try_from(reader.u32()?)?;
+            pipeline.stages.push(build(name, reader.take(len)?)?);
+        }
+        Ok(pipeline)
+    }
+
+    pub(crate) fn apply(&self, block: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        self.stages
+            .iter()
+            .try_fold(block, |block, stage| stage.apply(block))
+    }
+
+    /// Undoes every stage, borrowing `block` untouched when there are none.
+    pub(crate) fn reverse<'a>(&self, block: &'a [u8]) -> anyhow::Result<Cow<'a, [u8]>> {
+        if self.stages.is_empty() {
+            return Ok(Cow::Borrowed(block));
+        }
+        let block = self
+            .stages
+            .iter()
+            .rev()
+            .try_fold(block.to_vec(), |block, stage| stage.reverse(block))?;
+        Ok(Cow::Owned(block))
+    }
+}
+
+/// Appends the BLAKE3 hash of each block, so that a changed block is reported rather
+/// than decompressed into garbage.
+pub struct Checksum;
+
+impl Checksum {
+    pub const NAME: &str = "blake3";
+
+    fn build(params: &[u8]) -> anyhow::Result<Box<dyn Stage>> {
+        ensure!(params.is_empty(), "stage {} takes no settings", Self::NAME);
+        Ok(Box::new(Checksum))
+    }
+}
+
+impl Stage for Checksum {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn apply(&self, mut block: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let hash = blake3::hash(&block);
+        block.extend(hash.as_bytes());
+        Ok(block)
+    }
+
+    fn reverse(&self, mut block: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let Some(start) = block.len().checked_sub(blake3::OUT_LEN) else {
+            return Err(format::truncated());
+        };
+        let hash: [u8; blake3::OUT_LEN] = block[start..].try_into()?;
+        block.truncate(start);
+        if blake3::hash(&block) != hash {
+            return Err(diagnostic::error(
+                Code::Damaged,
+                "a block does not match its checksum",
+            ));
+        }
+        Ok(block)
+    }
+}
+
+/// Splits each block into Reed-Solomon codewords of 255 bytes, `parity` of which are
+/// check bytes, so that up to half as many changed bytes in each are corrected.
+pub struct ErrorCorrection {
+    parity: usize,
+}
+
+impl ErrorCorrection {
+    pub const NAME: &str = "reed-solomon";
+    /// Bytes of each codeword, data and check bytes together.
+    const CODEWORD: usize = 255;
+
+    /// Check bytes in each codeword, from 1 to 254.
+    pub fn new(parity: u8) -> anyhow::Result<Self> {
+        ensure!(
+            (1..u8::MAX).contains(&parity),
+            "a codeword needs from 1 to 254 check bytes, not {parity}"
+        );
+        Ok(ErrorCorrection {
+            parity: usize::from(parity),
+        })
+    }
+
+    fn build(params: &[u8]) -> anyhow::Result<Box<dyn Stage>> {
+        let &[parity] = params else {
+            bail!("stage {} takes one byte of settings", Self::NAME)
+        };
+        Ok(Box::new(Self::new(parity)?))
+    }
+}
+
+impl Stage for ErrorCorrection {
+    fn name(&self) -> &str {
+        Self::NAME
+    }
+
+    fn params(&self) -> Vec<u8> {
+        vec![u8::try_from(self.parity).unwrap_or(u8::MAX)]
+    }
+
+    fn apply(&self, block: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let encoder = reed_solomon::Encoder::new(self.parity);
+        Ok(block
+            .chunks(Self::CODEWORD - self.parity)
+            .flat_map(|data| encoder.encode(data).to_vec())
+            .collect())
+    }
+
+    fn reverse(&self, block: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+        let decoder = reed_solomon::Decoder::new(self.parity);
+        let mut data = Vec::with_capacity(block.len());
+        for codeword in block.chunks(Self::CODEWORD) {
+            if codeword.len() <= self.parity {
+                return Err(format::truncated());
+            }
+            let corrected = decoder.correct(codeword, None).map_err(|_| {
+                diagnostic::error(Code::Damaged, "a block has more errors than it can correct")
+            })?;
+            data.extend(corrected.data());
+        }
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{Codec, Info, Packing};
+
+    /// Flips every bit of a block, a stage no reader knows unless a test registers it.
+    struct Invert;
+
+    impl Stage for Invert {
+        fn name(&self) -> &'static str {
+            "invert"
+        }
+
+        fn apply(&self, block: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+            Ok(block.into_iter().map(|byte| !byte).collect())
+        }
+
+        fn reverse(&self, block: Vec<u8>) -> anyhow::Result<Vec<u8>> {
+            self.apply(block)
+        }
+    }
+
+    fn pack(payload: &[u8], pipeline: &Pipeline) -> Vec<u8> {
+        let packing = Packing {
+            pipeline: Some(pipeline),
+            block_size: Some(4_096),
+            ..Packing::new(Codec::Raw)
+        };
+        format::pack(payload, packing).unwrap()
+    }
+
+    fn payload() -> Vec<u8> {
+        (0..10_000u32).map(|i| (i * 29).to_le_bytes()[0]).collect()
+    }
+
+    #[test]
+    fn error_correction_undoes_changed_bytes() {
+        let payload = payload();
+        let pipeline = Pipeline::new().then(ErrorCorrection::new(16).unwrap());
+        let mut container = pack(&payload, &pipeline);
+        assert_eq!(format::unpack(&container, None).unwrap(), payload);
+        let len = container.len();
+        // Eight changed bytes in the last codeword are as many as 16 check bytes correct.
+        for byte in &mut container[len - 8..] {
+            *byte ^= 0x55;
+        }
+        assert_eq!(format::unpack(&container, None).unwrap(), payload);
+        container[len - 9] ^= 0x55;
+        let err = format::unpack(&container, None).unwrap_err();
+        assert!(err.to_string().contains("more errors"), "{err}");
+
+        assert!(ErrorCorrection::new(0).is_err());
+        assert!(ErrorCorrection::new(255).is_err());
+        assert!(build(ErrorCorrection::NAME, &[]).is_err());
+    }
+
+    #[test]
+    fn stages_are_rebuilt_from_the_header_in_order() {
+        let pipeline = Pipeline::new()
+            .then(ErrorCorrection::new(32).unwrap())
+            .then(Checksum);
+        let descriptor = pipeline.descriptor().unwrap();
+        let rebuilt = Pipeline::from_descriptor(&descriptor).unwrap();
+        assert!(rebuilt.names().eq(["reed-solomon", "blake3"]));
+        assert_eq!(rebuilt.descriptor().unwrap(), descriptor);
+        let payload = payload();
+        assert_eq!(
+            format::unpack(&pack(&payload, &pipeline), None).unwrap(),
+            payload
+        );
+        assert!(Pipeline::from_descriptor(&descriptor[..descriptor.len() - 1]).is_err());
+    }
+
+    #[test]
+    fn an_unknown_stage_is_named_until_it_is_registered() {
+        let payload = payload();
+        let container = pack(&payload, &Pipeline::new().then(Checksum).then(Invert));
+        let info = Info::read(&container, None).unwrap();
+        assert_eq!(info.stages, ["blake3", "invert"]);
+        let err = format::unpack(&container, None).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "image needs pipeline stage invert, which is not registered"
+        );
+
+        register("invert", |_| Ok(Box::new(Invert)));
+        assert_eq!(format::unpack(&container, None).unwrap(), payload);
+    }
+}
@@ -0,0 +1,363 @@
+//! Post-quantum hybrid age recipients, `picturerpq1...`, for images archived for long
+//! enough that someone who keeps them might one day break X25519. A file key is wrapped
+//! with a secret both X25519 and ML-KEM-768 agree on, so it stays safe while either
+//! holds. Only picturer reads the stanzas these write, and age refuses to mix them with
+//! classical recipients in one file, which would undo the point of them. Their
+//! identities are `PICTURER-PQ-SECRET-KEY-1...` lines, made with `picturer key
+//! generate-pq` and read from the same `--age-identity` files as age's.
+use crate::age::{Identities, Recipients};
+use std::path::Path;
+
+const PUBLIC_PREFIX: &str = "picturerpq1";
+const SECRET_PREFIX: &str = "PICTURER-PQ-SECRET-KEY-1";
+
+/// The recipient `line` holds if it is a hybrid public key, which fails to parse if it
+/// is a malformed one; `None` if it is not one at all.
+pub fn recipient(line: &str) -> Option<anyhow::Result<Recipients>> {
+    line.starts_with(PUBLIC_PREFIX).then(|| {
+        #[cfg(feature = "pq")]
+        return Ok(vec![Box::new(line.parse::<hybrid::Recipient>()?) as _]);
+        #[cfg(not(feature = "pq"))]
+        anyhow::bail!("picturer was built without the pq feature, which {PUBLIC_PREFIX} keys need")
+    })
+}
+
+/// The identity `line` holds if it is a hybrid secret key, as [`recipient`] reads public
+/// ones.
+pub fn identity(line: &str) -> Option<anyhow::Result<Identities>> {
+    line.starts_with(SECRET_PREFIX).then(|| {
+        #[cfg(feature = "pq")]
+        return Ok(vec![Box::new(line.parse::<hybrid::Identity>()?) as _]);
+        #[cfg(not(feature = "pq"))]
+        anyhow::bail!("picturer was built without the pq feature, which {SECRET_PREFIX} keys need")
+    })
+}
+
+/// Writes a new hybrid identity to `output`, readable by its owner only, or prints it,
+/// in the layout of `age-keygen`: its public key in a comment, then the secret key.
+pub fn generate(output: Option<&Path>) -> anyhow::Result<()> {
+    #[cfg(feature = "pq")]
+    return hybrid::generate(output);
+    #[cfg(not(feature = "pq"))]
+    {
+        let _ = output;
+        anyhow::bail!("picturer was built without the pq feature")
+    }
+}
+
+#[cfg(feature = "pq")]
+mod hybrid {
+    use super::{PUBLIC_PREFIX, SECRET_PREFIX};
+    use age_core::format::{FileKey, Stanza, FILE_KEY_BYTES};
+    use age_core::primitives::{aead_decrypt, aead_encrypt, bech32_decode, bech32_encode, hkdf};
+    use age_core::secrecy::ExposeSecret;
+    use anyhow::{anyhow, bail, Context};
+    use base64::prelude::{Engine, BASE64_STANDARD_NO_PAD};
+    use ml_kem::kem::{Decapsulate, Encapsulate};
+    use ml_kem::{Ciphertext, EncodedSizeUser, KemCore, MlKem768, B32};
+    use rand_core::OsRng;
+    use std::collections::HashSet;
+    use std::fmt;
+    use std::io::Write;
+    use std::path::Path;
+    use std::str::FromStr;
+    use x25519_dalek::{EphemeralSecret, PublicKey, StaticSecret};
+    use zeroize::Zeroizing;
+
+    type EncapsulationKey = <MlKem768 as KemCore>::EncapsulationKey;
+    type DecapsulationKey = <MlKem768 as KemCore>::DecapsulationKey;
+
+    const TAG: &str = "picturer-X25519-MLKEM768";
+    /// Keys with which age lets only other post-quantum stanzas share a file.
+    const LABEL: &str = "postquantum";
+    const KEY_LABEL: &[u8] = b"picturer.pq/X25519-MLKEM768";
+    const ENCAPSULATION_KEY_BYTES: usize = 1184;
+    const CIPHERTEXT_BYTES: usize = 1088;
+    const WRAPPED_BYTES: usize = FILE_KEY_BYTES + 16;
+
+    /// The public half of a hybrid identity.
+    #[derive(Clone)]
+    pub struct Recipient {
+        mlkem: EncapsulationKey,
+        x25519: PublicKey,
+    }
+
+    /// A hybrid secret key: one 32-byte seed, from which both keys are derived.
+    pub struct Identity {
+        seed: Zeroizing<[u8; 32]>,
+        mlkem: DecapsulationKey,
+        x25519: StaticSecret,
+        public: Recipient,
+    }
+
+    impl Identity {
+        fn from_seed(seed: [u8; 32]) -> Self {
+            let seed = Zeroizing::new(seed);
+            let d = B32::from(hkdf(&[], b"picturer.pq/mlkem-d", seed.as_ref()));
+            let z = B32::from(hkdf(&[], b"picturer.pq/mlkem-z", seed.as_ref()));
+            let (mlkem, encapsulation) = MlKem768::generate_deterministic(&d, &z);
+            let x25519 = StaticSecret::from(hkdf(&[], b"picturer.pq/x25519", seed.as_ref()));
+            let public = Recipient {
+                mlkem: encapsulation,
+                x25519: PublicKey::from(&x25519),
+            };
+            Identity {
+                seed,
+                mlkem,
+                x25519,
+                public,
+            }
+        }
+
+        fn secret_string(&self) -> Zeroizing<String> {
+            let hrp = bech32::Hrp::parse_unchecked(&SECRET_PREFIX[..SECRET_PREFIX.len() - 1]);
+            Zeroizing::new(bech32_encode(hrp, self.seed.as_ref()).to_uppercase())
+        }
+    }
+
+    /// The key both sides derive from the two shared secrets, bound to the ephemeral
+    /// share, the recipient and the ML-KEM ciphertext.
+    fn wrapping_key(
+        mlkem_secret: &[u8],
+        x25519_secret: &[u8; 32],
+        share: &PublicKey,
+        recipient: &PublicKey,
+        ciphertext: &[u8],
+    ) -> Zeroizing<[u8; 32]> {
+        let mut salt = Vec::with_capacity(64 + ciphertext.len());
+        salt.extend_from_slice(share.as_bytes());
+        salt.extend_from_slice(recipient.as_bytes());
+        salt.extend_from_slice(ciphertext);
+        let mut secret = Zeroizing::new(Vec::with_capacity(64));
+        secret.extend_from_slice(mlkem_secret);
+        secret.extend_from_slice(x25519_secret);
+        Zeroizing::new(hkdf(&salt, KEY_LABEL, &secret))
+    }
+
+    fn decode(given: &str, hrp: &str) -> anyhow::Result<Vec<u8>> {
+        bech32_decode(
+            given,
+            |_| anyhow!("{given:.24}... is not valid Bech32"),
+            |found| {
+                if found.as_str().eq_ignore_ascii_case(hrp) {
+                    Ok(())
+                } else {
+                    Err(anyhow!("{given:.24}... is not a {hrp}1 key"))
+                }
+            },
+            |_, bytes| Ok(bytes.collect()),
+        )
+    }
+
+    impl FromStr for Recipient {
+        type Err = anyhow::Error;
+
+        fn from_str(given: &str) -> anyhow::Result<Self> {
+            let bytes = decode(given, &PUBLIC_PREFIX[..PUBLIC_PREFIX.len() - 1])?;
+            anyhow::ensure!(
+                bytes.len() == ENCAPSULATION_KEY_BYTES + 32,
+                "{given:.24}... is not the length of a {PUBLIC_PREFIX} key"
+            );
+            let (mlkem, x25519) = bytes.split_at(ENCAPSULATION_KEY_BYTES);
+            let mlkem = EncapsulationKey::from_bytes(
+                mlkem
+                    .try_into()
+                    .context("the ML-KEM key has the wrong length")?,
+            );
+            let x25519 = PublicKey::from(<[u8; 32]>::try_from(x25519)?);
+            Ok(Recipient { mlkem, x25519 })
+        }
+    }
+
+    impl fmt::Display for Recipient {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            let mut bytes = self.mlkem.as_bytes().to_vec();
+            bytes.extend_from_slice(self.x25519.as_bytes());
+            let hrp = bech32::Hrp::parse_unchecked(&PUBLIC_PREFIX[..PUBLIC_PREFIX.len() - 1]);
+            f.write_str(&bech32_encode(hrp, &bytes))
+        }
+    }
+
+    impl FromStr for Identity {
+        type Err = anyhow::Error;
+
+        fn from_str(given: &str) -> anyhow::Result<Self> {
+            let bytes = Zeroizing::new(decode(given, &SECRET_PREFIX[..SECRET_PREFIX.len() - 1])?);
+            let seed = <[u8; 32]>::try_from(bytes.as_slice())
+                .map_err(|_| anyhow!("a {SECRET_PREFIX} key is not 32 bytes long"))?;
+            Ok(Identity::from_seed(seed))
+        }
+    }
+
+    impl age::Recipient for Recipient {
+        fn wrap_file_key(
+            &self,
+            file_key: &FileKey,
+        ) -> Result<(Vec<Stanza>, HashSet<String>), age::EncryptError> {
+            let (ciphertext, mlkem_secret) = self
+                .mlkem
+                .encapsulate(&mut OsRng)
+                .map_err(|()| age::EncryptError::Io(std::io::Error::other("ML-KEM failed")))?;
+            let ephemeral = EphemeralSecret::random_from_rng(OsRng);
+            let share = PublicKey::from(&ephemeral);
+            let x25519_secret = ephemeral.diffie_hellman(&self.x25519);
+            if !x25519_secret.was_contributory() {
+                return Err(age::EncryptError::Io(std::io::Error::other(
+                    "the X25519 key of the recipient is a low-order point",
+                )));
+            }
+            let key = wrapping_key(
+                &mlkem_secret,
+                x25519_secret.as_bytes(),
+                &share,
+                &self.x25519,
+                &ciphertext,
+            );
+            let mut body = ciphertext.to_vec();
+            body.extend(aead_encrypt(&key, file_key.expose_secret()));
+            let stanza = Stanza {
+                tag: TAG.to_owned(),
+                args: vec![BASE64_STANDARD_NO_PAD.encode(share.as_bytes())],
+                body,
+            };
+            Ok((vec![stanza], HashSet::from([LABEL.to_owned()])))
+        }
+    }
+
+    impl age::Identity for Identity {
+        fn unwrap_stanza(&self, stanza: &Stanza) -> Option<Result<FileKey, age::DecryptError>> {
+            if stanza.tag != TAG {
+                return None;
+            }
+            let share = match &stanza.args[..] {
+                [share] => BASE64_STANDARD_NO_PAD
+                    .decode(share)
+                    .ok()
+                    .and_then(|share| <[u8; 32]>::try_from(share).ok()),
+                _ => None,
+            };
+            let Some(share) =
+                share.filter(|_| stanza.body.len() == CIPHERTEXT_BYTES + WRAPPED_BYTES)
+            else {
+                return Some(Err(age::DecryptError::InvalidHeader));
+            };
+            let (ciphertext, wrapped) = stanza.body.split_at(CIPHERTEXT_BYTES);
+            let share = PublicKey::from(share);
+            let ciphertext = Ciphertext::<MlKem768>::try_from(ciphertext).ok()?;
+            // ML-KEM rejects a ciphertext for another key implicitly, with a secret that
+            // then fails to open the file key.
+            let mlkem_secret = self.mlkem.decapsulate(&ciphertext).ok()?;
+            let x25519_secret = self.x25519.diffie_hellman(&share);
+            if !x25519_secret.was_contributory() {
+                return Some(Err(age::DecryptError::InvalidHeader));
+            }
+            let key = wrapping_key(
+                &mlkem_secret,
+                x25519_secret.as_bytes(),
+                &share,
+                &self.public.x25519,
+                ciphertext.as_slice(),
+            );
+            // Not opening is no error: the stanza may be for another of the identities.
+            let file_key = Zeroizing::new(aead_decrypt(&key, FILE_KEY_BYTES, wrapped).ok()?);
+            Some(Ok(FileKey::init_with_mut(|key| {
+                key.copy_from_slice(&file_key);
+            })))
+        }
+    }
+
+    pub fn generate(output: Option<&Path>) -> anyhow::Result<()> {
+        let mut seed = Zeroizing::new([0; 32]);
+        getrandom::fill(seed.as_mut())?;
+        let identity = Identity::from_seed(*seed);
+        let text = Zeroizing::new(format!(
+            "# public key: {}\n{}\n",
+            identity.public,
+            identity.secret_string().as_str()
+        ));
+        let Some(output) = output else {
+            print!("{}", text.as_str());
+            return Ok(());
+        };
+        let mut options = std::fs::OpenOptions::new();
+        options.write(true).create_new(true);
+        #[cfg(unix)]
+        std::os::unix::fs::OpenOptionsExt::mode(&mut options, 0o600);
+        let mut file = match options.open(output) {
+            Ok(file) => file,
+            Err(error) if error.kind() == std::io::ErrorKind::AlreadyExists => {
+                bail!("{} already exists", output.display())
+            }
+            Err(error) => {
+                return Err(error).with_context(|| format!("cannot write {}", output.display()))
+            }
+        };
+        file.write_all(text.as_bytes())?;
+        file.sync_all()?;
+        eprintln!("Public key: {}", identity.public);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    mod tests {
+        use super::*;
+        use age::{Identity as _, Recipient as _};
+
+        fn file_key() -> FileKey {
+            FileKey::init_with_mut(|key| key.copy_from_slice(&[7; FILE_KEY_BYTES]))
+        }
+
+        /// The stanza `recipient` wraps [`file_key`] in.
+        fn stanza(recipient: &Recipient) -> Stanza {
+            let (mut stanzas, labels) = recipient.wrap_file_key(&file_key()).unwrap();
+            assert_eq!(labels, HashSet::from([LABEL.to_owned()]));
+            stanzas.remove(0)
+        }
+
+        #[test]
+        fn a_wrapped_file_key_unwraps_with_its_identity_only() {
+            let identity = Identity::from_seed([1; 32]);
+            let parsed: Identity = identity.secret_string().parse().unwrap();
+            let recipient: Recipient = identity.public.to_string().parse().unwrap();
+            assert_eq!(recipient.to_string(), parsed.public.to_string());
+            let stanza = stanza(&recipient);
+            let unwrapped = parsed.unwrap_stanza(&stanza).unwrap().unwrap();
+            assert_eq!(unwrapped.expose_secret(), file_key().expose_secret());
+            assert!(Identity::from_seed([2; 32])
+                .unwrap_stanza(&stanza)
+                .is_none());
+        }
+
+        #[test]
+        fn a_modified_stanza_is_rejected() {
+            let identity = Identity::from_seed([3; 32]);
+            let stanza = stanza(&identity.public);
+            let edited = |edit: &dyn Fn(&mut Stanza)| {
+                let mut stanza = Stanza {
+                    tag: stanza.tag.clone(),
+                    args: stanza.args.clone(),
+                    body: stanza.body.clone(),
+                };
+                edit(&mut stanza);
+                identity.unwrap_stanza(&stanza)
+            };
+            // Either half of the shared secret, or the wrapped key itself, changed.
+            assert!(edited(&|stanza| stanza.body[0] ^= 1).is_none());
+            assert!(edited(&|stanza| stanza.body[CIPHERTEXT_BYTES] ^= 1).is_none());
+            let share = PublicKey::from(&StaticSecret::from([9; 32]));
+            let other = BASE64_STANDARD_NO_PAD.encode(share.as_bytes());
+            assert!(edited(&|stanza| stanza.args = vec![other.clone()]).is_none());
+            // Malformed rather than for another key.
+            assert!(matches!(
+                edited(&|stanza| stanza.body.truncate(100)),
+                Some(Err(age::DecryptError::InvalidHeader))
+            ));
+            assert!(matches!(
+                edited(&|stanza| stanza.args.clear()),
+                Some(Err(age::DecryptError::InvalidHeader))
+            ));
+            assert!(edited(&|stanza| stanza.tag = "X25519".to_owned()).is_none());
+            assert!(identity.unwrap_stanza(&stanza).unwrap().is_ok());
+        }
+    }
+}
@@ -0,0 +1,206 @@
+use base64::Engine;
+use image::imageops::{self, FilterType};
+use image::{ImageFormat, RgbImage};
+use std::env;
+use std::fmt::Write as _;
+use std::io::{Cursor, Write};
+use std::path::Path;
+
+/// Widest preview, in terminal columns.
+const MAX_COLUMNS: u32 = 80;
+/// Largest side of the image sent to terminals that draw real pixels.
+const MAX_PIXELS: u32 = 512;
+
+#[derive(Clone, Copy)]
+enum Protocol {
+    Kitty,
+    Iterm,
+    Sixel,
+    Ansi,
+}
+
+impl Protocol {
+    /// Guesses what the terminal understands from its environment;
+    /// `PICTURER_PREVIEW` names a protocol outright.
+    fn detect() -> Self {
+        let var = |name| env::var(name).unwrap_or_default();
+        match var("PICTURER_PREVIEW").as_str() {
+            "kitty" => return Protocol::Kitty,
+            "iterm" => return Protocol::Iterm,
+            "sixel" => return Protocol::Sixel,
+            "ansi" => return Protocol::Ansi,
+            _ => {}
+        }
+        let term = var("TERM");
+        let program = var("TERM_PROGRAM");
+        if term == "xterm-kitty" || env::var_os("KITTY_WINDOW_ID").is_some() {
+            Protocol::Kitty
+        } else if matches!(program.as_str(), "iTerm.app" | "WezTerm") {
+            Protocol::Iterm
+        } else if ["foot", "mlterm", "contour"]
+            .iter()
+            .any(|name| term.starts_with(name))
+            || term.contains("sixel")
+        {
+            Protocol::Sixel
+        } else {
+            Protocol::Ansi
+        }
+    }
+}
+
+/// Draws the image at `path` inline on stdout. The alpha channel carries data like
+/// the others, so it is dropped rather than letting the terminal blend it away.
+pub fn show(path: &Path) -> anyhow::Result<()> {
+    let image = crate::read_image(path)?.into_rgb8();
+    let columns = env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(MAX_COLUMNS)
+        .clamp(1, MAX_COLUMNS);
+    let out = match Protocol::detect() {
+        Protocol::Kitty => kitty(&png(&fit(&image, MAX_PIXELS))?, columns),
+        Protocol::Iterm => iterm(&png(&fit(&image, MAX_PIXELS))?, columns),
+        Protocol::Sixel => sixel(&fit(&image, columns * 8)),
+        Protocol::Ansi => ansi(&fit(&image, columns)),
+    };
+    let mut stdout = std::io::stdout().lock();
+    stdout.write_all(out.as_bytes())?;
+    Ok(stdout.flush()?)
+}
+
+/// Scales `image` down, keeping its aspect, so neither side exceeds `max`.
+/// Nearest-neighbour keeps the blocky look of encoded data instead of smearing it.
+fn fit(image: &RgbImage, max: u32) -> RgbImage {
+    let (width, height) = image.dimensions();
+    if width <= max && height <= max {
+        return image.clone();
+    }
+    let scale = f64::from(max) / f64::from(width.max(height));
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    let scaled = |side: u32| ((f64::from(side) * scale) as u32).max(1);
+    imageops::resize(image, scaled(width), scaled(height), FilterType::Nearest)
+}
+
+fn png(image: &RgbImage) -> anyhow::Result<Vec<u8>> {
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    Ok(png)
+}
+
+/// Kitty graphics protocol: base64 PNG in chunks of at most 4096 bytes.
+fn kitty(png: &[u8], columns: u32) -> String {
+    let data = base64::engine::general_purpose::STANDARD.encode(png);
+    let chunks: Vec<&[u8]> = data.as_bytes().chunks(4096).collect();
+    let mut out = String::new();
+    for (index, chunk) in chunks.iter().enumerate() {
+        let more = u8::from(index + 1 < chunks.len());
+        let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+        if index == 0 {
+            let _ = write!(out, "\x1b_Gf=100,a=T,c={columns},m={more};{chunk}\x1b\\");
+        } else {
+            let _ = write!(out, "\x1b_Gm={more};{chunk}\x1b\\");
+        }
+    }
+    out.push('\n');
+    out
+}
+
+/// iTerm2 inline images, also understood by wezterm.
+fn iterm(png: &[u8], columns: u32) -> String {
+    let data = base64::engine::general_purpose::STANDARD.encode(png);
+    format!(
+        "\x1b]1337;File=inline=1;size={};width={columns}:{data}\x07\n",
+        png.len()
+    )
+}
+
+/// Sixel graphics, with colours rounded to a 6x6x6 cube.
+fn sixel(image: &RgbImage) -> String {
+    const LEVELS: u32 = 6;
+    let level = |channel: u8| u32::from(channel) * (LEVELS - 1) / 255;
+    let index = |pixel: &image::Rgb<u8>| {
+        let [r, g, b] = pixel.0.map(level);
+        (r * LEVELS + g) * LEVELS + b
+    };
+    let mut out = String::from("\x1bPq");
+    for color in 0..LEVELS.pow(3) {
+        let percent = |level: u32| level * 100 / (LEVELS - 1);
+        let (r, g, b) = (color / 36, color / 6 % 6, color % 6);
+        let _ = write!(
+            out,
+            "#{color};2;{};{};{}",
+            percent(r),
+            percent(g),
+            percent(b)
+        );
+    }
+    let (width, height) = image.dimensions();
+    for band in (0..height).step_by(6) {
+        let rows = band..(band + 6).min(height);
+        let mut colors: Vec<u32> = rows
+            .clone()
+            .flat_map(|y| (0..width).map(move |x| (x, y)))
+            .map(|(x, y)| index(image.get_pixel(x, y)))
+            .collect();
+        colors.sort_unstable();
+        colors.dedup();
+        for (n, &color) in colors.iter().enumerate() {
+            if n > 0 {
+                out.push('$');
+            }
+            let _ = write!(out, "#{color}");
+            let mut run: Option<(u8, u32)> = None;
+            for x in 0..width {
+                let bits = rows
+                    .clone()
+                    .filter(|&y| index(image.get_pixel(x, y)) == color)
+                    .fold(0u8, |bits, y| bits | 1 << (y - band));
+                match &mut run {
+                    Some((last, count)) if *last == bits => *count += 1,
+                    _ => {
+                        flush_run(&mut out, run);
+                        run = Some((bits, 1));
+                    }
+                }
+            }
+            flush_run(&mut out, run);
+        }
+        out.push('-');
+    }
+    out.push_str("\x1b\\\n");
+    out
+}
+
+fn flush_run(out: &mut String, run: Option<(u8, u32)>) {
+    let Some((bits, count)) = run else {
+        return;
+    };
+    let sixel = char::from(63 + bits);
+    if count > 3 {
+        let _ = write!(out, "!{count}{sixel}");
+    } else {
+        for _ in 0..count {
+            out.push(sixel);
+        }
+    }
+}
+
+/// Half-block characters in 24-bit colour: each cell shows two pixels, one above the other.
+pub fn ansi(image: &RgbImage) -> String {
+    let (width, height) = image.dimensions();
+    let mut out = String::new();
+    for y in (0..height).step_by(2) {
+        for x in 0..width {
+            let [r, g, b] = image.get_pixel(x, y).0;
+            let _ = write!(out, "\x1b[38;2;{r};{g};{b}m");
+            if y + 1 < height {
+                let [r, g, b] = image.get_pixel(x, y + 1).0;
+                let _ = write!(out, "\x1b[48;2;{r};{g};{b}m");
+            }
+            out.push('▀');
+        }
+        out.push_str("\x1b[0m\n");
+    }
+    out
+}
@@ -0,0 +1,46 @@
+//! Progress of encoding and decoding, reported as blocks are compressed and decoded to a
+//! hook registered with [`set_hook`], so a GUI, or the progress line of the CLI, can
+//! follow along without the library drawing anything itself. There is one hook for the
+//! whole process, called from whichever thread finished a block, one call at a time.
+use crate::timings::Stage;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Mutex, PoisonError};
+
+type Hook = Box<dyn FnMut(Progress) + Send>;
+
+static HOOK: Mutex<Option<Hook>> = Mutex::new(None);
+/// Whether there is a hook, so that reporting to none takes no lock.
+static HOOKED: AtomicBool = AtomicBool::new(false);
+
+/// How far a payload got through one stage.
+#[derive(Clone, Copy, Debug)]
+pub struct Progress {
+    pub stage: Stage,
+    /// Bytes the stage went through so far.
+    pub done: u64,
+    /// Bytes it goes through in all, if known.
+    pub total: Option<u64>,
+}
+
+/// Calls `hook` with the progress of every stage from now on, in place of any hook
+/// registered before. The hook must not encode or decode, which would wait on itself.
+pub fn set_hook(hook: impl FnMut(Progress) + Send + 'static) {
+    *HOOK.lock().unwrap_or_else(PoisonError::into_inner) = Some(Box::new(hook));
+    HOOKED.store(true, Ordering::Relaxed);
+}
+
+/// Stops reporting progress, returning the hook that was registered, if any.
+pub fn take_hook() -> Option<Box<dyn FnMut(Progress) + Send>> {
+    HOOKED.store(false, Ordering::Relaxed);
+    HOOK.lock().unwrap_or_else(PoisonError::into_inner).take()
+}
+
+/// Reports that `stage` went through `done` of `total` bytes.
+pub fn report(stage: Stage, done: u64, total: Option<u64>) {
+    if !HOOKED.load(Ordering::Relaxed) {
+        return;
+    }
+    if let Some(hook) = HOOK.lock().unwrap_or_else(PoisonError::into_inner).as_mut() {
+        hook(Progress { stage, done, total });
+    }
+}
@@ -0,0 +1,158 @@
+//! Python module, built with `maturin build` from `pyproject.toml`.
+use crate::dict::Dictionary;
+use crate::format::{self, Codec, Packing};
+use pyo3::create_exception;
+use pyo3::exceptions::PyException;
+use pyo3::prelude::*;
+use pyo3::types::PyBytes;
+use std::path::PathBuf;
+
+create_exception!(
+    picturer,
+    Error,
+    PyException,
+    "Raised when picturer cannot encode or decode."
+);
+
+#[allow(clippy::needless_pass_by_value)]
+fn error(error: anyhow::Error) -> PyErr {
+    Error::new_err(format!("{error:#}"))
+}
+
+/// How `encode_bytes` and `encode_file` pack the data.
+#[pyclass(module = "picturer", from_py_object)]
+#[derive(Clone, Default)]
+pub struct EncodeOptions {
+    /// `raw`, `zlib[-0..9]` or `zstd[-1..22]`; `None` for the default.
+    #[pyo3(get, set)]
+    codec: Option<String>,
+    /// Path of a zstd dictionary to compress with.
+    #[pyo3(get, set)]
+    dict: Option<PathBuf>,
+}
+
+#[pymethods]
+impl EncodeOptions {
+    #[new]
+    #[pyo3(signature = (codec = None, dict = None))]
+    fn new(codec: Option<String>, dict: Option<PathBuf>) -> Self {
+        EncodeOptions { codec, dict }
+    }
+}
+
+/// How `decode_bytes` and `decode_file` read the payload back.
+#[pyclass(module = "picturer", from_py_object)]
+#[derive(Clone, Default)]
+pub struct DecodeOptions {
+    /// Path of the zstd dictionary the image was compressed with.
+    #[pyo3(get, set)]
+    dict: Option<PathBuf>,
+    /// Byte range of the payload to return.
+    #[pyo3(get, set)]
+    offset: u64,
+    #[pyo3(get, set)]
+    length: Option<u64>,
+}
+
+#[pymethods]
+impl DecodeOptions {
+    #[new]
+    #[pyo3(signature = (dict = None, offset = 0, length = None))]
+    fn new(dict: Option<PathBuf>, offset: u64, length: Option<u64>) -> Self {
+        DecodeOptions {
+            dict,
+            offset,
+            length,
+        }
+    }
+}
+
+fn load(dict: Option<&PathBuf>) -> PyResult<Option<Dictionary>> {
+    dict.map(|path| Dictionary::load(path))
+        .transpose()
+        .map_err(error)
+}
+
+fn encode(data: &[u8], options: Option<EncodeOptions>) -> PyResult<Vec<u8>> {
+    let options = options.unwrap_or_default();
+    let dict = load(options.dict.as_ref())?;
+    let codec = match (&options.codec, &dict) {
+        (Some(codec), _) => codec.parse().map_err(error)?,
+        (None, Some(_)) => Codec::ZSTD,
+        (None, None) => Codec::DEFAULT,
+    };
+    let packing = Packing {
+        codec,
+        dict: dict.as_ref(),
+        pipeline: None,
+        content_type: None,
+        created: None,
+        comment: None,
+        meta: None,
+        meta_hidden: false,
+        block_size: None,
+        id: None,
+        split_on: None,
+    };
+    crate::encode_png(data, packing).map_err(error)
+}
+
+fn decode(png: &[u8], options: Option<DecodeOptions>) -> PyResult<Vec<u8>> {
+    let options = options.unwrap_or_default();
+    let dict = load(options.dict.as_ref())?;
+    let payload = crate::decode_png(png, dict.as_ref()).map_err(error)?;
+    format::slice(payload, options.offset, options.length).map_err(error)
+}
+
+/// Encodes `data` as a PNG file, returned as bytes.
+#[pyfunction]
+#[pyo3(signature = (data, options = None))]
+fn encode_bytes<'py>(
+    py: Python<'py>,
+    data: &[u8],
+    options: Option<EncodeOptions>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    Ok(PyBytes::new(py, &encode(data, options)?))
+}
+
+/// Encodes the file at `path` as the PNG file `out`.
+#[pyfunction]
+#[pyo3(signature = (path, out, options = None))]
+fn encode_file(path: PathBuf, out: PathBuf, options: Option<EncodeOptions>) -> PyResult<()> {
+    let png = encode(&std::fs::read(path)?, options)?;
+    Ok(std::fs::write(out, png)?)
+}
+
+/// Decodes a PNG file given as bytes.
+#[pyfunction]
+#[pyo3(signature = (png, options = None))]
+fn decode_bytes<'py>(
+    py: Python<'py>,
+    png: &[u8],
+    options: Option<DecodeOptions>,
+) -> PyResult<Bound<'py, PyBytes>> {
+    Ok(PyBytes::new(py, &decode(png, options)?))
+}
+
+/// Decodes the PNG file at `path`, returning the payload.
+#[pyfunction]
+#[pyo3(signature = (path, options = None))]
+fn decode_file(
+    py: Python<'_>,
+    path: PathBuf,
+    options: Option<DecodeOptions>,
+) -> PyResult<Bound<'_, PyBytes>> {
+    Ok(PyBytes::new(py, &decode(&std::fs::read(path)?, options)?))
+}
+
+#[pymodule]
+fn picturer(module: &Bound<'_, PyModule>) -> PyResult<()> {
+    module.add("Error", module.py().get_type::<Error>())?;
+    module.add_class::<EncodeOptions>()?;
+    module.add_class::<DecodeOptions>()?;
+    module.add_function(wrap_pyfunction!(encode_bytes, module)?)?;
+    module.add_function(wrap_pyfunction!(encode_file, module)?)?;
+    module.add_function(wrap_pyfunction!(decode_bytes, module)?)?;
+    module.add_function(wrap_pyfunction!(decode_file, module)?)?;
+    Ok(())
+}
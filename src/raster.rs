@@ -0,0 +1,122 @@
+//! Payloads written with `--wrap`: an input image stored as its decoded pixels, with
+//! its size and color type, rather than as the bytes of its file. Re-muxing or
+//! re-compressing the file then changes nothing that is stored, and decoding writes the
+//! pixels in whichever format the output is named for.
+use anyhow::{bail, Context};
+use image::{DynamicImage, ImageBuffer, ImageFormat, ImageReader};
+use picturer::format::Reader;
+use std::path::Path;
+
+/// Start of a payload holding the pixels of an image.
+const MAGIC: &[u8; 15] = b"PICTURER-PIXELS";
+const VERSION: u8 = 1;
+
+pub fn is_raster(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+/// Reads the image at `path`, turned as its orientation tag says since a copy written
+/// on decoding carries no tag, and returns its pixels after a header of magic, version,
+/// width, height and color type. 16-bit samples are stored little-endian; images with
+/// floating-point samples are refused.
+pub fn wrap(path: &Path) -> anyhow::Result<Vec<u8>> {
+    let reader = ImageReader::open(path)
+        .and_then(ImageReader::with_guessed_format)
+        .with_context(|| format!("cannot read {}", path.display()))?;
+    let (mut image, orientation) = picturer::read_image(reader)
+        .with_context(|| format!("--wrap needs an image, and {} is not one", path.display()))?;
+    image.apply_orientation(orientation);
+    let (color, samples) = match &image {
+        DynamicImage::ImageLuma8(image) => (1, image.as_raw().clone()),
+        DynamicImage::ImageLumaA8(image) => (2, image.as_raw().clone()),
+        DynamicImage::ImageRgb8(image) => (3, image.as_raw().clone()),
+        DynamicImage::ImageRgba8(image) => (4, image.as_raw().clone()),
+        DynamicImage::ImageLuma16(image) => (5, little_endian(image.as_raw())),
+        DynamicImage::ImageLumaA16(image) => (6, little_endian(image.as_raw())),
+        DynamicImage::ImageRgb16(image) => (7, little_endian(image.as_raw())),
+        DynamicImage::ImageRgba16(image) => (8, little_endian(image.as_raw())),
+        _ => bail!(
+            "{} has floating-point pixels, which --wrap cannot store",
+            path.display()
+        ),
+    };
+    let mut buf = Vec::with_capacity(MAGIC.len() + 1 + 4 + 4 + 1 + samples.len());
+    buf.extend(MAGIC);
+    buf.push(VERSION);
+    buf.extend(image.width().to_le_bytes());
+    buf.extend(image.height().to_le_bytes());
+    buf.push(color);
+    buf.extend(samples);
+    Ok(buf)
+}
+
+fn little_endian(samples: &[u16]) -> Vec<u8> {
+    samples
+        .iter()
+        .flat_map(|sample| sample.to_le_bytes())
+        .collect()
+}
+
+/// Writes the image `payload` holds to `out`, in the format its extension names, or as a
+/// PNG if it names none. Formats that cannot hold its color type, such as JPEG, which has
+/// no alpha, get it converted to one they can.
+pub fn unwrap(payload: &[u8], out: &Path) -> anyhow::Result<()> {
+    let mut reader = Reader(payload.strip_prefix(MAGIC).unwrap_or_default());
+    let version = reader.u8()?;
+    if version != VERSION {
+        bail!("unsupported pixel payload version {version}");
+    }
+    let (width, height) = (reader.u32()?, reader.u32()?);
+    let color = reader.u8()?;
+    let samples = reader.0;
+    let narrow = |channels: usize| -> anyhow::Result<Vec<u8>> {
+        check_len(samples.len(), width, height, channels)?;
+        Ok(samples.to_vec())
+    };
+    let wide = |channels: usize| -> anyhow::Result<Vec<u16>> {
+        check_len(samples.len(), width, height, channels * 2)?;
+        Ok(samples
+            .chunks_exact(2)
+            .map(|sample| u16::from_le_bytes([sample[0], sample[1]]))
+            .collect())
+    };
+    let image = match color {
+        1 => buffer(width, height, narrow(1)?).map(DynamicImage::ImageLuma8),
+        2 => buffer(width, height, narrow(2)?).map(DynamicImage::ImageLumaA8),
+        3 => buffer(width, height, narrow(3)?).map(DynamicImage::ImageRgb8),
+        4 => buffer(width, height, narrow(4)?).map(DynamicImage::ImageRgba8),
+        5 => buffer(width, height, wide(1)?).map(DynamicImage::ImageLuma16),
+        6 => buffer(width, height, wide(2)?).map(DynamicImage::ImageLumaA16),
+        7 => buffer(width, height, wide(3)?).map(DynamicImage::ImageRgb16),
+        8 => buffer(width, height, wide(4)?).map(DynamicImage::ImageRgba16),
+        _ => bail!("unknown color type {color} in pixel payload"),
+    }?;
+    let format = ImageFormat::from_path(out).unwrap_or(ImageFormat::Png);
+    let image = match format {
+        ImageFormat::Jpeg => DynamicImage::ImageRgb8(image.into_rgb8()),
+        ImageFormat::Gif => DynamicImage::ImageRgba8(image.into_rgba8()),
+        _ => image,
+    };
+    image
+        .save_with_format(out, format)
+        .with_context(|| format!("cannot write {}", out.display()))
+}
+
+/// Fails unless `len` bytes are what `width` by `height` pixels of `bytes` each take.
+fn check_len(len: usize, width: u32, height: u32, bytes: usize) -> anyhow::Result<()> {
+    let expected = (width as usize)
+        .checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(bytes));
+    if expected != Some(len) {
+        bail!("pixel payload holds {len} bytes, not the {width}x{height} pixels its header says");
+    }
+    Ok(())
+}
+
+fn buffer<P: image::Pixel>(
+    width: u32,
+    height: u32,
+    samples: Vec<P::Subpixel>,
+) -> anyhow::Result<ImageBuffer<P, Vec<P::Subpixel>>> {
+    ImageBuffer::from_raw(width, height, samples).context("pixel payload is too short")
+}
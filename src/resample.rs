@@ -0,0 +1,190 @@
+//! Signs that an image is not the one picturer wrote but a copy that was resized or
+//! re-compressed, which changes the pixels the payload is stored in.
+use image::{DynamicImage, GenericImageView, ImageFormat, RgbaImage};
+use picturer::diagnostic::{self, Code};
+use picturer::format;
+use std::path::Path;
+
+/// Most pixels looked at; larger images are sampled in evenly spaced rows.
+const SAMPLE: u64 = 1 << 20;
+
+/// Fails, saying why and what to do instead, if `image`, read from `path`, looks
+/// resized or re-compressed. Only images that hold no header picturer writes, or
+/// whose header does not give the size of the image, are looked at, so the payloads of
+/// intact images cannot be mistaken for damage. Rows added below the ones the header
+/// needs, as tools that pad images to a tile size add, leave the payload intact, and
+/// so does the larger layout `--pad-to` writes, unless its pixels look resampled.
+pub fn check(path: &Path, image: &DynamicImage) -> anyhow::Result<()> {
+    let opaque = picturer::is_opaque(image);
+    let converted;
+    let pixels = if opaque {
+        converted = image.to_rgb8().into_raw();
+        &converted
+    } else if let Some(rgba) = image.as_rgba8() {
+        rgba.as_raw()
+    } else {
+        converted = image.to_rgba8().into_raw();
+        &converted
+    };
+    let (width, height) = image.dimensions();
+    let size = (usize::try_from(width)?, usize::try_from(height)?);
+    let layout_size = if opaque {
+        picturer::opaque_layout_size
+    } else {
+        picturer::layout_size
+    };
+    let expected = if format::is_container(pixels) {
+        format::container_len(pixels).map(layout_size)
+    } else {
+        format::legacy_len(pixels).map(legacy_size)
+    };
+    if expected.is_some_and(|(width, height)| size.0 == width && size.1 >= height) {
+        return Ok(());
+    }
+    let misshapen = expected.is_some() || format::is_container(pixels);
+    let padded = expected.is_some_and(|(width, _)| {
+        size.0 > width && (size.0.saturating_sub(1)..=size.0).contains(&size.1)
+    });
+    let evidence = diagnose(path, image, misshapen);
+    if padded && evidence.is_none() {
+        return Ok(());
+    }
+    let evidence = evidence.or_else(|| {
+        misshapen.then(|| {
+            format!("it is {width}x{height}, which is not the size of the payload its header gives")
+        })
+    });
+    let Some(evidence) = evidence else {
+        return Err(diagnostic::error(
+            Code::NoHeader,
+            format!(
+                "{} holds no header picturer writes; if it is a copy of an image picturer \
+                 wrote, it was changed on the way",
+                path.display()
+            ),
+        ));
+    };
+    Err(diagnostic::error(
+        Code::Resampled,
+        format!(
+            "{} was resized or re-compressed since it was written: {evidence}, so the \
+             payload cannot be recovered from it",
+            path.display()
+        ),
+    ))
+}
+
+/// `image` shrunk back by the largest whole factor every block of it is one color at,
+/// and that factor, as a rasterizer leaves an image scaled up with crisp edges; `None`
+/// if no factor above 1 divides both of its sides that way.
+pub fn unscale(image: &DynamicImage) -> Option<(DynamicImage, u32)> {
+    let rgba = image.to_rgba8();
+    let (width, height) = rgba.dimensions();
+    let scale = (2..=width.min(height)).rev().find(|&scale| {
+        width % scale == 0
+            && height % scale == 0
+            && rgba
+                .enumerate_pixels()
+                .all(|(x, y, pixel)| pixel == rgba.get_pixel(x - x % scale, y - y % scale))
+    })?;
+    let small = RgbaImage::from_fn(width / scale, height / scale, |x, y| {
+        *rgba.get_pixel(x * scale, y * scale)
+    });
+    Some((DynamicImage::from(small), scale))
+}
+
+/// Describes what, besides its size, suggests `image` was resized or re-compressed, or
+/// returns `None` if nothing does.
+fn diagnose(path: &Path, image: &DynamicImage, misshapen: bool) -> Option<String> {
+    let format = image::ImageReader::open(path)
+        .ok()?
+        .with_guessed_format()
+        .ok()?
+        .format();
+    let lossy = match format {
+        Some(ImageFormat::Jpeg) => Some("JPEG"),
+        Some(ImageFormat::WebP) => Some("WebP"),
+        Some(ImageFormat::Avif) => Some("AVIF"),
+        _ => None,
+    };
+    if let Some(lossy) = lossy {
+        return Some(format!("it is a {lossy} file, which stores pixels lossily"));
+    }
+    if !image.color().has_alpha() && !misshapen {
+        return Some(
+            "it has no alpha channel, which picturer images store data in unless encoded \
+             with --opaque"
+                .to_owned(),
+        );
+    }
+    if let Some(stats) = Neighbours::measure(&image.to_rgba8()) {
+        if stats.repeated * 10 >= stats.pixels {
+            return Some(format!(
+                "{}% of its pixels repeat the one before them, as nearest-neighbour resizing \
+                 leaves",
+                stats.repeated * 100 / stats.pixels
+            ));
+        }
+        if stats.near * 10 < stats.far * 9 {
+            return Some(
+                "neighbouring pixels are closer in color than distant ones, as smoothing \
+                 leaves"
+                    .to_owned(),
+            );
+        }
+    }
+    None
+}
+
+/// Width and height of an image written before the block container, holding `len`
+/// bytes.
+fn legacy_size(len: usize) -> (usize, usize) {
+    let side = len.isqrt() + usize::from(len.isqrt().pow(2) < len);
+    let width = side + (4 - side % 4);
+    let height = width / 4 - 1;
+    (width / 2, height * 2)
+}
+
+/// How sampled pixels compare with the pixels beside them.
+struct Neighbours {
+    pixels: u64,
+    /// Pixels equal, in every channel, to the pixel on their left.
+    repeated: u64,
+    /// Sum of the channel differences with the pixel on the left.
+    near: u64,
+    /// Sum of the channel differences with the pixel [`FAR`] columns to the left.
+    far: u64,
+}
+
+const FAR: u32 = 7;
+
+impl Neighbours {
+    fn measure(image: &RgbaImage) -> Option<Neighbours> {
+        let (width, height) = image.dimensions();
+        if width <= FAR {
+            return None;
+        }
+        let step = (u64::from(width) * u64::from(height) / SAMPLE).max(1);
+        let step = u32::try_from(step).unwrap_or(u32::MAX);
+        let difference = |a: [u8; 4], b: [u8; 4]| -> u64 {
+            a.iter().zip(b).map(|(a, b)| u64::from(a.abs_diff(b))).sum()
+        };
+        let mut stats = Neighbours {
+            pixels: 0,
+            repeated: 0,
+            near: 0,
+            far: 0,
+        };
+        for y in (0..height).step_by(step as usize) {
+            for x in FAR..width {
+                let pixel = image.get_pixel(x, y).0;
+                let left = image.get_pixel(x - 1, y).0;
+                stats.pixels += 1;
+                stats.repeated += u64::from(pixel == left);
+                stats.near += difference(pixel, left);
+                stats.far += difference(pixel, image.get_pixel(x - FAR, y).0);
+            }
+        }
+        (stats.pixels > 0).then_some(stats)
+    }
+}
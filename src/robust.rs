@@ -0,0 +1,631 @@
+//! A layout for images that will be re-encoded, as social networks do with uploads.
+//! Every 8x8 block, the unit JPEG compresses, is one flat color holding 2 bits per
+//! channel. The bytes are Reed-Solomon coded and interleaved across the image, and a
+//! border of alternating blocks lets the decoder find the grid again, even in a copy
+//! that was scaled or a screenshot that shows it among other things.
+use anyhow::{bail, ensure};
+use image::{DynamicImage, GrayImage, Rgb, RgbImage};
+use picturer::MAX_SIDE;
+use reed_solomon::{Decoder, Encoder};
+
+/// Side of a block in the image as written.
+const BLOCK: usize = 8;
+/// Channel values of the four symbols, far enough apart to survive re-compression.
+const LEVELS: [u8; 4] = [0, 85, 170, 255];
+/// Symbol held by each level, in Gray code so that mistaking a level for its
+/// neighbour costs one bit.
+const SYMBOLS: [u8; 4] = [0b00, 0b01, 0b11, 0b10];
+const BITS_PER_BLOCK: usize = 6;
+const CODEWORD: usize = 255;
+/// Bytes of each codeword that carry data. The other 128 correct up to 64 bad bytes.
+const DATA: usize = 127;
+const MAGIC: &[u8; 4] = b"PRB1";
+/// Default for `--tolerance`, half the distance from a level to the middle of the next.
+pub const TOLERANCE: u8 = 21;
+/// The `--tolerance` at which no channel is unknown, half the distance between levels.
+pub const MAX_TOLERANCE: u8 = 43;
+/// Quality of `--format jpeg` images. Each block is one flat color, which JPEG keeps
+/// in its DC coefficient alone, so this is far more than the layout needs.
+pub const JPEG_QUALITY: u8 = 90;
+const HEADER_LEN: usize = MAGIC.len() + 4;
+/// Share of its correcting capacity a codeword may need before the decoder warns that
+/// the image should be written again.
+pub const RENEW_AT: f64 = 0.5;
+
+/// Lays `container` out as a robust image: a square of blocks inside the border, as
+/// small as fits every codeword.
+pub fn encode(container: &[u8]) -> anyhow::Result<RgbImage> {
+    let mut framed = Vec::with_capacity(HEADER_LEN + container.len());
+    framed.extend_from_slice(MAGIC);
+    framed.extend_from_slice(&u32::try_from(container.len())?.to_le_bytes());
+    framed.extend_from_slice(container);
+
+    let needed = framed.len().div_ceil(DATA).max(1);
+    let mut inner = (needed * CODEWORD * 8).div_ceil(BITS_PER_BLOCK).isqrt();
+    while inner * inner * BITS_PER_BLOCK < needed * CODEWORD * 8 {
+        inner += 1;
+    }
+    let blocks = inner.max(2) + 2;
+    ensure!(
+        blocks * BLOCK <= MAX_SIDE,
+        "payload of {} bytes is too large for --robust, which holds at most {} packed bytes",
+        container.len(),
+        capacity(MAX_SIDE / BLOCK) * DATA - HEADER_LEN
+    );
+    let count = capacity(blocks);
+    framed.resize(count * DATA, 0);
+
+    let encoder = Encoder::new(CODEWORD - DATA);
+    let codewords: Vec<_> = framed
+        .chunks(DATA)
+        .map(|data| encoder.encode(data))
+        .collect();
+    // Consecutive bytes come from different codewords, so a damaged patch of the image
+    // spreads its errors over many of them.
+    let mut stream = vec![0; count * CODEWORD];
+    for (index, codeword) in codewords.iter().enumerate() {
+        for (offset, byte) in codeword.iter().enumerate() {
+            stream[offset * count + index] = *byte;
+        }
+    }
+
+    let side = u32::try_from(blocks * BLOCK)?;
+    let mut image = RgbImage::new(side, side);
+    for row in 0..blocks {
+        for col in 0..blocks {
+            let color = if is_border(row, col, blocks, blocks) {
+                let value = if (row + col) % 2 == 0 { 255 } else { 0 };
+                [value; 3]
+            } else {
+                let index = (row - 1) * (blocks - 2) + col - 1;
+                let symbol = |channel: usize| {
+                    let bit = index * BITS_PER_BLOCK + channel * 2;
+                    let level = stream
+                        .get(bit / 8)
+                        .map_or(0, |byte| (byte >> (6 - bit % 8)) & 0b11);
+                    LEVELS[SYMBOLS.iter().position(|&s| s == level).unwrap_or(0)]
+                };
+                [symbol(0), symbol(1), symbol(2)]
+            };
+            for y in row * BLOCK..(row + 1) * BLOCK {
+                for x in col * BLOCK..(col + 1) * BLOCK {
+                    image.put_pixel(u32::try_from(x)?, u32::try_from(y)?, Rgb(color));
+                }
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// Reads the container back out of a robust image, or returns `None` if `image` does
+/// not hold the border of one. The image may be a copy that was scaled, or a screenshot
+/// with the robust image anywhere inside it; the returned [`Transform`] says which, and
+/// the [`Report`] how much had to be corrected.
+///
+/// A channel further than `tolerance` from every level, as color management or a
+/// conversion may leave it, is read as unknown: Reed-Solomon corrects twice as many bytes
+/// it is told are wrong as bytes it has to find.
+pub fn decode(
+    image: &DynamicImage,
+    tolerance: u8,
+) -> anyhow::Result<Option<(Vec<u8>, Transform, Report)>> {
+    let Some(grid) = Grid::find(image) else {
+        return Ok(None);
+    };
+    let image = image.to_rgb8();
+    let inner = (grid.cols - 2) * (grid.rows - 2);
+    let count = inner * BITS_PER_BLOCK / 8 / CODEWORD;
+    let mut stream = vec![0u8; count * CODEWORD];
+    let mut unknown = vec![false; count * CODEWORD];
+    for row in 1..grid.rows - 1 {
+        for col in 1..grid.cols - 1 {
+            let color = grid.sample(&image, row, col);
+            let index = (row - 1) * (grid.cols - 2) + col - 1;
+            for (channel, value) in color.iter().enumerate() {
+                let bit = index * BITS_PER_BLOCK + channel * 2;
+                let distance = |level: &usize| (f64::from(LEVELS[*level]) - value).abs();
+                let nearest = (0..LEVELS.len())
+                    .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+                    .unwrap_or(0);
+                if let Some(byte) = stream.get_mut(bit / 8) {
+                    *byte |= SYMBOLS[nearest] << (6 - bit % 8);
+                    unknown[bit / 8] |= distance(&nearest) > f64::from(tolerance);
+                }
+            }
+        }
+    }
+
+    let decoder = Decoder::new(CODEWORD - DATA);
+    let mut framed = Vec::with_capacity(count * DATA);
+    let mut report = Report {
+        codewords: count,
+        damaged: 0,
+        failed: 0,
+        corrected: 0,
+        worst: 0,
+    };
+    for index in 0..count {
+        let codeword: Vec<u8> = (0..CODEWORD)
+            .map(|offset| stream[offset * count + index])
+            .collect();
+        let erasures: Vec<u8> = (0..CODEWORD)
+            .filter(|offset| unknown[offset * count + index])
+            .map(|offset| u8::try_from(offset).unwrap_or(u8::MAX))
+            .collect();
+        // The nearest levels alone are usually enough; the unknown bytes are named only
+        // when they are not, and when there are few enough to name.
+        // A byte found wrong takes two of the check bytes to correct, one named takes one.
+        let corrected = correct(decoder, &codeword, None)
+            .map(|(data, fixed)| (data, fixed, 2 * fixed))
+            .or_else(|| {
+                (!erasures.is_empty() && erasures.len() <= CODEWORD - DATA)
+                    .then(|| correct(decoder, &codeword, Some(&erasures)))
+                    .flatten()
+                    .map(|(data, fixed)| {
+                        let found = fixed.saturating_sub(erasures.len());
+                        (data, fixed, erasures.len() + 2 * found)
+                    })
+            });
+        let Some((corrected, fixed, spent)) = corrected else {
+            report.failed += 1;
+            continue;
+        };
+        if fixed > 0 {
+            report.damaged += 1;
+            report.corrected += fixed;
+        }
+        report.worst = report.worst.max(spent);
+        framed.extend_from_slice(&corrected);
+    }
+    if report.failed > 0 {
+        bail!(
+            "robust image is too damaged to recover: {} of its {count} codewords could not \
+             be corrected",
+            report.failed
+        );
+    }
+    ensure!(
+        framed.starts_with(MAGIC),
+        "image has the border of a robust image but not its header"
+    );
+    let len = u32::from_le_bytes(framed[MAGIC.len()..HEADER_LEN].try_into()?) as usize;
+    ensure!(
+        HEADER_LEN + len <= framed.len(),
+        "robust image header claims {len} bytes, more than the image holds"
+    );
+    framed.truncate(HEADER_LEN + len);
+    framed.drain(..HEADER_LEN);
+    Ok(Some((framed, grid.transform(), report)))
+}
+
+/// How much error correction a robust image needed, for telling a copy that is wearing
+/// out from one that is intact.
+pub struct Report {
+    pub codewords: usize,
+    /// Codewords with any byte corrected.
+    pub damaged: usize,
+    /// Codewords too damaged to correct.
+    pub failed: usize,
+    /// Bytes corrected in all.
+    pub corrected: usize,
+    /// Most check bytes any codeword spent correcting itself, of the `CODEWORD - DATA`
+    /// it has; one that needs more cannot be recovered.
+    pub worst: usize,
+}
+
+impl Report {
+    /// How much of what it could correct the most damaged codeword needed, from 0 to 1.
+    #[allow(clippy::cast_precision_loss)]
+    pub fn margin_used(&self) -> f64 {
+        self.worst as f64 / (CODEWORD - DATA) as f64
+    }
+}
+
+impl std::fmt::Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "corrected {} bytes in {} of {} codewords; the most damaged needed {:.0}% of \
+             what it can correct",
+            self.corrected,
+            self.damaged,
+            self.codewords,
+            self.margin_used() * 100.0
+        )
+    }
+}
+
+/// The data of `codeword` once corrected, and how many of its bytes were, or `None` if
+/// it is too damaged. The decoder can panic, rather than fail, on some codewords with
+/// erasures that it cannot correct; that counts as failing too, without the message a
+/// panic prints.
+fn correct(decoder: Decoder, codeword: &[u8], erasures: Option<&[u8]>) -> Option<(Vec<u8>, usize)> {
+    let hook = std::panic::take_hook();
+    std::panic::set_hook(Box::new(|_| {}));
+    let result = std::panic::catch_unwind(|| decoder.correct_err_count(codeword, erasures));
+    std::panic::set_hook(hook);
+    let (corrected, fixed) = result.ok()?.ok()?;
+    Some((corrected.data().to_vec(), fixed))
+}
+
+/// Codewords that fit inside the border of a square of `blocks` blocks.
+fn capacity(blocks: usize) -> usize {
+    (blocks - 2) * (blocks - 2) * BITS_PER_BLOCK / 8 / CODEWORD
+}
+
+fn is_border(row: usize, col: usize, rows: usize, cols: usize) -> bool {
+    row == 0 || col == 0 || row == rows - 1 || col == cols - 1
+}
+
+/// Where a robust image was found in the image decoded, and at what size.
+pub struct Transform {
+    pub x: f64,
+    pub y: f64,
+    /// Width and height now over width and height as written.
+    pub scale: (f64, f64),
+}
+
+impl Transform {
+    /// Whether the image is the one written, at its own size and position.
+    pub fn is_native(&self) -> bool {
+        self.x.abs() < 0.5
+            && self.y.abs() < 0.5
+            && (self.scale.0 - 1.0).abs() < 0.01
+            && (self.scale.1 - 1.0).abs() < 0.01
+    }
+}
+
+impl std::fmt::Display for Transform {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "at {:.0},{:.0} scaled {:.2}x by {:.2}x",
+            self.x, self.y, self.scale.0, self.scale.1
+        )
+    }
+}
+
+/// Where the blocks of a robust image are, in pixels of the image as it is now: the
+/// edges of every column, measured along the top and bottom of the border, and of every
+/// row, along its left and right. Blocks in between are placed from these, so uneven
+/// scaling and a slight skew are followed block by block.
+struct Grid {
+    cols: usize,
+    rows: usize,
+    top: Vec<f64>,
+    bottom: Vec<f64>,
+    left: Vec<f64>,
+    right: Vec<f64>,
+}
+
+impl Grid {
+    /// Finds the top of the border as a band of rows crossing a long stretch of equal
+    /// blocks, then its left side, its bottom and its right, and checks that every
+    /// border block has the color it should. Bands are tried from the top down, so
+    /// stripes elsewhere in a screenshot are passed over.
+    fn find(image: &DynamicImage) -> Option<Grid> {
+        let image = image.to_luma8();
+        let height = image.height();
+        let mut y = 0;
+        while y < height {
+            if row(&image, f64::from(y)).is_none() {
+                y += 2;
+                continue;
+            }
+            let first = y;
+            while y + 2 < height && row(&image, f64::from(y + 2)).is_some() {
+                y += 2;
+            }
+            // The middle of the band is clear of the blur re-compression leaves at
+            // its edges.
+            let grid =
+                row(&image, f64::from(first + y) / 2.0).and_then(|top| Grid::below(&image, &top));
+            if grid.is_some() {
+                return grid;
+            }
+            y += 2;
+        }
+        None
+    }
+
+    /// The grid whose top border `top` measured, if the rest of the border is there.
+    fn below(image: &GrayImage, top: &Ruler) -> Option<Grid> {
+        for top in top.corner_spans() {
+            let cols = top.len() - 1;
+            let Some(left) = column(image, f64::midpoint(top[0], top[1])) else {
+                continue;
+            };
+            // The image is square in blocks, so the rows count the same.
+            let rows = cols;
+            for left in left.corner_spans().filter(|left| left.len() == rows + 1) {
+                let bottom = row(image, f64::midpoint(left[rows - 1], left[rows]))
+                    .and_then(|bottom| bottom.align(cols, top[0]))
+                    .unwrap_or_else(|| top.clone());
+                let right = column(image, f64::midpoint(top[cols - 1], top[cols]))
+                    .and_then(|right| right.align(rows, left[0]))
+                    .unwrap_or_else(|| left.clone());
+                let grid = Grid {
+                    cols,
+                    rows,
+                    top: top.clone(),
+                    bottom,
+                    left,
+                    right,
+                };
+                if grid.border_matches(image) {
+                    return Some(grid);
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether at most one border block in 20 has the wrong color.
+    fn border_matches(&self, image: &GrayImage) -> bool {
+        let mut wrong = 0;
+        let mut border = 0;
+        for row in 0..self.rows {
+            for col in 0..self.cols {
+                if !is_border(row, col, self.rows, self.cols) {
+                    continue;
+                }
+                border += 1;
+                let (x0, y0, x1, y1) = self.bounds(row, col);
+                let (x, y) = (pixel(f64::midpoint(x0, x1)), pixel(f64::midpoint(y0, y1)));
+                let white =
+                    x < image.width() && y < image.height() && image.get_pixel(x, y).0[0] > 127;
+                if white != ((row + col) % 2 == 0) {
+                    wrong += 1;
+                }
+            }
+        }
+        wrong * 20 <= border
+    }
+
+    /// Left, top, right and bottom of a block, between the edges measured on opposite
+    /// sides of the border.
+    fn bounds(&self, row: usize, col: usize) -> (f64, f64, f64, f64) {
+        let down = float(row) / float(self.rows - 1);
+        let across = float(col) / float(self.cols - 1);
+        let x = |col: usize| lerp(self.top[col], self.bottom[col], down);
+        let y = |row: usize| lerp(self.left[row], self.right[row], across);
+        (x(col), y(row), x(col + 1), y(row + 1))
+    }
+
+    /// Mean color of the middle half of a block, away from the edges re-compression
+    /// blurs into its neighbours.
+    fn sample(&self, image: &RgbImage, row: usize, col: usize) -> [f64; 3] {
+        let (left, top, right, bottom) = self.bounds(row, col);
+        let (x0, y0) = (
+            pixel(lerp(left, right, 0.25)),
+            pixel(lerp(top, bottom, 0.25)),
+        );
+        let (x1, y1) = (
+            pixel(lerp(left, right, 0.75)).max(x0 + 1),
+            pixel(lerp(top, bottom, 0.75)).max(y0 + 1),
+        );
+        let mut sum = [0.0; 3];
+        let mut pixels = 0.0;
+        for y in y0..y1.min(image.height()) {
+            for x in x0..x1.min(image.width()) {
+                for (total, value) in sum.iter_mut().zip(image.get_pixel(x, y).0) {
+                    *total += f64::from(value);
+                }
+                pixels += 1.0;
+            }
+        }
+        sum.map(|total| total / f64::max(pixels, 1.0))
+    }
+
+    fn transform(&self) -> Transform {
+        Transform {
+            x: self.top[0],
+            y: self.left[0],
+            scale: (
+                (self.top[self.cols] - self.top[0]) / float(self.cols * BLOCK),
+                (self.left[self.rows] - self.left[0]) / float(self.rows * BLOCK),
+            ),
+        }
+    }
+}
+
+/// The blocks along one side of the border, as crossed by a row or column of pixels:
+/// the longest stretch of runs of about equal length. In a screenshot the blocks at
+/// either end may have run into a background of the same color, and are known only to
+/// be there if the run beyond the stretch is at least a block long; the background
+/// beyond them may just as well be taken for a block.
+struct Ruler {
+    edges: Vec<f64>,
+    first_bright: bool,
+    before: Option<f64>,
+    after: Option<f64>,
+}
+
+/// Fewest blocks a side of the border is recognised by; the smallest robust image
+/// has 21.
+const MIN_STRETCH: usize = 16;
+
+impl Ruler {
+    fn measure(values: impl Iterator<Item = bool>) -> Option<Ruler> {
+        let runs = runs(values);
+        let close = |len: usize, mean: f64| (float(len) - mean).abs() <= mean * 0.3 + 1.0;
+        let mut best = (0, 0, 0.0);
+        let mut start = 0;
+        while start < runs.len() {
+            let mut mean = float(runs[start].len);
+            let mut end = start + 1;
+            while end < runs.len() && close(runs[end].len, mean) {
+                mean += (float(runs[end].len) - mean) / float(end - start + 1);
+                end += 1;
+            }
+            if end - start > best.1 - best.0 {
+                best = (start, end, mean);
+            }
+            start = end;
+        }
+        let (start, end, pitch) = best;
+        if end - start < MIN_STRETCH || pitch < 3.0 {
+            return None;
+        }
+        let mut edges: Vec<f64> = runs[start..end]
+            .iter()
+            .map(|run| float(run.start))
+            .collect();
+        edges.push(float(runs[end - 1].start + runs[end - 1].len));
+        let long = |index: usize| {
+            runs.get(index)
+                .is_some_and(|run| float(run.len) >= pitch * 0.7)
+        };
+        Some(Ruler {
+            first_bright: runs[start].bright,
+            before: (start > 0 && long(start - 1)).then(|| edges[0] - pitch),
+            after: long(end).then(|| edges[edges.len() - 1] + pitch),
+            edges,
+        })
+    }
+
+    /// The edges of every stretch of blocks the side may be: the one measured, with the
+    /// blocks at either end, or without up to two blocks at either end, which may have
+    /// been background the size of a block. Each comes with whether its first block
+    /// is bright.
+    fn spans(&self) -> impl Iterator<Item = (bool, Vec<f64>)> + '_ {
+        let edges: Vec<f64> = self
+            .before
+            .into_iter()
+            .chain(self.edges.iter().copied())
+            .chain(self.after)
+            .collect();
+        let first_bright = self.first_bright != self.before.is_some();
+        (0..3).flat_map(move |start| {
+            let edges = edges.clone();
+            (0..3).filter_map(move |trim| {
+                let end = edges.len().checked_sub(trim)?;
+                (end > start + MIN_STRETCH)
+                    .then(|| (first_bright == (start % 2 == 0), edges[start..end].to_vec()))
+            })
+        })
+    }
+
+    /// Stretches along the top or left of the border, which start at the white corner.
+    fn corner_spans(&self) -> impl Iterator<Item = Vec<f64>> + '_ {
+        self.spans()
+            .filter_map(|(bright, edges)| bright.then_some(edges))
+    }
+
+    /// Edges for a side of `blocks` blocks starting as near `start` as the stretches
+    /// allow.
+    fn align(&self, blocks: usize, start: f64) -> Option<Vec<f64>> {
+        self.spans()
+            .map(|(_, edges)| edges)
+            .filter(|edges| edges.len() == blocks + 1)
+            .min_by(|a, b| (a[0] - start).abs().total_cmp(&(b[0] - start).abs()))
+    }
+}
+
+/// Samples on one side of the threshold.
+struct Run {
+    bright: bool,
+    start: usize,
+    len: usize,
+}
+
+/// Splits `values` into runs, folding runs of a single sample, which re-compression
+/// leaves at the edges between blocks, into the run before.
+fn runs(values: impl Iterator<Item = bool>) -> Vec<Run> {
+    let mut runs: Vec<Run> = Vec::new();
+    let mut pending: Option<Run> = None;
+    for (index, bright) in values.enumerate() {
+        match &mut pending {
+            Some(run) if run.bright == bright => run.len += 1,
+            _ => {
+                if let Some(run) = pending.take() {
+                    push_run(&mut runs, run);
+                }
+                pending = Some(Run {
+                    bright,
+                    start: index,
+                    len: 1,
+                });
+            }
+        }
+    }
+    if let Some(run) = pending {
+        push_run(&mut runs, run);
+    }
+    runs
+}
+
+fn push_run(runs: &mut Vec<Run>, run: Run) {
+    match runs.last_mut() {
+        Some(last) if run.len == 1 || last.bright == run.bright => last.len += run.len,
+        _ => runs.push(run),
+    }
+}
+
+fn row(image: &GrayImage, y: f64) -> Option<Ruler> {
+    let y = pixel(y).min(image.height() - 1);
+    Ruler::measure((0..image.width()).map(|x| image.get_pixel(x, y).0[0] > 127))
+}
+
+fn column(image: &GrayImage, x: f64) -> Option<Ruler> {
+    let x = pixel(x).min(image.width() - 1);
+    Ruler::measure((0..image.height()).map(|y| image.get_pixel(x, y).0[0] > 127))
+}
+
+fn lerp(from: f64, to: f64, fraction: f64) -> f64 {
+    from + (to - from) * fraction
+}
+
+/// The pixel holding the coordinate `value`.
+#[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+fn pixel(value: f64) -> u32 {
+    value.max(0.0) as u32
+}
+
+#[allow(clippy::cast_precision_loss)]
+fn float(value: usize) -> f64 {
+    value as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a robust image of a payload, paints `damaged` pixels of its inside with
+    /// `color`, leaving the border that locates it alone, and decodes what is left.
+    fn damage(damaged: impl Fn(u32, u32, u32) -> bool, color: u8) -> (Vec<u8>, Vec<u8>, Report) {
+        let payload: Vec<u8> = (0..40_000u32).map(|i| (i * 31).to_le_bytes()[0]).collect();
+        let mut image = encode(&payload).unwrap();
+        let side = image.width();
+        let block = u32::try_from(BLOCK).unwrap();
+        let inside = block..side - block;
+        for (x, y, pixel) in image.enumerate_pixels_mut() {
+            if inside.contains(&x) && inside.contains(&y) && damaged(x, y, side) {
+                *pixel = Rgb([color; 3]);
+            }
+        }
+        let (decoded, _, report) = decode(&DynamicImage::ImageRgb8(image), TOLERANCE)
+            .unwrap()
+            .unwrap();
+        (payload, decoded, report)
+    }
+
+    #[test]
+    fn a_strip_is_spread_over_every_codeword() {
+        // A band across a tenth of the image, as a crop or a torn edge leaves it. Were
+        // codewords laid out one after another, it would take out a tenth of them whole.
+        let (payload, decoded, report) = damage(|_, y, side| y / (side / 10) == 4, 0);
+        assert_eq!(decoded, payload);
+        assert!(report.damaged * 10 >= report.codewords * 9, "{report}");
+        assert!(report.margin_used() < 0.6, "{report}");
+    }
+
+    #[test]
+    fn a_scratch_is_spread_over_every_codeword() {
+        // A diagonal line, read as unknown, as a scratch or a smudge leaves it.
+        let (payload, decoded, report) = damage(|x, y, _| x.abs_diff(y) < 12, 128);
+        assert_eq!(decoded, payload);
+        assert!(report.damaged * 10 >= report.codewords * 9, "{report}");
+        assert!(report.margin_used() < 0.6, "{report}");
+    }
+}
@@ -0,0 +1,56 @@
+//! `picturer scan --id`: finds the images under a directory whose payload has an ID,
+//! reading only the first rows of each, where the header is.
+use crate::stream::PixelStream;
+use anyhow::{bail, ensure};
+use ignore::WalkBuilder;
+use picturer::format::{self, Source};
+use std::path::Path;
+
+/// Leading bytes of an image read for the header of its container.
+const HEADER_PROBE: usize = 1 << 20;
+
+/// Prints the ID and path of every image under `dir` whose payload ID starts with `id`,
+/// given with or without the dashes `info` prints, and fails if there is none. Files
+/// that are not images picturer wrote are skipped.
+pub fn run(id: &str, dir: &Path) -> anyhow::Result<()> {
+    let wanted = id.replace('-', "").to_ascii_lowercase();
+    ensure!(
+        !wanted.is_empty()
+            && wanted.len() <= 32
+            && wanted.bytes().all(|byte| byte.is_ascii_hexdigit()),
+        "{id} is not a payload ID, which is up to 32 hex digits"
+    );
+    let mut found = 0;
+    for entry in WalkBuilder::new(dir)
+        .standard_filters(false)
+        .sort_by_file_name(Ord::cmp)
+        .build()
+    {
+        let entry = entry?;
+        if !entry.file_type().is_some_and(|kind| kind.is_file()) {
+            continue;
+        }
+        let Some(stored) = read_id(entry.path()) else {
+            continue;
+        };
+        let printed = format::format_id(&stored);
+        if printed.replace('-', "").starts_with(&wanted) {
+            println!("{printed}  {}", entry.path().display());
+            found += 1;
+        }
+    }
+    if found == 0 {
+        bail!(
+            "no image under {} holds a payload with ID {id}",
+            dir.display()
+        );
+    }
+    Ok(())
+}
+
+/// The payload ID in the header of the image at `path`, or `None` if it is not an image
+/// or stores none.
+fn read_id(path: &Path) -> Option<[u8; 16]> {
+    let mut pixels = PixelStream::open(path).ok()?;
+    format::payload_id(pixels.prefix(HEADER_PROBE).ok()?)
+}
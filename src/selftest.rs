@@ -0,0 +1,203 @@
+//! `picturer selftest`: random payloads of many sizes, packed with each codec and
+//! setting, written as images and read back in memory, to check a build on the platform
+//! it runs on.
+use crate::robust;
+use crate::stream::ReadStream;
+use image::codecs::jpeg::JpegEncoder;
+use image::ImageFormat;
+use picturer::format::{self, Codec, Packing};
+use picturer::pipeline::{Checksum, Pipeline};
+use std::collections::BTreeMap;
+use std::io::Cursor;
+use std::time::Instant;
+
+/// Payload lengths tried: empty, tiny, around a block boundary and several blocks long.
+const SIZES: [usize; 7] = [0, 1, 100, 65_537, (1 << 20) - 1, (1 << 20) + 1, 3 << 20];
+/// Longest payload also tried as a robust image, which holds little.
+const ROBUST_MAX: usize = 100;
+
+/// What a payload is made of, since codecs take different paths for each.
+#[derive(Clone, Copy)]
+pub enum Kind {
+    Random,
+    Text,
+    Zeros,
+}
+
+impl Kind {
+    fn name(self) -> &'static str {
+        match self {
+            Kind::Random => "random",
+            Kind::Text => "text",
+            Kind::Zeros => "zeros",
+        }
+    }
+}
+
+/// Round-trips every case, printing one line each, and fails if any did not. `seed`
+/// repeats the payloads of an earlier run.
+pub fn run(seed: Option<u64>) -> anyhow::Result<()> {
+    let seed = if let Some(seed) = seed {
+        seed
+    } else {
+        let mut seed = [0; 8];
+        getrandom::fill(&mut seed)?;
+        u64::from_le_bytes(seed)
+    };
+    println!("seed {seed}");
+    let mut codecs = vec![Codec::Raw, Codec::Zlib(1), Codec::DEFAULT];
+    if cfg!(feature = "zstd") {
+        codecs.extend([Codec::Zstd(3), Codec::ZSTD]);
+    }
+    let checksum = Pipeline::new().then(Checksum);
+    let meta = BTreeMap::from([("selftest".to_owned(), "yes".to_owned())]);
+    let start = Instant::now();
+    let (mut passed, mut failed) = (0, 0);
+    let mut state = seed | 1;
+    for size in SIZES {
+        for kind in [Kind::Random, Kind::Text, Kind::Zeros] {
+            let payload = payload(kind, size, &mut state);
+            for codec in &codecs {
+                for opaque in [false, true] {
+                    let mut packing = Packing::new(*codec);
+                    let mut settings = vec![codec.to_string()];
+                    if opaque {
+                        settings.push("opaque".to_owned());
+                        packing.pipeline = Some(&checksum);
+                        settings.push("blake3".to_owned());
+                    } else {
+                        packing.content_type = Some("application/octet-stream");
+                        packing.comment = Some("selftest");
+                        packing.meta = Some(&meta);
+                        settings.push("metadata".to_owned());
+                    }
+                    let result = round_trip(&payload, packing, opaque);
+                    report(&result, kind, size, &settings.join(" "));
+                    if result.is_ok() {
+                        passed += 1;
+                    } else {
+                        failed += 1;
+                    }
+                }
+            }
+            if size <= ROBUST_MAX {
+                for jpeg in [false, true] {
+                    let result = robust_round_trip(&payload, jpeg);
+                    let settings = if jpeg { "robust jpeg" } else { "robust png" };
+                    report(&result, kind, size, settings);
+                    if result.is_ok() {
+                        passed += 1;
+                    } else {
+                        failed += 1;
+                    }
+                }
+            }
+        }
+    }
+    println!(
+        "{passed} passed, {failed} failed in {:.1}s",
+        start.elapsed().as_secs_f64()
+    );
+    anyhow::ensure!(
+        failed == 0,
+        "{failed} cases did not round-trip; rerun with --seed {seed} to repeat them"
+    );
+    Ok(())
+}
+
+fn report(result: &anyhow::Result<()>, kind: Kind, size: usize, settings: &str) {
+    let case = format!("{size:>8} bytes of {:<6} {settings}", kind.name());
+    match result {
+        Ok(()) => println!("ok    {case}"),
+        Err(err) => println!("FAIL  {case}: {err:#}"),
+    }
+}
+
+/// Packs `payload`, writes it as a PNG, reads the PNG back and unpacks it whole, as a
+/// range and by appending to it.
+fn round_trip(payload: &[u8], packing: Packing, opaque: bool) -> anyhow::Result<()> {
+    let container = format::pack(payload, packing)?;
+    let image = if opaque {
+        picturer::layout_opaque(&container)?
+    } else {
+        picturer::layout(container)?
+    };
+    let mut png = Vec::new();
+    image.write_to(&mut Cursor::new(&mut png), ImageFormat::Png)?;
+    let (read, _) = picturer::read_image(image::ImageReader::with_format(
+        Cursor::new(&png),
+        ImageFormat::Png,
+    ))?;
+    let pixels = picturer::pixels(picturer::canonical(read)?);
+    anyhow::ensure!(
+        format::unpack(&pixels, None)? == payload,
+        "the payload decoded differs"
+    );
+    let (offset, length) = (payload.len() / 3, payload.len() / 2);
+    let range = format::unpack_range(
+        &mut ReadStream::new(&pixels[..]),
+        offset as u64,
+        Some(length as u64),
+        None,
+    )?;
+    anyhow::ensure!(
+        range == payload[offset..offset + length],
+        "bytes {offset}..{} decoded differ",
+        offset + length
+    );
+    let appended = format::append(&pixels, b"appended", None)?;
+    let unpacked = format::unpack(&appended, None)?;
+    anyhow::ensure!(
+        unpacked.strip_suffix(b"appended") == Some(payload),
+        "the payload appended to differs"
+    );
+    Ok(())
+}
+
+/// Packs `payload` as a robust image, saved as a PNG or a JPEG, and reads it back.
+fn robust_round_trip(payload: &[u8], jpeg: bool) -> anyhow::Result<()> {
+    let image = robust::encode(&format::pack(payload, Packing::new(Codec::DEFAULT))?)?;
+    let mut file = Vec::new();
+    let format = if jpeg {
+        JpegEncoder::new_with_quality(&mut file, robust::JPEG_QUALITY).encode_image(&image)?;
+        ImageFormat::Jpeg
+    } else {
+        image.write_to(&mut Cursor::new(&mut file), ImageFormat::Png)?;
+        ImageFormat::Png
+    };
+    let read = image::load_from_memory_with_format(&file, format)?;
+    let Some((container, _, _)) = robust::decode(&read, robust::TOLERANCE)? else {
+        anyhow::bail!("no robust image was found")
+    };
+    anyhow::ensure!(
+        format::unpack(&container, None)? == payload,
+        "the payload decoded differs"
+    );
+    Ok(())
+}
+
+/// `size` bytes of `kind`, from the xorshift generator at `state`.
+pub fn payload(kind: Kind, size: usize, state: &mut u64) -> Vec<u8> {
+    let mut next = || {
+        *state ^= *state << 13;
+        *state ^= *state >> 7;
+        *state ^= *state << 17;
+        *state
+    };
+    let mut data = Vec::with_capacity(size + 64);
+    match kind {
+        Kind::Random => {
+            while data.len() < size {
+                data.extend(next().to_le_bytes());
+            }
+        }
+        Kind::Text => {
+            while data.len() < size {
+                data.extend(format!("{:016x} INFO request handled\n", next() % 997).bytes());
+            }
+        }
+        Kind::Zeros => data.resize(size, 0),
+    }
+    data.truncate(size);
+    data
+}
@@ -0,0 +1,156 @@
+//! `picturer serve`: encoding and decoding over HTTP, for services that would rather
+//! not spawn a process per file. `POST /encode[?codec=C]` takes the bytes and answers
+//! with the PNG; `POST /decode` takes a PNG and answers with the bytes.
+
+/// Largest request body accepted unless `--max-size` says otherwise.
+pub const DEFAULT_LIMIT: u64 = 64 << 20;
+/// Largest payload `/decode` answers with unless `--max-output-size` says otherwise,
+/// rather than the 16 GiB of decoding a file, as every request holds its own in memory.
+pub const DEFAULT_MAX_OUTPUT: u64 = 256 << 20;
+
+#[cfg_attr(not(feature = "serve"), allow(dead_code))]
+pub struct Config {
+    pub listen: String,
+    /// Largest request body, in bytes.
+    pub limit: u64,
+    /// Requests handled at once.
+    pub workers: usize,
+    /// Bearer tokens a request must present one of; with none, any request is served.
+    pub tokens: Vec<String>,
+}
+
+#[cfg(feature = "serve")]
+mod server {
+    use super::Config;
+    use crate::format::{Codec, Packing};
+    use sha2::{Digest, Sha256};
+    use std::io::Read;
+    use std::sync::Arc;
+    use tiny_http::{Header, Method, Request, Response, Server};
+
+    /// Status code and message of a request that failed.
+    type Failure = (u16, String);
+
+    pub fn run(config: Config) -> anyhow::Result<()> {
+        let server = Server::http(&config.listen)
+            .map_err(|error| anyhow::anyhow!("cannot listen on {}: {error}", config.listen))?;
+        eprintln!("listening on http://{}", config.listen);
+        // A fixed set of workers takes requests in turn, so that a burst of them waits
+        // rather than each getting a thread, and the memory to decode in, of its own.
+        let server = Arc::new(server);
+        let config = Arc::new(config);
+        let workers: Vec<_> = (0..config.workers)
+            .map(|_| {
+                let (server, config) = (Arc::clone(&server), Arc::clone(&config));
+                std::thread::spawn(move || {
+                    for request in server.incoming_requests() {
+                        respond(&config, request);
+                    }
+                })
+            })
+            .collect();
+        for worker in workers {
+            worker
+                .join()
+                .map_err(|_| anyhow::anyhow!("a worker stopped on a panic"))?;
+        }
+        Ok(())
+    }
+
+    fn respond(config: &Config, mut request: Request) {
+        let (status, content_type, body) = match handle(config, &mut request) {
+            Ok((content_type, body)) => (200, content_type, body),
+            Err((status, message)) => (
+                status,
+                "text/plain; charset=utf-8",
+                (message + "\n").into_bytes(),
+            ),
+        };
+        let header = Header::from_bytes("Content-Type", content_type).expect("valid header");
+        let _ = request.respond(
+            Response::from_data(body)
+                .with_status_code(status)
+                .with_header(header),
+        );
+    }
+
+    fn handle(config: &Config, request: &mut Request) -> Result<(&'static str, Vec<u8>), Failure> {
+        if !authorized(config, request) {
+            return Err((401, "missing or unknown bearer token".to_owned()));
+        }
+        let url = request.url().to_owned();
+        let (path, query) = url.split_once('?').unwrap_or((&url, ""));
+        if path != "/encode" && path != "/decode" {
+            return Err((
+                404,
+                format!("no endpoint {path}; POST to /encode or /decode"),
+            ));
+        }
+        if *request.method() != Method::Post {
+            return Err((405, format!("{path} only accepts POST")));
+        }
+        let body = read_body(config.limit, request)?;
+        let bad_request = |error: anyhow::Error| (400, format!("{error:#}"));
+        if path == "/encode" {
+            let codec = match query
+                .split('&')
+                .find_map(|pair| pair.strip_prefix("codec="))
+            {
+                Some(codec) => codec.parse().map_err(bad_request)?,
+                None => Codec::DEFAULT,
+            };
+            let png = picturer::encode_png(&body, Packing::new(codec)).map_err(bad_request)?;
+            Ok(("image/png", png))
+        } else {
+            let data = picturer::decode_png(&body, None).map_err(bad_request)?;
+            Ok(("application/octet-stream", data))
+        }
+    }
+
+    /// Compares digests rather than the tokens themselves, so the time taken says
+    /// nothing about how much of a token was right.
+    fn authorized(config: &Config, request: &Request) -> bool {
+        if config.tokens.is_empty() {
+            return true;
+        }
+        let Some(token) = request
+            .headers()
+            .iter()
+            .find(|header| header.field.equiv("Authorization"))
+            .and_then(|header| header.value.as_str().strip_prefix("Bearer "))
+        else {
+            return false;
+        };
+        let given = Sha256::digest(token);
+        config
+            .tokens
+            .iter()
+            .any(|token| Sha256::digest(token) == given)
+    }
+
+    fn read_body(limit: u64, request: &mut Request) -> Result<Vec<u8>, Failure> {
+        let too_large = || (413, format!("request body is larger than {limit} bytes"));
+        if request.body_length().is_some_and(|len| len as u64 > limit) {
+            return Err(too_large());
+        }
+        let mut body = Vec::new();
+        request
+            .as_reader()
+            .take(limit + 1)
+            .read_to_end(&mut body)
+            .map_err(|error| (400, format!("cannot read request body: {error}")))?;
+        if body.len() as u64 > limit {
+            return Err(too_large());
+        }
+        Ok(body)
+    }
+}
+
+#[cfg(not(feature = "serve"))]
+mod server {
+    pub fn run(_config: super::Config) -> anyhow::Result<()> {
+        anyhow::bail!("picturer was built without the serve feature")
+    }
+}
+
+pub use server::run;
@@ -0,0 +1,193 @@
+//! Detached Ed25519 signatures, written next to images as `IMAGE.sig` with `--sign-key`
+//! and checked before decoding with `--verify-key` or `--require-signature`, for signing
+//! images without the signature being part of them.
+use crate::hash::hex;
+use crate::{keys, volume};
+use anyhow::{bail, ensure, Context};
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+use std::path::{Path, PathBuf};
+use zeroize::Zeroizing;
+
+/// Where the signature of `image` is kept: `out.png` has `out.png.sig`.
+pub fn path(image: &Path) -> PathBuf {
+    let mut path = image.as_os_str().to_owned();
+    path.push(".sig");
+    PathBuf::from(path)
+}
+
+/// The key in the file given with `--sign-key`: the file itself if it is 32 bytes
+/// long, or else its hash, like a keyfile of a carrier.
+pub fn signing_key(path: &Path) -> anyhow::Result<SigningKey> {
+    let path = &keys::resolve(path);
+    let bytes = Zeroizing::new(
+        std::fs::read(path).with_context(|| format!("cannot read key {}", path.display()))?,
+    );
+    let secret = Zeroizing::new(
+        <[u8; 32]>::try_from(bytes.as_slice()).unwrap_or_else(|_| *blake3::hash(&bytes).as_bytes()),
+    );
+    Ok(SigningKey::from_bytes(&secret))
+}
+
+/// The key given with `--verify-key`: 64 hex digits, a file holding them or the 32
+/// bytes of the key, or the name of a key of `picturer key`, whose public key it is.
+pub fn verifying_key(given: &str) -> anyhow::Result<VerifyingKey> {
+    let bytes = if let Some(bytes) = unhex(given) {
+        bytes
+    } else if let Some(path) = keys::named(Path::new(given)) {
+        return Ok(signing_key(&path)?.verifying_key());
+    } else {
+        let file = std::fs::read(given).with_context(|| {
+            format!(
+                "--verify-key is neither 64 hex digits, a readable file nor a key name: {given}"
+            )
+        })?;
+        <[u8; 32]>::try_from(file.as_slice())
+            .ok()
+            .or_else(|| unhex(&String::from_utf8_lossy(&file)))
+            .with_context(|| format!("{given} does not hold an Ed25519 public key"))?
+    };
+    VerifyingKey::from_bytes(&bytes).context("--verify-key is not a valid Ed25519 public key")
+}
+
+/// Signs the bytes of `image` as they are on disk, writing the 64-byte signature to
+/// `IMAGE.sig`.
+pub fn write(image: &Path, key: &SigningKey) -> anyhow::Result<()> {
+    let signature = key.sign(&std::fs::read(image)?);
+    let path = path(image);
+    std::fs::write(&path, signature.to_bytes())
+        .with_context(|| format!("cannot write {}", path.display()))?;
+    eprintln!(
+        "signed {} with public key {}",
+        image.display(),
+        hex(key.verifying_key().as_bytes())
+    );
+    Ok(())
+}
+
+/// Checks `image`, and if it is a volume every other volume of its set, against the
+/// signature next to it, which must be by one of `keys`. A file without a signature is
+/// refused if `required`, and otherwise decoded unchecked; the detached signature is
+/// the only kind picturer writes, so nothing inside the image is looked for.
+pub fn check(image: &Path, keys: &[VerifyingKey], required: bool) -> anyhow::Result<()> {
+    check_file(image, keys, required)?;
+    volume::others(image)?
+        .iter()
+        .try_for_each(|other| check_file(other, keys, required))
+}
+
+fn check_file(image: &Path, keys: &[VerifyingKey], required: bool) -> anyhow::Result<()> {
+    let path = path(image);
+    let bytes = match std::fs::read(&path) {
+        Ok(bytes) => bytes,
+        Err(error) if error.kind() == std::io::ErrorKind::NotFound => {
+            ensure!(
+                !required,
+                "{} is not signed: there is no {}, and --require-signature refuses it",
+                image.display(),
+                path.display()
+            );
+            eprintln!(
+                "{} has no signature {}; decoding it unchecked",
+                image.display(),
+                path.display()
+            );
+            return Ok(());
+        }
+        Err(error) => {
+            return Err(error).with_context(|| format!("cannot read signature {}", path.display()))
+        }
+    };
+    let Ok(bytes) = <[u8; 64]>::try_from(bytes.as_slice()) else {
+        bail!("{} does not hold an Ed25519 signature", path.display())
+    };
+    let signature = Signature::from_bytes(&bytes);
+    let message = std::fs::read(image)?;
+    ensure!(
+        keys.iter()
+            .any(|key| key.verify_strict(&message, &signature).is_ok()),
+        "{} is not signed by a trusted key; its signature {} matches none of them",
+        image.display(),
+        path.display()
+    );
+    Ok(())
+}
+
+fn unhex(text: &str) -> Option<[u8; 32]> {
+    let text = text.trim();
+    if text.len() != 64 || !text.is_ascii() {
+        return None;
+    }
+    let mut bytes = [0; 32];
+    for (byte, pair) in bytes.iter_mut().zip(text.as_bytes().chunks_exact(2)) {
+        *byte = u8::from_str_radix(std::str::from_utf8(pair).ok()?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{self, Codec, Packing};
+
+    /// Writes an image of a short payload to `dir`.
+    fn image(dir: &Path) -> PathBuf {
+        let container = format::pack(b"signed payload", Packing::new(Codec::Raw)).unwrap();
+        let path = dir.join("out.png");
+        crate::layout(container).unwrap().save(&path).unwrap();
+        path
+    }
+
+    /// A key written to `dir` as its 32 bytes.
+    fn key(dir: &Path, name: &str, byte: u8) -> SigningKey {
+        let path = dir.join(name);
+        std::fs::write(&path, [byte; 32]).unwrap();
+        signing_key(&path).unwrap()
+    }
+
+    #[test]
+    fn a_signed_image_is_verified() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = image(dir.path());
+        let key = key(dir.path(), "sign.key", 1);
+        write(&image, &key).unwrap();
+        assert_eq!(std::fs::read(path(&image)).unwrap().len(), 64);
+
+        let public = hex(key.verifying_key().as_bytes());
+        let verifying = verifying_key(&public).unwrap();
+        assert_eq!(verifying, key.verifying_key());
+        let file = dir.path().join("public.txt");
+        std::fs::write(&file, format!("{public}\n")).unwrap();
+        assert_eq!(verifying_key(file.to_str().unwrap()).unwrap(), verifying);
+        check(&image, &[verifying], true).unwrap();
+    }
+
+    #[test]
+    fn a_tampered_image_or_another_key_fails() {
+        let dir = tempfile::tempdir().unwrap();
+        let image = image(dir.path());
+        let other = key(dir.path(), "other.key", 2).verifying_key();
+        let key = key(dir.path(), "sign.key", 1);
+        write(&image, &key).unwrap();
+        let err = check(&image, &[other], false).unwrap_err();
+        assert!(
+            err.to_string().contains("not signed by a trusted key"),
+            "{err}"
+        );
+
+        let mut bytes = std::fs::read(&image).unwrap();
+        bytes.push(0);
+        std::fs::write(&image, bytes).unwrap();
+        let err = check(&image, &[key.verifying_key()], false).unwrap_err();
+        assert!(
+            err.to_string().contains("not signed by a trusted key"),
+            "{err}"
+        );
+
+        std::fs::write(path(&image), [0; 63]).unwrap();
+        assert!(check(&image, &[key.verifying_key()], false).is_err());
+        std::fs::remove_file(path(&image)).unwrap();
+        check(&image, &[key.verifying_key()], false).unwrap();
+        let err = check(&image, &[key.verifying_key()], true).unwrap_err();
+        assert!(err.to_string().contains("is not signed"), "{err}");
+    }
+}
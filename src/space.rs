@@ -0,0 +1,41 @@
+//! Free disk space, checked before an output is written so that a full disk fails
+//! with a clear error rather than partway through the file.
+use anyhow::ensure;
+use std::path::Path;
+
+/// Fails if the file system that `path` is to be written on has fewer than `needed`
+/// bytes free. Where the free space cannot be learned, nothing is checked.
+pub fn ensure_free(path: &Path, needed: u64) -> anyhow::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new("."),
+    };
+    if let Some(free) = free(dir) {
+        ensure!(
+            needed <= free,
+            "writing {} needs up to {needed} bytes, but only {free} are free",
+            path.display()
+        );
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn free(dir: &Path) -> Option<u64> {
+    use std::os::unix::ffi::OsStrExt;
+    let dir = std::ffi::CString::new(dir.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+    // SAFETY: `dir` is a NUL-terminated path and `stat` has room for what is filled in.
+    if unsafe { libc::statvfs(dir.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    // SAFETY: statvfs succeeded, so it filled in `stat`.
+    let stat = unsafe { stat.assume_init() };
+    #[allow(clippy::useless_conversion)]
+    Some(u64::from(stat.f_bavail).saturating_mul(u64::from(stat.f_frsize)))
+}
+
+#[cfg(not(unix))]
+fn free(_dir: &Path) -> Option<u64> {
+    None
+}
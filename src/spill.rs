@@ -0,0 +1,83 @@
+//! Spilling to disk, once `--temp-dir` is given: the payload read for each image, and
+//! the container packed from it, are written to files there and mapped back rather than
+//! held in memory, so the kernel can page them out while the next stage runs. Each file
+//! is a [`TempFile`], removed when the run ends or a panic unwinds past it.
+use crate::fetch::{self, TempFile};
+use crate::space;
+use memmap2::Mmap;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Write};
+use std::ops::Deref;
+
+/// Bytes held in memory, or in a file of the temporary directory mapped into it.
+pub enum Buffer {
+    Memory(Vec<u8>),
+    /// The mapping, dropped before the file it maps is removed.
+    Disk {
+        map: Mmap,
+        _file: TempFile,
+    },
+}
+
+impl Deref for Buffer {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match self {
+            Buffer::Memory(bytes) => bytes,
+            Buffer::Disk { map, .. } => map,
+        }
+    }
+}
+
+/// Reads `input`, which gives at most `len` bytes, into memory, or into a file called
+/// `name` once spilling.
+pub fn read(input: impl Read, len: u64, name: &str) -> anyhow::Result<Buffer> {
+    let mut input = input.take(len);
+    let Some(file) = spill_file(name, len)? else {
+        let mut bytes = Vec::new();
+        input.read_to_end(&mut bytes)?;
+        return Ok(Buffer::Memory(bytes));
+    };
+    let mut out = create(&file)?;
+    std::io::copy(&mut input, &mut out)?;
+    map(file, &out)
+}
+
+/// `bytes` as they are, or moved to a file called `name` once spilling, freeing the
+/// memory they took.
+pub fn keep(bytes: Vec<u8>, name: &str) -> anyhow::Result<Buffer> {
+    let Some(file) = spill_file(name, bytes.len() as u64)? else {
+        return Ok(Buffer::Memory(bytes));
+    };
+    let mut out = create(&file)?;
+    out.write_all(&bytes)?;
+    drop(bytes);
+    map(file, &out)
+}
+
+/// The file to spill `len` bytes to, if a `--temp-dir` was given, once it has room.
+fn spill_file(name: &str, len: u64) -> anyhow::Result<Option<TempFile>> {
+    if fetch::temp_dir().is_none() {
+        return Ok(None);
+    }
+    let file = TempFile::new(name)?;
+    space::ensure_free(&file.path, len)?;
+    Ok(Some(file))
+}
+
+fn create(file: &TempFile) -> std::io::Result<File> {
+    OpenOptions::new()
+        .read(true)
+        .write(true)
+        .create(true)
+        .truncate(true)
+        .open(&file.path)
+}
+
+fn map(file: TempFile, out: &File) -> anyhow::Result<Buffer> {
+    // SAFETY: the file is one of this process's own temporary files, which nothing else
+    // writes to or truncates while it is mapped.
+    let map = unsafe { Mmap::map(out)? };
+    Ok(Buffer::Disk { map, _file: file })
+}
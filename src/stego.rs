@@ -0,0 +1,828 @@
+//! Hiding a payload in the low bits of each color channel of existing images, the
+//! carriers, so that what is written looks like the photos it was made from. A payload
+//! too large for one carrier is spread across several, each starting with a header
+//! that says which part of the whole it holds. Headers always take the lowest bit of
+//! their channels; the part after one takes as many as its density.
+//!
+//! With secrets, passphrases or keyfiles, the header and part are encrypted with a
+//! random key and scattered over channels in an order only that key gives, and each
+//! bit is stored by nudging a channel up or down by one rather than by overwriting its
+//! lowest bit, which leaves the telltale pairs of values that replacement does. Before
+//! them come a random salt and a slot per secret, the key encrypted with what the
+//! secret and salt make, which to anyone without a secret are all noise. After the part
+//! comes a tag of the header and part keyed the same way, so that neither a length nor
+//! any byte of the container, its codec and names among them, can be changed unnoticed.
+//! Carriers written before the tag, whose order and streams were keyed without the slot
+//! count, are still read, untagged, as [`Layout::Untagged`].
+use crate::{keys, volume};
+use anyhow::{bail, ensure, Context};
+use image::{DynamicImage, ImageFormat};
+use sha2::{Digest, Sha256};
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use zeroize::{Zeroize, ZeroizeOnDrop, Zeroizing};
+
+const MAGIC: &[u8; 4] = b"PICS";
+/// Magic, set, index, count, density and part length.
+const HEADER_LEN: usize = MAGIC.len() + 8 + 2 + 2 + 1 + 4;
+/// Channels a header takes, at one bit each.
+const HEADER_CHANNELS: usize = HEADER_LEN * 8;
+/// Default for `--density`, which barely changes a photo.
+pub const DENSITY: u8 = 1;
+/// Most low bits of a channel `--density` may take; beyond them the payload shows.
+pub const MAX_DENSITY: u8 = 4;
+/// Bytes of the salt a secret is mixed with, the first thing scattered.
+const SALT_LEN: usize = 16;
+const SALT_CHANNELS: usize = SALT_LEN * 8;
+/// Bytes of the slot that holds the key for one secret.
+const SLOT_LEN: usize = 32;
+/// Bytes of the tag after a deniable part.
+const TAG_LEN: usize = blake3::OUT_LEN;
+/// How the channels of a deniable carrier were laid out.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Layout {
+    /// With a tag after the part, and the order and streams keyed by the slot count too.
+    Tagged,
+    /// As carriers were written before they had a tag: keyed by the secret alone, and
+    /// with an order that could repeat a channel, which reading has to repeat as well.
+    Untagged,
+}
+
+/// A salt, and the keys the secrets make with it.
+type Keyed = (Vec<u8>, Vec<Zeroizing<[u8; 32]>>);
+
+/// Most secrets that can open one carrier. Readers try every slot count up to it, as
+/// nothing in the carrier says how many there are.
+pub const MAX_SECRETS: usize = 16;
+
+/// What the key of a deniable carrier is made from, wiped from memory once dropped.
+#[derive(Zeroize, ZeroizeOnDrop)]
+pub enum Secret {
+    Passphrase(String),
+    /// Read from a keyfile: the file itself if it is 32 bytes long, or else its hash.
+    Key([u8; 32]),
+}
+
+impl Secret {
+    /// The secrets given by `--passphrase`, `--keyfile` and `--recipient`. An empty
+    /// passphrase, from a `--passphrase` without a value, is asked for on the terminal,
+    /// and with `confirm` asked for again, as a mistyped one would lock the payload away.
+    pub fn given(
+        passphrase: Option<String>,
+        keyfile: Option<&Path>,
+        recipients: &[PathBuf],
+        confirm: bool,
+    ) -> anyhow::Result<Vec<Self>> {
+        let mut secrets = Vec::new();
+        match passphrase {
+            Some(passphrase) if passphrase.is_empty() => {
+                secrets.push(Secret::Passphrase(prompt(confirm)?));
+            }
+            Some(passphrase) => secrets.push(Secret::Passphrase(passphrase)),
+            None => {}
+        }
+        for keyfile in keyfile
+            .into_iter()
+            .chain(recipients.iter().map(PathBuf::as_path))
+        {
+            let keyfile = &keys::resolve(keyfile);
+            let bytes = Zeroizing::new(
+                std::fs::read(keyfile)
+                    .with_context(|| format!("cannot read keyfile {}", keyfile.display()))?,
+            );
+            let key = <[u8; 32]>::try_from(bytes.as_slice())
+                .unwrap_or_else(|_| *blake3::hash(&bytes).as_bytes());
+            secrets.push(Secret::Key(key));
+        }
+        Ok(secrets)
+    }
+
+    /// What the slot for this secret is encrypted with in the carrier whose salt is
+    /// `salt`, wiped once dropped. A passphrase is stretched, as it may be guessed; a key
+    /// only needs mixing with the salt.
+    fn key(&self, salt: &[u8]) -> anyhow::Result<Zeroizing<[u8; 32]>> {
+        let mut key = Zeroizing::new([0; 32]);
+        match self {
+            Secret::Passphrase(passphrase) => argon2::Argon2::default().hash_password_into(
+                passphrase.as_bytes(),
+                salt,
+                key.as_mut(),
+            )?,
+            Secret::Key(secret) => *key = *blake3::keyed_hash(secret, salt).as_bytes(),
+        }
+        Ok(key)
+    }
+}
+
+/// Reads a passphrase from the terminal without echoing it, twice if `confirm`.
+fn prompt(confirm: bool) -> anyhow::Result<String> {
+    let ask = |prompt| {
+        rpassword::prompt_password(prompt)
+            .map(Zeroizing::new)
+            .context(
+                "cannot ask for a passphrase without a terminal; give it with --passphrase P \
+                 or PICTURER_PASSPHRASE, or use --keyfile",
+            )
+    };
+    let passphrase = ask("Passphrase: ")?;
+    ensure!(!passphrase.is_empty(), "the passphrase is empty");
+    if confirm {
+        ensure!(
+            *ask("Passphrase again: ")? == *passphrase,
+            "the passphrases do not match"
+        );
+    }
+    Ok(passphrase.to_string())
+}
+
+/// What a carrier says about its part of the payload.
+struct Header {
+    /// Leading bytes of the SHA-256 of the whole container, the same in every carrier
+    /// of a set.
+    set: [u8; 8],
+    index: u16,
+    count: u16,
+    /// Low bits of each channel that hold the part.
+    density: u8,
+    len: u32,
+}
+
+impl Header {
+    fn bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(HEADER_LEN);
+        bytes.extend_from_slice(MAGIC);
+        bytes.extend_from_slice(&self.set);
+        bytes.extend_from_slice(&self.index.to_le_bytes());
+        bytes.extend_from_slice(&self.count.to_le_bytes());
+        bytes.push(self.density);
+        bytes.extend_from_slice(&self.len.to_le_bytes());
+        bytes
+    }
+
+    fn parse(bytes: &[u8]) -> Option<Header> {
+        if bytes.get(..MAGIC.len())? != MAGIC {
+            return None;
+        }
+        let header = Header {
+            set: bytes[4..12].try_into().ok()?,
+            index: u16::from_le_bytes(bytes[12..14].try_into().ok()?),
+            count: u16::from_le_bytes(bytes[14..16].try_into().ok()?),
+            density: *bytes.get(16)?,
+            len: u32::from_le_bytes(bytes.get(17..HEADER_LEN)?.try_into().ok()?),
+        };
+        let valid = header.index < header.count && (1..=MAX_DENSITY).contains(&header.density);
+        valid.then_some(header)
+    }
+}
+
+/// A carrier's pixels as 8-bit channels, keeping alpha only if it had one, which is
+/// left alone.
+struct Pixels {
+    raw: Vec<u8>,
+    alpha: bool,
+    width: u32,
+    height: u32,
+}
+
+impl Pixels {
+    fn new(image: &DynamicImage) -> Pixels {
+        let alpha = image.color().has_alpha();
+        let raw = if alpha {
+            image.to_rgba8().into_raw()
+        } else {
+            image.to_rgb8().into_raw()
+        };
+        Pixels {
+            raw,
+            alpha,
+            width: image.width(),
+            height: image.height(),
+        }
+    }
+
+    /// The channels that hold a bit each, in order.
+    fn channels(&self) -> impl Iterator<Item = &u8> {
+        let alpha = self.alpha;
+        self.raw
+            .iter()
+            .enumerate()
+            .filter(move |(i, _)| !alpha || i % 4 != 3)
+            .map(|(_, channel)| channel)
+    }
+
+    fn channel_count(&self) -> usize {
+        self.width as usize * self.height as usize * 3
+    }
+
+    /// The lowest bits of `channels`.
+    fn lows<'a>(
+        &'a self,
+        channels: impl Iterator<Item = usize> + 'a,
+    ) -> impl Iterator<Item = u8> + 'a {
+        channels.map(|channel| self.raw[self.position(channel)] & 1)
+    }
+
+    /// Where channel `channel` is in `raw`.
+    fn position(&self, channel: usize) -> usize {
+        if self.alpha {
+            channel / 3 * 4 + channel % 3
+        } else {
+            channel
+        }
+    }
+
+    /// Bytes of payload the carrier holds after its header, at `density`, or scattered
+    /// with a slot for each of `secrets` if there are any.
+    fn capacity(&self, density: u8, secrets: usize) -> usize {
+        if secrets > 0 {
+            let used = SALT_CHANNELS + (secrets * SLOT_LEN + TAG_LEN) * 8 + HEADER_CHANNELS;
+            return self.channel_count().saturating_sub(used) / 8;
+        }
+        self.channel_count().saturating_sub(HEADER_CHANNELS) * usize::from(density) / 8
+    }
+
+    /// `len` bytes from the `density` low bits of the channels after the first `skip`.
+    fn read(&self, skip: usize, density: u8, len: usize) -> Vec<u8> {
+        let bits = self
+            .channels()
+            .skip(skip)
+            .flat_map(|channel| (0..density).rev().map(move |i| channel >> i & 1));
+        bytes(bits, len)
+    }
+
+    /// Stores `bytes` in the `density` low bits of the channels after the first `skip`.
+    fn write(&mut self, skip: usize, density: u8, bytes: &[u8]) {
+        let alpha = self.alpha;
+        let mut bits = bits(bytes);
+        let channels = self
+            .raw
+            .iter_mut()
+            .enumerate()
+            .filter(|(i, _)| !alpha || i % 4 != 3)
+            .skip(skip);
+        let mask = (1 << density) - 1;
+        for (_, channel) in channels {
+            let (mut value, mut taken) = (0, 0);
+            for bit in bits.by_ref().take(density.into()) {
+                value = value << 1 | bit;
+                taken += 1;
+            }
+            if taken == 0 {
+                break;
+            }
+            *channel = *channel & !mask | value << (density - taken);
+        }
+    }
+
+    fn save(self, path: &Path) -> anyhow::Result<()> {
+        let mut out = BufWriter::new(File::create(path)?);
+        let (width, height) = (self.width, self.height);
+        let image = if self.alpha {
+            DynamicImage::from(
+                image::RgbaImage::from_raw(width, height, self.raw).context("bad carrier")?,
+            )
+        } else {
+            DynamicImage::from(
+                image::RgbImage::from_raw(width, height, self.raw).context("bad carrier")?,
+            )
+        };
+        image.write_to(&mut out, ImageFormat::Png)?;
+        Ok(out.flush()?)
+    }
+
+    /// The header and part of the payload this carrier holds, if it is one, in the
+    /// clear or scattered with a slot that one of `secrets` opens.
+    fn part(&self, secrets: &[Secret]) -> anyhow::Result<Option<(Header, Vec<u8>)>> {
+        if let Some(header) = Header::parse(&self.read(0, 1, HEADER_LEN)) {
+            let len = header.len as usize;
+            if len <= self.capacity(header.density, 0) {
+                let part = self.read(HEADER_CHANNELS, header.density, len);
+                return Ok(Some((header, part)));
+            }
+        }
+        if secrets.is_empty() {
+            return Ok(None);
+        }
+        let mut keyed = None;
+        for layout in [Layout::Tagged, Layout::Untagged] {
+            if let Some(found) = self.scattered(secrets, layout, &mut keyed)? {
+                return Ok(Some(found));
+            }
+        }
+        Ok(None)
+    }
+
+    /// The header and part scattered as `layout` lays them out with a slot that one of
+    /// `secrets` opens. `keyed` holds the salt and the keys last made from it, which are
+    /// reused while the salt is the same, as stretching a passphrase is slow.
+    fn scattered(
+        &self,
+        secrets: &[Secret],
+        layout: Layout,
+        keyed: &mut Option<Keyed>,
+    ) -> anyhow::Result<Option<(Header, Vec<u8>)>> {
+        let mut order = salt_order(self, layout);
+        let salt = bytes(self.lows(order.by_ref().take(SALT_CHANNELS)), SALT_LEN);
+        if keyed.as_ref().is_none_or(|(made, _)| *made != salt) {
+            let keys = secrets
+                .iter()
+                .map(|secret| secret.key(&salt))
+                .collect::<anyhow::Result<Vec<_>>>()?;
+            *keyed = Some((salt, keys));
+        }
+        let keys = keyed.as_ref().map_or(&[][..], |(_, keys)| keys);
+        let tag_len = if layout == Layout::Tagged { 0 } else { TAG_LEN };
+        let mut slots = Vec::new();
+        for count in 1..=MAX_SECRETS {
+            slots.push(bytes(
+                self.lows(order.by_ref().take(SLOT_LEN * 8)),
+                SLOT_LEN,
+            ));
+            for slot in &slots {
+                for key in keys {
+                    let key = Zeroizing::new(xor(slot, key));
+                    let mut scatter = Scatter::new(order.clone(), &key, count, layout);
+                    let Some(header) = Header::parse(&scatter.read(self, HEADER_LEN)) else {
+                        continue;
+                    };
+                    let len = header.len as usize;
+                    if header.density == 1 && len <= self.capacity(1, count) + tag_len {
+                        let part = scatter.read(self, len);
+                        ensure!(
+                            layout == Layout::Untagged || scatter.check(self),
+                            "it was changed since it was written, as the tag of its header \
+                             and part does not match them"
+                        );
+                        return Ok(Some((header, part)));
+                    }
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+/// The order a deniable carrier's channels are used in after its salt, the streams its
+/// bits are encrypted with and its nudges chosen by, and the tag of what it holds, all
+/// keyed by the secret.
+struct Scatter {
+    order: Shuffle,
+    cipher: blake3::OutputReader,
+    coins: blake3::OutputReader,
+    /// Every byte read or written so far, in the clear.
+    tag: blake3::Hasher,
+}
+
+impl Scatter {
+    /// Keys what is left of `order` with `key` and, as `layout` is tagged, the number of
+    /// slots before it, so that a reader trying too few or too many finds only noise.
+    fn new(mut order: Shuffle, key: &[u8; 32], slots: usize, layout: Layout) -> Scatter {
+        let hasher = |context| {
+            let mut hasher = blake3::Hasher::new_keyed(&blake3::derive_key(context, key));
+            if layout == Layout::Tagged {
+                hasher.update(&(slots as u64).to_le_bytes());
+            }
+            hasher
+        };
+        order.rng = hasher("picturer carrier order").finalize_xof();
+        Scatter {
+            order,
+            cipher: hasher("picturer carrier cipher").finalize_xof(),
+            coins: hasher("picturer carrier coins").finalize_xof(),
+            tag: hasher("picturer carrier tag"),
+        }
+    }
+
+    fn read(&mut self, pixels: &Pixels, len: usize) -> Vec<u8> {
+        let mut bytes = bytes(pixels.lows(self.order.by_ref().take(len * 8)), len);
+        self.crypt(&mut bytes);
+        self.tag.update(&bytes);
+        bytes
+    }
+
+    /// Stores `bytes`, encrypted, in the next channels.
+    fn write(&mut self, pixels: &mut Pixels, bytes: &[u8]) {
+        self.tag.update(bytes);
+        let mut bytes = bytes.to_vec();
+        self.crypt(&mut bytes);
+        for bit in bits(&bytes) {
+            let Some(channel) = self.order.next() else {
+                break;
+            };
+            self.nudge(pixels, channel, bit);
+        }
+    }
+
+    /// Stores the tag of everything written so far.
+    fn seal(&mut self, pixels: &mut Pixels) {
+        let tag = self.tag.finalize();
+        self.write(pixels, tag.as_bytes());
+    }
+
+    /// Whether the next channels hold the tag of everything read so far.
+    fn check(&mut self, pixels: &Pixels) -> bool {
+        let tag = self.tag.finalize();
+        let stored = self.read(pixels, TAG_LEN);
+        <[u8; TAG_LEN]>::try_from(stored).is_ok_and(|stored| tag == stored)
+    }
+
+    /// Gives `channel` the lowest bit `bit` by moving it one up or down, at random.
+    fn nudge(&mut self, pixels: &mut Pixels, channel: usize, bit: u8) {
+        let mut coin = [0];
+        self.coins.fill(&mut coin);
+        let position = pixels.position(channel);
+        let value = &mut pixels.raw[position];
+        if *value & 1 != bit {
+            *value = match *value {
+                0 => 1,
+                255 => 254,
+                value if coin[0] & 1 == 0 => value - 1,
+                value => value + 1,
+            };
+        }
+    }
+
+    fn crypt(&mut self, bytes: &mut [u8]) {
+        let mut stream = vec![0; bytes.len()];
+        self.cipher.fill(&mut stream);
+        for (byte, key) in bytes.iter_mut().zip(stream) {
+            *byte ^= key;
+        }
+    }
+}
+
+/// What `--deterministic` draws the salt and key of carrier `index` from instead of
+/// randomness: the keys the secrets give for a salt taken from the container, mixed with
+/// the carrier. Without a secret it cannot be told from random, and guessing a
+/// passphrase still costs a stretch of it per guess.
+fn derived_seed(
+    container: &[u8],
+    index: u16,
+    carrier: &Pixels,
+    secrets: &[Secret],
+) -> anyhow::Result<blake3::OutputReader> {
+    let salt = &Sha256::digest(container)[..SALT_LEN];
+    let mut keys = blake3::Hasher::new_derive_key("picturer deterministic carrier");
+    for secret in secrets {
+        keys.update(&*secret.key(salt)?);
+    }
+    let mut seed = blake3::Hasher::new_keyed(keys.finalize().as_bytes());
+    seed.update(&index.to_le_bytes());
+    seed.update(carrier.raw.as_slice());
+    Ok(seed.finalize_xof())
+}
+
+/// The order of the channels of `pixels` starting with the salt's, which any reader
+/// can repeat.
+fn salt_order(pixels: &Pixels, layout: Layout) -> Shuffle {
+    let mut size = [0; 8];
+    size[..4].copy_from_slice(&pixels.width.to_le_bytes());
+    size[4..].copy_from_slice(&pixels.height.to_le_bytes());
+    let seed = blake3::derive_key("picturer carrier salt order", &size);
+    Shuffle {
+        rng: blake3::Hasher::new_keyed(&seed).finalize_xof(),
+        len: pixels.channel_count(),
+        next: 0,
+        moved: HashMap::new(),
+        layout,
+    }
+}
+
+/// A random order of `0..len`, drawn one position at a time as Fisher-Yates shuffling
+/// does, keeping only the positions it has swapped.
+#[derive(Clone)]
+struct Shuffle {
+    rng: blake3::OutputReader,
+    len: usize,
+    next: usize,
+    moved: HashMap<usize, usize>,
+    layout: Layout,
+}
+
+impl Iterator for Shuffle {
+    type Item = usize;
+
+    fn next(&mut self) -> Option<usize> {
+        if self.next == self.len {
+            return None;
+        }
+        let mut draw = [0; 8];
+        self.rng.fill(&mut draw);
+        let range = (self.len - self.next) as u128;
+        #[allow(clippy::cast_possible_truncation)]
+        let pick = self.next + ((u128::from(u64::from_le_bytes(draw)) * range) >> 64) as usize;
+        let here = self.moved.remove(&self.next).unwrap_or(self.next);
+        let picked = if pick == self.next && self.layout == Layout::Tagged {
+            here
+        } else {
+            self.moved.insert(pick, here).unwrap_or(pick)
+        };
+        self.next += 1;
+        Some(picked)
+    }
+}
+
+/// The bits of `bytes`, most significant first.
+fn bits(bytes: &[u8]) -> impl Iterator<Item = u8> + '_ {
+    bytes
+        .iter()
+        .flat_map(|byte| (0..8).rev().map(move |i| byte >> i & 1))
+}
+
+/// Packs `bits`, most significant first, into `len` bytes.
+fn bytes(mut bits: impl Iterator<Item = u8>, len: usize) -> Vec<u8> {
+    (0..len)
+        .map(|_| bits.by_ref().take(8).fold(0, |byte, bit| byte << 1 | bit))
+        .collect()
+}
+
+fn xor(a: &[u8], b: &[u8; 32]) -> [u8; 32] {
+    let mut out = *b;
+    for (out, a) in out.iter_mut().zip(a) {
+        *out ^= a;
+    }
+    out
+}
+
+/// Whether the RGBA pixels `rgba` begin an image written with `--carrier`.
+pub fn is_carrier(rgba: &[u8]) -> bool {
+    let pixels = Pixels {
+        raw: rgba.to_vec(),
+        alpha: true,
+        width: 0,
+        height: 0,
+    };
+    pixels.read(0, 1, MAGIC.len()) == MAGIC
+}
+
+/// Width, height and capacity, at `density` or scattered with a slot for each of
+/// `secrets` if there are any, of the carrier at `path`.
+pub fn capacity(path: &Path, density: u8, secrets: usize) -> anyhow::Result<(u32, u32, usize)> {
+    let pixels = Pixels::new(&read_carrier(path)?);
+    Ok((
+        pixels.width,
+        pixels.height,
+        pixels.capacity(density, secrets),
+    ))
+}
+
+fn read_carrier(path: &Path) -> anyhow::Result<DynamicImage> {
+    crate::read_image(path).with_context(|| format!("cannot read carrier {}", path.display()))
+}
+
+/// Hides `container` in copies of `carriers`, taking `density` low bits of each channel
+/// and giving each carrier a share in proportion to its capacity, and writes them as
+/// PNGs: to `out` for a single carrier, and otherwise to `out.001.png`, `out.002.png`,
+/// ... in the order given. With `secrets`, each carrier is written deniably instead,
+/// for any of them to open, which needs a `density` of 1.
+pub fn embed(
+    container: &[u8],
+    carriers: &[PathBuf],
+    density: u8,
+    secrets: &[Secret],
+    out: &Path,
+    deterministic: bool,
+) -> anyhow::Result<Vec<PathBuf>> {
+    ensure!(
+        secrets.is_empty() || density == 1,
+        "--passphrase, --keyfile and --recipient hide payloads at --density 1 only, where \
+         the changes pass for noise"
+    );
+    ensure!(
+        secrets.len() <= MAX_SECRETS,
+        "at most {MAX_SECRETS} passphrases, keyfiles and recipients can open a carrier"
+    );
+    let count = u16::try_from(carriers.len()).context("too many carriers")?;
+    let mut pixels = Vec::with_capacity(carriers.len());
+    for carrier in carriers {
+        pixels.push(Pixels::new(&read_carrier(carrier)?));
+    }
+    let total: usize = pixels
+        .iter()
+        .map(|pixels| pixels.capacity(density, secrets.len()))
+        .sum();
+    ensure!(
+        container.len() <= total,
+        "payload of {} packed bytes does not fit the carriers, which hold {total} at \
+         --density {density}",
+        container.len()
+    );
+    let set = Sha256::digest(container)[..8].try_into()?;
+    let mut written = Vec::with_capacity(pixels.len());
+    let (mut start, mut held) = (0, 0);
+    for (index, mut carrier) in (0..count).zip(pixels) {
+        held += carrier.capacity(density, secrets.len());
+        // Shares in proportion to capacity, so every carrier is changed about as much.
+        let end = usize::try_from(container.len() as u128 * held as u128 / total.max(1) as u128)?;
+        let part = &container[start..end];
+        let header = Header {
+            set,
+            index,
+            count,
+            density,
+            len: u32::try_from(part.len())?,
+        };
+        if secrets.is_empty() {
+            carrier.write(0, 1, &header.bytes());
+            carrier.write(HEADER_CHANNELS, density, part);
+        } else {
+            let (mut salt, mut key) = ([0; SALT_LEN], Zeroizing::new([0; 32]));
+            if deterministic {
+                let mut seed = derived_seed(container, index, &carrier, secrets)?;
+                seed.fill(&mut salt);
+                seed.fill(key.as_mut());
+            } else {
+                getrandom::fill(&mut salt)?;
+                getrandom::fill(key.as_mut())?;
+            }
+            let mut clear = salt.to_vec();
+            for secret in secrets {
+                clear.extend_from_slice(&xor(key.as_slice(), &*secret.key(&salt)?));
+            }
+            let mut order = salt_order(&carrier, Layout::Tagged);
+            let channels: Vec<_> = order.by_ref().take(clear.len() * 8).collect();
+            let mut scatter = Scatter::new(order, &key, secrets.len(), Layout::Tagged);
+            for (channel, bit) in channels.into_iter().zip(bits(&clear)) {
+                scatter.nudge(&mut carrier, channel, bit);
+            }
+            scatter.write(&mut carrier, &header.bytes());
+            scatter.write(&mut carrier, part);
+            scatter.seal(&mut carrier);
+        }
+        let path = if count == 1 {
+            out.to_path_buf()
+        } else {
+            volume::path(out, index.into(), count.into())
+        };
+        carrier.save(&path)?;
+        written.push(path);
+        start = end;
+    }
+    Ok(written)
+}
+
+/// The container hidden in `image`, read from `path`, gathered from the other carriers
+/// of its set next to it, or `None` if it is not a carrier, or not one `secrets` open.
+pub fn extract(
+    path: &Path,
+    image: &DynamicImage,
+    secrets: &[Secret],
+) -> anyhow::Result<Option<Vec<u8>>> {
+    let Some((this, part)) = Pixels::new(image)
+        .part(secrets)
+        .with_context(|| format!("cannot open carrier {}", path.display()))?
+    else {
+        return Ok(None);
+    };
+    let mut container = Vec::new();
+    for index in 0..this.count {
+        if index == this.index {
+            container.extend_from_slice(&part);
+            continue;
+        }
+        let sibling = volume::path(&volume::base(path), index.into(), this.count.into());
+        let image = crate::read_image(&sibling).with_context(|| {
+            format!(
+                "cannot read {}, carrier {} of the {} holding this payload",
+                sibling.display(),
+                index + 1,
+                this.count
+            )
+        })?;
+        let Some((header, part)) = Pixels::new(&image)
+            .part(secrets)
+            .with_context(|| format!("cannot open carrier {}", sibling.display()))?
+        else {
+            bail!("{} is not a carrier", sibling.display())
+        };
+        ensure!(
+            header.set == this.set && header.index == index,
+            "{} is not carrier {} of this set",
+            sibling.display(),
+            index + 1
+        );
+        container.extend_from_slice(&part);
+    }
+    ensure!(
+        Sha256::digest(&container)[..8] == this.set,
+        "the carriers do not add up to the payload they were written with"
+    );
+    Ok(Some(container))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Writes a carrier of noise to `dir`, with an alpha channel or not.
+    fn carrier(dir: &Path, name: &str, seed: u32, alpha: bool) -> PathBuf {
+        let noise = |x: u32, y: u32, c: u32| ((x * 31 + y * 17 + c * 7) ^ seed).to_le_bytes()[0];
+        let path = dir.join(name);
+        let image = if alpha {
+            DynamicImage::from(image::RgbaImage::from_fn(64, 48, |x, y| {
+                image::Rgba([noise(x, y, 0), noise(x, y, 1), noise(x, y, 2), 255])
+            }))
+        } else {
+            DynamicImage::from(image::RgbImage::from_fn(64, 64, |x, y| {
+                image::Rgb([noise(x, y, 0), noise(x, y, 1), noise(x, y, 2)])
+            }))
+        };
+        image.save(&path).unwrap();
+        path
+    }
+
+    fn carriers(dir: &Path, seed: u32) -> Vec<PathBuf> {
+        (0..3)
+            .map(|i| carrier(dir, &format!("carrier-{seed}-{i}.png"), seed + i, i == 1))
+            .collect()
+    }
+
+    fn container(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i * 11 + i / 3).to_le_bytes()[0])
+            .collect()
+    }
+
+    fn extract_from(path: &Path, secrets: &[Secret]) -> anyhow::Result<Option<Vec<u8>>> {
+        extract(path, &crate::read_image(path)?, secrets)
+    }
+
+    #[test]
+    fn a_payload_spread_over_carriers_is_extracted_again() {
+        let dir = tempfile::tempdir().unwrap();
+        let carriers = carriers(dir.path(), 1);
+        let out = dir.path().join("out.png");
+        for density in [1, 3] {
+            let container = container(1_000 * usize::from(density));
+            let written = embed(&container, &carriers, density, &[], &out, false).unwrap();
+            assert_eq!(written.len(), 3);
+            for path in &written {
+                let rgba = crate::read_image(path).unwrap().to_rgba8().into_raw();
+                assert!(is_carrier(&rgba));
+                assert_eq!(extract_from(path, &[]).unwrap(), Some(container.clone()));
+            }
+        }
+        assert_eq!(extract_from(&carriers[0], &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn a_payload_hidden_with_a_passphrase_needs_it() {
+        let dir = tempfile::tempdir().unwrap();
+        let carriers = carriers(dir.path(), 2);
+        let out = dir.path().join("out.png");
+        let container = container(2_000);
+        let secrets = [
+            Secret::Passphrase("correct horse".into()),
+            Secret::Key([9; 32]),
+        ];
+        let written = embed(&container, &carriers, 1, &secrets, &out, false).unwrap();
+        let rgba = crate::read_image(&written[0])
+            .unwrap()
+            .to_rgba8()
+            .into_raw();
+        assert!(!is_carrier(&rgba));
+        assert_eq!(extract_from(&written[0], &[]).unwrap(), None);
+        for secret in secrets {
+            assert_eq!(
+                extract_from(&written[2], &[secret]).unwrap(),
+                Some(container.clone())
+            );
+        }
+        let wrong = [Secret::Passphrase("wrong horse".into())];
+        assert_eq!(extract_from(&written[1], &wrong).unwrap(), None);
+        assert!(embed(&container, &carriers, 2, &wrong, &out, false).is_err());
+    }
+
+    #[test]
+    fn a_carrier_of_another_set_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let out = dir.path().join("out.png");
+        let written = embed(
+            &container(3_000),
+            &carriers(dir.path(), 3),
+            1,
+            &[],
+            &out,
+            false,
+        )
+        .unwrap();
+        let other = dir.path().join("other.png");
+        let others = embed(
+            &container(2_900),
+            &carriers(dir.path(), 4),
+            1,
+            &[],
+            &other,
+            false,
+        )
+        .unwrap();
+        std::fs::copy(&others[1], &written[1]).unwrap();
+        let err = extract_from(&written[0], &[]).unwrap_err();
+        assert!(
+            err.to_string().contains("not carrier 2 of this set"),
+            "{err}"
+        );
+    }
+}
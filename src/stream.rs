@@ -0,0 +1,174 @@
+use crate::format::Source;
+use anyhow::ensure;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::Path;
+
+/// RGBA bytes that hold the magic of a carrier.
+const CARRIER_PREFIX: usize = 44;
+
+/// Pixel bytes of a PNG, decoded only as far as they have been asked for.
+/// Interlaced and non-RGBA images fall back to decoding everything up front.
+pub enum PixelStream {
+    Rows {
+        reader: Box<png::Reader<BufReader<File>>>,
+        /// Rows decoded so far, less the `released` bytes a [`Source::window`] let go of.
+        buf: Vec<u8>,
+        released: usize,
+    },
+    Full(Vec<u8>),
+}
+
+impl PixelStream {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let mut decoder = png::Decoder::new_with_limits(
+            BufReader::new(File::open(path)?),
+            png::Limits {
+                bytes: 2 * crate::MAX_BYTES,
+            },
+        );
+        decoder.set_transformations(png::Transformations::normalize_to_color8());
+        // Anything but a PNG, such as a robust image re-encoded as JPEG, is decoded whole.
+        let Ok(reader) = decoder.read_info() else {
+            return Ok(PixelStream::Full(crate::read_pixels(path)?));
+        };
+        if reader.info().interlaced
+            || reader.output_color_type() != (png::ColorType::Rgba, png::BitDepth::Eight)
+        {
+            return Ok(PixelStream::Full(crate::read_pixels(path)?));
+        }
+        let mut stream = PixelStream::Rows {
+            reader: Box::new(reader),
+            buf: Vec::new(),
+            released: 0,
+        };
+        // Carriers hold their payload in low bits, which only the whole image gives, and
+        // opaque images in three bytes of each pixel, which the first pixel tells.
+        let prefix = stream.prefix(CARRIER_PREFIX)?;
+        if crate::stego::is_carrier(prefix)
+            || crate::tile::is_tiled(prefix)
+            || prefix.get(3) == Some(&u8::MAX)
+        {
+            return Ok(PixelStream::Full(crate::read_pixels(path)?));
+        }
+        Ok(stream)
+    }
+}
+
+impl Source for PixelStream {
+    fn prefix(&mut self, len: usize) -> anyhow::Result<&[u8]> {
+        match self {
+            PixelStream::Rows {
+                reader,
+                buf,
+                released,
+            } => {
+                ensure!(
+                    *released == 0,
+                    "pixels before byte {released} were let go of"
+                );
+                while buf.len() < len {
+                    let Some(row) = reader.next_row()? else {
+                        break;
+                    };
+                    buf.extend_from_slice(row.data());
+                }
+                Ok(buf)
+            }
+            PixelStream::Full(buf) => Ok(buf),
+        }
+    }
+
+    fn total_len(&self) -> Option<usize> {
+        match self {
+            PixelStream::Rows { reader, .. } => {
+                let (width, height) = reader.info().size();
+                usize::try_from(u64::from(width) * u64::from(height) * 4).ok()
+            }
+            PixelStream::Full(buf) => Some(buf.len()),
+        }
+    }
+
+    fn window(&mut self, start: usize, end: usize) -> anyhow::Result<&[u8]> {
+        match self {
+            PixelStream::Rows {
+                reader,
+                buf,
+                released,
+            } => {
+                ensure!(
+                    start >= *released,
+                    "pixels before byte {released} were let go of"
+                );
+                let gone = (start - *released).min(buf.len());
+                buf.drain(..gone);
+                *released += gone;
+                while *released + buf.len() < end {
+                    let Some(row) = reader.next_row()? else {
+                        break;
+                    };
+                    buf.extend_from_slice(row.data());
+                }
+                Ok(buf.get(start - *released..).unwrap_or_default())
+            }
+            PixelStream::Full(buf) => Ok(buf.get(start..).unwrap_or_default()),
+        }
+    }
+}
+
+/// Bytes of a reader, such as a decrypted payload, read only as far as they have been
+/// asked for.
+pub struct ReadStream<R> {
+    reader: R,
+    buf: Vec<u8>,
+}
+
+impl<R: Read> ReadStream<R> {
+    pub fn new(reader: R) -> Self {
+        ReadStream {
+            reader,
+            buf: Vec::new(),
+        }
+    }
+}
+
+impl<R: Read> Source for ReadStream<R> {
+    fn prefix(&mut self, len: usize) -> anyhow::Result<&[u8]> {
+        if let Some(more) = len.checked_sub(self.buf.len()) {
+            (&mut self.reader)
+                .take(more as u64)
+                .read_to_end(&mut self.buf)?;
+        }
+        Ok(&self.buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::{self, Codec, Packing};
+
+    #[test]
+    fn windows_let_go_of_rows_already_read() {
+        let payload: Vec<u8> = (0..200_000u32).flat_map(u32::to_le_bytes).collect();
+        let container = format::pack(&payload, Packing::new(Codec::Raw)).unwrap();
+        let image = crate::layout(container.clone()).unwrap();
+        let row = image.width() as usize * 4;
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("image.png");
+        image.save(&path).unwrap();
+
+        let mut stream = PixelStream::open(&path).unwrap();
+        let step = 10 * row;
+        for start in (0..container.len()).step_by(step) {
+            let end = (start + step).min(container.len());
+            let window = stream.window(start, end).unwrap();
+            assert_eq!(&window[..end - start], &container[start..end]);
+            let PixelStream::Rows { buf, .. } = &stream else {
+                panic!("a plain container is read row by row")
+            };
+            assert!(buf.len() <= step + row, "{} bytes held", buf.len());
+        }
+        assert!(stream.prefix(1).is_err());
+    }
+}
@@ -0,0 +1,110 @@
+//! Images written as SVG with `--format svg`, a `rect` for each run of pixels of one
+//! color, for embedding in documents. Decoding reads these files back, and PNGs
+//! rasterized from them at any integer scale.
+use anyhow::{bail, ensure, Context};
+use image::{Rgb, RgbImage};
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+/// Writes `image` to `out` as SVG, a pixel to a unit, with edges kept crisp so that
+/// rasterizing it at an integer scale gives back blocks of its exact colors.
+pub fn write(image: &RgbImage, out: &Path) -> anyhow::Result<()> {
+    let (width, height) = image.dimensions();
+    let mut svg = format!(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{width}\" height=\"{height}\" \
+         viewBox=\"0 0 {width} {height}\" shape-rendering=\"crispEdges\">\n"
+    );
+    for (y, row) in image.rows().enumerate() {
+        let mut x = 0;
+        let pixels = row.collect::<Vec<_>>();
+        for run in pixels.chunk_by(|a, b| a == b) {
+            let [r, g, b] = run[0].0;
+            let _ = writeln!(
+                svg,
+                "<rect x=\"{x}\" y=\"{y}\" width=\"{}\" height=\"1\" fill=\"#{r:02x}{g:02x}{b:02x}\"/>",
+                run.len()
+            );
+            x += run.len();
+        }
+    }
+    svg.push_str("</svg>\n");
+    std::fs::write(out, svg).with_context(|| format!("cannot write {}", out.display()))
+}
+
+/// Whether the file at `path` starts like an SVG document.
+pub fn is_svg(path: &Path) -> anyhow::Result<bool> {
+    let mut start = Vec::new();
+    File::open(path)?.take(1024).read_to_end(&mut start)?;
+    Ok(String::from_utf8_lossy(&start).contains("<svg"))
+}
+
+/// The image the SVG at `path` draws, as [`write`] writes it: rects of whole pixels
+/// filled with `#rrggbb` colors, within the size the `svg` element gives.
+pub fn read(path: &Path) -> anyhow::Result<RgbImage> {
+    let text =
+        std::fs::read_to_string(path).with_context(|| format!("cannot read {}", path.display()))?;
+    let mut elements = text.split('<').map(|element| {
+        let end = element.find('>').unwrap_or(element.len());
+        element[..end].trim_end_matches('/')
+    });
+    let Some(svg) = elements.find(|element| element.starts_with("svg")) else {
+        bail!("{} holds no svg element", path.display())
+    };
+    let (width, height) = (number(svg, "width")?, number(svg, "height")?);
+    ensure!(
+        u64::from(width) * u64::from(height) <= 2 * crate::MAX_BYTES as u64,
+        "{} is {width}x{height}, larger than any image picturer writes",
+        path.display()
+    );
+    let mut image = RgbImage::new(width, height);
+    for rect in elements.filter(|element| element.starts_with("rect")) {
+        let (x, y) = (number(rect, "x")?, number(rect, "y")?);
+        let (w, h) = (number(rect, "width")?, number(rect, "height")?);
+        let fill = attribute(rect, "fill")
+            .and_then(|fill| fill.strip_prefix('#'))
+            .filter(|fill| fill.len() == 6)
+            .and_then(|fill| u32::from_str_radix(fill, 16).ok())
+            .with_context(|| {
+                format!(
+                    "{} has a rect not filled with a #rrggbb color",
+                    path.display()
+                )
+            })?;
+        let [_, rgb @ ..] = fill.to_be_bytes();
+        ensure!(
+            x.checked_add(w).is_some_and(|right| right <= width)
+                && y.checked_add(h).is_some_and(|bottom| bottom <= height),
+            "{} has a rect outside its {width}x{height} size",
+            path.display()
+        );
+        for y in y..y + h {
+            for x in x..x + w {
+                image.put_pixel(x, y, Rgb(rgb));
+            }
+        }
+    }
+    Ok(image)
+}
+
+/// The value of the attribute `name` of the element whose tag and attributes are `element`.
+fn attribute<'a>(element: &'a str, name: &str) -> Option<&'a str> {
+    element.split_whitespace().find_map(|pair| {
+        let value = pair.strip_prefix(name)?.strip_prefix('=')?;
+        value
+            .strip_prefix('"')
+            .and_then(|value| value.strip_suffix('"'))
+    })
+}
+
+/// The attribute `name` of `element` as a whole number of pixels.
+fn number(element: &str, name: &str) -> anyhow::Result<u32> {
+    attribute(element, name)
+        .and_then(|value| value.strip_suffix("px").unwrap_or(value).parse().ok())
+        .with_context(|| {
+            let tag = element.split_whitespace().next().unwrap_or_default();
+            format!("an SVG {tag} element has no whole-pixel {name}")
+        })
+}
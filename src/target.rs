@@ -0,0 +1,110 @@
+//! `--target-size`: the codec, or failing that the size of volumes, that keeps every
+//! image `-e` writes under a limit, such as the largest file a platform takes as an upload.
+use crate::format::{self, Codec, Packing};
+use crate::{spill, volume, Options};
+use anyhow::{bail, ensure};
+use std::fs;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+/// Volume sizes tried before giving up, each smaller by as much as the largest volume
+/// of the one before went over.
+const ATTEMPTS: u32 = 4;
+/// Payload bytes compressed to guess how well the rest compresses, when the payload is
+/// too long to try as one image.
+const SAMPLE: usize = 16 << 20;
+/// Fewest payload bytes a volume is made to hold, below which the images would be mostly
+/// headers, and far too many.
+const MIN_VOLUME: u64 = 64 << 10;
+/// Room left in every volume for what a guess at its compressed size misses.
+const MARGIN: f64 = 0.97;
+
+/// Codecs tried for a single image, each slower than the one before and usually smaller.
+fn codecs() -> &'static [Codec] {
+    if cfg!(feature = "zstd") {
+        &[Codec::DEFAULT, Codec::ZSTD, Codec::Zstd(22)]
+    } else {
+        &[Codec::DEFAULT]
+    }
+}
+
+/// Encodes `total` bytes from `input` to `out` as images of no more than `target` bytes:
+/// a single one if some codec makes it small enough, or else a volume set, packed with
+/// whichever codec compressed it best.
+#[allow(
+    clippy::cast_precision_loss,
+    clippy::cast_possible_truncation,
+    clippy::cast_sign_loss
+)]
+pub fn encode(
+    input: impl Read,
+    total: u64,
+    out: &Path,
+    target: u64,
+    options: &Options,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let payload = spill::read(input, total, "spill-payload")?;
+    let packing = |codec| Packing {
+        codec,
+        ..options.packing()
+    };
+    // The codec that compressed the payload best, and the bytes of container it made
+    // of every byte of payload.
+    let (mut codec, mut ratio) = (Codec::DEFAULT, f64::INFINITY);
+    if total > options.capacity() {
+        // Too long for one image: guess from its start how well zstd compresses it.
+        codec = *codecs().get(1).unwrap_or(&Codec::DEFAULT);
+        let sample = &payload[..payload.len().min(SAMPLE)];
+        let len = format::pack(sample, packing(codec))?.len();
+        ratio = len as f64 / sample.len().max(1) as f64;
+    }
+    for &tried in codecs().iter().filter(|_| total <= options.capacity()) {
+        let container = format::pack(&payload, packing(tried))?;
+        let len = container.len() as u64;
+        if len <= target {
+            options.write(&container, out)?;
+            let written = fs::metadata(out)?.len();
+            if written <= target {
+                eprintln!("{tried} fits {} in {written} bytes", out.display());
+                return Ok(vec![out.to_path_buf()]);
+            }
+            fs::remove_file(out)?;
+        }
+        if (len as f64 / total.max(1) as f64) < ratio {
+            (codec, ratio) = (tried, len as f64 / total.max(1) as f64);
+        }
+    }
+    let mut capacity = (target as f64 * MARGIN / ratio) as u64;
+    for _ in 0..ATTEMPTS {
+        capacity = capacity.min(options.capacity());
+        ensure!(
+            capacity >= MIN_VOLUME,
+            "--target-size {target} is too small: each volume would hold under {MIN_VOLUME} bytes"
+        );
+        let written = volume::encode(
+            &*payload,
+            total,
+            out,
+            packing(codec),
+            options.manifest,
+            capacity,
+            |container, path| options.write(container, path),
+        )?;
+        let mut largest = 0;
+        for path in &written {
+            largest = largest.max(fs::metadata(path)?.len());
+        }
+        if largest <= target {
+            eprintln!(
+                "{codec} fits {} volumes in at most {largest} bytes each",
+                written.len()
+            );
+            return Ok(written);
+        }
+        for path in &written {
+            fs::remove_file(path)?;
+        }
+        capacity = (capacity as f64 * target as f64 / largest as f64 * MARGIN) as u64;
+    }
+    bail!("cannot fit volumes under --target-size {target}")
+}
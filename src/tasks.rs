@@ -0,0 +1,42 @@
+//! Async versions of `encode_png` and `decode_png` for tokio. Reading and writing are
+//! awaited; compression runs on the blocking pool so it never stalls the executor.
+use crate::format::{Codec, Packing};
+use crate::MAX_BYTES;
+use anyhow::ensure;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Reads all of `input`, failing once it is longer than `limit` bytes.
+async fn read_all(
+    input: impl AsyncRead + Unpin,
+    limit: usize,
+    what: &str,
+) -> anyhow::Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    input.take(limit as u64 + 1).read_to_end(&mut buf).await?;
+    ensure!(buf.len() <= limit, "{what} is longer than {limit} bytes");
+    Ok(buf)
+}
+
+/// Encodes everything read from `input` as a PNG file written to `output`.
+pub async fn encode_async(
+    input: impl AsyncRead + Unpin,
+    mut output: impl AsyncWrite + Unpin,
+    codec: Codec,
+) -> anyhow::Result<()> {
+    let data = read_all(input, MAX_BYTES, "input").await?;
+    let png = tokio::task::spawn_blocking(move || crate::encode_png(&data, Packing::new(codec)))
+        .await??;
+    output.write_all(&png).await?;
+    Ok(output.flush().await?)
+}
+
+/// Decodes the PNG file read from `input`, writing the payload to `output`.
+pub async fn decode_async(
+    input: impl AsyncRead + Unpin,
+    mut output: impl AsyncWrite + Unpin,
+) -> anyhow::Result<()> {
+    let png = read_all(input, 2 * MAX_BYTES, "image").await?;
+    let data = tokio::task::spawn_blocking(move || crate::decode_png(&png, None)).await??;
+    output.write_all(&data).await?;
+    Ok(output.flush().await?)
+}
@@ -0,0 +1,284 @@
+//! Images written with `--tile WxH`: the container cut into tiles of a fixed size, laid
+//! out in a grid, each starting with a header that says where it belongs and hashes
+//! what it holds. Damage to one tile is then found and named rather than spreading to
+//! the rest, and tools that work on images tile by tile can split the work.
+use anyhow::{bail, ensure};
+use image::RgbaImage;
+use picturer::format::Reader;
+use picturer::MAX_SIDE;
+
+/// Starts every tile, unlike any container or volume.
+const MAGIC: &[u8; 4] = b"PTIL";
+/// Magic, index, count, tile width and height, columns, container length, bytes in the
+/// tile, and hash of the tile.
+const HEADER_LEN: usize = 4 + 4 + 4 + 4 + 4 + 4 + 8 + 4 + 4;
+/// Smallest tile side, so that a tile holds several times its header.
+const MIN_SIDE: u32 = 8;
+
+/// What the header of a tile says.
+struct Header {
+    index: u32,
+    count: u32,
+    size: (u32, u32),
+    columns: u32,
+    total: u64,
+    len: u32,
+    hash: [u8; 4],
+}
+
+impl Header {
+    fn write(&self, out: &mut Vec<u8>) {
+        out.extend_from_slice(MAGIC);
+        for field in [
+            self.index,
+            self.count,
+            self.size.0,
+            self.size.1,
+            self.columns,
+        ] {
+            out.extend_from_slice(&field.to_le_bytes());
+        }
+        out.extend_from_slice(&self.total.to_le_bytes());
+        out.extend_from_slice(&self.len.to_le_bytes());
+        out.extend_from_slice(&self.hash);
+    }
+
+    fn parse(bytes: &[u8]) -> Option<Header> {
+        let mut reader = Reader(bytes);
+        if reader.take(4).ok()? != MAGIC {
+            return None;
+        }
+        let header = Header {
+            index: reader.u32().ok()?,
+            count: reader.u32().ok()?,
+            size: (reader.u32().ok()?, reader.u32().ok()?),
+            columns: reader.u32().ok()?,
+            total: reader.u64().ok()?,
+            len: reader.u32().ok()?,
+            hash: reader.array().ok()?,
+        };
+        let side = MIN_SIDE..=u32::try_from(MAX_SIDE).ok()?;
+        let sane = header.index < header.count
+            && side.contains(&header.size.0)
+            && side.contains(&header.size.1)
+            && header.columns > 0
+            && header.columns <= header.count;
+        sane.then_some(header)
+    }
+}
+
+/// The first 4 bytes of the hash of a tile's place in the grid and what it holds, so that
+/// a tile moved to another place is caught too.
+fn hash(index: u32, data: &[u8]) -> [u8; 4] {
+    let mut hasher = blake3::Hasher::new();
+    hasher.update(&index.to_le_bytes());
+    hasher.update(data);
+    let hash = hasher.finalize();
+    let mut short = [0; 4];
+    short.copy_from_slice(&hash.as_bytes()[..4]);
+    short
+}
+
+/// The `WxH` given with `--tile`.
+pub fn parse_size(value: &str) -> Result<(u32, u32), String> {
+    let size = value
+        .split_once(['x', 'X'])
+        .and_then(|(width, height)| Some((width.parse().ok()?, height.parse().ok()?)));
+    match size {
+        Some((width, height)) if width >= MIN_SIDE && height >= MIN_SIDE => Ok((width, height)),
+        Some(_) => Err(format!("tiles are at least {MIN_SIDE}x{MIN_SIDE} pixels")),
+        None => Err("expected WxH, such as 256x256".to_owned()),
+    }
+}
+
+/// Lays `container` out as tiles of `size` pixels, in a grid as near square as the
+/// count of them allows.
+pub fn layout(container: &[u8], size: (u32, u32)) -> anyhow::Result<RgbaImage> {
+    let (width, height) = (size.0 as usize, size.1 as usize);
+    let tile_bytes = width * height * 4;
+    let room = tile_bytes - HEADER_LEN;
+    let count = container.len().div_ceil(room).max(1);
+    let columns = count.isqrt() + usize::from(count.isqrt().pow(2) < count);
+    let rows = count.div_ceil(columns);
+    ensure!(
+        columns * width <= MAX_SIDE && rows * height <= MAX_SIDE,
+        "{count} tiles of {}x{} do not fit a {MAX_SIDE}x{MAX_SIDE} image",
+        size.0,
+        size.1
+    );
+    let mut image = RgbaImage::new(
+        u32::try_from(columns * width)?,
+        u32::try_from(rows * height)?,
+    );
+    let stride = columns * width * 4;
+    let pixels: &mut [u8] = &mut image;
+    let mut tile = Vec::with_capacity(tile_bytes);
+    for (index, data) in (0..count).zip(container.chunks(room).chain(std::iter::repeat(&[][..]))) {
+        tile.clear();
+        let index32 = u32::try_from(index)?;
+        Header {
+            index: index32,
+            count: u32::try_from(count)?,
+            size,
+            columns: u32::try_from(columns)?,
+            total: container.len() as u64,
+            len: u32::try_from(data.len())?,
+            hash: hash(index32, data),
+        }
+        .write(&mut tile);
+        tile.extend_from_slice(data);
+        tile.resize(tile_bytes, 0);
+        let origin = (index / columns) * height * stride + (index % columns) * width * 4;
+        for (y, row) in tile.chunks(width * 4).enumerate() {
+            let start = origin + y * stride;
+            pixels[start..start + row.len()].copy_from_slice(row);
+        }
+    }
+    Ok(image)
+}
+
+/// Whether `prefix`, the first bytes of the first row of an image, starts a tile.
+pub fn is_tiled(prefix: &[u8]) -> bool {
+    prefix.starts_with(MAGIC)
+}
+
+/// The container a tiled image holds, or `None` if `image` is not one. The first intact
+/// header found along the top row gives the grid; if any tile is damaged, the error
+/// names each and the bytes of the container it held.
+pub fn decode(image: &RgbaImage) -> anyhow::Result<Option<Vec<u8>>> {
+    let pixels: &[u8] = image;
+    let stride = image.width() as usize * 4;
+    let Some(first) = (0..stride.min(pixels.len()))
+        .step_by(4)
+        .find_map(|start| Header::parse(pixels.get(start..start + HEADER_LEN)?))
+    else {
+        return Ok(None);
+    };
+    let (width, height) = (first.size.0 as usize, first.size.1 as usize);
+    let columns = first.columns as usize;
+    let count = first.count as usize;
+    ensure!(
+        columns * width * 4 <= stride
+            && count.div_ceil(columns) * height <= image.height() as usize,
+        "the tiles the image says it holds do not fit it; it was cropped or resized"
+    );
+    let room = width * height * 4 - HEADER_LEN;
+    let total = usize::try_from(first.total)?;
+    let mut container = Vec::with_capacity(total);
+    let mut damaged = Vec::new();
+    let mut tile = Vec::with_capacity(width * height * 4);
+    for index in 0..count {
+        tile.clear();
+        let origin = (index / columns) * height * stride + (index % columns) * width * 4;
+        for y in 0..height {
+            let start = origin + y * stride;
+            tile.extend_from_slice(&pixels[start..start + width * 4]);
+        }
+        let expected = total.saturating_sub(index * room).min(room);
+        let intact = Header::parse(&tile).filter(|header| {
+            header.index as usize == index
+                && header.len as usize == expected
+                && header.hash == hash(header.index, &tile[HEADER_LEN..HEADER_LEN + expected])
+        });
+        if intact.is_none() {
+            damaged.push(index);
+        }
+        container.extend_from_slice(&tile[HEADER_LEN..HEADER_LEN + expected]);
+    }
+    if !damaged.is_empty() {
+        let named = damaged
+            .iter()
+            .map(|&index| {
+                let start = index * room;
+                let end = (start + room).min(total);
+                format!(
+                    "tile {index} (column {}, row {}) held bytes {start}..{end}",
+                    index % columns,
+                    index / columns
+                )
+            })
+            .collect::<Vec<_>>();
+        bail!(
+            "{} of the {count} tiles are damaged; the rest are intact:\n  {}",
+            damaged.len(),
+            named.join("\n  ")
+        );
+    }
+    Ok(Some(container))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use image::{GenericImage, GenericImageView};
+
+    const SIZE: (u32, u32) = (16, 16);
+
+    fn container(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i * 19 + i / 5).to_le_bytes()[0])
+            .collect()
+    }
+
+    /// Copy of the tile at `column`, `row` of `image`.
+    fn tile(image: &RgbaImage, column: u32, row: u32) -> RgbaImage {
+        image
+            .view(column * SIZE.0, row * SIZE.1, SIZE.0, SIZE.1)
+            .to_image()
+    }
+
+    #[test]
+    fn tiles_are_laid_out_in_a_grid_and_read_back() {
+        for len in [0, 1, 984, 5_000] {
+            let container = container(len);
+            let image = layout(&container, SIZE).unwrap();
+            assert!(is_tiled(&image.as_raw()[..4]));
+            assert_eq!(decode(&image).unwrap(), Some(container));
+        }
+        // Six tiles of 984 bytes each, in three columns and two rows.
+        let image = layout(&container(5_000), SIZE).unwrap();
+        assert_eq!(image.dimensions(), (48, 32));
+        assert_eq!(decode(&RgbaImage::new(48, 32)).unwrap(), None);
+
+        assert_eq!(parse_size("256x128"), Ok((256, 128)));
+        assert!(parse_size("4x4").is_err());
+        assert!(parse_size("256").is_err());
+    }
+
+    #[test]
+    fn tiles_out_of_order_are_named() {
+        let mut image = layout(&container(5_000), SIZE).unwrap();
+        let (first, last) = (tile(&image, 0, 1), tile(&image, 2, 1));
+        image.copy_from(&last, 0, SIZE.1).unwrap();
+        image.copy_from(&first, 2 * SIZE.0, SIZE.1).unwrap();
+        let err = decode(&image).unwrap_err().to_string();
+        assert!(err.starts_with("2 of the 6 tiles are damaged"), "{err}");
+        assert!(
+            err.contains("tile 3 (column 0, row 1) held bytes 2952..3936"),
+            "{err}"
+        );
+        assert!(
+            err.contains("tile 5 (column 2, row 1) held bytes 4920..5000"),
+            "{err}"
+        );
+    }
+
+    #[test]
+    fn a_missing_tile_is_named() {
+        let mut image = layout(&container(5_000), SIZE).unwrap();
+        image
+            .copy_from(&RgbaImage::new(SIZE.0, SIZE.1), SIZE.0, 0)
+            .unwrap();
+        let err = decode(&image).unwrap_err().to_string();
+        assert!(err.starts_with("1 of the 6 tiles are damaged"), "{err}");
+        assert!(
+            err.contains("tile 1 (column 1, row 0) held bytes 984..1968"),
+            "{err}"
+        );
+
+        // Gone from the grid altogether, as a crop leaves it.
+        let cropped = tile(&layout(&container(5_000), SIZE).unwrap(), 0, 0);
+        let err = decode(&cropped).unwrap_err().to_string();
+        assert!(err.contains("cropped or resized"), "{err}");
+    }
+}
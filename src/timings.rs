@@ -0,0 +1,114 @@
+//! Wall-clock time and bytes spent in each stage of encoding and decoding, added up
+//! once [`enable`]d, for `--timings`. Stages that run across threads are timed on the
+//! thread that waits for them, so the times add up to no more than the run took.
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static SPENT: [Spent; Stage::ALL.len()] = [const { Spent::new() }; Stage::ALL.len()];
+
+/// One step a payload passes through on its way into or out of an image.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Reading the payload to encode.
+    Read,
+    /// Compressing its blocks.
+    Compress,
+    /// Passing the blocks through a pipeline and writing the container around them.
+    Pack,
+    /// Laying the container out as pixels and writing the PNG.
+    PngWrite,
+    /// Reading and decoding the image.
+    PngRead,
+    /// Parsing the container and joining its blocks.
+    Unpack,
+    /// Undoing the pipeline and decompressing the blocks.
+    Decompress,
+    /// Writing the decoded payload.
+    Write,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 8] = [
+        Stage::Read,
+        Stage::Compress,
+        Stage::Pack,
+        Stage::PngWrite,
+        Stage::PngRead,
+        Stage::Unpack,
+        Stage::Decompress,
+        Stage::Write,
+    ];
+
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Read => "read",
+            Stage::Compress => "compress",
+            Stage::Pack => "pack",
+            Stage::PngWrite => "png write",
+            Stage::PngRead => "png read",
+            Stage::Unpack => "unpack",
+            Stage::Decompress => "decompress",
+            Stage::Write => "write",
+        }
+    }
+}
+
+struct Spent {
+    nanos: AtomicU64,
+    bytes: AtomicU64,
+}
+
+impl Spent {
+    const fn new() -> Self {
+        Spent {
+            nanos: AtomicU64::new(0),
+            bytes: AtomicU64::new(0),
+        }
+    }
+}
+
+/// Starts adding up the time each stage takes.
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+/// When a stage starts, or `None` if timings are not enabled. Clocks are only read once
+/// they are, as some targets, such as wasm32-unknown-unknown, have none.
+#[must_use]
+pub fn start() -> Option<Instant> {
+    ENABLED.load(Ordering::Relaxed).then(Instant::now)
+}
+
+/// Adds the time since `started`, from [`start`], and the `bytes` it went through to
+/// `stage`.
+pub fn record(stage: Stage, started: Option<Instant>, bytes: u64) {
+    let Some(started) = started else {
+        return;
+    };
+    let spent = &SPENT[stage as usize];
+    let nanos = u64::try_from(started.elapsed().as_nanos()).unwrap_or(u64::MAX);
+    spent.nanos.fetch_add(nanos, Ordering::Relaxed);
+    spent.bytes.fetch_add(bytes, Ordering::Relaxed);
+}
+
+/// The time and bytes of each stage that ran, in the order a payload passes through
+/// them.
+#[must_use]
+pub fn spent() -> Vec<(Stage, Duration, u64)> {
+    Stage::ALL
+        .into_iter()
+        .filter_map(|stage| {
+            let spent = &SPENT[stage as usize];
+            let nanos = spent.nanos.load(Ordering::Relaxed);
+            (nanos > 0).then(|| {
+                (
+                    stage,
+                    Duration::from_nanos(nanos),
+                    spent.bytes.load(Ordering::Relaxed),
+                )
+            })
+        })
+        .collect()
+}
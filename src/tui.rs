@@ -0,0 +1,376 @@
+//! `picturer tui`: a terminal interface for picking a file, a mode and a codec,
+//! with an estimate of the image before anything is written.
+
+#[cfg(feature = "tui")]
+mod app {
+    use crate::format::{self, Codec, Packing};
+    use crate::{convert, is_png, volume};
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+    use ratatui::layout::{Constraint, Layout, Rect};
+    use ratatui::style::{Modifier, Style};
+    use ratatui::widgets::{Block, Clear, List, ListItem, ListState, Paragraph, Wrap};
+    use ratatui::{DefaultTerminal, Frame};
+    use std::fs::File;
+    use std::io::Read;
+    use std::path::{Path, PathBuf};
+    use std::thread::JoinHandle;
+    use std::time::{Duration, Instant};
+
+    /// Codecs `c` cycles through, from the default to fastest.
+    const CODECS: [Codec; 5] = [
+        Codec::DEFAULT,
+        Codec::ZSTD,
+        Codec::Zstd(3),
+        Codec::Zlib(1),
+        Codec::Raw,
+    ];
+    /// Bytes from the start of a file compressed to estimate its ratio.
+    const SAMPLE: u64 = 256 << 10;
+
+    struct Entry {
+        name: String,
+        path: PathBuf,
+        is_dir: bool,
+        len: u64,
+    }
+
+    /// What is being written, and the thread writing it.
+    struct Job {
+        title: String,
+        started: Instant,
+        handle: Option<JoinHandle<anyhow::Result<String>>>,
+        result: Option<Result<String, String>>,
+    }
+
+    struct App {
+        dir: PathBuf,
+        entries: Vec<Entry>,
+        list: ListState,
+        decode: bool,
+        codec: usize,
+        /// The estimate shown for the selected file, with the file, mode and codec it is for.
+        estimate: Option<((PathBuf, bool, usize), String)>,
+        job: Option<Job>,
+    }
+
+    pub fn run() -> anyhow::Result<()> {
+        let mut app = App {
+            dir: std::env::current_dir()?,
+            entries: Vec::new(),
+            list: ListState::default(),
+            decode: false,
+            codec: 0,
+            estimate: None,
+            job: None,
+        };
+        app.open(app.dir.clone())?;
+        let mut terminal = ratatui::init();
+        let result = app.run(&mut terminal);
+        ratatui::restore();
+        result
+    }
+
+    impl App {
+        fn run(&mut self, terminal: &mut DefaultTerminal) -> anyhow::Result<()> {
+            loop {
+                self.refresh_estimate();
+                self.poll_job();
+                terminal.draw(|frame| self.draw(frame))?;
+                if !event::poll(Duration::from_millis(100))? {
+                    continue;
+                }
+                let Event::Key(key) = event::read()? else {
+                    continue;
+                };
+                if key.kind != KeyEventKind::Press {
+                    continue;
+                }
+                if let Some(job) = &self.job {
+                    if job.result.is_some() {
+                        self.job = None;
+                    }
+                    continue;
+                }
+                match key.code {
+                    KeyCode::Char('q') | KeyCode::Esc => return Ok(()),
+                    KeyCode::Up | KeyCode::Char('k') => {
+                        self.list.select_previous();
+                        self.detect_mode();
+                    }
+                    KeyCode::Down | KeyCode::Char('j') => {
+                        self.list.select_next();
+                        self.detect_mode();
+                    }
+                    KeyCode::Backspace | KeyCode::Left => {
+                        if let Some(parent) = self.dir.parent() {
+                            self.open(parent.to_path_buf())?;
+                        }
+                    }
+                    KeyCode::Char('m') => self.decode = !self.decode,
+                    KeyCode::Char('c') => self.codec = (self.codec + 1) % CODECS.len(),
+                    KeyCode::Enter | KeyCode::Right => match self.selected() {
+                        Some(entry) if entry.is_dir => self.open(entry.path.clone())?,
+                        Some(_) => self.start(),
+                        None => {}
+                    },
+                    _ => {}
+                }
+            }
+        }
+
+        /// Lists `dir`, directories first, and selects its first entry.
+        fn open(&mut self, dir: PathBuf) -> anyhow::Result<()> {
+            let mut entries = Vec::new();
+            if let Some(parent) = dir.parent() {
+                entries.push(Entry {
+                    name: "..".to_owned(),
+                    path: parent.to_path_buf(),
+                    is_dir: true,
+                    len: 0,
+                });
+            }
+            let mut listed: Vec<Entry> = std::fs::read_dir(&dir)?
+                .filter_map(Result::ok)
+                .filter_map(|entry| {
+                    let metadata = entry.metadata().ok()?;
+                    Some(Entry {
+                        name: entry.file_name().to_string_lossy().into_owned(),
+                        path: entry.path(),
+                        is_dir: metadata.is_dir(),
+                        len: metadata.len(),
+                    })
+                })
+                .collect();
+            listed.sort_by(|a, b| b.is_dir.cmp(&a.is_dir).then_with(|| a.name.cmp(&b.name)));
+            entries.extend(listed);
+            self.dir = dir;
+            self.entries = entries;
+            self.list.select(Some(0));
+            self.estimate = None;
+            self.detect_mode();
+            Ok(())
+        }
+
+        fn selected(&self) -> Option<&Entry> {
+            self.entries.get(self.list.selected()?)
+        }
+
+        /// Picks decoding for PNG files and encoding for everything else.
+        fn detect_mode(&mut self) {
+            if let Some(entry) = self.selected() {
+                self.decode = is_png(&entry.path);
+            }
+        }
+
+        fn refresh_estimate(&mut self) {
+            let Some(entry) = self.selected().filter(|entry| !entry.is_dir) else {
+                return;
+            };
+            let key = (entry.path.clone(), self.decode, self.codec);
+            if self
+                .estimate
+                .as_ref()
+                .is_some_and(|(shown, _)| *shown == key)
+            {
+                return;
+            }
+            let text = if self.decode {
+                describe_image(&entry.path)
+            } else {
+                estimate(&entry.path, entry.len, CODECS[self.codec])
+            }
+            .unwrap_or_else(|error| format!("{error:#}"));
+            self.estimate = Some((key, text));
+        }
+
+        fn start(&mut self) {
+            let Some(entry) = self.selected() else {
+                return;
+            };
+            let path = entry.path.clone();
+            let (decode, codec) = (self.decode, CODECS[self.codec]);
+            let title = if decode {
+                format!("Decoding {}", entry.name)
+            } else {
+                format!("Encoding {} with {codec}", entry.name)
+            };
+            let handle = std::thread::spawn(move || convert(&path, decode, Some(codec)));
+            self.job = Some(Job {
+                title,
+                started: Instant::now(),
+                handle: Some(handle),
+                result: None,
+            });
+        }
+
+        fn poll_job(&mut self) {
+            let Some(job) = &mut self.job else {
+                return;
+            };
+            if !job.handle.as_ref().is_some_and(JoinHandle::is_finished) {
+                return;
+            }
+            let result = match job.handle.take().map(JoinHandle::join) {
+                Some(Ok(Ok(message))) => Ok(message),
+                Some(Ok(Err(error))) => Err(format!("{error:#}")),
+                _ => Err("the worker thread panicked".to_owned()),
+            };
+            job.result = Some(result);
+            let dir = self.dir.clone();
+            let selected = self.list.selected();
+            if self.open(dir).is_ok() {
+                self.list.select(selected);
+            }
+        }
+
+        fn draw(&mut self, frame: &mut Frame) {
+            let [main, help] =
+                Layout::vertical([Constraint::Min(0), Constraint::Length(1)]).areas(frame.area());
+            let [files, details] =
+                Layout::horizontal([Constraint::Percentage(50), Constraint::Percentage(50)])
+                    .areas(main);
+
+            let items: Vec<ListItem> = self
+                .entries
+                .iter()
+                .map(|entry| {
+                    ListItem::new(if entry.is_dir {
+                        format!("{}/", entry.name)
+                    } else {
+                        entry.name.clone()
+                    })
+                })
+                .collect();
+            let list = List::new(items)
+                .block(Block::bordered().title(format!(" {} ", self.dir.display())))
+                .highlight_style(Style::new().add_modifier(Modifier::REVERSED));
+            frame.render_stateful_widget(list, files, &mut self.list);
+
+            let mut lines = vec![
+                format!("Mode:  {}", if self.decode { "decode" } else { "encode" }),
+                format!("Codec: {}", CODECS[self.codec]),
+                String::new(),
+            ];
+            match self.selected() {
+                Some(entry) if !entry.is_dir => {
+                    lines.push(format!("Size:  {}", size(entry.len)));
+                    if let Some((_, estimate)) = &self.estimate {
+                        lines.push(estimate.clone());
+                    }
+                }
+                _ => lines.push("Select a file.".to_owned()),
+            }
+            frame.render_widget(
+                Paragraph::new(lines.join("\n"))
+                    .wrap(Wrap { trim: false })
+                    .block(Block::bordered().title(" Details ")),
+                details,
+            );
+            frame.render_widget(
+                Paragraph::new("↑↓ select  enter open/run  ← parent  m mode  c codec  q quit"),
+                help,
+            );
+
+            if let Some(job) = &self.job {
+                let area = centered(frame.area(), 60, 7);
+                let body = match &job.result {
+                    None => {
+                        let spinner = ['|', '/', '-', '\\'];
+                        let elapsed = job.started.elapsed();
+                        let tick = elapsed.as_millis() / 100 % 4;
+                        format!(
+                            "{} {:.1}s",
+                            spinner[usize::try_from(tick).unwrap_or(0)],
+                            elapsed.as_secs_f64()
+                        )
+                    }
+                    Some(Ok(message)) => format!("{message}\n\nPress any key."),
+                    Some(Err(error)) => format!("Failed: {error}\n\nPress any key."),
+                };
+                frame.render_widget(Clear, area);
+                frame.render_widget(
+                    Paragraph::new(body)
+                        .wrap(Wrap { trim: false })
+                        .block(Block::bordered().title(format!(" {} ", job.title))),
+                    area,
+                );
+            }
+        }
+    }
+
+    /// The image `-e` would write for `len` bytes of `path`, from packing its first bytes.
+    fn estimate(path: &Path, len: u64, codec: Codec) -> anyhow::Result<String> {
+        let mut sample = Vec::new();
+        File::open(path)?.take(SAMPLE).read_to_end(&mut sample)?;
+        let packed = format::pack(&sample, Packing::new(codec))?.len() as u64;
+        #[allow(clippy::cast_precision_loss, clippy::cast_possible_truncation)]
+        #[allow(clippy::cast_sign_loss)]
+        let packed = if sample.len() as u64 == len {
+            packed
+        } else {
+            (packed as f64 / sample.len().max(1) as f64 * len as f64) as u64
+        };
+        if len > volume::CAPACITY {
+            return Ok(format!(
+                "About {} of pixels over {} volumes",
+                size(packed),
+                len.div_ceil(volume::CAPACITY)
+            ));
+        }
+        let pixels = packed.div_ceil(4);
+        let mut side = pixels.isqrt();
+        if side * side < pixels {
+            side += 1;
+        }
+        let rows = pixels.div_ceil(side.max(1));
+        let exact = if sample.len() as u64 == len {
+            ""
+        } else {
+            "About "
+        };
+        Ok(format!(
+            "{exact}{side}x{rows} pixels, {} of pixel data",
+            size(packed)
+        ))
+    }
+
+    fn describe_image(path: &Path) -> anyhow::Result<String> {
+        let (width, height) = image::ImageReader::open(path)?
+            .with_guessed_format()?
+            .into_dimensions()?;
+        Ok(format!(
+            "{width}x{height} pixels, holding at most {}",
+            size(u64::from(width) * u64::from(height) * 4)
+        ))
+    }
+
+    #[allow(clippy::cast_precision_loss)]
+    fn size(bytes: u64) -> String {
+        match bytes {
+            0..1024 => format!("{bytes} B"),
+            1024..0x10_0000 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+            0x10_0000..0x4000_0000 => format!("{:.1} MiB", bytes as f64 / 1_048_576.0),
+            _ => format!("{:.1} GiB", bytes as f64 / 1_073_741_824.0),
+        }
+    }
+
+    fn centered(area: Rect, width: u16, height: u16) -> Rect {
+        let width = width.min(area.width);
+        let height = height.min(area.height);
+        Rect {
+            x: area.x + (area.width - width) / 2,
+            y: area.y + (area.height - height) / 2,
+            width,
+            height,
+        }
+    }
+}
+
+#[cfg(not(feature = "tui"))]
+mod app {
+    pub fn run() -> anyhow::Result<()> {
+        anyhow::bail!("picturer was built without the tui feature")
+    }
+}
+
+pub use app::run;
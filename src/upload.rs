@@ -0,0 +1,134 @@
+use anyhow::{bail, ensure, Context};
+use std::path::PathBuf;
+use std::process::Command;
+
+/// Built-in uploaders, usable by name in place of a command template.
+pub const PRESETS: [(&str, &str); 2] = [
+    ("0x0", "curl -fsS -F file=@{} https://0x0.st"),
+    (
+        "transfer",
+        "curl -fsS --upload-file {} https://transfer.sh/{name}",
+    ),
+];
+
+/// Resolves `uploader`, a preset name or a shell command in which `{}` stands for
+/// the image's path and `{name}` for its file name, to a command template.
+pub fn template(uploader: &str) -> anyhow::Result<&str> {
+    let template = PRESETS
+        .iter()
+        .find(|(name, _)| *name == uploader)
+        .map_or(uploader, |(_, template)| template);
+    ensure!(
+        template.contains("{}"),
+        "--upload expects a preset ({}) or a command containing {{}}",
+        PRESETS.map(|(name, _)| name).join(", ")
+    );
+    Ok(template)
+}
+
+/// Runs `template` on every image in `paths` and prints what it writes to stdout,
+/// normally the image's URL.
+pub fn run(template: &str, paths: &[PathBuf]) -> anyhow::Result<()> {
+    for path in paths {
+        let name = path.file_name().unwrap_or_default().to_string_lossy();
+        let command = template
+            .replace("{name}", &quote(&name))
+            .replace("{}", &quote(&path.to_string_lossy()));
+        let output = shell(&command)
+            .output()
+            .with_context(|| format!("cannot run uploader {command:?}"))?;
+        if !output.status.success() {
+            bail!(
+                "uploading {} failed ({}): {}",
+                path.display(),
+                output.status,
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        }
+        println!("{}", String::from_utf8_lossy(&output.stdout).trim());
+    }
+    Ok(())
+}
+
+#[cfg(unix)]
+fn shell(command: &str) -> Command {
+    let mut shell = Command::new("sh");
+    shell.arg("-c").arg(command);
+    shell
+}
+
+#[cfg(windows)]
+fn shell(command: &str) -> Command {
+    use std::os::windows::process::CommandExt;
+    let mut shell = Command::new("cmd");
+    // Given as it is: the quoting `arg` adds is for programs, and cmd does not follow it.
+    // With /S, cmd takes everything between the outer quotes as the command.
+    shell.raw_arg(format!("/S /C \"{command}\""));
+    shell
+}
+
+#[cfg(unix)]
+fn quote(arg: &str) -> String {
+    format!("'{}'", arg.replace('\'', r"'\''"))
+}
+
+#[cfg(windows)]
+fn quote(arg: &str) -> String {
+    cmd_quote(arg)
+}
+
+/// `arg` quoted as programs on Windows split their command line, then with every
+/// character cmd treats specially, the quotes included, escaped with `^`, so that cmd
+/// neither expands `%` nor acts on `&`, `|`, `<` or `>` and passes the rest on as it is.
+#[cfg_attr(not(windows), allow(dead_code))]
+fn cmd_quote(arg: &str) -> String {
+    let mut quoted = String::from('"');
+    let mut backslashes = 0;
+    for c in arg.chars() {
+        if c == '\\' {
+            backslashes += 1;
+        } else {
+            // Backslashes are literal unless they come before a quote.
+            if c == '"' {
+                quoted.push_str(&"\\".repeat(backslashes + 1));
+            }
+            backslashes = 0;
+        }
+        quoted.push(c);
+    }
+    quoted.push_str(&"\\".repeat(backslashes));
+    quoted.push('"');
+    let mut escaped = String::with_capacity(2 * quoted.len());
+    for c in quoted.chars() {
+        if "()%!^\"<>&|".contains(c) {
+            escaped.push('^');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn cmd_quote_escapes_what_cmd_acts_on() {
+        assert_eq!(
+            cmd_quote(r#"a "b" %PATH% ^&|<>"#),
+            r#"^"a \^"b\^" ^%PATH^% ^^^&^|^<^>^""#
+        );
+        assert_eq!(cmd_quote(r"C:\my dir\"), r#"^"C:\my dir\\^""#);
+        assert_eq!(cmd_quote(r#"x\"y"#), r#"^"x\\\^"y^""#);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn quote_passes_any_name_through_the_shell() {
+        let name = r#"it's "a" $HOME `id` ;&|*.png"#;
+        let output = shell(&format!("printf %s {}", quote(name)))
+            .output()
+            .unwrap();
+        assert_eq!(String::from_utf8(output.stdout).unwrap(), name);
+    }
+}
@@ -0,0 +1,371 @@
+//! `picturer vectors`: payloads, the settings they are packed with and the BLAKE3 hashes
+//! of the containers and pixels this encoder makes of them, fixed in the crate, so that
+//! another implementation, such as the wasm and JavaScript decoders or a port to another
+//! language, can check it reads and writes images byte for byte as picturer does.
+use crate::cli::{PngLevel, VectorsCommand};
+use crate::hash::hex;
+use crate::selftest::{self, Kind};
+use anyhow::Context;
+use picturer::format::{self, Codec, Packing};
+use picturer::pipeline::{Checksum, Pipeline};
+use serde::Serialize;
+use std::path::Path;
+
+/// Starts the generator of every payload, so each is the same from run to run.
+const SEED: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// A payload and how it is packed, with the hashes of what that gives.
+struct Vector {
+    name: &'static str,
+    kind: Kind,
+    len: usize,
+    codec: Codec,
+    block_size: usize,
+    /// Whether the blocks pass through the BLAKE3 checksum stage.
+    checksum: bool,
+    content_type: Option<&'static str>,
+    /// Whether the container is laid out three bytes to a pixel, every pixel opaque.
+    opaque: bool,
+    /// BLAKE3 of the container.
+    container: &'static str,
+    /// BLAKE3 of the pixels, as [`picturer::pixels`] reads them out of the image.
+    pixels: &'static str,
+}
+
+const VECTORS: [Vector; 10] = [
+    Vector {
+        name: "empty-raw",
+        kind: Kind::Zeros,
+        len: 0,
+        codec: Codec::Raw,
+        block_size: format::BLOCK_SIZE,
+        checksum: false,
+        content_type: None,
+        opaque: false,
+        container: "9a0a988857cf46464f5ec94a94c267d6fc542138c326bf39f5822054d6e58aec",
+        pixels: "fd23b6fa2817f8746360f5c96fc34d52cbc7b633e510e7ff389b24fb6060eed1",
+    },
+    Vector {
+        name: "byte-raw",
+        kind: Kind::Random,
+        len: 1,
+        codec: Codec::Raw,
+        block_size: format::BLOCK_SIZE,
+        checksum: false,
+        content_type: None,
+        opaque: false,
+        container: "5f7db89e9b4bfa363ca5b331ccde1367fabc7e589f6a03525d2571a002ea2431",
+        pixels: "c665b39302969c449d98f43160e2c11126bfd3bc263980ab277cbcfd9b826d42",
+    },
+    Vector {
+        name: "text-raw-opaque",
+        kind: Kind::Text,
+        len: 1000,
+        codec: Codec::Raw,
+        block_size: format::BLOCK_SIZE,
+        checksum: false,
+        content_type: Some("text/plain"),
+        opaque: true,
+        container: "b8d608dca908188d1d6bf3d99377756d0dfeef2f34a83389454e73fcc3c69f45",
+        pixels: "64dc495bc9f1a5963f55aef7302d407d5bc9d6bff0001f251ca5fd454c03b5e8",
+    },
+    Vector {
+        name: "random-raw-blocks",
+        kind: Kind::Random,
+        len: 200_000,
+        codec: Codec::Raw,
+        block_size: format::MIN_BLOCK_SIZE,
+        checksum: false,
+        content_type: None,
+        opaque: false,
+        container: "13505c43b259965f3209d4bd833f4a3117fc8f46c3f9a32c6d67b46517fa488d",
+        pixels: "bd9ae351ded8ad7626edfa252fbae439da471d711cd5d64495a05347464a47d3",
+    },
+    Vector {
+        name: "zeros-raw-checksum-opaque",
+        kind: Kind::Zeros,
+        len: 10_000,
+        codec: Codec::Raw,
+        block_size: format::BLOCK_SIZE,
+        checksum: true,
+        content_type: None,
+        opaque: true,
+        container: "02ccf0a9b83aad69559bdf74969286408c6baf4e6102963ab8d68da93ad4ffb2",
+        pixels: "2314e5d6c2b8d18b97657f4ed18201783aebba80be2f970a6a10221604c5a66d",
+    },
+    Vector {
+        name: "text-zlib",
+        kind: Kind::Text,
+        len: 50_000,
+        codec: Codec::Zlib(6),
+        block_size: format::BLOCK_SIZE,
+        checksum: false,
+        content_type: Some("text/plain"),
+        opaque: false,
+        container: "6240831be9190a7d501e9ab0e6dd4d5f979234f9d48633bc336d1659547adf83",
+        pixels: "0f5e758ab14c14f5bc84f72d600e2b7f7cf98eed39b00e2a1ac6d1fe04794584",
+    },
+    Vector {
+        name: "zeros-zlib-opaque",
+        kind: Kind::Zeros,
+        len: 100_000,
+        codec: Codec::Zlib(9),
+        block_size: format::BLOCK_SIZE,
+        checksum: false,
+        content_type: None,
+        opaque: true,
+        container: "937db0d1ff8bbe461d68e4a42d5bb829008f4f472f56b6d1be2a6cdad5346c21",
+        pixels: "42ee9070cdcd0d25e0489b7226f46e7ef31937102d4fe9cbe04b304d7f820969",
+    },
+    Vector {
+        name: "text-zstd",
+        kind: Kind::Text,
+        len: 50_000,
+        codec: Codec::Zstd(3),
+        block_size: format::BLOCK_SIZE,
+        checksum: false,
+        content_type: None,
+        opaque: false,
+        container: "dd92fdd32fec1da0015458e71e4440a1d92ad79855f0c6264a94256db7ca764f",
+        pixels: "4a9bc503483f2db37365a83d0c47403744103a7fe2bc9c8b0b2f6bf9254c4c2c",
+    },
+    Vector {
+        name: "random-zstd",
+        kind: Kind::Random,
+        len: 100_000,
+        codec: Codec::Zstd(3),
+        block_size: format::BLOCK_SIZE,
+        checksum: false,
+        content_type: None,
+        opaque: false,
+        container: "3d3526840b151c9be1b0d621a4fc68ee2bb7f12f890b4f33082f68dd50e3a03a",
+        pixels: "5e854b7726ae49d7644152e17c04d79ba2a40d2903d4e4c30d4af1dd5676d782",
+    },
+    Vector {
+        name: "text-zstd-checksum-blocks",
+        kind: Kind::Text,
+        len: 150_000,
+        codec: Codec::Zstd(19),
+        block_size: format::MIN_BLOCK_SIZE,
+        checksum: true,
+        content_type: None,
+        opaque: false,
+        container: "3e4017be5bb06458ab6df597a268d38661466825df88f1e5cb6b6ef0acd962a6",
+        pixels: "5f236b5900a4218a512a5e13d71333903a16e6721b2c4cdd2063eca0cb46093b",
+    },
+];
+
+/// What `vectors.json` says of each vector written.
+#[derive(Serialize)]
+struct Written {
+    name: &'static str,
+    payload: String,
+    image: String,
+    len: usize,
+    codec: String,
+    block_size: usize,
+    stages: Vec<&'static str>,
+    content_type: Option<&'static str>,
+    opaque: bool,
+    payload_blake3: String,
+    container_blake3: &'static str,
+    pixels_blake3: &'static str,
+}
+
+pub fn run(command: &VectorsCommand) -> anyhow::Result<()> {
+    match command {
+        VectorsCommand::Write { dir } => write(dir),
+        VectorsCommand::Verify { dir: None } => verify_self(),
+        VectorsCommand::Verify { dir: Some(dir) } => verify_dir(dir),
+    }
+}
+
+impl Vector {
+    fn payload(&self) -> Vec<u8> {
+        let mut state = SEED;
+        selftest::payload(self.kind, self.len, &mut state)
+    }
+
+    /// Why this build cannot make the container, if it cannot: zstd may be left out,
+    /// and the zlib streams are those of the C library, which the zlib feature builds in.
+    fn cannot_pack(&self) -> Option<&'static str> {
+        match self.codec {
+            Codec::Zlib(_) if !cfg!(feature = "zlib") => Some("needs the zlib feature"),
+            _ => self.cannot_decode(),
+        }
+    }
+
+    /// Why this build cannot decode the container, if it cannot.
+    fn cannot_decode(&self) -> Option<&'static str> {
+        match self.codec {
+            Codec::Zstd(_) if !cfg!(feature = "zstd") => Some("needs the zstd feature"),
+            _ => None,
+        }
+    }
+
+    /// The container of the payload, which needs the codec to be built in.
+    fn pack(&self, payload: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let checksum = Pipeline::new().then(Checksum);
+        format::pack(
+            payload,
+            Packing {
+                block_size: Some(self.block_size),
+                pipeline: self.checksum.then_some(&checksum),
+                content_type: self.content_type,
+                ..Packing::new(self.codec)
+            },
+        )
+    }
+
+    /// The pixels of the image the container is laid out as, read back as a decoder
+    /// reads them.
+    fn pixels(&self, container: &[u8]) -> anyhow::Result<Vec<u8>> {
+        let image = if self.opaque {
+            picturer::layout_opaque(container)?
+        } else {
+            picturer::layout(container.to_vec())?
+        };
+        Ok(picturer::pixels(picturer::canonical(image.into())?))
+    }
+}
+
+fn blake3(bytes: &[u8]) -> String {
+    hex(blake3::hash(bytes).as_bytes())
+}
+
+/// Writes `NAME.bin` and `NAME.png` for every vector to `dir`, with `vectors.json`.
+fn write(dir: &Path) -> anyhow::Result<()> {
+    std::fs::create_dir_all(dir).with_context(|| format!("cannot create {}", dir.display()))?;
+    let mut written = Vec::new();
+    for vector in VECTORS
+        .iter()
+        .filter(|vector| vector.cannot_pack().is_none())
+    {
+        let payload = vector.payload();
+        let container = vector.pack(&payload)?;
+        let (bin, png) = (
+            format!("{}.bin", vector.name),
+            format!("{}.png", vector.name),
+        );
+        std::fs::write(dir.join(&bin), &payload)?;
+        crate::write_container(
+            &container,
+            &dir.join(&png),
+            vector.opaque,
+            PngLevel::Default,
+        )?;
+        written.push(Written {
+            name: vector.name,
+            payload: bin,
+            image: png,
+            len: vector.len,
+            codec: vector.codec.to_string(),
+            block_size: vector.block_size,
+            stages: if vector.checksum {
+                vec!["blake3"]
+            } else {
+                Vec::new()
+            },
+            content_type: vector.content_type,
+            opaque: vector.opaque,
+            payload_blake3: blake3(&payload),
+            container_blake3: vector.container,
+            pixels_blake3: vector.pixels,
+        });
+    }
+    if written.len() < VECTORS.len() {
+        eprintln!("left out the vectors whose codec this build does not have");
+    }
+    let json = dir.join("vectors.json");
+    std::fs::write(&json, serde_json::to_string_pretty(&written)? + "\n")?;
+    eprintln!(
+        "wrote {} vectors and {} to {}",
+        written.len(),
+        json.display(),
+        dir.display()
+    );
+    Ok(())
+}
+
+/// Checks that this build packs every payload into the container and pixels its hashes
+/// name, and decodes them again.
+fn verify_self() -> anyhow::Result<()> {
+    let mut failed = 0;
+    for vector in &VECTORS {
+        if let Some(why) = vector.cannot_pack() {
+            println!("skip  {}: {why}", vector.name);
+            continue;
+        }
+        let payload = vector.payload();
+        let result = vector.pack(&payload).and_then(|container| {
+            let pixels = vector.pixels(&container)?;
+            anyhow::ensure!(
+                blake3(&container) == vector.container,
+                "container hashes to {}",
+                blake3(&container)
+            );
+            anyhow::ensure!(
+                blake3(&pixels) == vector.pixels,
+                "pixels hash to {}",
+                blake3(&pixels)
+            );
+            anyhow::ensure!(
+                format::unpack(&pixels, None)? == payload,
+                "the payload decoded differs"
+            );
+            Ok(())
+        });
+        match result {
+            Ok(()) => println!("ok    {}", vector.name),
+            Err(err) => {
+                failed += 1;
+                println!("FAIL  {}: {err:#}", vector.name);
+            }
+        }
+    }
+    anyhow::ensure!(failed == 0, "{failed} of {} vectors failed", VECTORS.len());
+    Ok(())
+}
+
+/// Checks the images another implementation wrote to `dir`, `NAME.png` for each vector
+/// it packed with the vector's settings: each must decode to the payload, and is exact
+/// if its pixels are those this encoder writes, which compressed vectors need not be.
+fn verify_dir(dir: &Path) -> anyhow::Result<()> {
+    let (mut checked, mut exact, mut failed) = (0, 0, 0);
+    for vector in &VECTORS {
+        let path = dir.join(format!("{}.png", vector.name));
+        if !path.is_file() {
+            println!("skip  {}: no {}", vector.name, path.display());
+            continue;
+        }
+        if let Some(why) = vector.cannot_decode() {
+            println!("skip  {}: {why}", vector.name);
+            continue;
+        }
+        checked += 1;
+        let result = crate::read_pixels(&path).and_then(|pixels| {
+            anyhow::ensure!(
+                format::unpack(&pixels, None)? == vector.payload(),
+                "the payload decoded differs"
+            );
+            Ok(blake3(&pixels) == vector.pixels)
+        });
+        match result {
+            Ok(true) => {
+                exact += 1;
+                println!("exact {}", vector.name);
+            }
+            Ok(false) => println!("ok    {}: decodes, with other pixels", vector.name),
+            Err(err) => {
+                failed += 1;
+                println!("FAIL  {}: {err:#}", vector.name);
+            }
+        }
+    }
+    println!("{checked} checked, {exact} exact, {failed} failed");
+    anyhow::ensure!(
+        failed == 0,
+        "{failed} of {checked} images did not decode to their payload"
+    );
+    Ok(())
+}
@@ -0,0 +1,85 @@
+use crate::{archive, Options};
+use anyhow::bail;
+use std::fs::File;
+use std::io::{self, BufReader, Cursor, Read, Write};
+
+/// Decodes `options.in_path` without writing the payload anywhere and compares it
+/// with `--against`, failing with the offset of the first byte that differs.
+/// A directory is compared with the archive it would be encoded as with `options.codec`.
+pub fn run(options: &Options) -> anyhow::Result<()> {
+    if options.check {
+        crate::checksum::check(options.given_path())?;
+    }
+    let Some(original) = &options.against else {
+        return Ok(());
+    };
+    let (expected, expected_len): (Box<dyn Read>, u64) = if original.is_dir() {
+        let deterministic = options.deterministic;
+        let archive = archive::pack(original, &options.tree, deterministic, options.codec)?;
+        let len = archive.len() as u64;
+        (Box::new(Cursor::new(archive)), len)
+    } else if crate::is_pipe(original) {
+        let bytes = std::fs::read(original)?;
+        let len = bytes.len() as u64;
+        (Box::new(Cursor::new(bytes)), len)
+    } else {
+        let file = File::open(original)?;
+        let len = file.metadata()?.len();
+        (Box::new(BufReader::new(file)), len)
+    };
+    let mut comparison = Comparison {
+        expected,
+        offset: 0,
+        difference: None,
+    };
+    crate::decode_into(options, &mut comparison)?;
+    let len = comparison.offset;
+    if let Some(at) = comparison
+        .difference
+        .or((len != expected_len).then(|| len.min(expected_len)))
+    {
+        bail!(
+            "payload differs from {} at byte {at} (payload is {len} bytes, original {expected_len})",
+            original.display()
+        );
+    }
+    eprintln!(
+        "{} matches {} ({len} bytes)",
+        options.label,
+        original.display()
+    );
+    Ok(())
+}
+
+/// Checks everything written to it against `expected`, remembering the first difference.
+struct Comparison {
+    expected: Box<dyn Read>,
+    /// Bytes written so far.
+    offset: u64,
+    difference: Option<u64>,
+}
+
+impl Write for Comparison {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.difference.is_none() {
+            let mut expected = Vec::with_capacity(buf.len());
+            (&mut self.expected)
+                .take(buf.len() as u64)
+                .read_to_end(&mut expected)?;
+            let same = buf
+                .iter()
+                .zip(&expected)
+                .take_while(|(a, b)| a == b)
+                .count();
+            if same < buf.len() {
+                self.difference = Some(self.offset + same as u64);
+            }
+        }
+        self.offset += buf.len() as u64;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,244 @@
+//! Payloads written with `--format mkv` as a lossless video: FFV1 frames in Matroska,
+//! each holding one volume, for payloads many times larger than any image picturer
+//! writes, which video tools and hosts handle better than a set of huge PNGs. Built
+//! with the video feature, which links the system ffmpeg libraries.
+use crate::dict::Dictionary;
+use crate::format::Packing;
+#[cfg(feature = "video")]
+use crate::format::{self, Volume};
+#[cfg(feature = "video")]
+use crate::volume::Frames;
+#[cfg(not(feature = "video"))]
+use anyhow::bail;
+#[cfg(feature = "video")]
+use anyhow::{ensure, Context};
+use std::fs::File;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Starts every Matroska file.
+const EBML_MAGIC: [u8; 4] = [0x1a, 0x45, 0xdf, 0xa3];
+/// Side of the frames of a video of more than one frame, each holding 64 MiB.
+#[cfg(feature = "video")]
+const FRAME_SIDE: usize = 4096;
+/// Raw payload bytes per frame, leaving 1/64 of the pixels for headers and for blocks
+/// that grow when compressed, as volumes do.
+#[cfg(feature = "video")]
+const FRAME_CAPACITY: u64 = (FRAME_SIDE * FRAME_SIDE * 4 - FRAME_SIDE * FRAME_SIDE * 4 / 64) as u64;
+/// A frame a second; the frames are volumes, not meant to be watched.
+#[cfg(feature = "video")]
+const TIME_BASE: ffmpeg_next::Rational = ffmpeg_next::Rational(1, 1);
+
+/// Whether the file at `path` is a Matroska file, as videos picturer writes are.
+pub fn is_video(path: &Path) -> anyhow::Result<bool> {
+    let mut start = [0; 4];
+    let read = File::open(path)?.read(&mut start)?;
+    Ok(start[..read] == EBML_MAGIC)
+}
+
+/// Reads `total` bytes from `input` and writes them to `out` as a video of a frame for
+/// every [`FRAME_CAPACITY`] bytes, each frame a volume laid out like a PNG one. A payload
+/// that fits one frame gets a frame only as large as it needs.
+#[cfg(feature = "video")]
+pub fn encode(
+    mut input: impl Read,
+    total: u64,
+    out: &Path,
+    packing: Packing,
+) -> anyhow::Result<Vec<PathBuf>> {
+    use ffmpeg_next::{codec, encoder, format::Pixel, frame};
+
+    ffmpeg_next::init()?;
+    let count = u32::try_from(total.div_ceil(FRAME_CAPACITY).max(1))?;
+    let mut volume = |index: u32| -> anyhow::Result<Vec<u8>> {
+        let offset = u64::from(index) * FRAME_CAPACITY;
+        let mut chunk = Vec::new();
+        (&mut input).take(FRAME_CAPACITY).read_to_end(&mut chunk)?;
+        ensure!(
+            chunk.len() as u64 == FRAME_CAPACITY.min(total - offset),
+            "input changed size while encoding"
+        );
+        let mut buf = Volume {
+            index,
+            count,
+            offset,
+            total,
+        }
+        .header();
+        format::pack_into(&mut buf, &chunk, packing)?;
+        Ok(buf)
+    };
+    let mut first = Some(volume(0)?);
+    let (width, height) = if count == 1 {
+        picturer::layout_size(first.as_ref().map_or(0, Vec::len))
+    } else {
+        (FRAME_SIDE, FRAME_SIDE)
+    };
+
+    let mut output = ffmpeg_next::format::output(out)
+        .with_context(|| format!("cannot write {}", out.display()))?;
+    let ffv1 = encoder::find(codec::Id::FFV1).context("the ffmpeg linked has no FFV1 encoder")?;
+    output.add_stream(ffv1)?;
+    let mut context = codec::context::Context::new_with_codec(ffv1)
+        .encoder()
+        .video()?;
+    context.set_width(u32::try_from(width)?);
+    context.set_height(u32::try_from(height)?);
+    context.set_format(Pixel::BGRA);
+    context.set_time_base(TIME_BASE);
+    context.set_frame_rate(Some(TIME_BASE));
+    if output
+        .format()
+        .flags()
+        .contains(ffmpeg_next::format::Flags::GLOBAL_HEADER)
+    {
+        context.set_flags(codec::Flags::GLOBAL_HEADER);
+    }
+    let mut encoder = context.open_as(ffv1)?;
+    output
+        .stream_mut(0)
+        .context("the video has no stream")?
+        .set_parameters(&encoder);
+    output.write_header()?;
+
+    let mut frame = frame::Video::new(Pixel::BGRA, encoder.width(), encoder.height());
+    for index in 0..count {
+        let buf = match first.take() {
+            Some(buf) => buf,
+            None => volume(index)?,
+        };
+        ensure!(
+            buf.len() <= width * height * 4,
+            "frame {} grew past its size when compressed",
+            index + 1
+        );
+        fill(&mut frame, &buf, width * 4);
+        frame.set_pts(Some(i64::from(index)));
+        encoder.send_frame(&frame)?;
+        write_packets(&mut encoder, &mut output)?;
+        if count > 1 {
+            eprintln!("encoded frame {} of {count}", index + 1);
+        }
+    }
+    encoder.send_eof()?;
+    write_packets(&mut encoder, &mut output)?;
+    output.write_trailer()?;
+    Ok(vec![out.to_path_buf()])
+}
+
+/// Copies `container` into the rows of `frame`, `row` bytes at a time, as BGRA with the
+/// bytes after it zero.
+#[cfg(feature = "video")]
+fn fill(frame: &mut ffmpeg_next::frame::Video, container: &[u8], row: usize) {
+    let stride = frame.stride(0);
+    let data = frame.data_mut(0);
+    data.fill(0);
+    for (y, pixels) in container.chunks(row).enumerate() {
+        let line = &mut data[y * stride..y * stride + pixels.len()];
+        line.copy_from_slice(pixels);
+        for pixel in line.chunks_exact_mut(4) {
+            pixel.swap(0, 2);
+        }
+    }
+}
+
+/// Writes every packet `encoder` has ready to the first stream of `output`.
+#[cfg(feature = "video")]
+fn write_packets(
+    encoder: &mut ffmpeg_next::encoder::Video,
+    output: &mut ffmpeg_next::format::context::Output,
+) -> anyhow::Result<()> {
+    let time_base = output
+        .stream(0)
+        .context("the video has no stream")?
+        .time_base();
+    let mut packet = ffmpeg_next::Packet::empty();
+    while encoder.receive_packet(&mut packet).is_ok() {
+        packet.set_stream(0);
+        packet.rescale_ts(TIME_BASE, time_base);
+        packet.write_interleaved(output)?;
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "video"))]
+pub fn encode(
+    _input: impl Read,
+    _total: u64,
+    _out: &Path,
+    _packing: Packing,
+) -> anyhow::Result<Vec<PathBuf>> {
+    bail!("--format mkv needs picturer built with the video feature")
+}
+
+/// Decodes every frame of the video at `path` in order, writing the payload its
+/// volumes hold to `output`.
+#[cfg(feature = "video")]
+pub fn decode(
+    path: &Path,
+    output: &mut impl Write,
+    dict: Option<&Dictionary>,
+) -> anyhow::Result<()> {
+    use ffmpeg_next::{codec, format::Pixel, frame, media};
+
+    ffmpeg_next::init()?;
+    let mut input = ffmpeg_next::format::input(path)
+        .with_context(|| format!("cannot read {}", path.display()))?;
+    let (index, parameters) = {
+        let stream = input
+            .streams()
+            .best(media::Type::Video)
+            .with_context(|| format!("{} holds no video", path.display()))?;
+        (stream.index(), stream.parameters())
+    };
+    let mut decoder = codec::context::Context::from_parameters(parameters)?
+        .decoder()
+        .video()?;
+    let mut frames = Frames::default();
+    let mut decoded = frame::Video::empty();
+    let mut pixels = Vec::new();
+    let mut receive = |decoder: &mut ffmpeg_next::decoder::Video| -> anyhow::Result<()> {
+        while decoder.receive_frame(&mut decoded).is_ok() {
+            ensure!(
+                decoded.format() == Pixel::BGRA,
+                "{} is not a video picturer wrote: its frames are not lossless BGRA",
+                path.display()
+            );
+            let (row, stride) = (decoded.width() as usize * 4, decoded.stride(0));
+            pixels.clear();
+            for line in decoded
+                .data(0)
+                .chunks(stride)
+                .take(decoded.height() as usize)
+            {
+                pixels.extend_from_slice(&line[..row]);
+            }
+            for pixel in pixels.chunks_exact_mut(4) {
+                pixel.swap(0, 2);
+            }
+            frames.decode(path, &pixels, output, dict)?;
+        }
+        Ok(())
+    };
+    for (stream, packet) in input.packets() {
+        if stream.index() == index {
+            decoder.send_packet(&packet)?;
+            receive(&mut decoder)?;
+        }
+    }
+    decoder.send_eof()?;
+    receive(&mut decoder)?;
+    frames.finish(path)
+}
+
+#[cfg(not(feature = "video"))]
+pub fn decode(
+    path: &Path,
+    _output: &mut impl Write,
+    _dict: Option<&Dictionary>,
+) -> anyhow::Result<()> {
+    bail!(
+        "{} is a video; decoding it needs picturer built with the video feature",
+        path.display()
+    )
+}
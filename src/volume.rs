@@ -0,0 +1,569 @@
+use crate::dict::Dictionary;
+use crate::format::{self, Packing, Skip, Source, Volume};
+use crate::manifest::{self, Manifest};
+use crate::spill;
+use crate::stream::PixelStream;
+use crate::timings::{self, Stage};
+use anyhow::{bail, ensure, Context};
+use rayon::prelude::*;
+use sha2::{Digest, Sha256};
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// Raw payload bytes per volume. Leaves 1/64 of the pixels for headers and
+/// for blocks that grow when compressed.
+pub const CAPACITY: u64 = (crate::MAX_BYTES - crate::MAX_BYTES / 64) as u64;
+/// Like [`CAPACITY`], for volumes written with every pixel opaque.
+const OPAQUE_CAPACITY: u64 = (picturer::MAX_OPAQUE_BYTES - picturer::MAX_OPAQUE_BYTES / 64) as u64;
+
+/// Raw payload bytes per volume, opaque or not.
+pub fn capacity(opaque: bool) -> u64 {
+    if opaque {
+        OPAQUE_CAPACITY
+    } else {
+        CAPACITY
+    }
+}
+
+/// Path of volume `index` in a set of `count` written to `base`:
+/// `out.png` becomes `out.001.png`, `out.002.png`, ...
+pub fn path(base: &Path, index: u32, count: u32) -> PathBuf {
+    let width = count.to_string().len().max(3);
+    let stem = base.file_stem().unwrap_or_default().to_string_lossy();
+    let ext = base.extension().unwrap_or_default().to_string_lossy();
+    base.with_file_name(format!("{stem}.{:0width$}.{ext}", index + 1))
+}
+
+/// Recovers the base path from the path of any volume in a set.
+pub fn base(volume: &Path) -> PathBuf {
+    let stem = volume.file_stem().unwrap_or_default().to_string_lossy();
+    let stem = match stem.rsplit_once('.') {
+        Some((stem, index)) if index.bytes().all(|b| b.is_ascii_digit()) => stem,
+        _ => &stem,
+    };
+    let ext = volume.extension().unwrap_or_default().to_string_lossy();
+    volume.with_file_name(format!("{stem}.{ext}"))
+}
+
+/// Paths of the other volumes of the set `image` is part of, or none if it is a single
+/// image.
+pub fn others(image: &Path) -> anyhow::Result<Vec<PathBuf>> {
+    Ok(position(image)?.map_or_else(Vec::new, |(index, count)| siblings(image, index, count)))
+}
+
+/// Index of `image` in the set it is a volume of and the number of volumes, or `None`
+/// if it is a single image. Files that hold an image, such as armored ones, stand alone,
+/// as none is written as a set, and so do videos and animations, which hold their
+/// volumes as frames.
+pub fn position(image: &Path) -> anyhow::Result<Option<(u32, u32)>> {
+    if crate::is_wrapped(image)? || crate::is_framed(image)? {
+        return Ok(None);
+    }
+    let mut pixels = PixelStream::open(image)?;
+    let header = Volume::parse(pixels.prefix(Volume::HEADER_LEN)?)?;
+    Ok(header.map(|(header, _)| (header.index, header.count)))
+}
+
+/// Paths of the volumes other than `index` of a set of `count` that `image` is one of.
+pub fn siblings(image: &Path, index: u32, count: u32) -> Vec<PathBuf> {
+    let base = base(image);
+    (0..count)
+        .filter(|other| *other != index)
+        .map(|other| path(&base, other, count))
+        .collect()
+}
+
+/// Reads `total` bytes from `input` and writes them as consecutive volumes of `capacity`
+/// payload bytes next to `out`, each with `write`, along with a [`Manifest`] of them if
+/// `manifest` is set. The manifest is updated after every volume; when one from an
+/// interrupted run is found, volumes it lists whose file and payload range still match
+/// are kept instead of being encoded again.
+pub fn encode(
+    mut input: impl Read,
+    total: u64,
+    out: &Path,
+    packing: Packing,
+    manifest: bool,
+    capacity: u64,
+    write: impl Fn(&[u8], &Path) -> anyhow::Result<()>,
+) -> anyhow::Result<Vec<PathBuf>> {
+    let count = u32::try_from(total.div_ceil(capacity))?;
+    let manifest_path = Manifest::path(out);
+    let previous = match Manifest::load(&manifest_path) {
+        Ok(previous) if manifest && previous.total == total => previous.volumes,
+        _ => Vec::new(),
+    };
+    let mut volumes = Vec::new();
+    let mut written = Vec::new();
+    for index in 0..count {
+        let offset = u64::from(index) * capacity;
+        let started = timings::start();
+        let chunk = spill::read(&mut input, capacity, "spill-payload")?;
+        timings::record(Stage::Read, started, chunk.len() as u64);
+        ensure!(
+            chunk.len() as u64 == capacity.min(total - offset),
+            "input changed size while encoding"
+        );
+        let path = path(out, index, count);
+        let file = path
+            .file_name()
+            .unwrap_or_default()
+            .to_string_lossy()
+            .into_owned();
+        let payload_sha256 = if manifest {
+            crate::hash::hex(&Sha256::digest(&*chunk))
+        } else {
+            String::new()
+        };
+        let kept = previous.iter().find(|entry| {
+            entry.sequence == index + 1
+                && entry.file == file
+                && entry.offset == offset
+                && entry.payload_sha256 == payload_sha256
+                && manifest::hash_file(&path)
+                    .is_ok_and(|hash| crate::hash::hex(&hash) == entry.sha256)
+        });
+        if let Some(entry) = kept {
+            eprintln!("kept {}", path.display());
+            volumes.push(entry.clone());
+            written.push(path);
+            continue;
+        }
+        let mut buf = Volume {
+            index,
+            count,
+            offset,
+            total,
+        }
+        .header();
+        format::pack_into(&mut buf, &chunk, packing)?;
+        let buf = spill::keep(buf, "spill-container")?;
+        write(&buf, &path)?;
+        eprintln!("wrote {}", path.display());
+        if manifest {
+            volumes.push(manifest::Entry {
+                file,
+                sequence: index + 1,
+                sha256: crate::hash::hex(&manifest::hash_file(&path)?),
+                offset,
+                length: chunk.len() as u64,
+                payload_sha256,
+            });
+            Manifest {
+                total,
+                volumes: volumes.clone(),
+            }
+            .write(&manifest_path)?;
+        }
+        written.push(path);
+    }
+    if manifest {
+        eprintln!("wrote {}", manifest_path.display());
+    }
+    Ok(written)
+}
+
+/// Decodes every volume of the set that `first` (read from `path`) belongs to,
+/// writing the payload to `output` in order.
+pub fn decode(
+    path: &Path,
+    first: Volume,
+    output: &mut impl Write,
+    dict: Option<&Dictionary>,
+) -> anyhow::Result<()> {
+    let base = base(path);
+    let paths: Vec<PathBuf> = (0..first.count)
+        .map(|index| self::path(&base, index, first.count))
+        .collect();
+    decode_files(&paths, first, output, dict)
+}
+
+/// Decodes the volumes at `paths`, which must be the whole set `first` belongs to, in order.
+pub fn decode_files(
+    paths: &[PathBuf],
+    first: Volume,
+    output: &mut impl Write,
+    dict: Option<&Dictionary>,
+) -> anyhow::Result<()> {
+    ensure!(
+        paths.len() == first.count as usize,
+        "volume set has {} volumes, but {} were given",
+        first.count,
+        paths.len()
+    );
+    format::check_output(first.total)?;
+    let mut written = 0u64;
+    for (index, path) in (0..).zip(paths) {
+        let pixels = crate::read_pixels(path)
+            .with_context(|| format!("cannot read volume {}", path.display()))?;
+        let (volume, container) = member(path, &pixels, index, first)?;
+        ensure!(
+            volume.offset == written,
+            "{} starts at byte {} but {written} bytes were decoded so far",
+            path.display(),
+            volume.offset
+        );
+        let bytes = format::unpack(container, dict)?;
+        let started = timings::start();
+        output.write_all(&bytes)?;
+        timings::record(Stage::Write, started, bytes.len() as u64);
+        written += bytes.len() as u64;
+    }
+    ensure!(
+        written == first.total,
+        "volume set holds {written} bytes, expected {}",
+        first.total
+    );
+    Ok(())
+}
+
+/// Like [`decode`], but into the file at `out`, as [`decode_files_to`] does.
+pub fn decode_to(
+    path: &Path,
+    first: Volume,
+    out: &Path,
+    dict: Option<&Dictionary>,
+    jobs: usize,
+) -> anyhow::Result<()> {
+    let base = base(path);
+    let paths: Vec<PathBuf> = (0..first.count)
+        .map(|index| self::path(&base, index, first.count))
+        .collect();
+    decode_files_to(&paths, first, out, dict, jobs)
+}
+
+/// Decodes the volumes at `paths`, the whole set `first` belongs to, into the file at
+/// `out`, `jobs` of them at a time. The file is sized to the payload first and each
+/// volume written at its offset once decoded, so no volume waits on the one before it;
+/// each holds its pixels and payload in memory until then.
+pub fn decode_files_to(
+    paths: &[PathBuf],
+    first: Volume,
+    out: &Path,
+    dict: Option<&Dictionary>,
+    jobs: usize,
+) -> anyhow::Result<()> {
+    ensure!(
+        paths.len() == first.count as usize,
+        "volume set has {} volumes, but {} were given",
+        first.count,
+        paths.len()
+    );
+    format::check_output(first.total)?;
+    File::create(out)?.set_len(first.total)?;
+    let decoded = write_volumes(paths, first, out, dict, jobs);
+    // Sized up front, what a failure leaves would pass for the whole payload.
+    if decoded.is_err() {
+        let _ = std::fs::remove_file(out);
+    }
+    decoded
+}
+
+fn write_volumes(
+    paths: &[PathBuf],
+    first: Volume,
+    out: &Path,
+    dict: Option<&Dictionary>,
+    jobs: usize,
+) -> anyhow::Result<()> {
+    let decode_one = |(index, path): (u32, &PathBuf)| -> anyhow::Result<(u64, u64)> {
+        let pixels = crate::read_pixels(path)
+            .with_context(|| format!("cannot read volume {}", path.display()))?;
+        let (volume, container) = member(path, &pixels, index, first)?;
+        let bytes = format::unpack(container, dict)?;
+        let len = bytes.len() as u64;
+        ensure!(
+            volume
+                .offset
+                .checked_add(len)
+                .is_some_and(|end| end <= first.total),
+            "{} holds bytes past the end of the {}-byte payload",
+            path.display(),
+            first.total
+        );
+        let started = timings::start();
+        let mut file = OpenOptions::new().write(true).open(out)?;
+        file.seek(SeekFrom::Start(volume.offset))?;
+        file.write_all(&bytes)?;
+        timings::record(Stage::Write, started, len);
+        Ok((volume.offset, len))
+    };
+    let indexed: Vec<(u32, &PathBuf)> = (0..).zip(paths).collect();
+    // A pool of `jobs` threads rather than batches of them, so that one slow volume
+    // holds up none but itself.
+    let pool = rayon::ThreadPoolBuilder::new()
+        .num_threads(jobs.max(1))
+        .build()?;
+    let parts = pool.install(|| {
+        indexed
+            .par_iter()
+            .copied()
+            .map(decode_one)
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+    let mut written = 0u64;
+    for (path, (offset, len)) in paths.iter().zip(parts) {
+        ensure!(
+            offset == written,
+            "{} starts at byte {offset} but the volumes before it hold {written} bytes",
+            path.display()
+        );
+        written += len;
+    }
+    ensure!(
+        written == first.total,
+        "volume set holds {written} bytes, expected {}",
+        first.total
+    );
+    Ok(())
+}
+
+/// Like [`decode`], but writes only `length` bytes starting at `offset` to `output`,
+/// reading just enough of each volume to find the blocks overlapping the range.
+pub fn decode_range(
+    path: &Path,
+    first: Volume,
+    offset: u64,
+    length: Option<u64>,
+    output: &mut impl Write,
+    dict: Option<&Dictionary>,
+) -> anyhow::Result<()> {
+    let base = base(path);
+    let end = length.map_or(u64::MAX, |length| offset.saturating_add(length));
+    for index in 0..first.count {
+        let path = self::path(&base, index, first.count);
+        let mut pixels = PixelStream::open(&path)
+            .with_context(|| format!("cannot read volume {}", path.display()))?;
+        let (volume, _) = member(&path, pixels.prefix(Volume::HEADER_LEN)?, index, first)?;
+        if volume.offset >= end {
+            break;
+        }
+        let bytes = format::unpack_range(
+            &mut Skip(pixels, Volume::HEADER_LEN),
+            offset.saturating_sub(volume.offset),
+            length.map(|_| end - offset.max(volume.offset)),
+            dict,
+        )?;
+        let started = timings::start();
+        output.write_all(&bytes)?;
+        timings::record(Stage::Write, started, bytes.len() as u64);
+    }
+    Ok(())
+}
+
+/// Follows a volume set read from the frames of one file, such as a video or an
+/// animation, checking each frame holds the next volume of the set.
+#[derive(Default)]
+pub struct Frames {
+    first: Option<Volume>,
+    next: u32,
+    written: u64,
+}
+
+impl Frames {
+    /// Decodes the volume the next frame of `path` holds as `pixels`, writing its part
+    /// of the payload to `output`.
+    pub fn decode(
+        &mut self,
+        path: &Path,
+        pixels: &[u8],
+        output: &mut impl Write,
+        dict: Option<&Dictionary>,
+    ) -> anyhow::Result<()> {
+        let Some((volume, container)) = Volume::parse(pixels)? else {
+            bail!(
+                "frame {} of {} holds no volume",
+                self.next + 1,
+                path.display()
+            )
+        };
+        let first = *self.first.get_or_insert(volume);
+        ensure!(
+            volume.index == self.next
+                && volume.count == first.count
+                && volume.total == first.total
+                && volume.offset == self.written,
+            "frame {} of {} holds volume {} of {}, not the next one of its set",
+            self.next + 1,
+            path.display(),
+            volume.index + 1,
+            volume.count
+        );
+        if self.next == 0 {
+            format::check_output(volume.total)?;
+        }
+        let bytes = format::unpack(container, dict)?;
+        let started = timings::start();
+        output.write_all(&bytes)?;
+        timings::record(Stage::Write, started, bytes.len() as u64);
+        self.written += bytes.len() as u64;
+        self.next += 1;
+        Ok(())
+    }
+
+    /// Checks every volume of the set was found once the frames of `path` run out.
+    pub fn finish(&self, path: &Path) -> anyhow::Result<()> {
+        let Some(first) = self.first else {
+            bail!("{} has no frames", path.display())
+        };
+        ensure!(
+            self.next == first.count && self.written == first.total,
+            "{} holds {} of the {} volumes of its set; the rest are missing",
+            path.display(),
+            self.next,
+            first.count
+        );
+        Ok(())
+    }
+}
+
+/// Parses the volume header of `pixels`, checking it is volume `index` of `first`'s set.
+fn member<'a>(
+    path: &Path,
+    pixels: &'a [u8],
+    index: u32,
+    first: Volume,
+) -> anyhow::Result<(Volume, &'a [u8])> {
+    let Some((volume, container)) = Volume::parse(pixels)? else {
+        bail!("{} is not part of a volume set", path.display())
+    };
+    ensure!(
+        volume.index == index && volume.count == first.count && volume.total == first.total,
+        "{} belongs to a different volume set",
+        path.display()
+    );
+    Ok((volume, container))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::format::Codec;
+
+    const SMALL: u64 = 1_000;
+
+    /// Writes `payload` as a set of volumes of [`SMALL`] bytes named after `out`.
+    fn split(payload: &[u8], out: &Path) -> Vec<PathBuf> {
+        encode(
+            payload,
+            payload.len() as u64,
+            out,
+            Packing::new(Codec::Raw),
+            false,
+            SMALL,
+            write_image,
+        )
+        .unwrap()
+    }
+
+    fn write_image(container: &[u8], path: &Path) -> anyhow::Result<()> {
+        Ok(crate::layout(container.to_vec())?.save(path)?)
+    }
+
+    /// The header of the volume at `path`.
+    fn header(path: &Path) -> Volume {
+        Volume::parse(&crate::read_pixels(path).unwrap())
+            .unwrap()
+            .unwrap()
+            .0
+    }
+
+    fn join(paths: &[PathBuf]) -> anyhow::Result<Vec<u8>> {
+        let mut out = Vec::new();
+        decode_files(paths, header(&paths[0]), &mut out, None)?;
+        Ok(out)
+    }
+
+    fn payload(len: usize) -> Vec<u8> {
+        (0..len)
+            .map(|i| (i * 13 + i / 7).to_le_bytes()[0])
+            .collect()
+    }
+
+    #[test]
+    fn a_split_payload_joins_back_together() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = payload(3_500);
+        let paths = split(&payload, &dir.path().join("out.png"));
+        assert_eq!(paths.len(), 4);
+        assert_eq!(paths[2], dir.path().join("out.003.png"));
+        assert_eq!(base(&paths[2]), dir.path().join("out.png"));
+        assert_eq!(position(&paths[2]).unwrap(), Some((2, 4)));
+
+        assert_eq!(join(&paths).unwrap(), payload);
+        let mut out = Vec::new();
+        decode(&paths[3], header(&paths[0]), &mut out, None).unwrap();
+        assert_eq!(out, payload);
+    }
+
+    #[test]
+    fn a_missing_volume_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = split(&payload(3_500), &dir.path().join("out.png"));
+        std::fs::remove_file(&paths[1]).unwrap();
+        let err = decode(&paths[0], header(&paths[0]), &mut Vec::new(), None).unwrap_err();
+        assert!(err.to_string().contains("cannot read volume"), "{err}");
+        let err = join(&paths[..3]).unwrap_err();
+        assert!(err.to_string().contains("but 3 were given"), "{err}");
+    }
+
+    #[test]
+    fn a_duplicated_volume_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = split(&payload(3_500), &dir.path().join("out.png"));
+        paths[2] = paths[1].clone();
+        let err = join(&paths).unwrap_err();
+        assert!(err.to_string().contains("different volume set"), "{err}");
+    }
+
+    #[test]
+    fn a_volume_at_the_wrong_offset_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = payload(3_500);
+        let paths = split(&payload, &dir.path().join("out.png"));
+        let mut buf = Volume {
+            offset: SMALL - 1,
+            ..header(&paths[1])
+        }
+        .header();
+        format::pack_into(&mut buf, &payload[999..2_000], Packing::new(Codec::Raw)).unwrap();
+        write_image(&buf, &paths[1]).unwrap();
+        let err = join(&paths).unwrap_err();
+        assert!(err.to_string().contains("starts at byte 999"), "{err}");
+    }
+
+    #[test]
+    fn a_volume_of_another_set_is_refused() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut paths = split(&payload(3_500), &dir.path().join("out.png"));
+        let others = split(&payload(3_600), &dir.path().join("other.png"));
+        paths[3] = others[3].clone();
+        let err = join(&paths).unwrap_err();
+        assert!(err.to_string().contains("different volume set"), "{err}");
+    }
+
+    #[test]
+    fn volumes_decoded_in_parallel_land_at_their_offsets() {
+        let dir = tempfile::tempdir().unwrap();
+        let payload = payload(7_300);
+        let paths = split(&payload, &dir.path().join("out.png"));
+        for jobs in [1, 3, 8] {
+            let out = dir.path().join(format!("joined-{jobs}"));
+            decode_files_to(&paths, header(&paths[0]), &out, None, jobs).unwrap();
+            assert_eq!(std::fs::read(&out).unwrap(), payload, "{jobs} jobs");
+        }
+    }
+
+    #[test]
+    fn a_bad_volume_leaves_no_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let paths = split(&payload(7_300), &dir.path().join("out.png"));
+        let others = split(&payload(7_400), &dir.path().join("other.png"));
+        std::fs::copy(&others[5], &paths[5]).unwrap();
+        let out = dir.path().join("joined");
+        let err = decode_to(&paths[0], header(&paths[0]), &out, None, 4).unwrap_err();
+        assert!(err.to_string().contains("different volume set"), "{err}");
+        assert!(!out.exists());
+    }
+}
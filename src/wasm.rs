@@ -0,0 +1,21 @@
+//! Browser API, built with `wasm-pack build -- --no-default-features --features std`:
+//! the default features pull in C code that wasm32-unknown-unknown cannot compile.
+use crate::format::{Codec, Packing};
+use wasm_bindgen::prelude::*;
+
+/// Encodes `bytes` as a PNG file with the default codec.
+#[wasm_bindgen]
+pub fn encode(bytes: &[u8]) -> Result<Vec<u8>, JsError> {
+    crate::encode_png(bytes, Packing::new(Codec::DEFAULT)).map_err(js_error)
+}
+
+/// Decodes a PNG file written by `encode` or the command line tool.
+#[wasm_bindgen]
+pub fn decode(png: &[u8]) -> Result<Vec<u8>, JsError> {
+    crate::decode_png(png, None).map_err(js_error)
+}
+
+#[allow(clippy::needless_pass_by_value)]
+fn js_error(error: anyhow::Error) -> JsError {
+    JsError::new(&format!("{error:#}"))
+}